@@ -0,0 +1,392 @@
+//! Shorthands for building expressions by hand, e.g. in tests:
+//!
+//! ```
+//! use ast::expr::{apply, int, var};
+//! let e = apply(var("f"), int(92));
+//! assert_eq!(format!("{:?}", e), "(f 92)");
+//! ```
+
+use Ident;
+use Type;
+use exprs::{Expr, ExprKind, Literal, BinOp, ArithOp, CmpOp, LogicOp, Not, List, Cons, Concat, UnOp, ListOp, RefOp,
+            PrintOp, ConvertOp, Assign, Seq, Record, Field, If, Fun, Let, LetFun, LetRec, TypeAlias, Apply};
+
+pub fn var(name: &str) -> Expr {
+    ExprKind::Var(Ident::from_str(name)).into()
+}
+
+pub fn int(value: i64) -> Expr {
+    Literal::Number(value).into()
+}
+
+pub fn float(value: f64) -> Expr {
+    Literal::Float(value).into()
+}
+
+pub fn bool(value: bool) -> Expr {
+    Literal::Bool(value).into()
+}
+
+pub fn string(value: &str) -> Expr {
+    Literal::Str(value.to_owned()).into()
+}
+
+/// `()`
+pub fn unit() -> Expr {
+    Literal::Unit.into()
+}
+
+pub fn add(lhs: Expr, rhs: Expr) -> Expr {
+    arith(ArithOp::Add, lhs, rhs)
+}
+
+pub fn sub(lhs: Expr, rhs: Expr) -> Expr {
+    arith(ArithOp::Sub, lhs, rhs)
+}
+
+pub fn mul(lhs: Expr, rhs: Expr) -> Expr {
+    arith(ArithOp::Mul, lhs, rhs)
+}
+
+pub fn div(lhs: Expr, rhs: Expr) -> Expr {
+    arith(ArithOp::Div, lhs, rhs)
+}
+
+/// `lhs % rhs`, `mod` being a keyword.
+pub fn rem(lhs: Expr, rhs: Expr) -> Expr {
+    arith(ArithOp::Mod, lhs, rhs)
+}
+
+/// `-arg`: a negative literal if `arg` is a number, `0 - arg` otherwise, so
+/// a float which isn't a literal is negated with `0.0 - x`.
+pub fn neg(arg: Expr) -> Expr {
+    match arg.kind {
+        ExprKind::Literal(Literal::Number(n)) => int(-n),
+        ExprKind::Literal(Literal::Float(x)) => float(-x),
+        _ => sub(int(0), arg),
+    }
+}
+
+pub fn eq(lhs: Expr, rhs: Expr) -> Expr {
+    cmp(CmpOp::Eq, lhs, rhs)
+}
+
+pub fn ne(lhs: Expr, rhs: Expr) -> Expr {
+    cmp(CmpOp::Ne, lhs, rhs)
+}
+
+pub fn lt(lhs: Expr, rhs: Expr) -> Expr {
+    cmp(CmpOp::Lt, lhs, rhs)
+}
+
+pub fn le(lhs: Expr, rhs: Expr) -> Expr {
+    cmp(CmpOp::Le, lhs, rhs)
+}
+
+pub fn gt(lhs: Expr, rhs: Expr) -> Expr {
+    cmp(CmpOp::Gt, lhs, rhs)
+}
+
+pub fn ge(lhs: Expr, rhs: Expr) -> Expr {
+    cmp(CmpOp::Ge, lhs, rhs)
+}
+
+pub fn and(lhs: Expr, rhs: Expr) -> Expr {
+    logic(LogicOp::And, lhs, rhs)
+}
+
+pub fn or(lhs: Expr, rhs: Expr) -> Expr {
+    logic(LogicOp::Or, lhs, rhs)
+}
+
+pub fn not(arg: Expr) -> Expr {
+    Not { arg: arg }.into()
+}
+
+/// `[items]`, see `nil` for an empty list.
+pub fn list(items: Vec<Expr>) -> Expr {
+    List {
+        items: items,
+        type_: None,
+    }
+    .into()
+}
+
+/// `[]: element_type list`
+pub fn nil(element_type: Type) -> Expr {
+    List {
+        items: vec![],
+        type_: Some(element_type.list()),
+    }
+    .into()
+}
+
+pub fn cons(head: Expr, tail: Expr) -> Expr {
+    Cons {
+        head: head,
+        tail: tail,
+    }
+    .into()
+}
+
+pub fn concat(lhs: Expr, rhs: Expr) -> Expr {
+    Concat {
+        lhs: lhs,
+        rhs: rhs,
+    }
+    .into()
+}
+
+pub fn head(list: Expr) -> Expr {
+    list_op(ListOp::Head, list)
+}
+
+pub fn tail(list: Expr) -> Expr {
+    list_op(ListOp::Tail, list)
+}
+
+pub fn is_empty(list: Expr) -> Expr {
+    list_op(ListOp::IsEmpty, list)
+}
+
+/// `ref value`, `ref` being a keyword.
+pub fn ref_(value: Expr) -> Expr {
+    UnOp {
+        kind: RefOp::Ref,
+        arg: value,
+    }
+    .into()
+}
+
+/// `!cell`
+pub fn deref(cell: Expr) -> Expr {
+    UnOp {
+        kind: RefOp::Deref,
+        arg: cell,
+    }
+    .into()
+}
+
+/// `print s`, which writes the string `s`.
+pub fn print(s: Expr) -> Expr {
+    UnOp {
+        kind: PrintOp::Print,
+        arg: s,
+    }
+    .into()
+}
+
+/// `print_int n`
+pub fn print_int(n: Expr) -> Expr {
+    UnOp {
+        kind: PrintOp::PrintInt,
+        arg: n,
+    }
+    .into()
+}
+
+/// `int_of_float x`
+pub fn int_of_float(x: Expr) -> Expr {
+    UnOp {
+        kind: ConvertOp::IntOfFloat,
+        arg: x,
+    }
+    .into()
+}
+
+/// `float_of_int n`
+pub fn float_of_int(n: Expr) -> Expr {
+    UnOp {
+        kind: ConvertOp::FloatOfInt,
+        arg: n,
+    }
+    .into()
+}
+
+/// `cell := value`
+pub fn assign(cell: Expr, value: Expr) -> Expr {
+    Assign {
+        cell: cell,
+        value: value,
+    }
+    .into()
+}
+
+/// `first; second`
+pub fn seq(first: Expr, second: Expr) -> Expr {
+    Seq {
+        first: first,
+        second: second,
+    }
+    .into()
+}
+
+/// `{name = value, ...}`
+pub fn record(fields: Vec<(&str, Expr)>) -> Expr {
+    Record { fields: fields.into_iter().map(|(name, value)| (Ident::from_str(name), value)).collect() }.into()
+}
+
+/// `record.name`
+pub fn field(record: Expr, name: &str) -> Expr {
+    Field {
+        record: record,
+        name: Ident::from_str(name),
+    }
+    .into()
+}
+
+pub fn if_(cond: Expr, tru: Expr, fls: Expr) -> Expr {
+    If {
+        cond: cond,
+        tru: tru,
+        fls: fls,
+    }
+    .into()
+}
+
+/// `fun name(arg: arg_type): fun_type is body`, see also `Fun::new`.
+pub fn fun(name: &str, arg: &str, arg_type: Type, fun_type: Type, body: Expr) -> Expr {
+    Fun::new(name, arg, arg_type, fun_type, body).into()
+}
+
+/// `let name = value in body`.
+pub fn let_(name: &str, value: Expr, body: Expr) -> Expr {
+    Let {
+        name: Ident::from_str(name),
+        value: value,
+        body: body,
+    }
+    .into()
+}
+
+pub fn let_fun(fun: Fun, body: Expr) -> Expr {
+    LetFun {
+        fun: fun,
+        body: body,
+    }
+    .into()
+}
+
+pub fn let_rec(funs: Vec<Fun>, body: Expr) -> Expr {
+    LetRec {
+        funs: funs,
+        body: body,
+    }
+    .into()
+}
+
+/// `type name = type_ in body`.
+pub fn type_alias(name: &str, type_: Type, body: Expr) -> Expr {
+    TypeAlias {
+        name: Ident::from_str(name),
+        type_: type_,
+        body: body,
+    }
+    .into()
+}
+
+pub fn apply(fun: Expr, arg: Expr) -> Expr {
+    Apply {
+        fun: fun,
+        arg: arg,
+    }
+    .into()
+}
+
+fn arith(kind: ArithOp, lhs: Expr, rhs: Expr) -> Expr {
+    BinOp {
+        kind: kind,
+        lhs: lhs,
+        rhs: rhs,
+    }
+    .into()
+}
+
+fn list_op(kind: ListOp, arg: Expr) -> Expr {
+    UnOp {
+        kind: kind,
+        arg: arg,
+    }
+    .into()
+}
+
+fn cmp(kind: CmpOp, lhs: Expr, rhs: Expr) -> Expr {
+    BinOp {
+        kind: kind,
+        lhs: lhs,
+        rhs: rhs,
+    }
+    .into()
+}
+
+fn logic(kind: LogicOp, lhs: Expr, rhs: Expr) -> Expr {
+    BinOp {
+        kind: kind,
+        lhs: lhs,
+        rhs: rhs,
+    }
+    .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use {Fun, Type};
+
+    #[test]
+    fn test_builders() {
+        let fact = Fun::new("fact",
+                            "n",
+                            Type::int(),
+                            Type::int(),
+                            if_(eq(var("n"), int(0)),
+                                int(1),
+                                mul(var("n"), apply(var("fact"), sub(var("n"), int(1))))));
+        let e = let_fun(fact, apply(var("fact"), int(5)));
+        assert_eq!(format!("{:?}", e),
+                   "(let fact λ(n: int): int (if (== n 0) 1 (* n (fact (- n 1)))) in (fact 5))");
+        assert_eq!(format!("{:?}", fun("id", "b", Type::bool(), Type::bool(), bool(true))),
+                   "(λ id (b: bool): bool true)");
+        let add_fun = Fun::new("add", "x", Type::int(), Type::int(), add(var("x"), var("y")))
+                          .with_arg("y", Type::int());
+        assert_eq!(format!("{:?}", add_fun), "(λ add (x: int, y: int): int (+ x y))");
+        assert_eq!(format!("{:?}", type_alias("pred", Type::int().arrow(Type::bool()), var("p"))),
+                   "(type pred = int -> bool in p)");
+        assert_eq!(format!("{:?}", field(record(vec![("x", int(1)), ("y", var("p"))]), "y")),
+                   "{x = 1, y = p}.y");
+        assert_eq!(format!("{:?}", cons(int(1), list(vec![int(2), head(var("xs"))]))),
+                   "(:: 1 [2, (head xs)])");
+        assert_eq!(format!("{:?}", is_empty(nil(Type::bool()))), "(isEmpty []: bool list)");
+        assert_eq!(format!("{:?}", let_("x", add(int(2), int(2)), mul(var("x"), var("x")))),
+                   "(let x (+ 2 2) in (* x x))");
+        assert_eq!(format!("{:?}", concat(string("say \"hi\"\n"), string("\\"))),
+                   r#"(++ "say \"hi\"\n" "\\")"#);
+        assert_eq!(format!("{:?}", neg(add(neg(int(1)), rem(var("x"), int(2))))),
+                   "(- 0 (+ -1 (% x 2)))");
+        assert_eq!(format!("{:?}", or(not(var("a")), and(bool(true), lt(var("x"), int(1))))),
+                   "(|| (not a) (&& true (< x 1)))");
+        assert_eq!(format!("{:?}", and(le(var("a"), int(1)), or(ge(var("b"), int(2)), ne(var("c"), int(3))))),
+                   "(&& (<= a 1) (|| (>= b 2) (!= c 3)))");
+        assert_eq!(format!("{:?}", let_("r", ref_(int(1)), assign(var("r"), add(deref(var("r")), int(1))))),
+                   "(let r (ref 1) in (:= r (+ (! r) 1)))");
+        assert_eq!(format!("{:?}", seq(assign(var("r"), int(1)), seq(unit(), deref(var("r"))))),
+                   "(; (:= r 1) (; () (! r)))");
+        assert_eq!(format!("{:?}", seq(print(string("hi")), print_int(deref(var("r"))))),
+                   "(; (print \"hi\") (print_int (! r)))");
+        assert_eq!(format!("{:?}", add(float(1.0), float_of_int(int_of_float(neg(float(2.5)))))),
+                   "(+ 1.0 (float_of_int (int_of_float -2.5)))");
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde() {
+        let e = let_("xs", list(vec![float(1.5), neg(var("x"))]), field(record(vec![("y", var("xs"))]), "y"));
+        let json = ::serde_json::to_string(&e).unwrap();
+        assert!(json.contains(r#"{"Var":"xs"}"#), "{}", json);
+        let parsed: Expr = ::serde_json::from_str(&json).unwrap();
+        assert_eq!(format!("{:?}", parsed), format!("{:?}", e));
+        let type_ = Type::int().arrow(Type::bool().list());
+        let parsed: Type = ::serde_json::from_str(&::serde_json::to_string(&type_).unwrap()).unwrap();
+        assert_eq!(parsed, type_);
+    }
+}
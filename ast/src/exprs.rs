@@ -1,17 +1,68 @@
 use Type;
 use Ident;
+use Span;
 use std::fmt::{self, Write};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// An expression and where it came from in the source.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+impl Expr {
+    pub fn at(mut self, span: Span) -> Expr {
+        self.span = span;
+        self
+    }
+}
+
+impl From<ExprKind> for Expr {
+    fn from(kind: ExprKind) -> Expr {
+        Expr {
+            kind: kind,
+            span: Span::default(),
+        }
+    }
+}
 
+/// Spans are not shown, so that the same code parses to the same text wherever it is.
+impl fmt::Debug for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.kind.fmt(f)
+    }
+}
 
-pub enum Expr {
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum ExprKind {
     Var(Ident),
     Literal(Literal),
     ArithBinOp(Box<ArithBinOp>),
     CmpBinOp(Box<CmpBinOp>),
+    LogicBinOp(Box<LogicBinOp>),
+    Not(Box<Not>),
+    List(Box<List>),
+    Cons(Box<Cons>),
+    Concat(Box<Concat>),
+    ListUnOp(Box<ListUnOp>),
+    RefUnOp(Box<RefUnOp>),
+    PrintUnOp(Box<PrintUnOp>),
+    ConvertUnOp(Box<ConvertUnOp>),
+    Assign(Box<Assign>),
+    Seq(Box<Seq>),
+    Record(Box<Record>),
+    Field(Box<Field>),
+    Construct(Box<Construct>),
+    Match(Box<Match>),
     If(Box<If>),
     Fun(Box<Fun>),
+    Let(Box<Let>),
     LetFun(Box<LetFun>),
     LetRec(Box<LetRec>),
+    LetType(Box<LetType>),
+    TypeAlias(Box<TypeAlias>),
     Apply(Box<Apply>),
 }
 
@@ -19,29 +70,48 @@ macro_rules! into_expr {
     ($id:ident) => {
         impl Into<Expr> for $id {
             fn into(self) -> Expr {
-                Expr::$id(Box::new(self))
+                ExprKind::$id(Box::new(self)).into()
             }
         }
     }
 }
 
-impl fmt::Debug for Expr {
+impl fmt::Debug for ExprKind {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        use self::Expr::*;
+        use self::ExprKind::*;
         match *self {
             Var(ref s) => f.write_str(s.as_ref()),
             Literal(ref l) => l.fmt(f),
             ArithBinOp(ref op) => op.fmt(f),
             CmpBinOp(ref op) => op.fmt(f),
+            LogicBinOp(ref op) => op.fmt(f),
+            Not(ref not) => not.fmt(f),
+            List(ref list) => list.fmt(f),
+            Cons(ref cons) => cons.fmt(f),
+            Concat(ref concat) => concat.fmt(f),
+            ListUnOp(ref op) => op.fmt(f),
+            RefUnOp(ref op) => op.fmt(f),
+            PrintUnOp(ref op) => op.fmt(f),
+            ConvertUnOp(ref op) => op.fmt(f),
+            Assign(ref assign) => assign.fmt(f),
+            Seq(ref seq) => seq.fmt(f),
+            Record(ref record) => record.fmt(f),
+            Field(ref field) => field.fmt(f),
+            Construct(ref construct) => construct.fmt(f),
+            Match(ref match_) => match_.fmt(f),
             If(ref if_) => if_.fmt(f),
             Apply(ref apply) => apply.fmt(f),
             Fun(ref fun) => fun.fmt(f),
+            Let(ref let_) => let_.fmt(f),
             LetFun(ref let_fun) => let_fun.fmt(f),
             LetRec(ref let_rec) => let_rec.fmt(f),
+            LetType(ref let_type) => let_type.fmt(f),
+            TypeAlias(ref alias) => alias.fmt(f),
         }
     }
 }
 
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct BinOp<T> {
     pub kind: T,
     pub lhs: Expr,
@@ -55,9 +125,11 @@ impl<T: fmt::Debug> fmt::Debug for BinOp<T> {
 }
 
 #[derive(Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum ArithOp {
     Mul,
     Div,
+    Mod,
     Add,
     Sub,
 }
@@ -68,6 +140,7 @@ impl fmt::Debug for ArithOp {
         f.write_char(match *self {
             Mul => '*',
             Div => '\\',
+            Mod => '%',
             Add => '+',
             Sub => '-',
         })
@@ -79,10 +152,14 @@ pub type ArithBinOp = BinOp<ArithOp>;
 into_expr!(ArithBinOp);
 
 #[derive(Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum CmpOp {
     Eq,
+    Ne,
     Lt,
+    Le,
     Gt,
+    Ge,
 }
 
 impl fmt::Debug for CmpOp {
@@ -90,8 +167,11 @@ impl fmt::Debug for CmpOp {
         use self::CmpOp::*;
         f.write_str(match *self {
             Eq => "==",
+            Ne => "!=",
             Lt => "<",
+            Le => "<=",
             Gt => ">",
+            Ge => ">=",
         })
     }
 }
@@ -100,6 +180,323 @@ pub type CmpBinOp = BinOp<CmpOp>;
 
 into_expr!(CmpBinOp);
 
+/// `&&` and `||`. The right operand is only evaluated if the left one
+/// doesn't decide the result.
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum LogicOp {
+    And,
+    Or,
+}
+
+impl fmt::Debug for LogicOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            LogicOp::And => "&&",
+            LogicOp::Or => "||",
+        })
+    }
+}
+
+pub type LogicBinOp = BinOp<LogicOp>;
+
+into_expr!(LogicBinOp);
+
+/// `not arg`
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Not {
+    pub arg: Expr,
+}
+
+into_expr!(Not);
+
+impl fmt::Debug for Not {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "(not {:?})", self.arg)
+    }
+}
+
+/// `[a, b, c]`. An empty list has to be annotated with its type, `[]: int list`.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct List {
+    pub items: Vec<Expr>,
+    pub type_: Option<Type>,
+}
+
+into_expr!(List);
+
+impl fmt::Debug for List {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(f.write_str("["));
+        for (i, item) in self.items.iter().enumerate() {
+            if i > 0 {
+                try!(f.write_str(", "));
+            }
+            try!(write!(f, "{:?}", item));
+        }
+        try!(f.write_str("]"));
+        match self.type_ {
+            Some(ref type_) => write!(f, ": {:?}", type_),
+            None => Ok(()),
+        }
+    }
+}
+
+/// `head :: tail`
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Cons {
+    pub head: Expr,
+    pub tail: Expr,
+}
+
+into_expr!(Cons);
+
+impl fmt::Debug for Cons {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "(:: {:?} {:?})", self.head, self.tail)
+    }
+}
+
+/// `lhs ++ rhs`, the concatenation of two strings.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Concat {
+    pub lhs: Expr,
+    pub rhs: Expr,
+}
+
+into_expr!(Concat);
+
+impl fmt::Debug for Concat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "(++ {:?} {:?})", self.lhs, self.rhs)
+    }
+}
+
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct UnOp<T> {
+    pub kind: T,
+    pub arg: Expr,
+}
+
+impl<T: fmt::Debug> fmt::Debug for UnOp<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "({:?} {:?})", self.kind, self.arg)
+    }
+}
+
+/// The list primitives. They are keywords rather than functions, because
+/// there are no polymorphic functions.
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum ListOp {
+    Head,
+    Tail,
+    IsEmpty,
+}
+
+impl fmt::Debug for ListOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::ListOp::*;
+        f.write_str(match *self {
+            Head => "head",
+            Tail => "tail",
+            IsEmpty => "isEmpty",
+        })
+    }
+}
+
+pub type ListUnOp = UnOp<ListOp>;
+
+into_expr!(ListUnOp);
+
+/// `ref value` makes a new mutable cell holding `value`, and `!cell` reads it.
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum RefOp {
+    Ref,
+    Deref,
+}
+
+impl fmt::Debug for RefOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            RefOp::Ref => "ref",
+            RefOp::Deref => "!",
+        })
+    }
+}
+
+pub type RefUnOp = UnOp<RefOp>;
+
+into_expr!(RefUnOp);
+
+/// `print s` writes the string `s` and a newline, and `print_int n` the
+/// number `n`. Both evaluate to `()`.
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum PrintOp {
+    Print,
+    PrintInt,
+}
+
+impl fmt::Debug for PrintOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            PrintOp::Print => "print",
+            PrintOp::PrintInt => "print_int",
+        })
+    }
+}
+
+pub type PrintUnOp = UnOp<PrintOp>;
+
+into_expr!(PrintUnOp);
+
+/// `int_of_float x` rounds the float `x` towards zero, and `float_of_int n`
+/// turns the int `n` into a float.
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum ConvertOp {
+    IntOfFloat,
+    FloatOfInt,
+}
+
+impl fmt::Debug for ConvertOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            ConvertOp::IntOfFloat => "int_of_float",
+            ConvertOp::FloatOfInt => "float_of_int",
+        })
+    }
+}
+
+pub type ConvertUnOp = UnOp<ConvertOp>;
+
+into_expr!(ConvertUnOp);
+
+/// `cell := value`, which stores `value` in a cell made by `ref`.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Assign {
+    pub cell: Expr,
+    pub value: Expr,
+}
+
+into_expr!(Assign);
+
+impl fmt::Debug for Assign {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "(:= {:?} {:?})", self.cell, self.value)
+    }
+}
+
+/// `first; second` evaluates `first`, a `unit`, for its effects and then `second`.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Seq {
+    pub first: Expr,
+    pub second: Expr,
+}
+
+into_expr!(Seq);
+
+impl fmt::Debug for Seq {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "(; {:?} {:?})", self.first, self.second)
+    }
+}
+
+/// `{x = 1, y = true}`, with at least one field and each name used once.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Record {
+    pub fields: Vec<(Ident, Expr)>,
+}
+
+into_expr!(Record);
+
+impl fmt::Debug for Record {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(f.write_str("{"));
+        for (i, field) in self.fields.iter().enumerate() {
+            if i > 0 {
+                try!(f.write_str(", "));
+            }
+            try!(write!(f, "{} = {:?}", field.0, field.1));
+        }
+        f.write_str("}")
+    }
+}
+
+/// `record.name`, the value of a field of a record.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Field {
+    pub record: Expr,
+    pub name: Ident,
+}
+
+into_expr!(Field);
+
+impl fmt::Debug for Field {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}.{}", self.record, self.name)
+    }
+}
+
+/// A constructor of a type declared with `type`, applied to its argument if
+/// it has one: `Circle 1` or `Empty`. Constructors start with a capital letter.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Construct {
+    pub name: Ident,
+    pub arg: Option<Expr>,
+}
+
+into_expr!(Construct);
+
+impl fmt::Debug for Construct {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.arg {
+            Some(ref arg) => write!(f, "({} {:?})", self.name, arg),
+            None => write!(f, "{}", self.name),
+        }
+    }
+}
+
+/// `match shape with Circle r -> r | Square a -> a`. There must be exactly
+/// one arm for each constructor of the type.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Match {
+    pub scrutinee: Expr,
+    pub arms: Vec<Arm>,
+}
+
+into_expr!(Match);
+
+impl fmt::Debug for Match {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "(match {:?}", self.scrutinee));
+        for arm in &self.arms {
+            try!(write!(f, " {:?}", arm));
+        }
+        f.write_str(")")
+    }
+}
+
+/// `Circle r -> body`, where `r` is bound to the argument of the constructor.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Arm {
+    pub constructor: Ident,
+    pub binding: Option<Ident>,
+    pub body: Expr,
+}
+
+impl fmt::Debug for Arm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.binding {
+            Some(ref binding) => write!(f, "[({} {}) {:?}]", self.constructor, binding, self.body),
+            None => write!(f, "[{} {:?}]", self.constructor, self.body),
+        }
+    }
+}
+
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct If {
     pub cond: Expr,
     pub tru: Expr,
@@ -114,28 +511,79 @@ impl fmt::Debug for If {
     }
 }
 
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Fun {
     pub fun_name: Ident,
-    pub arg_name: Ident,
-    pub arg_type: Type,
+    /// At least one parameter. With several, the function is curried:
+    /// `fun add(x: int, y: int): int` has type `int -> int -> int`. Types
+    /// left out, here and for the result, are `Type::Infer`.
+    pub args: Vec<(Ident, Type)>,
     pub fun_type: Type,
     pub body: Expr,
 }
 
 into_expr!(Fun);
 
+impl Fun {
+    pub fn new(fun_name: &str, arg_name: &str, arg_type: Type, fun_type: Type, body: Expr) -> Fun {
+        Fun {
+            fun_name: Ident::from_str(fun_name),
+            args: vec![(Ident::from_str(arg_name), arg_type)],
+            fun_type: fun_type,
+            body: body,
+        }
+    }
+
+    /// Adds a parameter after the existing ones.
+    pub fn with_arg(mut self, arg_name: &str, arg_type: Type) -> Fun {
+        self.args.push((Ident::from_str(arg_name), arg_type));
+        self
+    }
+}
+
 impl fmt::Debug for Fun {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f,
-        "(λ {} ({}: {:?}): {:?} {:?})",
+        "(λ {} {:?}: {:?} {:?})",
         self.fun_name,
-        self.arg_name,
-        self.arg_type,
+        Args(&self.args),
         self.fun_type,
         self.body)
     }
 }
 
+/// Formats parameters as `(x: int, y: int)`.
+struct Args<'a>(&'a [(Ident, Type)]);
+
+impl<'a> fmt::Debug for Args<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "("));
+        for (i, arg) in self.0.iter().enumerate() {
+            if i > 0 {
+                try!(write!(f, ", "));
+            }
+            try!(write!(f, "{}: {:?}", arg.0, arg.1));
+        }
+        write!(f, ")")
+    }
+}
+
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Let {
+    pub name: Ident,
+    pub value: Expr,
+    pub body: Expr,
+}
+
+into_expr!(Let);
+
+impl fmt::Debug for Let {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "(let {} {:?} in {:?})", self.name, self.value, self.body)
+    }
+}
+
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct LetFun {
     pub fun: Fun,
     pub body: Expr,
@@ -146,16 +594,16 @@ into_expr!(LetFun);
 impl fmt::Debug for LetFun {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f,
-        "(let {} λ({}: {:?}): {:?} {:?} in {:?})",
+        "(let {} λ{:?}: {:?} {:?} in {:?})",
         self.fun.fun_name,
-        self.fun.arg_name,
-        self.fun.arg_type,
+        Args(&self.fun.args),
         self.fun.fun_type,
         self.fun.body,
         self.body)
     }
 }
 
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct LetRec {
     pub funs: Vec<Fun>,
     pub body: Expr,
@@ -173,6 +621,62 @@ impl fmt::Debug for LetRec {
     }
 }
 
+/// `type shape = Circle of int | Empty in body`
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct LetType {
+    pub name: Ident,
+    pub variants: Vec<Variant>,
+    pub body: Expr,
+}
+
+into_expr!(LetType);
+
+impl fmt::Debug for LetType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "(type {} [", self.name));
+        for (i, variant) in self.variants.iter().enumerate() {
+            if i > 0 {
+                try!(f.write_str(" | "));
+            }
+            try!(write!(f, "{:?}", variant));
+        }
+        write!(f, "] in {:?})", self.body)
+    }
+}
+
+/// `type predicate = int -> bool in body`, another name for a type.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct TypeAlias {
+    pub name: Ident,
+    pub type_: Type,
+    pub body: Expr,
+}
+
+into_expr!(TypeAlias);
+
+impl fmt::Debug for TypeAlias {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "(type {} = {:?} in {:?})", self.name, self.type_, self.body)
+    }
+}
+
+/// A constructor in a `type` declaration, with the type of its argument.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Variant {
+    pub name: Ident,
+    pub arg: Option<Type>,
+}
+
+impl fmt::Debug for Variant {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.arg {
+            Some(ref arg) => write!(f, "{} of {:?}", self.name, arg),
+            None => write!(f, "{}", self.name),
+        }
+    }
+}
+
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Apply {
     pub fun: Expr,
     pub arg: Expr,
@@ -186,14 +690,19 @@ impl fmt::Debug for Apply {
     }
 }
 
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Literal {
     Number(i64),
+    Float(f64),
     Bool(bool),
+    Str(String),
+    /// `()`
+    Unit,
 }
 
 impl Into<Expr> for Literal {
     fn into(self) -> Expr {
-        Expr::Literal(self)
+        ExprKind::Literal(self).into()
     }
 }
 
@@ -201,7 +710,47 @@ impl fmt::Debug for Literal {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             Literal::Number(x) => x.fmt(f),
+            // `{:?}` keeps the point of a whole number.
+            Literal::Float(x) => write!(f, "{:?}", x),
             Literal::Bool(b) => b.fmt(f),
+            Literal::Str(ref s) => write!(f, "\"{}\"", escape(s)),
+            Literal::Unit => f.write_str("()"),
+        }
+    }
+}
+
+/// Writes `s` back with the escapes string literals can have.
+pub fn escape(s: &str) -> String {
+    let mut result = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\n' => result.push_str("\\n"),
+            '\t' => result.push_str("\\t"),
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            c => result.push(c),
+        }
+    }
+    result
+}
+
+/// The string a literal stands for, given the text between its quotes.
+/// Returns `None` for escapes other than `\n`, `\t`, `\"` and `\\`.
+pub fn unescape(literal: &str) -> Option<String> {
+    let mut result = String::with_capacity(literal.len());
+    let mut chars = literal.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
         }
+        result.push(match chars.next() {
+            Some('n') => '\n',
+            Some('t') => '\t',
+            Some('"') => '"',
+            Some('\\') => '\\',
+            _ => return None,
+        });
     }
+    Some(result)
 }
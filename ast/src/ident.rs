@@ -1,17 +1,26 @@
 use std::fmt;
 
-#[derive(PartialEq, Eq, Hash)]
-pub struct Ident(String);
+use Symbol;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Ident(Symbol);
 
 impl Ident {
     pub fn from_str(name: &str) -> Ident {
-        Ident(name.to_owned())
+        Ident(Symbol::intern(name))
+    }
+
+    pub fn symbol(&self) -> Symbol {
+        self.0
     }
 }
 
 impl AsRef<str> for Ident {
     fn as_ref(&self) -> &str {
-        self.0.as_ref()
+        self.0.as_str()
     }
 }
 
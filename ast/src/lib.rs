@@ -1,7 +1,23 @@
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(all(test, feature = "serde"))]
+extern crate serde_json;
+
+mod symbol;
+mod span;
 mod ident;
 mod types;
 mod exprs;
+mod program;
+pub mod expr;
+pub mod pretty;
 
+pub use symbol::Symbol;
 pub use ident::Ident;
+pub use span::Span;
 pub use types::Type;
-pub use exprs::{Expr, Literal, BinOp, ArithOp, ArithBinOp, CmpOp, CmpBinOp, If, Fun, LetFun, LetRec, Apply};
\ No newline at end of file
+pub use exprs::{Expr, ExprKind, Literal, BinOp, ArithOp, ArithBinOp, CmpOp, CmpBinOp, LogicOp, LogicBinOp, Not,
+                List, Cons, Concat, UnOp, ListOp, ListUnOp, RefOp, RefUnOp, PrintOp, PrintUnOp, ConvertOp,
+                ConvertUnOp, Assign, Seq, Record, Field, Construct, Match, Arm, If, Fun, Let, LetFun, LetRec, LetType, TypeAlias,
+                Variant, Apply, escape, unescape};
+pub use program::{Program, Decl};
@@ -0,0 +1,524 @@
+//! Writes expressions back in the surface syntax, while `Debug` shows their
+//! structure:
+//!
+//! ```
+//! use ast::expr::{add, int, mul};
+//! use ast::pretty::{self, Style};
+//! let e = mul(add(int(1), int(2)), int(3));
+//! assert_eq!(pretty::expr(&e, &Style::default()), "(1 + 2) * 3");
+//! ```
+//!
+//! An expression which fits in the width is written on one line. Otherwise
+//! the bodies of `let`, `if`, `match` and functions, the statements of a
+//! sequence and the items of lists and records go on lines of their own.
+
+use std::fmt::Write;
+
+use {Expr, ArithOp, CmpOp, LogicOp, Fun, Program, Decl, Type};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Style {
+    /// The number of columns the code is kept within where it can be.
+    pub width: usize,
+    /// The number of spaces a nested block is indented by.
+    pub indent: usize,
+}
+
+impl Default for Style {
+    fn default() -> Style {
+        Style {
+            width: 80,
+            indent: 4,
+        }
+    }
+}
+
+pub fn expr(expr: &Expr, style: &Style) -> String {
+    let mut printer = Printer::new(style, false);
+    printer.expr(expr, 0, true);
+    printer.out
+}
+
+/// Writes the declarations of `program` and its body, separated by blank
+/// lines. A body with an empty span is left out, as the parser makes up a
+/// call of `main` for a file without one.
+pub fn program(program: &Program, style: &Style) -> String {
+    let mut printer = Printer::new(style, false);
+    for decl in &program.decls {
+        printer.decl(decl);
+        printer.out.push_str("\n\n");
+    }
+    if program.body.span.is_empty() {
+        printer.out.pop();
+    } else {
+        printer.expr(&program.body, 0, true);
+        printer.out.push('\n');
+    }
+    printer.out
+}
+
+struct Printer<'s> {
+    style: &'s Style,
+    out: String,
+    /// How many blocks deep the current line is.
+    depth: usize,
+    /// Whether everything goes on one line, however long.
+    flat: bool,
+}
+
+impl<'s> Printer<'s> {
+    fn new(style: &'s Style, flat: bool) -> Printer<'s> {
+        Printer {
+            style: style,
+            out: String::new(),
+            depth: 0,
+            flat: flat,
+        }
+    }
+
+    /// Writes `expr` where an expression binding at least as tight as `prec`
+    /// is expected, in parens if it binds looser. Expressions like `if`,
+    /// which take everything to their right, need parens unless they're in
+    /// the `tail` of the enclosing one.
+    fn expr(&mut self, expr: &Expr, prec: u8, tail: bool) {
+        if precedence(expr) < prec || (is_open(expr) && !tail) {
+            self.out.push('(');
+            self.expr(expr, 0, true);
+            self.out.push(')');
+            return;
+        }
+        if self.flat {
+            return self.kind(expr, tail, true);
+        }
+        let flat = self.flat_expr(expr, tail);
+        if self.fits(&flat) {
+            self.out.push_str(&flat);
+        } else {
+            self.kind(expr, tail, false);
+        }
+    }
+
+    fn kind(&mut self, expr: &Expr, tail: bool, flat: bool) {
+        use ExprKind::*;
+        match expr.kind {
+            Var(ref name) => self.out.push_str(name.as_ref()),
+            Literal(ref literal) => write!(self.out, "{:?}", literal).unwrap(),
+            ArithBinOp(ref op) => {
+                let (symbol, prec) = match op.kind {
+                    ArithOp::Mul => ("*", 7),
+                    ArithOp::Div => ("/", 7),
+                    ArithOp::Mod => ("%", 7),
+                    ArithOp::Add => ("+", 6),
+                    ArithOp::Sub => ("-", 6),
+                };
+                self.binary(&op.lhs, symbol, &op.rhs, (prec, prec + 1), tail)
+            }
+            CmpBinOp(ref op) => {
+                let symbol = match op.kind {
+                    CmpOp::Eq => "==",
+                    CmpOp::Ne => "!=",
+                    CmpOp::Lt => "<",
+                    CmpOp::Le => "<=",
+                    CmpOp::Gt => ">",
+                    CmpOp::Ge => ">=",
+                };
+                self.binary(&op.lhs, symbol, &op.rhs, (5, 5), tail)
+            }
+            LogicBinOp(ref op) => {
+                match op.kind {
+                    LogicOp::Or => self.binary(&op.lhs, "||", &op.rhs, (3, 2), tail),
+                    LogicOp::And => self.binary(&op.lhs, "&&", &op.rhs, (4, 3), tail),
+                }
+            }
+            Not(ref not) => self.prefix("not ", &not.arg),
+            List(ref list) => {
+                self.items("[", &list.items, "]", flat, |printer, item| printer.expr(item, 0, true));
+                if let Some(ref type_) = list.type_ {
+                    write!(self.out, ": {:?}", type_).unwrap();
+                }
+            }
+            Cons(ref cons) => self.binary(&cons.head, "::", &cons.tail, (6, 5), tail),
+            Concat(ref concat) => self.binary(&concat.lhs, "++", &concat.rhs, (6, 5), tail),
+            ListUnOp(ref op) => self.prefix(&format!("{:?} ", op.kind), &op.arg),
+            RefUnOp(ref op) => {
+                match op.kind {
+                    ::RefOp::Ref => self.prefix("ref ", &op.arg),
+                    ::RefOp::Deref => self.prefix("!", &op.arg),
+                }
+            }
+            PrintUnOp(ref op) => self.prefix(&format!("{:?} ", op.kind), &op.arg),
+            ConvertUnOp(ref op) => self.prefix(&format!("{:?} ", op.kind), &op.arg),
+            Assign(ref assign) => self.binary(&assign.cell, ":=", &assign.value, (2, 1), tail),
+            Seq(ref seq) => {
+                self.expr(&seq.first, 1, false);
+                self.out.push(';');
+                self.separator(flat);
+                // The rest of a broken sequence is broken too, one statement a line.
+                match seq.second.kind {
+                    Seq(_) if !flat => self.kind(&seq.second, tail, false),
+                    _ => self.expr(&seq.second, 0, tail),
+                }
+            }
+            Record(ref record) => {
+                self.items("{", &record.fields, "}", flat, |printer, field| {
+                    write!(printer.out, "{} = ", field.0).unwrap();
+                    printer.expr(&field.1, 0, true);
+                })
+            }
+            Field(ref field) => {
+                self.expr(&field.record, 11, false);
+                write!(self.out, ".{}", field.name).unwrap();
+            }
+            Construct(ref construct) => {
+                self.out.push_str(construct.name.as_ref());
+                if let Some(ref arg) = construct.arg {
+                    self.out.push(' ');
+                    self.expr(arg, 11, tail);
+                }
+            }
+            Match(ref match_) => {
+                self.out.push_str("match ");
+                self.expr(&match_.scrutinee, 0, true);
+                self.out.push_str(" with");
+                self.depth += 1;
+                for (i, arm) in match_.arms.iter().enumerate() {
+                    self.separator(flat);
+                    if i > 0 {
+                        self.out.push_str("| ");
+                    }
+                    self.out.push_str(arm.constructor.as_ref());
+                    if let Some(ref binding) = arm.binding {
+                        write!(self.out, " {}", binding).unwrap();
+                    }
+                    self.out.push_str(" ->");
+                    // An arm but the last one ends before the `|` of the next.
+                    let last = i + 1 == match_.arms.len();
+                    self.body(&arm.body, tail && last, "");
+                }
+                self.depth -= 1;
+            }
+            If(ref if_) => {
+                self.out.push_str("if ");
+                self.expr(&if_.cond, 0, true);
+                self.out.push_str(" then");
+                self.block(flat, &if_.tru, true);
+                self.separator(flat);
+                self.out.push_str("else");
+                match if_.fls.kind {
+                    If(_) if !flat => {
+                        self.out.push(' ');
+                        self.expr(&if_.fls, 0, tail);
+                    }
+                    _ => self.block(flat, &if_.fls, tail),
+                }
+            }
+            Fun(ref fun) => {
+                self.fun(fun, "");
+            }
+            Let(ref let_) => {
+                write!(self.out, "let {} =", let_.name).unwrap();
+                let inline = self.body(&let_.value, true, " in");
+                self.in_(inline, flat, &let_.body, tail);
+            }
+            LetFun(ref let_fun) => {
+                self.out.push_str("let ");
+                let inline = self.fun(&let_fun.fun, " in");
+                self.in_(inline, flat, &let_fun.body, tail);
+            }
+            LetRec(ref let_rec) => {
+                let inline = self.funs(&let_rec.funs, flat, " in");
+                self.in_(inline, flat, &let_rec.body, tail);
+            }
+            LetType(ref let_type) => {
+                let variants = let_type.variants.iter().map(|variant| format!("{:?}", variant)).collect::<Vec<_>>();
+                let header = format!("type {} = {} in", let_type.name, variants.join(" | "));
+                if flat || self.fits(&header) {
+                    self.out.push_str(&header);
+                } else {
+                    write!(self.out, "type {} =", let_type.name).unwrap();
+                    self.depth += 1;
+                    for (i, variant) in variants.iter().enumerate() {
+                        self.newline();
+                        if i > 0 {
+                            self.out.push_str("| ");
+                        }
+                        self.out.push_str(variant);
+                    }
+                    self.depth -= 1;
+                    self.newline();
+                    self.out.push_str("in");
+                }
+                self.separator(flat);
+                self.expr(&let_type.body, 0, tail);
+            }
+            TypeAlias(ref alias) => {
+                write!(self.out, "type {} = {:?} in", alias.name, alias.type_).unwrap();
+                self.separator(flat);
+                self.expr(&alias.body, 0, tail);
+            }
+            Apply(ref apply) => {
+                self.expr(&apply.fun, 9, false);
+                // `f head xs` parses as `f (head xs)`, but reads better with the parens.
+                self.out.push(' ');
+                self.expr(&apply.arg, 11, tail);
+            }
+        }
+    }
+
+    fn decl(&mut self, decl: &Decl) {
+        match *decl {
+            Decl::Fun(ref fun) => {
+                self.out.push_str("let ");
+                self.fun(fun, ";;");
+            }
+            Decl::LetRec(ref funs) => {
+                self.funs(funs, false, ";;");
+            }
+            Decl::Value(ref name, ref value) => {
+                write!(self.out, "let {} =", name).unwrap();
+                self.body(value, true, ";;");
+            }
+        }
+        self.out.push_str(";;");
+    }
+
+    fn binary(&mut self, lhs: &Expr, symbol: &str, rhs: &Expr, prec: (u8, u8), tail: bool) {
+        self.expr(lhs, prec.0, false);
+        write!(self.out, " {} ", symbol).unwrap();
+        self.expr(rhs, prec.1, tail);
+    }
+
+    /// Writes a prefix operator and its argument, in parens unless it's an
+    /// atom, as in `head (tail xs)`.
+    fn prefix(&mut self, op: &str, arg: &Expr) {
+        self.out.push_str(op);
+        self.expr(arg, 11, false);
+    }
+
+    /// Writes `[a, b, c]` on one line or each item on a line of its own.
+    fn items<T, F>(&mut self, open: &str, items: &[T], close: &str, flat: bool, mut item: F)
+        where F: FnMut(&mut Printer<'s>, &T)
+    {
+        self.out.push_str(open);
+        self.depth += 1;
+        for (i, t) in items.iter().enumerate() {
+            if flat {
+                if i > 0 {
+                    self.out.push_str(", ");
+                }
+            } else {
+                if i > 0 {
+                    self.out.push(',');
+                }
+                self.newline();
+            }
+            item(self, t);
+        }
+        self.depth -= 1;
+        if !flat {
+            self.newline();
+        }
+        self.out.push_str(close);
+    }
+
+    /// Writes the header of `fun` and its body, see `body`.
+    fn fun(&mut self, fun: &Fun, end: &str) -> bool {
+        write!(self.out, "fun {}(", fun.fun_name).unwrap();
+        for (i, arg) in fun.args.iter().enumerate() {
+            if i > 0 {
+                self.out.push_str(", ");
+            }
+            self.out.push_str(arg.0.as_ref());
+            self.annotation(&arg.1);
+        }
+        self.out.push(')');
+        self.annotation(&fun.fun_type);
+        self.out.push_str(" is");
+        self.body(&fun.body, true, end)
+    }
+
+    /// Writes `: type` after a parameter or a function header, unless the type is left to inference.
+    fn annotation(&mut self, type_: &Type) {
+        if *type_ != Type::Infer {
+            write!(self.out, ": {:?}", type_).unwrap();
+        }
+    }
+
+    /// Writes `let rec fun ... and fun ...`, each function starting a line
+    /// unless `flat`, and returns whether the last body is on its header's line.
+    fn funs(&mut self, funs: &[Fun], flat: bool, end: &str) -> bool {
+        self.out.push_str("let rec ");
+        let mut inline = true;
+        for (i, fun) in funs.iter().enumerate() {
+            if i > 0 {
+                self.separator(flat);
+                self.out.push_str("and ");
+            }
+            inline = self.fun(fun, if i + 1 == funs.len() { end } else { "" });
+        }
+        inline
+    }
+
+    /// Writes `in` after a binding and then `body`, on the next line unless
+    /// `flat`. `in` ends the line of the bound value if it's `inline`.
+    fn in_(&mut self, inline: bool, flat: bool, body: &Expr, tail: bool) {
+        if inline {
+            self.out.push_str(" in");
+        } else {
+            self.newline();
+            self.out.push_str("in");
+        }
+        self.separator(flat);
+        self.expr(body, 0, tail);
+    }
+
+    /// Writes `expr` after a space if it fits on the line together with the
+    /// `end` the caller writes next, or indented on the next lines otherwise.
+    /// Returns whether it's on the same line.
+    fn body(&mut self, expr: &Expr, tail: bool, end: &str) -> bool {
+        let flat = self.flat_expr(expr, tail);
+        if self.flat || self.fits(&format!(" {}{}", flat, end)) {
+            self.out.push(' ');
+            self.out.push_str(&flat);
+            return true;
+        }
+        self.depth += 1;
+        self.newline();
+        self.expr(expr, 0, tail);
+        self.depth -= 1;
+        false
+    }
+
+    /// Writes `expr` after a space if `flat`, or indented on the next lines.
+    fn block(&mut self, flat: bool, expr: &Expr, tail: bool) {
+        if flat {
+            self.out.push(' ');
+            return self.expr(expr, 0, tail);
+        }
+        self.depth += 1;
+        self.newline();
+        self.expr(expr, 0, tail);
+        self.depth -= 1;
+    }
+
+    fn separator(&mut self, flat: bool) {
+        if flat {
+            self.out.push(' ');
+        } else {
+            self.newline();
+        }
+    }
+
+    fn newline(&mut self) {
+        self.out.push('\n');
+        for _ in 0..self.depth * self.style.indent {
+            self.out.push(' ');
+        }
+    }
+
+    /// `expr` written on one line.
+    fn flat_expr(&self, expr: &Expr, tail: bool) -> String {
+        let mut printer = Printer::new(self.style, true);
+        printer.expr(expr, 0, tail);
+        printer.out
+    }
+
+    fn fits(&self, text: &str) -> bool {
+        let line = &self.out[self.out.rfind('\n').map_or(0, |i| i + 1)..];
+        line.chars().count() + text.chars().count() <= self.style.width
+    }
+}
+
+/// How tightly `expr` binds: `;` binds loosest, then `:=`, `||`, `&&`,
+/// comparisons, `::` and `++`, sums, products, unary minus, applications,
+/// prefix operators like `not` and finally fields and atoms. Expressions like
+/// `if`, which take everything to their right, bind loosest of all.
+fn precedence(expr: &Expr) -> u8 {
+    use ExprKind::*;
+    match expr.kind {
+        Seq(_) => 0,
+        Assign(_) => 1,
+        LogicBinOp(ref op) => {
+            match op.kind {
+                LogicOp::Or => 2,
+                LogicOp::And => 3,
+            }
+        }
+        CmpBinOp(_) => 4,
+        Cons(_) | Concat(_) => 5,
+        ArithBinOp(ref op) => {
+            match op.kind {
+                ArithOp::Add | ArithOp::Sub => 6,
+                ArithOp::Mul | ArithOp::Div | ArithOp::Mod => 7,
+            }
+        }
+        Literal(::Literal::Number(n)) if n < 0 => 8,
+        Literal(::Literal::Float(x)) if x.is_sign_negative() => 8,
+        Apply(_) => 9,
+        Construct(ref construct) if construct.arg.is_some() => 9,
+        Not(_) | ListUnOp(_) | RefUnOp(_) | PrintUnOp(_) | ConvertUnOp(_) => 10,
+        _ if is_open(expr) => 0,
+        _ => 11,
+    }
+}
+
+fn is_open(expr: &Expr) -> bool {
+    use ExprKind::*;
+    match expr.kind {
+        If(_) | Match(_) | Fun(_) | Let(_) | LetFun(_) | LetRec(_) | LetType(_) | TypeAlias(_) => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Type;
+    use exprs::Fun;
+    use expr::*;
+
+    fn pretty(expr: &Expr) -> String {
+        super::expr(expr, &Style::default())
+    }
+
+    #[test]
+    fn test_precedence() {
+        assert_eq!(pretty(&add(int(1), mul(int(2), int(3)))), "1 + 2 * 3");
+        assert_eq!(pretty(&sub(sub(int(1), int(2)), sub(int(3), int(4)))), "1 - 2 - (3 - 4)");
+        assert_eq!(pretty(&div(int(1), int(-2))), "1 / -2");
+        assert_eq!(pretty(&apply(var("f"), int(-2))), "f (-2)");
+        assert_eq!(pretty(&apply(apply(var("f"), var("x")), apply(var("g"), var("y")))), "f x (g y)");
+        assert_eq!(pretty(&cons(cons(int(1), nil(Type::int())), nil(Type::int().list()))),
+                   "(1 :: []: int list) :: []: int list list");
+        assert_eq!(pretty(&not(field(deref(var("r")), "x"))), "not (!r).x");
+        assert_eq!(pretty(&deref(field(var("r"), "x"))), "!r.x");
+        assert_eq!(pretty(&apply(var("f"), head(tail(var("xs"))))), "f (head (tail xs))");
+        assert_eq!(pretty(&seq(assign(var("r"), int(1)), print(string("a\n")))), "r := 1; print \"a\\n\"");
+    }
+
+    #[test]
+    fn test_open_ended() {
+        let if_ = || if_(var("c"), int(1), int(2));
+        assert_eq!(pretty(&add(int(1), if_())), "1 + (if c then 1 else 2)");
+        assert_eq!(pretty(&seq(if_(), if_())), "(if c then 1 else 2); if c then 1 else 2");
+        assert_eq!(pretty(&apply(var("f"), fun("g", "x", Type::int(), Type::int(), var("x")))),
+                   "f (fun g(x: int): int is x)");
+        assert_eq!(pretty(&fun("id", "x", Type::Infer, Type::Infer, var("x"))), "fun id(x) is x");
+        assert_eq!(pretty(&let_("x", if_(), if_())), "let x = if c then 1 else 2 in if c then 1 else 2");
+    }
+
+    #[test]
+    fn test_breaking() {
+        let style = Style {
+            width: 20,
+            indent: 2,
+        };
+        let fun = Fun::new("f", "x", Type::int(), Type::int(), add(var("x"), var("long_name")));
+        let expr = let_fun(fun, if_(var("c"), apply(var("f"), int(1)), if_(var("d"), int(2), int(3))));
+        assert_eq!(super::expr(&expr, &style),
+                   "let fun f(x: int): int is\n  x + long_name\nin\nif c then\n  f 1\nelse if d then\n  2\n\
+                    else\n  3");
+        let expr = list(vec![string("a long string"), string("another")]);
+        assert_eq!(super::expr(&expr, &style), "[\n  \"a long string\",\n  \"another\"\n]");
+    }
+}
@@ -0,0 +1,64 @@
+use std::fmt;
+
+use Ident;
+use exprs::{Expr, ExprKind, Fun};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Decl {
+    Fun(Fun),
+    LetRec(Vec<Fun>),
+    Value(Ident, Expr),
+}
+
+impl Decl {
+    /// The names the declaration binds.
+    pub fn names(&self) -> Vec<&Ident> {
+        match *self {
+            Decl::Fun(ref fun) => vec![&fun.fun_name],
+            Decl::LetRec(ref funs) => funs.iter().map(|fun| &fun.fun_name).collect(),
+            Decl::Value(ref name, _) => vec![name],
+        }
+    }
+}
+
+impl fmt::Debug for Decl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Decl::Fun(ref fun) => write!(f, "(let {:?})", fun),
+            Decl::LetRec(ref funs) => {
+                try!(write!(f, "(letrec ["));
+                for fun in funs {
+                    try!(write!(f, "{:?}", fun));
+                }
+                write!(f, "])")
+            }
+            Decl::Value(ref name, ref value) => write!(f, "(let {} {:?})", name, value),
+        }
+    }
+}
+
+/// A sequence of top-level declarations followed by the expression the
+/// program evaluates to. Files without a trailing expression evaluate `main`.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Program {
+    pub decls: Vec<Decl>,
+    pub body: Expr,
+}
+
+impl Program {
+    pub fn with_entry(mut self, name: &str) -> Program {
+        self.body = ExprKind::Var(Ident::from_str(name)).into();
+        self
+    }
+}
+
+impl fmt::Debug for Program {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for decl in &self.decls {
+            try!(writeln!(f, "{:?}", decl));
+        }
+        write!(f, "{:?}", self.body)
+    }
+}
@@ -0,0 +1,65 @@
+use std::fmt;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// The byte offsets of an expression in the text it was parsed from.
+/// Expressions built by hand have an empty span at the start. Offsets are
+/// `u32` to keep `Expr` small.
+#[derive(PartialEq, Eq, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Span {
+    pub start: u32,
+    pub end: u32,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Span {
+        Span {
+            start: start as u32,
+            end: end as u32,
+        }
+    }
+
+    /// Parsed expressions are never empty, so an empty span means "unknown".
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    /// The line and column of the start of the span in `text`, both counted from one.
+    /// The column counts characters, not bytes, so that `λ` is one column wide.
+    pub fn line_col(&self, text: &str) -> (usize, usize) {
+        let mut start = ::std::cmp::min(self.start as usize, text.len());
+        // A span from another text may point into the middle of a character.
+        while !text.is_char_boundary(start) {
+            start -= 1;
+        }
+        let before = &text[..start];
+        let line = before.matches('\n').count() + 1;
+        let column = before.chars().rev().take_while(|&c| c != '\n').count() + 1;
+        (line, column)
+    }
+}
+
+impl fmt::Debug for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}..{}", self.start, self.end)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_line_col() {
+        let text = "let x = 1;;\nx + true";
+        assert_eq!(Span::new(0, 3).line_col(text), (1, 1));
+        assert_eq!(Span::new(16, 20).line_col(text), (2, 5));
+        assert_eq!(Span::new(12, 12).line_col(text), (2, 1));
+
+        let text = "let é = 1;;\nlet λ = é + ü";
+        assert_eq!(Span::new(4, 6).line_col(text), (1, 5));
+        assert_eq!(Span::new(27, 29).line_col(text), (2, 13));
+        assert_eq!(Span::new(5, 6).line_col(text), (1, 5));
+    }
+}
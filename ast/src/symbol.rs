@@ -0,0 +1,83 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Mutex;
+
+/// An interned string. Each distinct name is stored once, for the whole
+/// process, so symbols are cheap to copy, compare and hash.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Symbol(u32);
+
+struct SymbolTable {
+    symbols: HashMap<&'static str, Symbol>,
+    names: Vec<&'static str>,
+}
+
+static TABLE: Mutex<Option<SymbolTable>> = Mutex::new(None);
+
+impl Symbol {
+    pub fn intern(name: &str) -> Symbol {
+        let mut table = TABLE.lock().unwrap();
+        let table = table.get_or_insert_with(|| {
+            SymbolTable {
+                symbols: HashMap::new(),
+                names: Vec::new(),
+            }
+        });
+        if let Some(&symbol) = table.symbols.get(name) {
+            return symbol;
+        }
+        // Names live as long as the table, which is forever.
+        let name: &'static str = Box::leak(name.to_owned().into_boxed_str());
+        let symbol = Symbol(table.names.len() as u32);
+        table.names.push(name);
+        table.symbols.insert(name, symbol);
+        symbol
+    }
+
+    pub fn as_str(self) -> &'static str {
+        TABLE.lock().unwrap().as_ref().unwrap().names[self.0 as usize]
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.as_str().fmt(f)
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.as_str().fmt(f)
+    }
+}
+
+/// Symbols are serialized as their names, as the numbers are only
+/// meaningful within one process.
+#[cfg(feature = "serde")]
+impl serde::Serialize for Symbol {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Symbol {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Symbol, D::Error> {
+        let name = try!(String::deserialize(deserializer));
+        Ok(Symbol::intern(&name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_intern() {
+        let a = Symbol::intern("spam");
+        assert_eq!(a, Symbol::intern("spam"));
+        assert!(a != Symbol::intern("eggs"));
+        assert_eq!(a.as_str(), "spam");
+        assert_eq!(format!("{} {:?}", a, a), "spam \"spam\"");
+    }
+}
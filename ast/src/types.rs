@@ -1,15 +1,82 @@
 use std::fmt;
 
-#[derive(PartialEq, Eq)]
+use Ident;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+#[derive(PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Type {
     Int,
+    Float,
     Bool,
+    Str,
+    /// The type of `()`, for expressions evaluated for their effects.
+    Unit,
     Arrow(Box<Type>, Box<Type>),
+    List(Box<Type>),
+    /// A mutable cell holding a value of the type, `int ref`.
+    Ref(Box<Type>),
+    /// `{x: int, y: bool}`, the type of records with these fields.
+    Record(Vec<(Ident, Type)>),
+    /// A type declared with `type`, like `shape`.
+    Named(Ident),
+    /// A type parameter like `'a`, named without its quote, in the signature
+    /// of a function generic in it: `fun id(x: 'a): 'a is x`.
+    Param(Ident),
+    /// The type of a function parameter or result written without one, which
+    /// the typechecker infers.
+    Infer,
 }
 
 impl Type {
-    pub fn arrow(arg: Type, ret: Type) -> Type {
-        Type::Arrow(Box::new(arg), Box::new(ret))
+    pub fn int() -> Type {
+        Type::Int
+    }
+
+    pub fn float() -> Type {
+        Type::Float
+    }
+
+    pub fn bool() -> Type {
+        Type::Bool
+    }
+
+    pub fn string() -> Type {
+        Type::Str
+    }
+
+    pub fn unit() -> Type {
+        Type::Unit
+    }
+
+    /// The type of functions from `self` to `ret`.
+    pub fn arrow(self, ret: Type) -> Type {
+        Type::Arrow(Box::new(self), Box::new(ret))
+    }
+
+    /// The type of lists of `self`s.
+    pub fn list(self) -> Type {
+        Type::List(Box::new(self))
+    }
+
+    /// The type of reference cells holding `self`s.
+    pub fn ref_(self) -> Type {
+        Type::Ref(Box::new(self))
+    }
+
+    /// The type of records with the `fields`, like `{x: int, y: bool}`.
+    pub fn record(fields: Vec<(&str, Type)>) -> Type {
+        Type::Record(fields.into_iter().map(|(name, type_)| (Ident::from_str(name), type_)).collect())
+    }
+
+    pub fn named(name: &str) -> Type {
+        Type::Named(Ident::from_str(name))
+    }
+
+    /// The type parameter `'name`.
+    pub fn param(name: &str) -> Type {
+        Type::Param(Ident::from_str(name))
     }
 }
 
@@ -19,13 +86,41 @@ impl fmt::Debug for Type {
 
         match *self {
             Int => f.write_str("int"),
+            Float => f.write_str("float"),
             Bool => f.write_str("bool"),
+            Str => f.write_str("string"),
+            Unit => f.write_str("unit"),
             Arrow(ref l, ref r) => {
                 match **l {
                     Arrow(..) => write!(f, "({:?}) -> {:?}", l, r),
                     _ => write!(f, "{:?} -> {:?}", l, r),
                 }
             }
+            List(ref t) => {
+                match **t {
+                    Arrow(..) => write!(f, "({:?}) list", t),
+                    _ => write!(f, "{:?} list", t),
+                }
+            }
+            Ref(ref t) => {
+                match **t {
+                    Arrow(..) => write!(f, "({:?}) ref", t),
+                    _ => write!(f, "{:?} ref", t),
+                }
+            }
+            Record(ref fields) => {
+                try!(f.write_str("{"));
+                for (i, field) in fields.iter().enumerate() {
+                    if i > 0 {
+                        try!(f.write_str(", "));
+                    }
+                    try!(write!(f, "{}: {:?}", field.0, field.1));
+                }
+                f.write_str("}")
+            }
+            Named(ref name) => write!(f, "{}", name),
+            Param(ref name) => write!(f, "'{}", name),
+            Infer => f.write_str("_"),
         }
     }
 }
@@ -41,5 +136,33 @@ mod tests {
 
         let foo = Type::arrow(Type::arrow(Type::Int, Type::Bool), Type::Int);
         assert_eq!(format!("{:?}", foo), "(int -> bool) -> int");
+
+        let foo = Type::int().arrow(Type::bool()).arrow(Type::int());
+        assert_eq!(format!("{:?}", foo), "(int -> bool) -> int");
+    }
+
+    #[test]
+    fn test_list() {
+        assert_eq!(format!("{:?}", Type::int().list().list()), "int list list");
+        assert_eq!(format!("{:?}", Type::int().arrow(Type::int().list())), "int -> int list");
+        assert_eq!(format!("{:?}", Type::int().arrow(Type::int()).list()), "(int -> int) list");
+        assert_eq!(format!("{:?}", Type::named("shape").list()), "shape list");
+        assert_eq!(format!("{:?}", Type::unit().arrow(Type::unit())), "unit -> unit");
+        assert_eq!(format!("{:?}", Type::float().list()), "float list");
+        assert_eq!(format!("{:?}", Type::param("a").arrow(Type::param("b").list())), "'a -> 'b list");
+    }
+
+    #[test]
+    fn test_ref() {
+        assert_eq!(format!("{:?}", Type::int().ref_().list()), "int ref list");
+        assert_eq!(format!("{:?}", Type::int().list().ref_()), "int list ref");
+        assert_eq!(format!("{:?}", Type::int().arrow(Type::int()).ref_()), "(int -> int) ref");
+    }
+
+    #[test]
+    fn test_record() {
+        let point = Type::record(vec![("x", Type::int()), ("y", Type::int().arrow(Type::bool()))]);
+        assert_eq!(format!("{:?}", point), "{x: int, y: int -> bool}");
+        assert_eq!(format!("{:?}", point.list().arrow(Type::unit())), "{x: int, y: int -> bool} list -> unit");
     }
 }
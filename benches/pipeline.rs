@@ -0,0 +1,140 @@
+//! Times each phase of the pipeline, from parsing to running the bytecode,
+//! on a few programs: `cargo bench`, or `cargo bench -- fib` for the
+//! benchmarks with `fib` in their name, like `fib/exec`. With
+//! `--features jit` they run with the JIT as well, as `fib/jit`.
+
+extern crate miniml;
+
+use std::env;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use miniml::{Machine, compile_program, parse_program, typecheck_program};
+
+/// The programs, with what they evaluate to.
+const PROGRAMS: [(&'static str, &'static str, &'static str); 4] = [
+    ("fib", "
+let rec fun fib(n: int): int is
+  if n < 2 then n else fib (n - 1) + fib (n - 2);;
+
+fib 25
+", "75025"),
+    ("ackermann", "
+let rec fun ack(m: int, n: int): int is
+  if m == 0 then n + 1
+  else if n == 0 then ack (m - 1) 1
+  else ack (m - 1) (ack m (n - 1));;
+
+ack 3 5
+", "253"),
+    ("tak", "
+let rec fun tak(x: int, y: int, z: int): int is
+  if y < x then tak (tak (x - 1) y z) (tak (y - 1) z x) (tak (z - 1) x y) else z;;
+
+tak 18 12 6
+", "7"),
+    // Builds and consumes lists through closures passed around and
+    // partially applied.
+    ("closures", "
+let rec fun range(n: int): int list is
+  if n == 0 then []: int list else n :: range (n - 1);;
+
+let rec fun map(f: int -> int, xs: int list): int list is
+  if isEmpty xs then xs else f (head xs) :: map f (tail xs);;
+
+let rec fun filter(p: int -> bool, xs: int list): int list is
+  if isEmpty xs then xs
+  else if p (head xs) then head xs :: filter p (tail xs)
+  else filter p (tail xs);;
+
+let rec fun fold(f: int -> int -> int, acc: int, xs: int list): int is
+  if isEmpty xs then acc else fold f (f acc (head xs)) (tail xs);;
+
+let fun compose(f: int -> int, g: int -> int, x: int): int is f (g x);;
+let fun add(x: int, y: int): int is x + y;;
+let fun mul(x: int, y: int): int is x * y;;
+let fun is_even(x: int): bool is x - x / 2 * 2 == 0;;
+
+let rec fun repeat(n: int): int is
+  if n == 0 then 0
+  else fold add 0 (map (compose (add 1) (mul 3)) (filter is_even (range 200))) - repeat (n - 1);;
+
+repeat 25
+", "30400"),
+];
+
+/// How long each benchmark runs `f` for, at the least.
+const BUDGET: Duration = Duration::from_millis(500);
+
+fn main() {
+    // `cargo bench` passes `--bench` as well.
+    let filter = env::args().skip(1).find(|arg| !arg.starts_with("--"));
+    for &(name, src, expected) in PROGRAMS.iter() {
+        let program = parse_program(src).unwrap();
+        typecheck_program(&program).unwrap();
+        let frame = compile_program(&program);
+        let run = || {
+            let mut machine = Machine::new(&frame);
+            machine.exec().map(|value| machine.show(value)).unwrap()
+        };
+        assert_eq!(run(), expected, "{} evaluates to something else", name);
+
+        let bench = |phase: &str, f: &mut dyn FnMut()| {
+            let name = format!("{}/{}", name, phase);
+            if filter.as_ref().map_or(true, |filter| name.contains(filter.as_str())) {
+                println!("{}", measure(&name, f));
+            }
+        };
+        bench("parse", &mut || {
+            black_box(parse_program(black_box(src)).unwrap());
+        });
+        bench("typecheck", &mut || {
+            black_box(typecheck_program(black_box(&program)).unwrap());
+        });
+        bench("compile", &mut || {
+            black_box(compile_program(black_box(&program)));
+        });
+        bench("exec", &mut || {
+            black_box(run());
+        });
+        #[cfg(feature = "jit")]
+        {
+            let run = || {
+                let mut machine = Machine::new(&frame).with_jit();
+                machine.exec().map(|value| machine.show(value)).unwrap()
+            };
+            assert_eq!(run(), expected, "{} evaluates to something else with the JIT", name);
+            bench("jit", &mut || {
+                black_box(run());
+            });
+        }
+    }
+}
+
+/// Runs `f` over and over for `BUDGET`, and at least ten times, and shows
+/// the median and the fastest of the runs. The median is less thrown off by
+/// the rest of the system than the mean.
+fn measure(name: &str, f: &mut dyn FnMut()) -> String {
+    let mut times = Vec::new();
+    let start = Instant::now();
+    while start.elapsed() < BUDGET || times.len() < 10 {
+        let run = Instant::now();
+        f();
+        times.push(run.elapsed());
+    }
+    times.sort();
+    format!("{:<20} {:>10} median {:>10} fastest {:>8} runs",
+            name,
+            show(times[times.len() / 2]),
+            show(times[0]),
+            times.len())
+}
+
+fn show(time: Duration) -> String {
+    let micros = time.as_secs_f64() * 1e6;
+    if micros < 1000.0 {
+        format!("{:.1}µs", micros)
+    } else {
+        format!("{:.2}ms", micros / 1000.0)
+    }
+}
@@ -0,0 +1,21 @@
+-- Church numerals: the number n is a function applying `f` n times.
+-- There is no polymorphism, so they work on ints only.
+-- expect: 45
+
+let fun zero(f: int -> int, x: int): int is x;;
+
+let fun succ(n: (int -> int) -> int -> int, f: int -> int, x: int): int is f (n f x);;
+
+let fun add(m: (int -> int) -> int -> int, n: (int -> int) -> int -> int, f: int -> int, x: int): int is
+  m f (n f x);;
+
+let fun mul(m: (int -> int) -> int -> int, n: (int -> int) -> int -> int, f: int -> int): int -> int is
+  m (n f);;
+
+let fun inc(x: int): int is x + 1;;
+let fun to_int(n: (int -> int) -> int -> int): int is n inc 0;;
+
+let two = succ (succ zero);;
+let three = succ two;;
+
+to_int (mul (add two three) (mul three three))
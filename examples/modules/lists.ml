@@ -0,0 +1,11 @@
+-- Functions on lists of ints.
+
+let rec fun length(xs: int list): int is
+  if isEmpty xs then 0 else 1 + length (tail xs);;
+
+let rec fun sum(xs: int list): int is
+  if isEmpty xs then 0 else head xs + sum (tail xs);;
+
+let rec fun map(f: int -> int): int list -> int list is
+  fun map_f(xs: int list): int list is
+    if isEmpty xs then xs else f (head xs) :: map f (tail xs);;
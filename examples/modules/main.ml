@@ -0,0 +1,10 @@
+-- A program split into files: `stats.ml` imports `lists.ml` too, which is
+-- loaded once. Run with `cargo run examples/modules/main.ml`.
+-- expect: 6
+
+import "lists.ml";;
+import "stats.ml";;
+
+let xs = map (fun double(x: int): int is 2 * x) [1, 2, 3, 4, 5];;
+
+variance xs - length xs / 2
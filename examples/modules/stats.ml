@@ -0,0 +1,11 @@
+-- Statistics of lists of ints, rounded down.
+
+import "lists.ml";;
+
+let fun mean(xs: int list): int is sum xs / length xs;;
+
+let fun square(x: int): int is x * x;;
+
+let fun variance(xs: int list): int is
+  let m = mean xs in
+  mean (map (fun deviation(x: int): int is square (x - m)) xs);;
@@ -0,0 +1,16 @@
+-- Counts the primes below 100 by trial division.
+-- expect: 25
+
+let rec fun has_divisor(n: int): int -> bool is
+  fun has_divisor_from(d: int): bool is
+    if d * d > n then false
+    else if n - n / d * d == 0 then true
+    else has_divisor n (d + 1);;
+
+let fun is_prime(n: int): bool is
+  if n < 2 then false else if has_divisor n 2 then false else true;;
+
+let rec fun count(n: int): int is
+  if n == 0 then 0 else (if is_prime n then 1 else 0) + count (n - 1);;
+
+count 99
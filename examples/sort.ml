@@ -0,0 +1,13 @@
+-- Insertion sort.
+-- expect: [26, 31, 41, 53, 58, 59, 97]
+
+let rec fun insert(x: int): int list -> int list is
+  fun insert_x(xs: int list): int list is
+    if isEmpty xs then [x]
+    else if x < head xs then x :: xs
+    else head xs :: insert x (tail xs);;
+
+let rec fun sort(xs: int list): int list is
+  if isEmpty xs then xs else insert (head xs) (sort (tail xs));;
+
+sort [31, 41, 59, 26, 53, 58, 97]
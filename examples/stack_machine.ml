@@ -0,0 +1,26 @@
+-- An interpreter for a tiny stack machine. The program is an int read
+-- digit by digit from the lowest one:
+--
+--   1 d  push the digit d
+--   2    add the two topmost values
+--   3    multiply the two topmost values
+--   4    duplicate the topmost value
+--
+-- The stack is an int too, three decimal digits per value.
+-- expect: 400
+
+let fun mod(n: int, m: int): int is n - n / m * m;;
+
+let fun top(stack: int): int is mod stack 1000;;
+let fun pop(stack: int): int is stack / 1000;;
+let fun push(stack: int, x: int): int is stack * 1000 + x;;
+
+let rec fun run(program: int, stack: int): int is
+  if program == 0 then top stack
+  else if mod program 10 == 1 then run (program / 100) (push stack (mod (program / 10) 10))
+  else if mod program 10 == 2 then run (program / 10) (push (pop (pop stack)) (top (pop stack) + top stack))
+  else if mod program 10 == 3 then run (program / 10) (push (pop (pop stack)) (top (pop stack) * top stack))
+  else run (program / 10) (push stack (top stack));;
+
+-- push 2, push 3, add, push 4, mul, dup, mul: ((2 + 3) * 4) * ((2 + 3) * 4)
+run 3434123121 0
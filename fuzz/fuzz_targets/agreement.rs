@@ -0,0 +1,21 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate miniml_fuzz;
+
+use miniml_fuzz::{gen, reference, run_machine};
+
+// The machine must compute what the reference interpreter does.
+fuzz_target!(|data: &[u8]| {
+    let expr = gen::expr(data);
+    // The reference interpreter recurses on each step, so it gets less fuel
+    // than the machine to stay within the stack.
+    let expected = match reference::eval(&expr, 2_000) {
+        Ok(value) => Ok(value),
+        Err(reference::Error::Runtime(message)) => Err(message),
+        Err(reference::Error::Overflow) | Err(reference::Error::OutOfFuel) => return,
+    };
+    if let Some(actual) = run_machine(&expr, 1_000_000) {
+        assert_eq!(actual, expected, "{:?}", expr);
+    }
+});
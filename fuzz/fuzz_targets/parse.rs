@@ -0,0 +1,15 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate miniml;
+extern crate syntax_ll;
+
+use std::str;
+
+// Both parsers must reject bad input with an error rather than a panic.
+fuzz_target!(|data: &[u8]| {
+    if let Ok(src) = str::from_utf8(data) {
+        let _ = miniml::parse_program(&miniml::strip_comments(src));
+        let _ = syntax_ll::parse_program(src);
+    }
+});
@@ -0,0 +1,17 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate miniml;
+extern crate miniml_fuzz;
+
+use miniml_fuzz::{gen, run_machine};
+
+// Generated programs are well typed, and the rest of the pipeline must
+// handle them without panicking.
+fuzz_target!(|data: &[u8]| {
+    let expr = gen::expr(data);
+    if let Err(e) = miniml::typecheck(&expr) {
+        panic!("{:?} doesn't typecheck: {}", expr, e);
+    }
+    let _ = run_machine(&expr, 100_000);
+});
@@ -0,0 +1,353 @@
+//! Builds well typed expressions from fuzzer input, so that the fuzzer
+//! explores the typechecker, compiler and machine rather than the parser.
+
+use ast::{Arm, Construct, Expr, Fun, Ident, LetType, Match, Type, Variant};
+use ast::expr::*;
+
+/// How deep expressions nest; the input usually runs out before that.
+const MAX_DEPTH: usize = 8;
+
+pub fn expr(data: &[u8]) -> Expr {
+    let mut gen = Gen {
+        data: data,
+        names: 0,
+    };
+    let type_ = gen.type_(2);
+    gen.expr(&type_, &mut Vec::new(), MAX_DEPTH)
+}
+
+struct Gen<'a> {
+    data: &'a [u8],
+    names: usize,
+}
+
+type Env = Vec<(String, Type)>;
+
+impl<'a> Gen<'a> {
+    /// Zero once the input is exhausted, which makes every choice pick the
+    /// simplest option and the generation terminate.
+    fn choose(&mut self, n: u8) -> u8 {
+        match self.data.split_first() {
+            Some((&byte, rest)) => {
+                self.data = rest;
+                byte % n
+            }
+            None => 0,
+        }
+    }
+
+    fn name(&mut self, prefix: &str) -> String {
+        self.names += 1;
+        format!("{}{}", prefix, self.names)
+    }
+
+    fn type_(&mut self, depth: usize) -> Type {
+        match if depth == 0 { self.choose(4) } else { self.choose(8) } {
+            0 => Type::int(),
+            1 => Type::bool(),
+            2 => Type::string(),
+            3 => Type::float(),
+            4 => self.type_(depth - 1).list(),
+            5 => Type::record(vec![("a", self.type_(depth - 1)), ("b", self.type_(depth - 1))]),
+            _ => self.type_(depth - 1).arrow(self.type_(depth - 1)),
+        }
+    }
+
+    fn expr(&mut self, type_: &Type, env: &mut Env, depth: usize) -> Expr {
+        if depth == 0 {
+            return self.leaf(type_, env);
+        }
+        let depth = depth - 1;
+        match self.choose(9) {
+            0 => self.leaf(type_, env),
+            1 => {
+                let cond = self.expr(&Type::bool(), env, depth);
+                let tru = self.expr(type_, env, depth);
+                let fls = self.expr(type_, env, depth);
+                if_(cond, tru, fls)
+            }
+            2 => {
+                let arg_type = self.type_(1);
+                let fun_type = arg_type.clone().arrow(type_.clone());
+                let fun = self.expr(&fun_type, env, depth);
+                let arg = self.expr(&arg_type, env, depth);
+                apply(fun, arg)
+            }
+            3 => {
+                let arg_type = self.type_(1);
+                let fun_type = self.type_(1);
+                let fun = self.fun(arg_type, fun_type, env, depth);
+                let body = self.scoped(&[&fun], type_, env, depth);
+                let_fun(fun, body)
+            }
+            4 => {
+                let a = self.fun_signature(env);
+                let b = self.fun_signature(env);
+                let a = self.fun_body(a, env, depth);
+                let b = self.fun_body(b, env, depth);
+                env.truncate(env.len() - 2);
+                let body = self.scoped(&[&a, &b], type_, env, depth);
+                let_rec(vec![a, b], body)
+            }
+            5 => {
+                let value_type = self.type_(1);
+                let value = self.expr(&value_type, env, depth);
+                let name = self.name("v");
+                env.push((name.clone(), value_type));
+                let body = self.expr(type_, env, depth);
+                env.pop();
+                let_(&name, value, body)
+            }
+            6 => {
+                match (type_, self.choose(4)) {
+                    (&Type::List(_), 0) => tail(self.expr(type_, env, depth)),
+                    (_, 0) => head(self.expr(&type_.clone().list(), env, depth)),
+                    (_, 1) => deref(self.expr(&type_.clone().ref_(), env, depth)),
+                    (_, 2) => {
+                        let value = self.expr(type_, env, depth);
+                        let other = self.expr(&Type::int(), env, depth);
+                        field(record(vec![("value", value), ("other", other)]), "value")
+                    }
+                    _ => seq(self.expr(&Type::unit(), env, depth), self.expr(type_, env, depth)),
+                }
+            }
+            7 => self.variant(type_, env, depth),
+            _ => {
+                match *type_ {
+                    Type::Int => {
+                        let lhs = self.expr(&Type::int(), env, depth);
+                        let rhs = self.expr(&Type::int(), env, depth);
+                        match self.choose(7) {
+                            0 => add(lhs, rhs),
+                            1 => sub(lhs, rhs),
+                            2 => mul(lhs, rhs),
+                            3 => div(lhs, rhs),
+                            4 => rem(lhs, rhs),
+                            5 => int_of_float(self.expr(&Type::float(), env, depth)),
+                            _ => neg(sub(lhs, rhs)),
+                        }
+                    }
+                    Type::Float => {
+                        let lhs = self.expr(&Type::float(), env, depth);
+                        let rhs = self.expr(&Type::float(), env, depth);
+                        match self.choose(6) {
+                            0 => add(lhs, rhs),
+                            1 => sub(lhs, rhs),
+                            2 => mul(lhs, rhs),
+                            3 => div(lhs, rhs),
+                            4 => rem(lhs, rhs),
+                            _ => float_of_int(self.expr(&Type::int(), env, depth)),
+                        }
+                    }
+                    Type::Bool if self.choose(4) == 0 => {
+                        let lhs = self.expr(&Type::string(), env, depth);
+                        let rhs = self.expr(&Type::string(), env, depth);
+                        if self.choose(2) == 0 { eq(lhs, rhs) } else { ne(lhs, rhs) }
+                    }
+                    Type::Bool if self.choose(3) == 0 => {
+                        let lhs = self.expr(&Type::bool(), env, depth);
+                        match self.choose(3) {
+                            0 => not(lhs),
+                            1 => and(lhs, self.expr(&Type::bool(), env, depth)),
+                            _ => or(lhs, self.expr(&Type::bool(), env, depth)),
+                        }
+                    }
+                    Type::Bool => {
+                        let lhs = self.expr(&Type::int(), env, depth);
+                        let rhs = self.expr(&Type::int(), env, depth);
+                        match self.choose(6) {
+                            0 => eq(lhs, rhs),
+                            1 => ne(lhs, rhs),
+                            2 => lt(lhs, rhs),
+                            3 => le(lhs, rhs),
+                            4 => gt(lhs, rhs),
+                            _ => ge(lhs, rhs),
+                        }
+                    }
+                    Type::Str => {
+                        let lhs = self.expr(&Type::string(), env, depth);
+                        let rhs = self.expr(&Type::string(), env, depth);
+                        concat(lhs, rhs)
+                    }
+                    Type::Arrow(ref arg, ref ret) => {
+                        self.fun((**arg).clone(), (**ret).clone(), env, depth).into()
+                    }
+                    Type::List(ref element) => {
+                        let head = self.expr(element, env, depth);
+                        let tail = self.expr(type_, env, depth);
+                        cons(head, tail)
+                    }
+                    Type::Unit => {
+                        match self.choose(3) {
+                            0 => print(self.expr(&Type::string(), env, depth)),
+                            1 => print_int(self.expr(&Type::int(), env, depth)),
+                            _ => {
+                                let value_type = self.type_(1);
+                                let cell = self.expr(&value_type.clone().ref_(), env, depth);
+                                assign(cell, self.expr(&value_type, env, depth))
+                            }
+                        }
+                    }
+                    Type::Ref(ref value) => ref_(self.expr(value, env, depth)),
+                    Type::Record(ref fields) => {
+                        // In another order than in the type, which doesn't matter.
+                        let fields = fields.iter()
+                                           .rev()
+                                           .map(|&(ref name, ref t)| (name.as_ref(), self.expr(t, env, depth)))
+                                           .collect();
+                        record(fields)
+                    }
+                    Type::Named(_) => unreachable!("named types don't escape `variant`"),
+                    Type::Param(_) | Type::Infer => unreachable!("generated types are all concrete"),
+                }
+            }
+        }
+    }
+
+    /// A literal or a variable of `type_`, or a function returning one.
+    fn leaf(&mut self, type_: &Type, env: &mut Env) -> Expr {
+        let vars = env.iter()
+                      .rev()
+                      .filter(|&&(_, ref bound)| bound == type_)
+                      .map(|&(ref name, _)| name.clone())
+                      .collect::<Vec<_>>();
+        if !vars.is_empty() && self.choose(2) == 0 {
+            let i = self.choose(vars.len().min(255) as u8) as usize;
+            return var(&vars[i]);
+        }
+        match *type_ {
+            Type::Int => int(self.choose(255) as i64),
+            // Quarters print exactly, so both lexers read back the same number
+            Type::Float => float(self.choose(255) as f64 / 4.0),
+            Type::Bool => bool(self.choose(2) == 0),
+            // Escapes make the printers and both lexers agree on more than plain text
+            Type::Str => string(["", "a", "bc", "\n", "\"", "\\"][self.choose(6) as usize]),
+            Type::Unit => unit(),
+            Type::Arrow(ref arg, ref ret) => self.fun((**arg).clone(), (**ret).clone(), env, 0).into(),
+            Type::List(ref element) => nil((**element).clone()),
+            Type::Ref(ref value) => ref_(self.leaf(value, env)),
+            Type::Record(ref fields) => {
+                record(fields.iter().map(|&(ref name, ref t)| (name.as_ref(), self.leaf(t, env))).collect())
+            }
+            Type::Named(_) => unreachable!("named types don't escape `variant`"),
+            Type::Param(_) | Type::Infer => unreachable!("generated types are all concrete"),
+        }
+    }
+
+    /// `type t = C of arg | D in match (C ... or D) with C x -> ... | D -> ...`,
+    /// maybe with `arg` given a name by `type a = arg in ...` first.
+    fn variant(&mut self, type_: &Type, env: &mut Env, depth: usize) -> Expr {
+        let type_name = self.name("t");
+        let some = Ident::from_str(&self.name("C"));
+        let none = Ident::from_str(&self.name("D"));
+        let arg_type = self.type_(1);
+        let scrutinee = if self.choose(2) == 0 {
+            Construct {
+                name: some,
+                arg: Some(self.expr(&arg_type, env, depth)),
+            }
+        } else {
+            Construct {
+                name: none,
+                arg: None,
+            }
+        };
+        let binding = self.name("x");
+        env.push((binding.clone(), arg_type.clone()));
+        let some_body = self.expr(type_, env, depth);
+        env.pop();
+        let none_body = self.expr(type_, env, depth);
+        let match_ = Match {
+            scrutinee: scrutinee.into(),
+            arms: vec![Arm {
+                           constructor: some,
+                           binding: Some(Ident::from_str(&binding)),
+                           body: some_body,
+                       },
+                       Arm {
+                           constructor: none,
+                           binding: None,
+                           body: none_body,
+                       }],
+        };
+        let alias = if self.choose(2) == 0 { Some(self.name("a")) } else { None };
+        let let_type = LetType {
+            name: Ident::from_str(&type_name),
+            variants: vec![Variant {
+                               name: some,
+                               arg: Some(match alias {
+                                   Some(ref alias) => Type::Named(Ident::from_str(alias)),
+                                   None => arg_type.clone(),
+                               }),
+                           },
+                           Variant {
+                               name: none,
+                               arg: None,
+                           }],
+            body: match_.into(),
+        };
+        match alias {
+            Some(alias) => type_alias(&alias, arg_type, let_type.into()),
+            None => let_type.into(),
+        }
+    }
+
+    fn fun(&mut self, arg_type: Type, fun_type: Type, env: &mut Env, depth: usize) -> Fun {
+        let name = self.name("f");
+        env.push((name.clone(), arg_type.clone().arrow(fun_type.clone())));
+        let fun = self.fun_body((name, arg_type, fun_type), env, depth);
+        env.pop();
+        fun
+    }
+
+    /// Adds a function to `env` ahead of its body, for `let rec`.
+    fn fun_signature(&mut self, env: &mut Env) -> (String, Type, Type) {
+        let name = self.name("f");
+        let arg_type = self.type_(1);
+        let fun_type = self.type_(1);
+        env.push((name.clone(), arg_type.clone().arrow(fun_type.clone())));
+        (name, arg_type, fun_type)
+    }
+
+    fn fun_body(&mut self,
+                (name, arg_type, fun_type): (String, Type, Type),
+                env: &mut Env,
+                depth: usize)
+                -> Fun {
+        let arg = self.name("x");
+        env.push((arg.clone(), arg_type.clone()));
+        // A function returning a function may take both arguments at once.
+        let fun = match fun_type {
+            Type::Arrow(ref arg2_type, ref ret_type) if self.choose(2) == 0 => {
+                let arg2 = self.name("x");
+                env.push((arg2.clone(), (**arg2_type).clone()));
+                let body = self.expr(ret_type, env, depth);
+                env.pop();
+                Fun::new(&name, &arg, arg_type, (**ret_type).clone(), body)
+                    .with_arg(&arg2, (**arg2_type).clone())
+            }
+            _ => {
+                let body = self.expr(&fun_type, env, depth);
+                Fun::new(&name, &arg, arg_type, fun_type, body)
+            }
+        };
+        env.pop();
+        fun
+    }
+
+    /// An expression of `type_` with `funs` in scope.
+    fn scoped(&mut self, funs: &[&Fun], type_: &Type, env: &mut Env, depth: usize) -> Expr {
+        for fun in funs {
+            let fun_type = fun.args
+                              .iter()
+                              .rev()
+                              .fold(fun.fun_type.clone(), |ret_type, &(_, ref arg_type)| {
+                                  arg_type.clone().arrow(ret_type)
+                              });
+            env.push((fun.fun_name.to_string(), fun_type));
+        }
+        let body = self.expr(type_, env, depth);
+        env.truncate(env.len() - funs.len());
+        body
+    }
+}
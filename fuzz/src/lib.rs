@@ -0,0 +1,33 @@
+//! Helpers shared by the fuzz targets: a generator of well typed
+//! expressions, and a direct AST interpreter to check the machine against.
+
+extern crate ast;
+extern crate miniml;
+
+pub mod gen;
+pub mod reference;
+
+use miniml::{Machine, Status, compile};
+
+/// Runs `expr` on the machine for at most `fuel` instructions, showing its
+/// value after whatever it printed. Returns `None` if it doesn't finish in time.
+pub fn run_machine(expr: &ast::Expr, fuel: u64) -> Option<Result<String, String>> {
+    let frame = compile(expr);
+    let mut output = Vec::new();
+    let result = {
+        let mut machine = Machine::new(&frame).with_output(&mut output);
+        step(&mut machine, fuel)
+    };
+    result.map(|result| result.map(|value| String::from_utf8_lossy(&output).into_owned() + &value))
+}
+
+fn step(machine: &mut Machine, fuel: u64) -> Option<Result<String, String>> {
+    for _ in 0..fuel {
+        match machine.step() {
+            Status::Finished(value) => return Some(Ok(machine.show(value))),
+            Status::Running => {}
+            Status::Error(e) => return Some(Err(e.to_string())),
+        }
+    }
+    None
+}
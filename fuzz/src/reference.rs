@@ -0,0 +1,371 @@
+//! A direct interpreter of the AST, as a reference for the machine.
+//! It is slow and simple: environments are copied on every call.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use ast::{ArithOp, CmpOp, ConvertOp, Expr, ExprKind, Fun, ListOp, Literal, LogicOp, PrintOp, RefOp, escape};
+
+pub enum Error {
+    Runtime(String),
+    /// The machine's behavior on overflow isn't pinned down yet.
+    Overflow,
+    OutOfFuel,
+}
+
+#[derive(Clone)]
+enum Value<'e> {
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Unit,
+    Str(String),
+    /// Function `index` of a group of mutually recursive functions, maybe
+    /// applied to some of its parameters.
+    Closure(Rc<Closure<'e>>),
+    List(Rc<Vec<Value<'e>>>),
+    /// A constructor, applied to its argument if it has one.
+    Variant(&'e str, Option<Rc<Value<'e>>>),
+    /// The fields, sorted by name.
+    Record(Rc<Vec<(&'e str, Value<'e>)>>),
+    Ref(Rc<RefCell<Value<'e>>>),
+}
+
+struct Closure<'e> {
+    group: &'e [Fun],
+    index: usize,
+    /// How many parameters are bound in `env`.
+    applied: usize,
+    env: Env<'e>,
+}
+
+type Env<'e> = Vec<(&'e str, Value<'e>)>;
+
+/// Evaluates `expr`, formatting the result like `Machine::show` does, after
+/// whatever the program printed. Gives up after `fuel` evaluation steps.
+pub fn eval(expr: &Expr, fuel: u64) -> Result<String, Error> {
+    let mut interpreter = Interpreter {
+        fuel: fuel,
+        output: String::new(),
+    };
+    let value = try!(interpreter.eval(expr, &Vec::new()));
+    Ok(interpreter.output + &show(&value))
+}
+
+fn show(value: &Value) -> String {
+    match *value {
+        Value::Int(i) => i.to_string(),
+        Value::Float(x) => format!("{:?}", x),
+        Value::Bool(b) => b.to_string(),
+        Value::Unit => "()".to_owned(),
+        Value::Str(ref s) => format!("\"{}\"", escape(s)),
+        Value::Closure(_) => "<closure>".to_owned(),
+        Value::List(ref items) => {
+            format!("[{}]", items.iter().map(show).collect::<Vec<_>>().join(", "))
+        }
+        Value::Variant(name, None) => name.to_owned(),
+        Value::Variant(name, Some(ref arg)) => format!("{} {}", name, show_arg(arg)),
+        Value::Record(ref fields) => {
+            let fields = fields.iter().map(|&(name, ref value)| format!("{} = {}", name, show(value)));
+            format!("{{{}}}", fields.collect::<Vec<_>>().join(", "))
+        }
+        Value::Ref(ref cell) => format!("ref {}", show_arg(&cell.borrow())),
+    }
+}
+
+/// Applications like `Some 1` and `ref 1` are parenthesized as arguments.
+fn show_arg(value: &Value) -> String {
+    match *value {
+        Value::Variant(_, Some(_)) | Value::Ref(_) => format!("({})", show(value)),
+        _ => show(value),
+    }
+}
+
+struct Interpreter {
+    fuel: u64,
+    output: String,
+}
+
+impl Interpreter {
+    fn eval<'e>(&mut self, expr: &'e Expr, env: &Env<'e>) -> Result<Value<'e>, Error> {
+        if self.fuel == 0 {
+            return Err(Error::OutOfFuel);
+        }
+        self.fuel -= 1;
+        match expr.kind {
+            ExprKind::Var(ref name) => {
+                match env.iter().rev().find(|&&(bound, _)| bound == name.as_ref()) {
+                    Some(&(_, ref value)) => Ok(value.clone()),
+                    None => Err(Error::Runtime(format!("Undefined variable {}", name))),
+                }
+            }
+            ExprKind::Literal(Literal::Number(n)) => Ok(Value::Int(n)),
+            ExprKind::Literal(Literal::Float(x)) => Ok(Value::Float(x)),
+            ExprKind::Literal(Literal::Bool(b)) => Ok(Value::Bool(b)),
+            ExprKind::Literal(Literal::Str(ref s)) => Ok(Value::Str(s.clone())),
+            ExprKind::Literal(Literal::Unit) => Ok(Value::Unit),
+            ExprKind::ArithBinOp(ref op) => {
+                let (lhs, rhs) = match try!(self.eval(&op.lhs, env)) {
+                    Value::Int(lhs) => (lhs, try!(self.int(&op.rhs, env))),
+                    Value::Float(lhs) => {
+                        let rhs = try!(self.float(&op.rhs, env));
+                        return Ok(Value::Float(match op.kind {
+                            ArithOp::Add => lhs + rhs,
+                            ArithOp::Sub => lhs - rhs,
+                            ArithOp::Mul => lhs * rhs,
+                            ArithOp::Div => lhs / rhs,
+                            ArithOp::Mod => lhs % rhs,
+                        }));
+                    }
+                    _ => return Err(Error::Runtime("Expected a number".to_owned())),
+                };
+                let result = match op.kind {
+                    ArithOp::Add => lhs.checked_add(rhs),
+                    ArithOp::Sub => lhs.checked_sub(rhs),
+                    ArithOp::Mul => lhs.checked_mul(rhs),
+                    ArithOp::Div | ArithOp::Mod if rhs == 0 => {
+                        return Err(Error::Runtime("Division by zero".to_owned()))
+                    }
+                    ArithOp::Div => lhs.checked_div(rhs),
+                    ArithOp::Mod => lhs.checked_rem(rhs),
+                };
+                result.map(Value::Int).ok_or(Error::Overflow)
+            }
+            ExprKind::CmpBinOp(ref op) => {
+                let lhs = try!(self.eval(&op.lhs, env));
+                let rhs = try!(self.eval(&op.rhs, env));
+                Ok(Value::Bool(match (op.kind, lhs, rhs) {
+                    (CmpOp::Eq, Value::Str(lhs), Value::Str(rhs)) => lhs == rhs,
+                    (CmpOp::Ne, Value::Str(lhs), Value::Str(rhs)) => lhs != rhs,
+                    (CmpOp::Eq, Value::Int(lhs), Value::Int(rhs)) => lhs == rhs,
+                    (CmpOp::Ne, Value::Int(lhs), Value::Int(rhs)) => lhs != rhs,
+                    (CmpOp::Lt, Value::Int(lhs), Value::Int(rhs)) => lhs < rhs,
+                    (CmpOp::Le, Value::Int(lhs), Value::Int(rhs)) => lhs <= rhs,
+                    (CmpOp::Gt, Value::Int(lhs), Value::Int(rhs)) => lhs > rhs,
+                    (CmpOp::Ge, Value::Int(lhs), Value::Int(rhs)) => lhs >= rhs,
+                    (CmpOp::Eq, Value::Float(lhs), Value::Float(rhs)) => lhs == rhs,
+                    (CmpOp::Ne, Value::Float(lhs), Value::Float(rhs)) => lhs != rhs,
+                    (CmpOp::Lt, Value::Float(lhs), Value::Float(rhs)) => lhs < rhs,
+                    (CmpOp::Le, Value::Float(lhs), Value::Float(rhs)) => lhs <= rhs,
+                    (CmpOp::Gt, Value::Float(lhs), Value::Float(rhs)) => lhs > rhs,
+                    (CmpOp::Ge, Value::Float(lhs), Value::Float(rhs)) => lhs >= rhs,
+                    _ => return Err(Error::Runtime("Expected numbers".to_owned())),
+                }))
+            }
+            ExprKind::LogicBinOp(ref op) => {
+                let lhs = try!(self.bool(&op.lhs, env));
+                match op.kind {
+                    LogicOp::And if !lhs => Ok(Value::Bool(false)),
+                    LogicOp::Or if lhs => Ok(Value::Bool(true)),
+                    _ => self.bool(&op.rhs, env).map(Value::Bool),
+                }
+            }
+            ExprKind::Not(ref not) => self.bool(&not.arg, env).map(|b| Value::Bool(!b)),
+            ExprKind::List(ref list) => {
+                let mut items = Vec::new();
+                for item in &list.items {
+                    items.push(try!(self.eval(item, env)));
+                }
+                Ok(Value::List(Rc::new(items)))
+            }
+            ExprKind::Cons(ref cons) => {
+                let head = try!(self.eval(&cons.head, env));
+                let mut items = vec![head];
+                items.extend(try!(self.list(&cons.tail, env)).iter().cloned());
+                Ok(Value::List(Rc::new(items)))
+            }
+            ExprKind::Concat(ref concat) => {
+                let lhs = try!(self.string(&concat.lhs, env));
+                let rhs = try!(self.string(&concat.rhs, env));
+                Ok(Value::Str(lhs + &rhs))
+            }
+            ExprKind::ListUnOp(ref op) => {
+                let items = try!(self.list(&op.arg, env));
+                match op.kind {
+                    ListOp::IsEmpty => Ok(Value::Bool(items.is_empty())),
+                    ListOp::Head | ListOp::Tail if items.is_empty() => {
+                        let message = match op.kind {
+                            ListOp::Head => "Head of an empty list",
+                            _ => "Tail of an empty list",
+                        };
+                        Err(Error::Runtime(message.to_owned()))
+                    }
+                    ListOp::Head => Ok(items[0].clone()),
+                    ListOp::Tail => Ok(Value::List(Rc::new(items[1..].to_vec()))),
+                }
+            }
+            ExprKind::RefUnOp(ref op) => {
+                let value = try!(self.eval(&op.arg, env));
+                match (op.kind, value) {
+                    (RefOp::Ref, value) => Ok(Value::Ref(Rc::new(RefCell::new(value)))),
+                    (RefOp::Deref, Value::Ref(cell)) => Ok(cell.borrow().clone()),
+                    _ => Err(Error::Runtime("Expected a reference".to_owned())),
+                }
+            }
+            ExprKind::PrintUnOp(ref op) => {
+                let line = match op.kind {
+                    PrintOp::Print => try!(self.string(&op.arg, env)),
+                    PrintOp::PrintInt => try!(self.int(&op.arg, env)).to_string(),
+                };
+                self.output.push_str(&line);
+                self.output.push('\n');
+                Ok(Value::Unit)
+            }
+            ExprKind::ConvertUnOp(ref op) => {
+                match op.kind {
+                    ConvertOp::IntOfFloat => self.float(&op.arg, env).map(|x| Value::Int(x as i64)),
+                    ConvertOp::FloatOfInt => self.int(&op.arg, env).map(|i| Value::Float(i as f64)),
+                }
+            }
+            ExprKind::Assign(ref assign) => {
+                let cell = match try!(self.eval(&assign.cell, env)) {
+                    Value::Ref(cell) => cell,
+                    _ => return Err(Error::Runtime("Expected a reference".to_owned())),
+                };
+                let value = try!(self.eval(&assign.value, env));
+                *cell.borrow_mut() = value;
+                Ok(Value::Unit)
+            }
+            ExprKind::Seq(ref seq) => {
+                try!(self.eval(&seq.first, env));
+                self.eval(&seq.second, env)
+            }
+            ExprKind::Record(ref record) => {
+                let mut fields = Vec::new();
+                for &(ref name, ref value) in &record.fields {
+                    fields.push((name.as_ref(), try!(self.eval(value, env))));
+                }
+                fields.sort_by_key(|&(name, _)| name);
+                Ok(Value::Record(Rc::new(fields)))
+            }
+            ExprKind::Field(ref field) => {
+                let fields = match try!(self.eval(&field.record, env)) {
+                    Value::Record(fields) => fields,
+                    _ => return Err(Error::Runtime("Expected a record".to_owned())),
+                };
+                match fields.iter().find(|&&(name, _)| name == field.name.as_ref()) {
+                    Some(&(_, ref value)) => Ok(value.clone()),
+                    None => Err(Error::Runtime(format!("No field {}", field.name))),
+                }
+            }
+            ExprKind::Construct(ref construct) => {
+                let arg = match construct.arg {
+                    Some(ref arg) => Some(Rc::new(try!(self.eval(arg, env)))),
+                    None => None,
+                };
+                Ok(Value::Variant(construct.name.as_ref(), arg))
+            }
+            ExprKind::Match(ref match_) => {
+                let (name, arg) = match try!(self.eval(&match_.scrutinee, env)) {
+                    Value::Variant(name, arg) => (name, arg),
+                    _ => return Err(Error::Runtime("Expected a variant".to_owned())),
+                };
+                let arm = match match_.arms.iter().find(|arm| arm.constructor.as_ref() == name) {
+                    Some(arm) => arm,
+                    None => return Err(Error::Runtime(format!("No arm for {}", name))),
+                };
+                let mut env = env.clone();
+                if let (&Some(ref binding), Some(arg)) = (&arm.binding, arg) {
+                    env.push((binding.as_ref(), (*arg).clone()));
+                }
+                self.eval(&arm.body, &env)
+            }
+            ExprKind::If(ref if_) => {
+                match try!(self.eval(&if_.cond, env)) {
+                    Value::Bool(true) => self.eval(&if_.tru, env),
+                    Value::Bool(false) => self.eval(&if_.fls, env),
+                    _ => Err(Error::Runtime("Expected a bool".to_owned())),
+                }
+            }
+            ExprKind::Fun(ref fun) => Ok(closure(::std::slice::from_ref(&**fun), 0, env)),
+            ExprKind::Let(ref let_) => {
+                let value = try!(self.eval(&let_.value, env));
+                let mut env = env.clone();
+                env.push((let_.name.as_ref(), value));
+                self.eval(&let_.body, &env)
+            }
+            ExprKind::LetFun(ref let_fun) => {
+                let group = ::std::slice::from_ref(&let_fun.fun);
+                self.eval(&let_fun.body, &bind_group(group, env))
+            }
+            ExprKind::LetRec(ref let_rec) => {
+                self.eval(&let_rec.body, &bind_group(&let_rec.funs, env))
+            }
+            ExprKind::LetType(ref let_type) => self.eval(&let_type.body, env),
+            ExprKind::TypeAlias(ref alias) => self.eval(&alias.body, env),
+            ExprKind::Apply(ref apply) => {
+                let fun = match try!(self.eval(&apply.fun, env)) {
+                    Value::Closure(closure) => closure,
+                    _ => return Err(Error::Runtime("Expected a function".to_owned())),
+                };
+                let arg = try!(self.eval(&apply.arg, env));
+                let (group, index, applied) = (fun.group, fun.index, fun.applied);
+                let mut env = if applied == 0 { bind_group(group, &fun.env) } else { fun.env.clone() };
+                let fun = &group[index];
+                env.push((fun.args[applied].0.as_ref(), arg));
+                if applied + 1 < fun.args.len() {
+                    return Ok(Value::Closure(Rc::new(Closure {
+                        group: group,
+                        index: index,
+                        applied: applied + 1,
+                        env: env,
+                    })));
+                }
+                self.eval(&fun.body, &env)
+            }
+        }
+    }
+
+    fn list<'e>(&mut self, expr: &'e Expr, env: &Env<'e>) -> Result<Rc<Vec<Value<'e>>>, Error> {
+        match try!(self.eval(expr, env)) {
+            Value::List(items) => Ok(items),
+            _ => Err(Error::Runtime("Expected a list".to_owned())),
+        }
+    }
+
+    fn bool<'e>(&mut self, expr: &'e Expr, env: &Env<'e>) -> Result<bool, Error> {
+        match try!(self.eval(expr, env)) {
+            Value::Bool(b) => Ok(b),
+            _ => Err(Error::Runtime("Expected a bool".to_owned())),
+        }
+    }
+
+    fn string<'e>(&mut self, expr: &'e Expr, env: &Env<'e>) -> Result<String, Error> {
+        match try!(self.eval(expr, env)) {
+            Value::Str(s) => Ok(s),
+            _ => Err(Error::Runtime("Expected a string".to_owned())),
+        }
+    }
+
+    fn int<'e>(&mut self, expr: &'e Expr, env: &Env<'e>) -> Result<i64, Error> {
+        match try!(self.eval(expr, env)) {
+            Value::Int(i) => Ok(i),
+            _ => Err(Error::Runtime("Expected an int".to_owned())),
+        }
+    }
+
+    fn float<'e>(&mut self, expr: &'e Expr, env: &Env<'e>) -> Result<f64, Error> {
+        match try!(self.eval(expr, env)) {
+            Value::Float(x) => Ok(x),
+            _ => Err(Error::Runtime("Expected a float".to_owned())),
+        }
+    }
+}
+
+fn closure<'e>(group: &'e [Fun], index: usize, env: &Env<'e>) -> Value<'e> {
+    Value::Closure(Rc::new(Closure {
+        group: group,
+        index: index,
+        applied: 0,
+        env: env.clone(),
+    }))
+}
+
+/// `env` extended with the functions of `group`, which can call each other.
+fn bind_group<'e>(group: &'e [Fun], env: &Env<'e>) -> Env<'e> {
+    let mut result = env.clone();
+    for (i, fun) in group.iter().enumerate() {
+        result.push((fun.fun_name.as_ref(), closure(group, i, env)));
+    }
+    result
+}
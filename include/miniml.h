@@ -0,0 +1,82 @@
+#ifndef MINIML_H
+#define MINIML_H
+
+/* Generated by cbindgen from src/ffi.rs, do not edit. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+#define MINIML_INT 0
+
+#define MINIML_BOOL 1
+
+#define MINIML_FUNCTION 2
+
+#define MINIML_LIST 3
+
+#define MINIML_VARIANT 4
+
+#define MINIML_STRING 5
+
+#define MINIML_REF 6
+
+#define MINIML_UNIT 7
+
+#define MINIML_FLOAT 8
+
+#define MINIML_RECORD 9
+
+/**
+ * A result of `miniml_eval`. Functions, lists, variants, records and references can only be shown,
+ * as they can't outlive the machine that made them.
+ */
+typedef struct MinimlValue MinimlValue;
+
+/**
+ * Evaluates the program `src`, a NUL-terminated UTF-8 string.
+ * The result must be freed with `miniml_value_free`.
+ */
+MinimlValue *miniml_eval(const char *src);
+
+/**
+ * The error of the last failed `miniml_eval` on this thread, or null.
+ * The string is owned by the library and valid until the next `miniml_eval`.
+ */
+const char *miniml_last_error(void);
+
+/**
+ * One of `MINIML_INT`, `MINIML_FLOAT`, `MINIML_BOOL`, `MINIML_STRING`, `MINIML_FUNCTION`,
+ * `MINIML_LIST`, `MINIML_VARIANT`, `MINIML_RECORD`, `MINIML_REF` and `MINIML_UNIT`.
+ */
+int miniml_value_kind(const MinimlValue *value);
+
+/**
+ * The value of an `int`, or 0 for other kinds.
+ */
+int64_t miniml_value_int(const MinimlValue *value);
+
+/**
+ * The value of a `float`, or 0 for other kinds.
+ */
+double miniml_value_float(const MinimlValue *value);
+
+/**
+ * The value of a `bool`, or false for other kinds.
+ */
+bool miniml_value_bool(const MinimlValue *value);
+
+/**
+ * The contents of a `string`, or null for other kinds. Valid as long as `value` is.
+ */
+const char *miniml_value_string(const MinimlValue *value);
+
+/**
+ * The value as the REPL shows it. Valid as long as `value` is.
+ */
+const char *miniml_value_to_string(const MinimlValue *value);
+
+void miniml_value_free(MinimlValue *value);
+
+#endif /* MINIML_H */
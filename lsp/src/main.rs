@@ -0,0 +1,259 @@
+//! A language server for miniml, which an editor starts and talks to over
+//! the standard input and output. It shows the errors of a program as it is
+//! edited, the type of the expression under the cursor on hover, and where a
+//! let-bound name is bound, with what `miniml::ide` finds.
+//!
+//! The editor sends the changed parts of a document, which are given to
+//! `Analysis::edit`, so that only the declarations they touch are parsed
+//! again.
+
+extern crate ast;
+extern crate lsp_server;
+extern crate lsp_types;
+extern crate miniml;
+extern crate serde;
+extern crate serde_json;
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::process;
+
+use ast::Span;
+use lsp_server::{Connection, ErrorCode, Message, Notification, Request, Response};
+use lsp_types::{Diagnostic, DiagnosticSeverity, DidChangeTextDocumentParams, DidCloseTextDocumentParams,
+                DidOpenTextDocumentParams, GotoDefinitionParams, GotoDefinitionResponse, Hover, HoverContents,
+                HoverParams, HoverProviderCapability, Location, MarkupContent, MarkupKind, OneOf, Position,
+                PublishDiagnosticsParams, Range, ServerCapabilities, TextDocumentSyncCapability,
+                TextDocumentSyncKind, Url};
+use miniml::ide::Analysis;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+type Result<T> = ::std::result::Result<T, Box<dyn Error + Send + Sync>>;
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("miniml-lsp: {}", e);
+        process::exit(1);
+    }
+}
+
+fn run() -> Result<()> {
+    let (connection, io_threads) = Connection::stdio();
+    let capabilities = ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::INCREMENTAL)),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        definition_provider: Some(OneOf::Left(true)),
+        ..ServerCapabilities::default()
+    };
+    try!(connection.initialize(try!(serde_json::to_value(capabilities))));
+    let server = Server {
+        connection: connection,
+        documents: HashMap::new(),
+    };
+    try!(server.run());
+    try!(io_threads.join());
+    Ok(())
+}
+
+struct Server {
+    connection: Connection,
+    documents: HashMap<Url, Analysis>,
+}
+
+impl Server {
+    /// Handles messages until the editor shuts the server down.
+    fn run(mut self) -> Result<()> {
+        let receiver = self.connection.receiver.clone();
+        for message in receiver {
+            match message {
+                Message::Request(request) => {
+                    if try!(self.connection.handle_shutdown(&request)) {
+                        return Ok(());
+                    }
+                    let response = self.request(request);
+                    try!(self.connection.sender.send(Message::Response(response)));
+                }
+                Message::Notification(notification) => try!(self.notification(notification)),
+                Message::Response(_) => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn request(&self, request: Request) -> Response {
+        match request.method.as_str() {
+            "textDocument/hover" => respond(request, |params| self.hover(params)),
+            "textDocument/definition" => respond(request, |params| self.definition(params)),
+            _ => {
+                let message = format!("unknown request `{}`", request.method);
+                Response::new_err(request.id, ErrorCode::MethodNotFound as i32, message)
+            }
+        }
+    }
+
+    /// Keeps documents up to date with the editor, and sends their errors
+    /// whenever they change. Other notifications are ignored.
+    fn notification(&mut self, notification: Notification) -> Result<()> {
+        match notification.method.as_str() {
+            "textDocument/didOpen" => {
+                let params: DidOpenTextDocumentParams = try!(serde_json::from_value(notification.params));
+                let document = Analysis::new(&params.text_document.text);
+                self.documents.insert(params.text_document.uri.clone(), document);
+                self.publish_diagnostics(params.text_document.uri)
+            }
+            "textDocument/didChange" => {
+                let params: DidChangeTextDocumentParams = try!(serde_json::from_value(notification.params));
+                let uri = params.text_document.uri;
+                if let Some(document) = self.documents.get_mut(&uri) {
+                    for change in params.content_changes {
+                        match change.range {
+                            Some(range) => {
+                                let start = offset(document.text(), range.start);
+                                let end = offset(document.text(), range.end);
+                                document.edit(start..end, &change.text);
+                            }
+                            None => document.update(&change.text),
+                        }
+                    }
+                }
+                self.publish_diagnostics(uri)
+            }
+            "textDocument/didClose" => {
+                let params: DidCloseTextDocumentParams = try!(serde_json::from_value(notification.params));
+                self.documents.remove(&params.text_document.uri);
+                self.publish_diagnostics(params.text_document.uri)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Sends the errors of the document at `uri`, none if it isn't open.
+    fn publish_diagnostics(&self, uri: Url) -> Result<()> {
+        let diagnostics = match self.documents.get(&uri) {
+            Some(document) => {
+                document.diagnostics()
+                        .into_iter()
+                        .map(|diagnostic| {
+                            Diagnostic {
+                                range: range(document.text(), diagnostic.span),
+                                severity: Some(DiagnosticSeverity::ERROR),
+                                source: Some("miniml".to_owned()),
+                                message: diagnostic.message,
+                                ..Diagnostic::default()
+                            }
+                        })
+                        .collect()
+            }
+            None => Vec::new(),
+        };
+        let params = PublishDiagnosticsParams {
+            uri: uri,
+            diagnostics: diagnostics,
+            version: None,
+        };
+        let notification = Notification::new("textDocument/publishDiagnostics".to_owned(), params);
+        try!(self.connection.sender.send(Message::Notification(notification)));
+        Ok(())
+    }
+
+    fn hover(&self, params: HoverParams) -> Option<Hover> {
+        let at = params.text_document_position_params;
+        let document = match self.documents.get(&at.text_document.uri) {
+            Some(document) => document,
+            None => return None,
+        };
+        let (span, type_) = match document.type_at(offset(document.text(), at.position)) {
+            Some(found) => found,
+            None => return None,
+        };
+        let contents = MarkupContent {
+            kind: MarkupKind::PlainText,
+            value: format!("{:?}", type_),
+        };
+        Some(Hover {
+            contents: HoverContents::Markup(contents),
+            range: Some(range(document.text(), span)),
+        })
+    }
+
+    fn definition(&self, params: GotoDefinitionParams) -> Option<GotoDefinitionResponse> {
+        let at = params.text_document_position_params;
+        let document = match self.documents.get(&at.text_document.uri) {
+            Some(document) => document,
+            None => return None,
+        };
+        document.definition(offset(document.text(), at.position)).map(|span| {
+            GotoDefinitionResponse::Scalar(Location::new(at.text_document.uri.clone(), range(document.text(), span)))
+        })
+    }
+}
+
+/// Answers `request` with `f` of its parameters.
+fn respond<P, R, F>(request: Request, f: F) -> Response
+    where P: DeserializeOwned,
+          R: Serialize,
+          F: FnOnce(P) -> R
+{
+    match serde_json::from_value(request.params) {
+        Ok(params) => Response::new_ok(request.id, f(params)),
+        Err(e) => Response::new_err(request.id, ErrorCode::InvalidParams as i32, e.to_string()),
+    }
+}
+
+/// The position of the byte `offset` of `text`. As in the protocol, lines
+/// and columns are counted from zero, and columns in UTF-16 code units.
+fn position(text: &str, offset: usize) -> Position {
+    let before = &text[..offset];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    Position::new(before.matches('\n').count() as u32,
+                  before[line_start..].encode_utf16().count() as u32)
+}
+
+/// The byte offset of `position` in `text`. Positions past the end of a line
+/// are at its end.
+fn offset(text: &str, position: Position) -> usize {
+    let mut line_start = 0;
+    for _ in 0..position.line {
+        match text[line_start..].find('\n') {
+            Some(i) => line_start += i + 1,
+            None => return text.len(),
+        }
+    }
+    let line = text[line_start..].split('\n').next().unwrap_or("");
+    let mut column = 0;
+    for (i, c) in line.char_indices() {
+        if column >= position.character as usize {
+            return line_start + i;
+        }
+        column += c.len_utf16();
+    }
+    line_start + line.len()
+}
+
+fn range(text: &str, span: Span) -> Range {
+    Range::new(position(text, span.start as usize), position(text, span.end as usize))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn positions() {
+        let text = "let é = 1;;\nlet 𝕩 = é;;\n𝕩";
+        let cases = [(0, Position::new(0, 0)),
+                     (4, Position::new(0, 4)),
+                     (6, Position::new(0, 5)),
+                     (13, Position::new(1, 0)),
+                     (17, Position::new(1, 4)),
+                     (21, Position::new(1, 6)),
+                     (text.len(), Position::new(2, 2))];
+        for &(offset, position) in &cases {
+            assert_eq!(super::position(text, offset), position);
+            assert_eq!(super::offset(text, position), offset);
+        }
+        assert_eq!(super::offset(text, Position::new(0, 100)), 12);
+        assert_eq!(super::offset(text, Position::new(5, 0)), text.len());
+    }
+}
@@ -0,0 +1,528 @@
+//! A compact binary form of `Frame`s, for caching compiled programs.
+//!
+//! A program starts with a header: the magic bytes `SECD`, the format
+//! version (2 bytes) and the name and version of the compiler that produced
+//! it (a 2 byte length followed by UTF-8). Then comes the instruction count
+//! of the frame (4 bytes) and the instructions. Each instruction is a one
+//! byte opcode followed by its operands: integers, names and tags as 8
+//! bytes, bools as a byte, strings like the compiler name, slots, jump offsets
+//! and the lengths of closure bodies as 4 bytes. The offsets of a `Case` are
+//! preceded by their count (4 bytes), so are the field names of a `Record`
+//! and the names of a `Fix`, and the captures of a `Closure` by a
+//! bool saying whether there are any and their count. All numbers are little
+//! endian.
+//!
+//! `FORMAT_VERSION` changes whenever the encoding does. Programs in other
+//! versions are rejected by `decode`; `decode_with` lets the caller convert
+//! them instead.
+
+use program::{ArithInstruction, CmpInstruction, Frame, Instruction, ListInstruction};
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct DecodeError {
+    pub message: String,
+}
+
+fn decode_error(message: &str) -> DecodeError {
+    DecodeError { message: message.to_owned() }
+}
+
+/// The version of the encoding written by `encode`.
+pub const FORMAT_VERSION: u16 = 15;
+
+const MAGIC: &'static [u8] = b"SECD";
+
+/// Who wrote an encoded program.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Header {
+    pub format: u16,
+    pub compiler: String,
+}
+
+const ADD: u8 = 0;
+const SUB: u8 = 1;
+const MUL: u8 = 2;
+const DIV: u8 = 3;
+const LT: u8 = 4;
+const EQ: u8 = 5;
+const GT: u8 = 6;
+const PUSH_INT: u8 = 7;
+const PUSH_BOOL: u8 = 8;
+const JUMP_IF_FALSE: u8 = 9;
+const VAR: u8 = 10;
+const CLOSURE: u8 = 11;
+const CALL: u8 = 12;
+const POP_ENV: u8 = 13;
+const PUSH_NIL: u8 = 14;
+const CONS: u8 = 15;
+const HEAD: u8 = 16;
+const TAIL: u8 = 17;
+const IS_EMPTY: u8 = 18;
+const CONSTRUCT: u8 = 19;
+const CASE: u8 = 20;
+const PAYLOAD: u8 = 21;
+const PUSH_STR: u8 = 22;
+const CONCAT: u8 = 23;
+const TAIL_CALL: u8 = 24;
+const JUMP: u8 = 25;
+const MOD: u8 = 26;
+const LE: u8 = 27;
+const GE: u8 = 28;
+const NE: u8 = 29;
+const SLOT: u8 = 30;
+const REF: u8 = 31;
+const DEREF: u8 = 32;
+const ASSIGN: u8 = 33;
+const PUSH_UNIT: u8 = 34;
+const POP: u8 = 35;
+const PRINT: u8 = 36;
+const PUSH_FLOAT: u8 = 37;
+const INT_OF_FLOAT: u8 = 38;
+const FLOAT_OF_INT: u8 = 39;
+const RECORD: u8 = 40;
+const FIELD: u8 = 41;
+const FIX: u8 = 42;
+
+/// Encodes `frame` in the current format, recording `compiler` as its author.
+pub fn encode(frame: &Frame, compiler: &str) -> Vec<u8> {
+    let mut bytes = MAGIC.to_vec();
+    bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    encode_str(compiler, &mut bytes);
+    encode_frame(frame, &mut bytes);
+    bytes
+}
+
+fn encode_str(s: &str, bytes: &mut Vec<u8>) {
+    bytes.extend_from_slice(&(s.len() as u16).to_le_bytes());
+    bytes.extend_from_slice(s.as_bytes());
+}
+
+fn encode_u32(n: usize, bytes: &mut Vec<u8>) {
+    bytes.extend_from_slice(&(n as u32).to_le_bytes());
+}
+
+fn encode_frame(frame: &Frame, bytes: &mut Vec<u8>) {
+    encode_u32(frame.len(), bytes);
+    for inst in frame {
+        match *inst {
+            Instruction::ArithInstruction(op) => {
+                bytes.push(match op {
+                    ArithInstruction::Add => ADD,
+                    ArithInstruction::Sub => SUB,
+                    ArithInstruction::Mul => MUL,
+                    ArithInstruction::Div => DIV,
+                    ArithInstruction::Mod => MOD,
+                })
+            }
+            Instruction::CmpInstruction(op) => {
+                bytes.push(match op {
+                    CmpInstruction::Lt => LT,
+                    CmpInstruction::Le => LE,
+                    CmpInstruction::Eq => EQ,
+                    CmpInstruction::Ne => NE,
+                    CmpInstruction::Gt => GT,
+                    CmpInstruction::Ge => GE,
+                })
+            }
+            Instruction::ListInstruction(op) => {
+                bytes.push(match op {
+                    ListInstruction::Cons => CONS,
+                    ListInstruction::Head => HEAD,
+                    ListInstruction::Tail => TAIL,
+                    ListInstruction::IsEmpty => IS_EMPTY,
+                })
+            }
+            Instruction::PushInt(i) => {
+                bytes.push(PUSH_INT);
+                bytes.extend_from_slice(&i.to_le_bytes());
+            }
+            Instruction::PushFloat(x) => {
+                bytes.push(PUSH_FLOAT);
+                bytes.extend_from_slice(&x.to_bits().to_le_bytes());
+            }
+            Instruction::PushBool(b) => {
+                bytes.push(PUSH_BOOL);
+                bytes.push(b as u8);
+            }
+            Instruction::PushStr(ref s) => {
+                bytes.push(PUSH_STR);
+                encode_str(s, bytes);
+            }
+            Instruction::PushNil => bytes.push(PUSH_NIL),
+            Instruction::PushUnit => bytes.push(PUSH_UNIT),
+            Instruction::Pop => bytes.push(POP),
+            Instruction::Print => bytes.push(PRINT),
+            Instruction::Concat => bytes.push(CONCAT),
+            Instruction::IntOfFloat => bytes.push(INT_OF_FLOAT),
+            Instruction::FloatOfInt => bytes.push(FLOAT_OF_INT),
+            Instruction::Construct { tag, ref name, arg } => {
+                bytes.push(CONSTRUCT);
+                bytes.extend_from_slice(&(tag as u64).to_le_bytes());
+                encode_str(name, bytes);
+                bytes.push(arg as u8);
+            }
+            Instruction::Case(ref offsets) => {
+                bytes.push(CASE);
+                encode_u32(offsets.len(), bytes);
+                for &offset in offsets {
+                    encode_u32(offset, bytes);
+                }
+            }
+            Instruction::Payload => bytes.push(PAYLOAD),
+            Instruction::Record(ref names) => {
+                bytes.push(RECORD);
+                encode_u32(names.len(), bytes);
+                for name in names {
+                    encode_str(name, bytes);
+                }
+            }
+            Instruction::Field(ref name) => {
+                bytes.push(FIELD);
+                encode_str(name, bytes);
+            }
+            Instruction::Ref => bytes.push(REF),
+            Instruction::Deref => bytes.push(DEREF),
+            Instruction::Assign => bytes.push(ASSIGN),
+            Instruction::Jump(n) => {
+                bytes.push(JUMP);
+                encode_u32(n, bytes);
+            }
+            Instruction::JumpIfFalse(n) => {
+                bytes.push(JUMP_IF_FALSE);
+                encode_u32(n, bytes);
+            }
+            Instruction::Var(name) => {
+                bytes.push(VAR);
+                bytes.extend_from_slice(&(name as u64).to_le_bytes());
+            }
+            Instruction::Slot(n) => {
+                bytes.push(SLOT);
+                encode_u32(n, bytes);
+            }
+            Instruction::Closure { name, arg, len, ref captures } => {
+                bytes.push(CLOSURE);
+                bytes.extend_from_slice(&(name as u64).to_le_bytes());
+                bytes.extend_from_slice(&(arg as u64).to_le_bytes());
+                encode_u32(len, bytes);
+                bytes.push(captures.is_some() as u8);
+                if let Some(ref captures) = *captures {
+                    encode_u32(captures.len(), bytes);
+                    for &name in captures {
+                        bytes.extend_from_slice(&(name as u64).to_le_bytes());
+                    }
+                }
+            }
+            Instruction::Fix(ref names) => {
+                bytes.push(FIX);
+                encode_u32(names.len(), bytes);
+                for &name in names {
+                    bytes.extend_from_slice(&(name as u64).to_le_bytes());
+                }
+            }
+            Instruction::Call => bytes.push(CALL),
+            Instruction::TailCall => bytes.push(TAIL_CALL),
+            Instruction::PopEnv => bytes.push(POP_ENV),
+        }
+    }
+}
+
+/// Decodes a program written by `encode` in the current format.
+pub fn decode(bytes: &[u8]) -> Result<(Header, Frame), DecodeError> {
+    decode_with(bytes, |header, _| {
+        Err(DecodeError {
+            message: format!("Bytecode format version {} is not supported, expected version {}",
+                             header.format,
+                             FORMAT_VERSION),
+        })
+    })
+}
+
+/// Like `decode`, but passes the frame of a program in another format to
+/// `migrate`, which should convert it to the current one.
+pub fn decode_with<F>(bytes: &[u8], migrate: F) -> Result<(Header, Frame), DecodeError>
+    where F: FnOnce(&Header, &[u8]) -> Result<Vec<u8>, DecodeError>
+{
+    let mut decoder = Decoder { bytes: bytes };
+    if try!(decoder.take(MAGIC.len()).map_err(|_| not_bytecode())) != MAGIC {
+        return Err(not_bytecode());
+    }
+    let header = try!(decoder.header());
+    let migrated;
+    if header.format != FORMAT_VERSION {
+        migrated = try!(migrate(&header, decoder.bytes));
+        decoder.bytes = &migrated;
+    }
+    let frame = try!(decoder.frame());
+    if !decoder.bytes.is_empty() {
+        return Err(decode_error("Trailing bytes after the program"));
+    }
+    Ok((header, frame))
+}
+
+fn not_bytecode() -> DecodeError {
+    decode_error("Not a bytecode file")
+}
+
+struct Decoder<'b> {
+    bytes: &'b [u8],
+}
+
+impl<'b> Decoder<'b> {
+    fn take(&mut self, n: usize) -> Result<&'b [u8], DecodeError> {
+        if self.bytes.len() < n {
+            return Err(decode_error("Unexpected end of the program"));
+        }
+        let (taken, rest) = self.bytes.split_at(n);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn byte(&mut self) -> Result<u8, DecodeError> {
+        self.take(1).map(|bytes| bytes[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, DecodeError> {
+        let mut buf = [0; 2];
+        buf.copy_from_slice(try!(self.take(2)));
+        Ok(u16::from_le_bytes(buf))
+    }
+
+    fn u32(&mut self) -> Result<u32, DecodeError> {
+        let mut buf = [0; 4];
+        buf.copy_from_slice(try!(self.take(4)));
+        Ok(u32::from_le_bytes(buf))
+    }
+
+    fn u64(&mut self) -> Result<u64, DecodeError> {
+        let mut buf = [0; 8];
+        buf.copy_from_slice(try!(self.take(8)));
+        Ok(u64::from_le_bytes(buf))
+    }
+
+    fn bool(&mut self) -> Result<bool, DecodeError> {
+        match try!(self.byte()) {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(decode_error("Invalid bool")),
+        }
+    }
+
+    fn string(&mut self, what: &str) -> Result<String, DecodeError> {
+        let len = try!(self.u16()) as usize;
+        String::from_utf8(try!(self.take(len)).to_vec())
+            .map_err(|_| DecodeError { message: format!("Invalid {}", what) })
+    }
+
+    fn header(&mut self) -> Result<Header, DecodeError> {
+        let format = try!(self.u16());
+        let compiler = try!(self.string("compiler name"));
+        Ok(Header {
+            format: format,
+            compiler: compiler,
+        })
+    }
+
+    fn frame(&mut self) -> Result<Frame, DecodeError> {
+        let len = try!(self.u32());
+        let mut frame = Vec::new();
+        for _ in 0..len {
+            frame.push(try!(self.instruction()));
+        }
+        Ok(frame)
+    }
+
+    fn instruction(&mut self) -> Result<Instruction, DecodeError> {
+        let inst = match try!(self.byte()) {
+            ADD => Instruction::ArithInstruction(ArithInstruction::Add),
+            SUB => Instruction::ArithInstruction(ArithInstruction::Sub),
+            MUL => Instruction::ArithInstruction(ArithInstruction::Mul),
+            DIV => Instruction::ArithInstruction(ArithInstruction::Div),
+            MOD => Instruction::ArithInstruction(ArithInstruction::Mod),
+            LT => Instruction::CmpInstruction(CmpInstruction::Lt),
+            EQ => Instruction::CmpInstruction(CmpInstruction::Eq),
+            GT => Instruction::CmpInstruction(CmpInstruction::Gt),
+            LE => Instruction::CmpInstruction(CmpInstruction::Le),
+            GE => Instruction::CmpInstruction(CmpInstruction::Ge),
+            NE => Instruction::CmpInstruction(CmpInstruction::Ne),
+            PUSH_INT => Instruction::PushInt(try!(self.u64()) as i64),
+            PUSH_FLOAT => Instruction::PushFloat(f64::from_bits(try!(self.u64()))),
+            PUSH_BOOL => Instruction::PushBool(try!(self.bool())),
+            JUMP => Instruction::Jump(try!(self.u32()) as usize),
+            JUMP_IF_FALSE => Instruction::JumpIfFalse(try!(self.u32()) as usize),
+            VAR => Instruction::Var(try!(self.u64()) as usize),
+            SLOT => Instruction::Slot(try!(self.u32()) as usize),
+            CLOSURE => {
+                let name = try!(self.u64()) as usize;
+                let arg = try!(self.u64()) as usize;
+                let len = try!(self.u32()) as usize;
+                let captures = if try!(self.bool()) {
+                    let count = try!(self.u32());
+                    let mut captures = Vec::new();
+                    for _ in 0..count {
+                        captures.push(try!(self.u64()) as usize);
+                    }
+                    Some(captures)
+                } else {
+                    None
+                };
+                Instruction::Closure {
+                    name: name,
+                    arg: arg,
+                    len: len,
+                    captures: captures,
+                }
+            }
+            FIX => {
+                let len = try!(self.u32());
+                let mut names = Vec::new();
+                for _ in 0..len {
+                    names.push(try!(self.u64()) as usize);
+                }
+                Instruction::Fix(names)
+            }
+            CALL => Instruction::Call,
+            TAIL_CALL => Instruction::TailCall,
+            POP_ENV => Instruction::PopEnv,
+            PUSH_STR => Instruction::PushStr(try!(self.string("string literal"))),
+            PUSH_NIL => Instruction::PushNil,
+            PUSH_UNIT => Instruction::PushUnit,
+            POP => Instruction::Pop,
+            PRINT => Instruction::Print,
+            CONCAT => Instruction::Concat,
+            INT_OF_FLOAT => Instruction::IntOfFloat,
+            FLOAT_OF_INT => Instruction::FloatOfInt,
+            CONS => Instruction::ListInstruction(ListInstruction::Cons),
+            HEAD => Instruction::ListInstruction(ListInstruction::Head),
+            TAIL => Instruction::ListInstruction(ListInstruction::Tail),
+            IS_EMPTY => Instruction::ListInstruction(ListInstruction::IsEmpty),
+            CONSTRUCT => {
+                let tag = try!(self.u64()) as usize;
+                let name = try!(self.string("constructor name"));
+                Instruction::Construct {
+                    tag: tag,
+                    name: name,
+                    arg: try!(self.bool()),
+                }
+            }
+            CASE => {
+                let len = try!(self.u32());
+                let mut offsets = Vec::new();
+                for _ in 0..len {
+                    offsets.push(try!(self.u32()) as usize);
+                }
+                Instruction::Case(offsets)
+            }
+            PAYLOAD => Instruction::Payload,
+            RECORD => {
+                let len = try!(self.u32());
+                let mut names = Vec::new();
+                for _ in 0..len {
+                    names.push(try!(self.string("field name")));
+                }
+                Instruction::Record(names)
+            }
+            FIELD => Instruction::Field(try!(self.string("field name"))),
+            REF => Instruction::Ref,
+            DEREF => Instruction::Deref,
+            ASSIGN => Instruction::Assign,
+            opcode => return Err(DecodeError { message: format!("Unknown opcode {}", opcode) }),
+        };
+        Ok(inst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        let frame = vec![
+            Instruction::Closure {
+                name: 0,
+                arg: 2,
+                len: 7,
+                captures: None,
+            },
+            Instruction::Var(2),
+            Instruction::PushInt(-1),
+            Instruction::CmpInstruction(CmpInstruction::Lt),
+            Instruction::CmpInstruction(CmpInstruction::Ge),
+            Instruction::CmpInstruction(CmpInstruction::Ne),
+            Instruction::JumpIfFalse(2),
+            Instruction::PushBool(true),
+            Instruction::Jump(1),
+            Instruction::PushBool(false),
+            Instruction::PopEnv,
+            Instruction::PushInt(92),
+            Instruction::Call,
+            Instruction::Var(0),
+            Instruction::Slot(1),
+            Instruction::PushInt(62),
+            Instruction::ArithInstruction(ArithInstruction::Mod),
+            Instruction::TailCall,
+            Instruction::Closure {
+                name: 1,
+                arg: 3,
+                len: 0,
+                captures: Some(vec![0, 4]),
+            },
+            Instruction::PushNil,
+            Instruction::ListInstruction(ListInstruction::Cons),
+            Instruction::ListInstruction(ListInstruction::Tail),
+            Instruction::ListInstruction(ListInstruction::IsEmpty),
+            Instruction::Construct {
+                tag: 1,
+                name: "Some".to_owned(),
+                arg: true,
+            },
+            Instruction::Case(vec![0, 2]),
+            Instruction::PushInt(0),
+            Instruction::Jump(1),
+            Instruction::Payload,
+            Instruction::PushStr("hello, ".to_owned()),
+            Instruction::PushStr("wörld".to_owned()),
+            Instruction::Concat,
+            Instruction::Ref,
+            Instruction::Deref,
+            Instruction::Assign,
+            Instruction::PushUnit,
+            Instruction::Pop,
+            Instruction::Print,
+            Instruction::PushFloat(-0.5),
+            Instruction::IntOfFloat,
+            Instruction::FloatOfInt,
+            Instruction::Record(vec!["x".to_owned(), "y".to_owned()]),
+            Instruction::Field("y".to_owned()),
+            Instruction::Fix(vec![3, 5]),
+        ];
+        let bytes = encode(&frame, "test 1.0");
+        let header = Header {
+            format: FORMAT_VERSION,
+            compiler: "test 1.0".to_owned(),
+        };
+        assert_eq!(decode(&bytes), Ok((header, frame)));
+        assert_eq!(decode(&bytes[..bytes.len() - 1]).unwrap_err().message,
+                   "Unexpected end of the program");
+        assert_eq!(decode(b"SECD\x0f\x00\x00\x00\x01\x00\x00\x00\x63").unwrap_err().message,
+                   "Unknown opcode 99");
+        assert_eq!(decode(b"\x01\x00\x00\x00\x0c").unwrap_err().message, "Not a bytecode file");
+    }
+
+    #[test]
+    fn test_versions() {
+        // A hypothetical version 0 without the instruction count of the top-level frame.
+        let old = b"SECD\x00\x00\x03\x00old\x0c";
+        assert_eq!(decode(old).unwrap_err().message,
+                   "Bytecode format version 0 is not supported, expected version 15");
+        let (header, frame) = decode_with(old, |header, body| {
+                                  assert_eq!(header.format, 0);
+                                  let mut migrated = vec![1, 0, 0, 0];
+                                  migrated.extend_from_slice(body);
+                                  Ok(migrated)
+                              })
+                                  .unwrap();
+        assert_eq!((header.format, header.compiler.as_ref(), frame),
+                   (0, "old", vec![Instruction::Call]));
+    }
+}
@@ -0,0 +1,735 @@
+//! Compiles the functions a machine built `with_jit` calls often to native
+//! code with Cranelift.
+//!
+//! Only functions of ints which compute an int with arithmetic, comparisons,
+//! conditionals and calls to themselves are compiled, like `fib` or `tak`:
+//! their values fit in registers and their bodies need nothing from the heap.
+//! A curried function gets its arguments one by one from the closures it
+//! returns, so it is its innermost body which gets hot; that is compiled to
+//! take all the arguments at once, and its calls to the function become
+//! native calls once they have all of theirs. Everything else runs on the
+//! machine as before.
+//!
+//! Native code counts the steps the machine would have taken, so `steps` and
+//! fuel work as without it. It gives up when it would run out of fuel or
+//! recurse deeper than the native stack can take, and the machine makes the
+//! call itself instead; the functions have no effects, so nothing happens
+//! twice.
+
+use std::collections::HashMap;
+use std::{fmt, mem};
+
+use cranelift_codegen::ir::condcodes::IntCC;
+use cranelift_codegen::ir::{self, types, AbiParam, Block, FuncRef, InstBuilder, MemFlags, Signature, Type};
+use cranelift_codegen::settings::{self, Configurable};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{FuncId, Module};
+
+use program::{ArithInstruction, CmpInstruction, Instruction, Name};
+use value::{Closure, Value};
+use {IntArithmetic, RuntimeErrorKind};
+
+/// Calls of a function before it is compiled.
+const THRESHOLD: u32 = 64;
+/// Native calls nested in each other before native code gives up, so that it
+/// stays well within the stack of the thread running the machine.
+const MAX_DEPTH: i64 = 4096;
+/// The most arguments a compiled function takes.
+const MAX_ARITY: usize = 8;
+
+/// What native code has to say besides its result, in `Context::status`.
+const OK: i64 = 0;
+const OVERFLOW: i64 = 1;
+const DIVISION_BY_ZERO: i64 = 2;
+const TOO_DEEP: i64 = 3;
+const OUT_OF_FUEL: i64 = 4;
+
+/// Shared by the native calls started by one call of the machine.
+#[repr(C)]
+struct Context {
+    status: i64,
+    steps: i64,
+    /// The steps native code may take before it gives up.
+    limit: i64,
+}
+
+const STATUS: i32 = 0;
+const STEPS: i32 = 8;
+const LIMIT: i32 = 16;
+
+/// Takes the context and the arguments, in an array.
+type Entry = unsafe extern "C" fn(*mut Context, *const i64) -> i64;
+
+pub struct Jit {
+    /// Taken to free the code when the `Jit` is dropped.
+    module: Option<JITModule>,
+    /// By the start and the length of the body of each function called.
+    functions: HashMap<(usize, usize), Function>,
+}
+
+enum Function {
+    /// Called this many times so far.
+    Cold(u32),
+    Compiled(Compiled),
+    /// Can't be compiled, or recursed too deep for native code.
+    Interpreted,
+}
+
+struct Compiled {
+    entry: Entry,
+    /// The variables the arguments but the last are in, in the environment
+    /// of the closure called, if the body uses them.
+    args: Vec<Option<(usize, Name)>>,
+    /// The variable the function is in, and the start of its code.
+    root: (usize, Name, *const Instruction),
+}
+
+/// What a call made by the machine came to.
+pub enum Outcome {
+    /// The machine has to make the call itself.
+    Interpret,
+    /// The result, and the steps taken.
+    Finished(i64, u64),
+    Failed(RuntimeErrorKind, u64),
+}
+
+impl Jit {
+    /// `None` if Cranelift doesn't know the host.
+    pub fn new() -> Option<Jit> {
+        let mut flags = settings::builder();
+        flags.set("opt_level", "speed").unwrap();
+        flags.set("use_colocated_libcalls", "false").unwrap();
+        flags.set("is_pic", "true").unwrap();
+        let isa = match cranelift_native::builder() {
+            Ok(builder) => builder.finish(settings::Flags::new(flags)).ok(),
+            Err(_) => None,
+        };
+        isa.map(|isa| {
+            let builder = JITBuilder::with_isa(isa, cranelift_module::default_libcall_names());
+            Jit {
+                module: Some(JITModule::new(builder)),
+                functions: HashMap::new(),
+            }
+        })
+    }
+
+    /// Forgets the functions of `program`, which is about to be freed, so that
+    /// code put in its place later isn't taken for them.
+    pub fn forget(&mut self, program: &[Instruction]) {
+        let begin = program.as_ptr() as usize;
+        let end = begin + mem::size_of_val(program);
+        self.functions.retain(|&(start, _), _| start < begin || start >= end);
+    }
+
+    /// Calls `closure` with `arg` natively, compiling it first if it got hot.
+    /// `env` is the environment of the closure, and `fuel` the steps the
+    /// machine may still take.
+    pub fn call<'p>(&mut self,
+                    closure: Closure<'p>,
+                    env: &[(Name, Value<'p>)],
+                    arg: Value<'p>,
+                    arithmetic: IntArithmetic,
+                    fuel: u64)
+                    -> Outcome {
+        let key = (closure.frame.as_ptr() as usize, closure.frame.len());
+        let hot = match *self.functions.entry(key).or_insert(Function::Cold(0)) {
+            Function::Cold(ref mut calls) => {
+                *calls += 1;
+                *calls >= THRESHOLD
+            }
+            Function::Compiled(_) => false,
+            Function::Interpreted => return Outcome::Interpret,
+        };
+        if hot {
+            let function = match self.compile(closure, env, arithmetic) {
+                Ok(compiled) => Function::Compiled(compiled),
+                Err(Unsupported) => Function::Interpreted,
+            };
+            self.functions.insert(key, function);
+        }
+        let (status, result, steps) = match self.functions[&key] {
+            Function::Compiled(ref compiled) => {
+                match run(compiled, env, arg, fuel) {
+                    Some(outcome) => outcome,
+                    None => return Outcome::Interpret,
+                }
+            }
+            _ => return Outcome::Interpret,
+        };
+        // Native code only looks at the fuel when a call starts, and the
+        // machine stops on the very step the fuel runs out.
+        if steps > fuel {
+            return Outcome::Interpret;
+        }
+        match status {
+            OK => Outcome::Finished(result, steps),
+            OVERFLOW => Outcome::Failed(RuntimeErrorKind::Overflow, steps),
+            DIVISION_BY_ZERO => Outcome::Failed(RuntimeErrorKind::DivisionByZero, steps),
+            TOO_DEEP => {
+                self.functions.insert(key, Function::Interpreted);
+                Outcome::Interpret
+            }
+            _ => Outcome::Interpret,
+        }
+    }
+
+    /// The number of functions in native code.
+    pub fn compiled(&self) -> usize {
+        self.functions
+            .values()
+            .filter(|function| {
+                match **function {
+                    Function::Compiled(_) => true,
+                    _ => false,
+                }
+            })
+            .count()
+    }
+
+    fn compile(&mut self,
+               closure: Closure,
+               env: &[(Name, Value)],
+               arithmetic: IntArithmetic)
+               -> Result<Compiled, Unsupported> {
+        let layout = try!(Layout::new(closure, env));
+        let module = self.module.as_mut().unwrap();
+        let pointer = module.target_config().pointer_type();
+
+        let mut body_signature = module.make_signature();
+        body_signature.params.push(AbiParam::new(pointer));
+        body_signature.params.push(AbiParam::new(types::I64));
+        for _ in 0..layout.arity {
+            body_signature.params.push(AbiParam::new(types::I64));
+        }
+        body_signature.returns.push(AbiParam::new(types::I64));
+        let body = try!(module.declare_anonymous_function(&body_signature).map_err(|_| Unsupported));
+        try!(define(module, body, body_signature, |builder, module| {
+            let callee = module.declare_func_in_func(body, builder.func);
+            let mut translation = Translation {
+                builder: builder,
+                layout: &layout,
+                names: env.iter().map(|&(name, _)| name).chain(Some(closure.arg)).collect(),
+                arithmetic: arithmetic,
+                callee: callee,
+                context: None,
+                steps: 0,
+            };
+            translation.function(closure.frame)
+        }));
+
+        let mut entry_signature = module.make_signature();
+        entry_signature.params.push(AbiParam::new(pointer));
+        entry_signature.params.push(AbiParam::new(pointer));
+        entry_signature.returns.push(AbiParam::new(types::I64));
+        let entry = try!(module.declare_anonymous_function(&entry_signature).map_err(|_| Unsupported));
+        try!(define(module, entry, entry_signature, |builder, module| {
+            let callee = module.declare_func_in_func(body, builder.func);
+            let block = builder.create_block();
+            builder.append_block_params_for_function_params(block);
+            builder.switch_to_block(block);
+            let (context, args) = (builder.block_params(block)[0], builder.block_params(block)[1]);
+            let mut call_args = vec![context, builder.ins().iconst(types::I64, 0)];
+            for i in 0..layout.arity {
+                call_args.push(builder.ins().load(types::I64, MemFlags::trusted(), args, 8 * i as i32));
+            }
+            let call = builder.ins().call(callee, &call_args);
+            let result = builder.inst_results(call)[0];
+            builder.ins().return_(&[result]);
+            Ok(())
+        }));
+        try!(module.finalize_definitions().map_err(|_| Unsupported));
+        let code = module.get_finalized_function(entry);
+        Ok(Compiled {
+            entry: unsafe { mem::transmute::<*const u8, Entry>(code) },
+            args: layout.args,
+            root: layout.root,
+        })
+    }
+}
+
+impl fmt::Debug for Jit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<jit, {} functions compiled>", self.compiled())
+    }
+}
+
+impl Drop for Jit {
+    fn drop(&mut self) {
+        // Nothing can call the code any more.
+        if let Some(module) = self.module.take() {
+            unsafe { module.free_memory() }
+        }
+    }
+}
+
+/// Calls `compiled` with the arguments from `env` and `arg`, if they are
+/// what it was compiled for. Returns the status native code ended with, its
+/// result and the steps it took.
+fn run(compiled: &Compiled, env: &[(Name, Value)], arg: Value, fuel: u64) -> Option<(i64, i64, u64)> {
+    let (index, name, frame) = compiled.root;
+    match env.get(index) {
+        Some(&(n, Value::Closure(root))) if n == name && root.frame.as_ptr() == frame => {}
+        _ => return None,
+    }
+    let mut args = [0; MAX_ARITY];
+    for (i, variable) in compiled.args.iter().enumerate() {
+        args[i] = match *variable {
+            Some((index, name)) => {
+                match env.get(index) {
+                    Some(&(n, Value::Int(value))) if n == name => value,
+                    _ => return None,
+                }
+            }
+            None => 0,
+        };
+    }
+    args[compiled.args.len()] = match arg {
+        Value::Int(value) => value,
+        _ => return None,
+    };
+    let mut context = Context {
+        status: OK,
+        steps: 0,
+        limit: fuel.min(i64::MAX as u64) as i64,
+    };
+    let result = unsafe { (compiled.entry)(&mut context, args.as_ptr()) };
+    Some((context.status, result, context.steps as u64))
+}
+
+/// Builds the function `id` with `signature` in `module`, with the
+/// instructions `f` adds.
+fn define<F>(module: &mut JITModule, id: FuncId, signature: Signature, f: F) -> Result<(), Unsupported>
+    where F: FnOnce(&mut FunctionBuilder, &mut JITModule) -> Result<(), Unsupported>
+{
+    let mut context = module.make_context();
+    context.func.signature = signature;
+    let mut builder_context = FunctionBuilderContext::new();
+    {
+        let mut builder = FunctionBuilder::new(&mut context.func, &mut builder_context);
+        try!(f(&mut builder, module));
+        builder.seal_all_blocks();
+        builder.finalize();
+    }
+    let result = module.define_function(id, &mut context).map_err(|_| Unsupported);
+    module.clear_context(&mut context);
+    result
+}
+
+/// The function can't be compiled.
+struct Unsupported;
+
+/// Where the variables of the body of a function are in its environment.
+struct Layout {
+    arity: usize,
+    args: Vec<Option<(usize, Name)>>,
+    root: (usize, Name, *const Instruction),
+}
+
+impl Layout {
+    /// Finds the function `closure` is the innermost body of, in `env`,
+    /// and where the arguments it was applied to are. The closure itself is
+    /// such a function of one argument, but the body may call the one which
+    /// takes all of them.
+    fn new(closure: Closure, env: &[(Name, Value)]) -> Result<Layout, Unsupported> {
+        let root = env.iter().enumerate().filter_map(|(index, &(name, value))| {
+            match value {
+                Value::Closure(root) => {
+                    let (args, body) = curried(root);
+                    if body.as_ptr() == closure.frame.as_ptr() && body.len() == closure.frame.len() {
+                        Some((index, name, root.frame.as_ptr(), args))
+                    } else {
+                        None
+                    }
+                }
+                _ => None,
+            }
+        }).max_by_key(|&(_, _, _, ref args)| args.len());
+        let (index, name, frame, args) = match root {
+            Some(root) => root,
+            None => return Err(Unsupported),
+        };
+        if args.len() > MAX_ARITY || args.last() != Some(&closure.arg) {
+            return Err(Unsupported);
+        }
+        let variables = args[..args.len() - 1]
+                            .iter()
+                            .map(|&arg| {
+                                env.iter().rposition(|&(name, _)| name == arg).map(|index| (index, arg))
+                            })
+                            .collect();
+        Ok(Layout {
+            arity: args.len(),
+            args: variables,
+            root: (index, name, frame),
+        })
+    }
+}
+
+/// The names of the arguments a function takes one after another, by
+/// returning closures, and the body which has them all.
+fn curried<'p>(closure: Closure<'p>) -> (Vec<Name>, &'p [Instruction]) {
+    let mut args = vec![closure.arg];
+    let mut body = closure.frame;
+    while let Some(&Instruction::Closure { arg, len, .. }) = body.first() {
+        if len + 2 != body.len() || body.last() != Some(&Instruction::PopEnv) {
+            break;
+        }
+        args.push(arg);
+        body = &body[1..1 + len];
+    }
+    (args, body)
+}
+
+/// What is on the stack of the machine while the body runs.
+#[derive(Clone)]
+enum Operand {
+    Int(ir::Value),
+    Bool(ir::Value),
+    /// The function compiled, applied to some of its arguments.
+    Fun(Vec<ir::Value>),
+}
+
+impl Operand {
+    fn type_(&self) -> Result<Type, Unsupported> {
+        match *self {
+            Operand::Int(_) => Ok(types::I64),
+            Operand::Bool(_) => Ok(types::I8),
+            Operand::Fun(_) => Err(Unsupported),
+        }
+    }
+
+    fn value(&self) -> Result<ir::Value, Unsupported> {
+        match *self {
+            Operand::Int(value) | Operand::Bool(value) => Ok(value),
+            Operand::Fun(_) => Err(Unsupported),
+        }
+    }
+
+    fn int(self) -> Result<ir::Value, Unsupported> {
+        match self {
+            Operand::Int(value) => Ok(value),
+            _ => Err(Unsupported),
+        }
+    }
+}
+
+struct Translation<'a, 'b: 'a, 'l> {
+    builder: &'a mut FunctionBuilder<'b>,
+    layout: &'l Layout,
+    /// The names of the variables of the body: the environment of the
+    /// closure, then its argument.
+    names: Vec<Name>,
+    arithmetic: IntArithmetic,
+    /// The function itself.
+    callee: FuncRef,
+    context: Option<ir::Value>,
+    /// Instructions executed since the count in the context was last brought up to date.
+    steps: i64,
+}
+
+impl<'a, 'b, 'l> Translation<'a, 'b, 'l> {
+    fn function(&mut self, code: &[Instruction]) -> Result<(), Unsupported> {
+        let entry = self.builder.create_block();
+        self.builder.append_block_params_for_function_params(entry);
+        self.builder.switch_to_block(entry);
+        let params = self.builder.block_params(entry).to_vec();
+        self.context = Some(params[0]);
+
+        // Where the function starts over after a tail call to itself, and
+        // gives up if the native stack or the fuel has run out.
+        let header = self.builder.create_block();
+        for _ in 0..self.layout.arity {
+            self.builder.append_block_param(header, types::I64);
+        }
+        self.builder.ins().jump(header, &params[2..]);
+        self.builder.switch_to_block(header);
+        let args = self.builder.block_params(header).to_vec();
+        let too_deep = self.builder.ins().icmp_imm(IntCC::SignedGreaterThan, params[1], MAX_DEPTH);
+        self.fail_if(too_deep, TOO_DEEP);
+        let steps = self.load(STEPS);
+        let limit = self.load(LIMIT);
+        let out_of_fuel = self.builder.ins().icmp(IntCC::SignedGreaterThan, steps, limit);
+        self.fail_if(out_of_fuel, OUT_OF_FUEL);
+
+        // The blocks starting at the targets of jumps, with the types of the
+        // values on the stack there.
+        let mut targets: HashMap<usize, (Block, Vec<Type>)> = HashMap::new();
+        let mut stack = Vec::new();
+        let mut reachable = true;
+        for (i, inst) in code.iter().enumerate() {
+            if let Some(&(block, ref types)) = targets.get(&i) {
+                if reachable {
+                    try!(self.jump(block, types, &stack));
+                }
+                self.builder.switch_to_block(block);
+                stack = self.builder
+                            .block_params(block)
+                            .iter()
+                            .zip(types)
+                            .map(|(&value, &type_)| {
+                                if type_ == types::I8 { Operand::Bool(value) } else { Operand::Int(value) }
+                            })
+                            .collect();
+                reachable = true;
+            }
+            if !reachable {
+                continue;
+            }
+            self.steps += 1;
+            match *inst {
+                Instruction::PushInt(i) => stack.push(Operand::Int(self.builder.ins().iconst(types::I64, i))),
+                Instruction::PushBool(b) => {
+                    stack.push(Operand::Bool(self.builder.ins().iconst(types::I8, b as i64)))
+                }
+                Instruction::Var(name) => {
+                    let index = try!(self.names.iter().rposition(|&n| n == name).ok_or(Unsupported));
+                    stack.push(try!(self.variable(index, &args)));
+                }
+                Instruction::Slot(slot) => {
+                    if slot >= self.names.len() {
+                        return Err(Unsupported);
+                    }
+                    let index = self.names.len() - 1 - slot;
+                    stack.push(try!(self.variable(index, &args)));
+                }
+                Instruction::ArithInstruction(ref op) => {
+                    let rhs = try!(try!(pop(&mut stack)).int());
+                    let lhs = try!(try!(pop(&mut stack)).int());
+                    stack.push(Operand::Int(self.arith(op, lhs, rhs)));
+                }
+                Instruction::CmpInstruction(op) => {
+                    let rhs = try!(try!(pop(&mut stack)).int());
+                    let lhs = try!(try!(pop(&mut stack)).int());
+                    let cond = match op {
+                        CmpInstruction::Lt => IntCC::SignedLessThan,
+                        CmpInstruction::Le => IntCC::SignedLessThanOrEqual,
+                        CmpInstruction::Eq => IntCC::Equal,
+                        CmpInstruction::Ne => IntCC::NotEqual,
+                        CmpInstruction::Gt => IntCC::SignedGreaterThan,
+                        CmpInstruction::Ge => IntCC::SignedGreaterThanOrEqual,
+                    };
+                    stack.push(Operand::Bool(self.builder.ins().icmp(cond, lhs, rhs)));
+                }
+                Instruction::Jump(n) => {
+                    let (block, types) = try!(self.target(&mut targets, code, i + 1 + n, &stack));
+                    try!(self.jump(block, &types, &stack));
+                    reachable = false;
+                }
+                Instruction::JumpIfFalse(n) => {
+                    let cond = match try!(pop(&mut stack)) {
+                        Operand::Bool(cond) => cond,
+                        _ => return Err(Unsupported),
+                    };
+                    let (block, _) = try!(self.target(&mut targets, code, i + 1 + n, &stack));
+                    let args = try!(stack.iter().map(Operand::value).collect::<Result<Vec<_>, _>>());
+                    self.flush();
+                    let next = self.builder.create_block();
+                    self.builder.ins().brif(cond, next, &[], block, &args);
+                    self.builder.switch_to_block(next);
+                }
+                Instruction::Call | Instruction::TailCall => {
+                    let arg = try!(try!(pop(&mut stack)).int());
+                    let mut applied = match try!(pop(&mut stack)) {
+                        Operand::Fun(applied) => applied,
+                        _ => return Err(Unsupported),
+                    };
+                    applied.push(arg);
+                    if applied.len() < self.layout.arity {
+                        if let Instruction::TailCall = *inst {
+                            return Err(Unsupported);
+                        }
+                        // The machine would make a closure for the rest.
+                        self.steps += 2;
+                        stack.push(Operand::Fun(applied));
+                        continue;
+                    }
+                    self.flush();
+                    if let Instruction::TailCall = *inst {
+                        if !stack.is_empty() {
+                            return Err(Unsupported);
+                        }
+                        self.builder.ins().jump(header, &applied);
+                        reachable = false;
+                        continue;
+                    }
+                    let depth = self.builder.ins().iadd_imm(params[1], 1);
+                    let mut call_args = vec![params[0], depth];
+                    call_args.extend(applied);
+                    let call = self.builder.ins().call(self.callee, &call_args);
+                    let result = self.builder.inst_results(call)[0];
+                    let status = self.load(STATUS);
+                    self.exit_if(status);
+                    stack.push(Operand::Int(result));
+                }
+                Instruction::PopEnv => {
+                    if stack.len() != 1 {
+                        return Err(Unsupported);
+                    }
+                    let result = try!(try!(pop(&mut stack)).int());
+                    self.flush();
+                    self.builder.ins().return_(&[result]);
+                    reachable = false;
+                }
+                _ => return Err(Unsupported),
+            }
+        }
+        if reachable {
+            return Err(Unsupported);
+        }
+        Ok(())
+    }
+
+    /// The value of the variable at `index` in `names`, given the
+    /// arguments of the function.
+    fn variable(&self, index: usize, args: &[ir::Value]) -> Result<Operand, Unsupported> {
+        let layout = self.layout;
+        if index + 1 == self.names.len() {
+            return Ok(Operand::Int(args[layout.arity - 1]));
+        }
+        // The closure itself, which has all the arguments but the last.
+        if index + 2 == self.names.len() {
+            if layout.args.iter().any(Option::is_none) {
+                return Err(Unsupported);
+            }
+            return Ok(Operand::Fun(args[..layout.arity - 1].to_vec()));
+        }
+        if index == layout.root.0 {
+            return Ok(Operand::Fun(vec![]));
+        }
+        match layout.args.iter().position(|arg| arg.map_or(false, |(i, _)| i == index)) {
+            Some(arg) => Ok(Operand::Int(args[arg])),
+            None => Err(Unsupported),
+        }
+    }
+
+    /// The block at `target`, which must be the same for every jump there.
+    fn target(&mut self,
+              targets: &mut HashMap<usize, (Block, Vec<Type>)>,
+              code: &[Instruction],
+              target: usize,
+              stack: &[Operand])
+              -> Result<(Block, Vec<Type>), Unsupported> {
+        if target >= code.len() {
+            return Err(Unsupported);
+        }
+        let types = try!(stack.iter().map(Operand::type_).collect::<Result<Vec<_>, _>>());
+        if let Some(&(block, ref known)) = targets.get(&target) {
+            return if *known == types { Ok((block, types)) } else { Err(Unsupported) };
+        }
+        let block = self.builder.create_block();
+        for &type_ in &types {
+            self.builder.append_block_param(block, type_);
+        }
+        targets.insert(target, (block, types.clone()));
+        Ok((block, types))
+    }
+
+    fn jump(&mut self, block: Block, types: &[Type], stack: &[Operand]) -> Result<(), Unsupported> {
+        let actual = try!(stack.iter().map(Operand::type_).collect::<Result<Vec<_>, _>>());
+        if actual != types {
+            return Err(Unsupported);
+        }
+        let args = try!(stack.iter().map(Operand::value).collect::<Result<Vec<_>, _>>());
+        self.flush();
+        self.builder.ins().jump(block, &args);
+        Ok(())
+    }
+
+    fn arith(&mut self,
+             op: &ArithInstruction,
+             lhs: ir::Value,
+             rhs: ir::Value)
+             -> ir::Value {
+        let checked = self.arithmetic == IntArithmetic::Checked;
+        let ins = self.builder.ins();
+        match *op {
+            ArithInstruction::Add | ArithInstruction::Sub | ArithInstruction::Mul if checked => {
+                let (result, overflow) = match *op {
+                    ArithInstruction::Add => ins.sadd_overflow(lhs, rhs),
+                    ArithInstruction::Sub => ins.ssub_overflow(lhs, rhs),
+                    _ => ins.smul_overflow(lhs, rhs),
+                };
+                self.fail_if(overflow, OVERFLOW);
+                result
+            }
+            ArithInstruction::Add => ins.iadd(lhs, rhs),
+            ArithInstruction::Sub => ins.isub(lhs, rhs),
+            ArithInstruction::Mul => ins.imul(lhs, rhs),
+            ArithInstruction::Div | ArithInstruction::Mod => {
+                let zero = ins.icmp_imm(IntCC::Equal, rhs, 0);
+                self.fail_if(zero, DIVISION_BY_ZERO);
+                // `i64::MIN / -1` overflows, and the processor traps.
+                let min = self.builder.ins().icmp_imm(IntCC::Equal, lhs, i64::MIN);
+                let minus_one = self.builder.ins().icmp_imm(IntCC::Equal, rhs, -1);
+                let overflow = self.builder.ins().band(min, minus_one);
+                let rhs = if checked {
+                    self.fail_if(overflow, OVERFLOW);
+                    rhs
+                } else {
+                    let one = self.builder.ins().iconst(types::I64, 1);
+                    self.builder.ins().select(overflow, one, rhs)
+                };
+                if *op == ArithInstruction::Div {
+                    self.builder.ins().sdiv(lhs, rhs)
+                } else {
+                    self.builder.ins().srem(lhs, rhs)
+                }
+            }
+        }
+    }
+
+    /// Returns with `status` if `cond` holds.
+    fn fail_if(&mut self, cond: ir::Value, status: i64) {
+        let fail = self.builder.create_block();
+        let next = self.builder.create_block();
+        self.builder.ins().brif(cond, fail, &[], next, &[]);
+        self.builder.switch_to_block(fail);
+        // The steps up to the instruction which fails count, as on the machine.
+        self.flush_without_reset();
+        let status = self.builder.ins().iconst(types::I64, status);
+        self.store(STATUS, status);
+        let zero = self.builder.ins().iconst(types::I64, 0);
+        self.builder.ins().return_(&[zero]);
+        self.builder.switch_to_block(next);
+    }
+
+    /// Returns if `status`, set by a call which gave up or failed, isn't `OK`.
+    fn exit_if(&mut self, status: ir::Value) {
+        let exit = self.builder.create_block();
+        let next = self.builder.create_block();
+        self.builder.ins().brif(status, exit, &[], next, &[]);
+        self.builder.switch_to_block(exit);
+        let zero = self.builder.ins().iconst(types::I64, 0);
+        self.builder.ins().return_(&[zero]);
+        self.builder.switch_to_block(next);
+    }
+
+    /// Adds the steps counted since the last time to the context.
+    fn flush(&mut self) {
+        self.flush_without_reset();
+        self.steps = 0;
+    }
+
+    fn flush_without_reset(&mut self) {
+        if self.steps > 0 {
+            let steps = self.load(STEPS);
+            let steps = self.builder.ins().iadd_imm(steps, self.steps);
+            self.store(STEPS, steps);
+        }
+    }
+
+    fn load(&mut self, offset: i32) -> ir::Value {
+        let context = self.context.unwrap();
+        self.builder.ins().load(types::I64, MemFlags::trusted(), context, offset)
+    }
+
+    fn store(&mut self, offset: i32, value: ir::Value) {
+        let context = self.context.unwrap();
+        self.builder.ins().store(MemFlags::trusted(), value, context, offset);
+    }
+}
+
+fn pop(stack: &mut Vec<Operand>) -> Result<Operand, Unsupported> {
+    stack.pop().ok_or(Unsupported)
+}
@@ -0,0 +1,2717 @@
+//! A [SECD machine](https://en.wikipedia.org/wiki/SECD_machine) with
+//! integers, booleans, unit, strings, closures and lists, which is the compilation target of
+//! miniml. It knows nothing about miniml's syntax, so other frontends can
+//! target it as well.
+//!
+//! A program is a `Frame`, a flat list of `Instruction`s. Arithmetic pops
+//! its operands from the stack and pushes the result, `JumpIfFalse` pops a
+//! bool and skips forward unless it is true, `Closure` pushes a function
+//! whose body are the instructions following it, and `Call` pops an
+//! argument and a function and executes the body with `name` bound to the
+//! function and `arg` to the argument. `PopEnv` ends a function body, and
+//! `TailCall` ends it with a call which reuses its place on the call stack,
+//! so that loops written as recursion run in constant space. Variables are
+//! `Name`s, plain integers; `resolve` makes closures capture only the ones
+//! they use and reads them by their `Slot`s. `PushNil` and
+//! `ListInstruction`s build and take apart lists. `Construct` tags a value
+//! with the number of its constructor, `Case` skips to the code for that
+//! number and `Payload` gets the value back. `Fix` ties closures made one
+//! after another into a group of mutually recursive functions. `PushStr` puts a copy of a
+//! string on the heap and `Concat` joins two strings into a new one; `Eq`
+//! compares strings as well as integers. `Record` puts named values together
+//! on the heap and `Field` reads one of them by its name. `Ref` puts a value
+//! in a mutable cell on the heap, which `Deref` reads and `Assign` overwrites. `Pop` drops the
+//! value of an expression evaluated for its effects, and `PushUnit` pushes the
+//! `()` such expressions evaluate to. `Print` writes a value to the standard
+//! output, or wherever `Machine::with_output` says.
+//!
+//! ```
+//! use secd::{ArithInstruction, Instruction, Machine, Value};
+//!
+//! // (fun f(x) is x * 2) 46
+//! let program = vec![
+//!     Instruction::Closure {
+//!         name: 0,
+//!         arg: 1,
+//!         len: 4,
+//!         captures: None,
+//!     },
+//!     Instruction::Var(1),
+//!     Instruction::PushInt(2),
+//!     Instruction::ArithInstruction(ArithInstruction::Mul),
+//!     Instruction::PopEnv,
+//!     Instruction::PushInt(46),
+//!     Instruction::Call,
+//! ];
+//! let mut machine = Machine::new(&program);
+//! assert_eq!(machine.exec().unwrap(), Value::Int(92));
+//! ```
+//!
+//! Environments of closures, cells of lists, strings, constructed values,
+//! records and reference cells live in a heap which is garbage collected every few
+//! instructions, or as configured with `Machine::with_gc`; with the `tracing`
+//! feature each collection is reported as an event. Use `Machine::show` to
+//! print the values on the heap.
+//!
+//! The host can give programs functions written in Rust with
+//! `Machine::register_native`; they are called like closures. With the `jit`
+//! feature, `Machine::with_jit` compiles the functions of ints a program
+//! calls often to native code.
+
+#[cfg(feature = "tracing")]
+extern crate tracing;
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(all(test, feature = "serde"))]
+extern crate serde_json;
+#[cfg(feature = "jit")]
+extern crate cranelift_codegen;
+#[cfg(feature = "jit")]
+extern crate cranelift_frontend;
+#[cfg(feature = "jit")]
+extern crate cranelift_jit;
+#[cfg(feature = "jit")]
+extern crate cranelift_module;
+#[cfg(feature = "jit")]
+extern crate cranelift_native;
+
+use std::collections::{HashMap, HashSet};
+use std::{error, fmt, io, mem, slice};
+use std::time::Duration;
+
+pub use program::{Frame, Instruction, Name, ArithInstruction, CmpInstruction, ListInstruction, disassemble,
+                  resolve};
+pub use value::{Value, Native, NativeFn};
+pub use cancel::{CancelToken, Cancelled};
+pub use bytecode::{DecodeError, FORMAT_VERSION, Header, decode, decode_with, encode};
+pub use scheduler::{ProgramId, Scheduler};
+pub use tracer::{MachineSnapshot, Tracer};
+pub use stopwatch::Stopwatch;
+pub use state::MachineState;
+pub use profile::Profile;
+
+mod value;
+mod program;
+mod cancel;
+mod bytecode;
+mod scheduler;
+mod tracer;
+mod stopwatch;
+mod state;
+mod profile;
+#[cfg(feature = "jit")]
+mod jit;
+
+#[derive(Debug)]
+pub struct RuntimeError {
+    pub kind: RuntimeErrorKind,
+    /// The source identifier of the variable of an `UndefinedVariable`, once
+    /// `with_names` has found it.
+    pub variable: Option<String>,
+}
+
+impl RuntimeError {
+    /// Looks up the source identifier of the variable the error is about,
+    /// which the machine only knows the `Name` of.
+    pub fn with_names<F: FnOnce(Name) -> Option<String>>(self, names: F) -> RuntimeError {
+        let variable = match self.kind {
+            RuntimeErrorKind::UndefinedVariable(name) => names(name),
+            _ => None,
+        };
+        RuntimeError { variable: variable.or(self.variable), ..self }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RuntimeErrorKind {
+    DivisionByZero,
+    /// An int operation whose result doesn't fit in 64 bits, unless the
+    /// machine wraps around, see `Machine::with_arithmetic`.
+    Overflow,
+    HeadOfEmptyList,
+    TailOfEmptyList,
+    /// The `CancelToken` of the machine was cancelled.
+    Cancelled,
+    /// A program ran out of the instructions the `Scheduler` or
+    /// `Machine::with_limits` allowed it.
+    OutOfFuel,
+    /// The heap grew larger than the embedder allows.
+    OutOfMemory,
+    /// A `Native` function failed with this message.
+    Native(String),
+    /// `Print` couldn't write to the output of the machine.
+    Output(String),
+    /// A `Var` of a name which isn't bound, as when a global isn't defined.
+    UndefinedVariable(Name),
+    /// The code is broken, which a correct compiler never causes.
+    Fatal(&'static str),
+}
+
+impl fmt::Display for RuntimeErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::RuntimeErrorKind::*;
+        match *self {
+            DivisionByZero => f.write_str("Division by zero"),
+            Overflow => f.write_str("Integer overflow"),
+            HeadOfEmptyList => f.write_str("Head of an empty list"),
+            TailOfEmptyList => f.write_str("Tail of an empty list"),
+            Cancelled => f.write_str("Cancelled"),
+            OutOfFuel => f.write_str("Out of fuel"),
+            OutOfMemory => f.write_str("Out of memory"),
+            Native(ref message) => f.write_str(message),
+            Output(ref message) => write!(f, "Can't write output: {}", message),
+            UndefinedVariable(name) => write!(f, "Undefined variable v{}", name),
+            Fatal(message) => write!(f, "Fatal: {} :(", message),
+        }
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.variable {
+            Some(ref variable) => write!(f, "Undefined variable '{}'", variable),
+            None => self.kind.fmt(f),
+        }
+    }
+}
+
+impl error::Error for RuntimeError {}
+
+impl From<RuntimeErrorKind> for RuntimeError {
+    fn from(kind: RuntimeErrorKind) -> RuntimeError {
+        RuntimeError {
+            kind: kind,
+            variable: None,
+        }
+    }
+}
+
+fn runtime_error(kind: RuntimeErrorKind) -> RuntimeError {
+    RuntimeError::from(kind)
+}
+
+fn fatal_error(message: &'static str) -> RuntimeError {
+    runtime_error(RuntimeErrorKind::Fatal(message))
+}
+
+pub type Result<T> = ::std::result::Result<T, RuntimeError>;
+
+type Activation<'p> = &'p [Instruction];
+
+/// What a `Machine` is doing after a `step`.
+#[derive(Debug)]
+pub enum Status<'p> {
+    /// There are instructions left to execute.
+    Running,
+    Finished(Value<'p>),
+    /// The program failed; stepping further is meaningless.
+    Error(RuntimeError),
+}
+
+/// When the garbage collector runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GcTrigger {
+    /// After every `n` instructions.
+    Steps(u64),
+    /// Once `heap_size` has grown by `n` objects since the previous collection.
+    HeapSize(usize),
+    /// Never, for short-lived machines.
+    Never,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GcConfig {
+    pub trigger: GcTrigger,
+    /// Whether a collection looks only at the objects made since the
+    /// previous one, unless the objects which survived have doubled since
+    /// the last collection of the whole heap. Short pauses, more garbage.
+    pub generational: bool,
+}
+
+impl Default for GcConfig {
+    fn default() -> GcConfig {
+        GcConfig {
+            trigger: GcTrigger::Steps(92),
+            generational: false,
+        }
+    }
+}
+
+/// What int operations do when the result doesn't fit in 64 bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IntArithmetic {
+    /// Fail with `Overflow`.
+    Checked,
+    /// Wrap around, as `i64::wrapping_add` does.
+    Wrapping,
+}
+
+impl Default for IntArithmetic {
+    fn default() -> IntArithmetic {
+        IntArithmetic::Checked
+    }
+}
+
+/// What the garbage collector has done so far, see `Machine::gc_stats`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GcStats {
+    pub collections: u64,
+    /// Collections of the whole heap rather than of the young objects only.
+    pub major_collections: u64,
+    /// Heap objects freed: environments, list cells, strings, constructed values and reference cells.
+    pub reclaimed: u64,
+    /// The longest collection, see `Machine::gc_time` for the total.
+    pub max_pause: Duration,
+}
+
+/// Instructions executed while tracing, with the call depth they were executed at.
+#[derive(Debug)]
+pub struct Trace<'p> {
+    pub instructions: Vec<(usize, &'p Instruction)>,
+    /// Number of instructions executed after `instructions` got full.
+    pub omitted: u64,
+    limit: usize,
+}
+
+#[derive(Debug)]
+pub struct Machine<'p> {
+    storage: Vec<Object<'p>>,
+    values: Vec<Value<'p>>,
+    environments: Vec<Env<'p>>,
+    activations: Vec<Activation<'p>>,
+    /// The programs given to `new` and `run`, which all code is part of.
+    programs: Vec<&'p Frame>,
+    steps: u64,
+    max_steps: Option<u64>,
+    /// The step `exec_with_fuel` stops at.
+    step_limit: u64,
+    arithmetic: IntArithmetic,
+    gc: GcConfig,
+    gc_stats: GcStats,
+    /// `heap_size` right after the previous collection.
+    heap_after_gc: usize,
+    /// Objects below this index in `storage` have survived a collection.
+    /// Only reference cells change once made, so the others can't point to
+    /// younger objects, and a minor collection leaves them alone.
+    old: usize,
+    /// The old reference cells which were given young values since the
+    /// previous collection. A minor collection treats their values as roots.
+    remembered: Vec<usize>,
+    /// `old` right after the previous major collection.
+    old_after_major: usize,
+    gc_time: Duration,
+    trace: Option<Trace<'p>>,
+    tracer: Option<Box<dyn Tracer + 'p>>,
+    profile: Option<Profile>,
+    cancel: Option<CancelToken>,
+    output: Output<'p>,
+    natives: Vec<NativeImpl<'p>>,
+    #[cfg(feature = "jit")]
+    jit: Option<Box<jit::Jit>>,
+}
+
+/// A function registered with `Machine::register_native`.
+struct NativeImpl<'p> {
+    name: &'static str,
+    arity: usize,
+    fun: Box<NativeBody<'p>>,
+}
+
+type NativeBody<'p> = dyn Fn(&[Value<'p>]) -> Result<Value<'p>> + 'p;
+
+impl<'p> fmt::Debug for NativeImpl<'p> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<builtin {}/{}>", self.name, self.arity)
+    }
+}
+
+/// Where `Print` writes, see `Machine::with_output`.
+struct Output<'p>(Box<dyn io::Write + 'p>);
+
+impl<'p> fmt::Debug for Output<'p> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("<output>")
+    }
+}
+
+/// The variables of a function: those its closure captured, or all of
+/// those in scope where it was made, then the function itself, then its
+/// argument. Names are kept for `Var` and `env`,
+/// while `Slot` counts from the end.
+type Env<'p> = Vec<(Name, Value<'p>)>;
+
+/// What lives on the heap: environments of closures, list cells, strings, constructed values,
+/// records, reference cells and native functions with the arguments they got so far.
+#[derive(Debug)]
+enum Object<'p> {
+    Env(Env<'p>),
+    Cons(Value<'p>, Value<'p>),
+    Str(String),
+    Variant {
+        tag: usize,
+        name: &'p str,
+        arg: Option<Value<'p>>,
+    },
+    /// The fields, sorted by name.
+    Record(Vec<(&'p str, Value<'p>)>),
+    Ref(Value<'p>),
+    Native {
+        native: usize,
+        args: Vec<Value<'p>>,
+    },
+}
+
+impl<'p> Object<'p> {
+    fn values_mut(&mut self) -> Vec<&mut Value<'p>> {
+        match *self {
+            Object::Env(ref mut env) => env.iter_mut().map(|&mut (_, ref mut value)| value).collect(),
+            Object::Cons(ref mut head, ref mut tail) => vec![head, tail],
+            Object::Str(_) => vec![],
+            Object::Variant { ref mut arg, .. } => arg.iter_mut().collect(),
+            Object::Record(ref mut fields) => fields.iter_mut().map(|&mut (_, ref mut value)| value).collect(),
+            Object::Ref(ref mut value) => vec![value],
+            Object::Native { ref mut args, .. } => args.iter_mut().collect(),
+        }
+    }
+}
+
+impl<'p> Machine<'p> {
+    pub fn new(program: &'p Frame) -> Self {
+        let mut machine = Machine::empty();
+        machine.programs.push(program);
+        machine.activations.push(program);
+        machine
+    }
+
+    /// A machine with nothing to execute yet, see `run`.
+    pub fn empty() -> Self {
+        Machine {
+            storage: vec![],
+            values: vec![],
+            environments: vec![Env::new()],
+            activations: vec![],
+            programs: vec![],
+            steps: 0,
+            max_steps: None,
+            step_limit: u64::MAX,
+            arithmetic: IntArithmetic::default(),
+            gc: GcConfig::default(),
+            gc_stats: GcStats::default(),
+            heap_after_gc: 0,
+            old: 0,
+            remembered: vec![],
+            old_after_major: 0,
+            gc_time: Duration::new(0, 0),
+            trace: None,
+            tracer: None,
+            profile: None,
+            cancel: None,
+            output: Output(Box::new(io::stdout())),
+            natives: vec![],
+            #[cfg(feature = "jit")]
+            jit: None,
+        }
+    }
+
+    /// Executes `program` in the top-level environment left by the previous runs.
+    /// After an error the machine is reset to that environment.
+    pub fn run(&mut self, program: &'p Frame) -> Result<Value<'p>> {
+        if !self.programs.iter().any(|&known| known as *const Frame == program as *const Frame) {
+            self.programs.push(program);
+        }
+        self.activations.push(program);
+        let result = self.exec();
+        if result.is_err() {
+            self.values.clear();
+            self.environments.truncate(1);
+            self.activations.clear();
+        }
+        result
+    }
+
+    /// Forgets the programs given to `new` and `run` which nothing in the
+    /// machine points into after a collection of the whole heap: no closure,
+    /// name of a constructor or field, code left to execute or traced
+    /// instruction. Returns where they are, so that their owner can free them
+    /// if nothing outside the machine, like a `Tracer`, points into them
+    /// either. The programs after them move down in the numbering of
+    /// `profile` and `snapshot`.
+    pub fn release_programs(&mut self) -> Vec<*const Frame> {
+        self.collect_garbage(true);
+        let mut code: Vec<&'p [Instruction]> = self.activations.clone();
+        let mut names = HashSet::new();
+        {
+            let mut values: Vec<Value<'p>> = self.values.clone();
+            values.extend(self.environments.iter().flat_map(|env| env.iter().map(|&(_, value)| value)));
+            for object in &mut self.storage {
+                match *object {
+                    Object::Variant { name, .. } => {
+                        names.insert(name.as_ptr());
+                    }
+                    Object::Record(ref fields) => names.extend(fields.iter().map(|&(name, _)| name.as_ptr())),
+                    _ => {}
+                }
+                values.extend(object.values_mut().into_iter().map(|value| *value));
+            }
+            code.extend(values.into_iter().filter_map(|value| value.into_closure().ok()).map(|c| c.frame));
+        }
+        if let Some(ref trace) = self.trace {
+            code.extend(trace.instructions.iter().map(|&(_, inst)| slice::from_ref(inst)));
+        }
+        let mut used = vec![false; self.programs.len()];
+        for frame in code {
+            if let Some((program, _)) = self.position(frame) {
+                used[program] = true;
+            }
+        }
+        for (program, used) in self.programs.iter().zip(&mut used) {
+            *used = *used || program.iter().any(|inst| match *inst {
+                Instruction::Construct { ref name, .. } => names.contains(&name.as_ptr()),
+                Instruction::Record(ref fields) => fields.iter().any(|name| names.contains(&name.as_ptr())),
+                _ => false,
+            });
+        }
+
+        let mut numbers = Vec::with_capacity(used.len());
+        let mut released = Vec::new();
+        for (program, used) in mem::replace(&mut self.programs, Vec::new()).into_iter().zip(used) {
+            if used {
+                numbers.push(Some(self.programs.len()));
+                self.programs.push(program);
+            } else {
+                numbers.push(None);
+                released.push(program as *const Frame);
+                #[cfg(feature = "jit")]
+                {
+                    if let Some(ref mut jit) = self.jit {
+                        jit.forget(program);
+                    }
+                }
+            }
+        }
+        if let Some(ref mut profile) = self.profile {
+            profile.instructions = profile.instructions
+                .drain()
+                .filter_map(|((program, position), steps)| numbers[program].map(|n| ((n, position), steps)))
+                .collect();
+        }
+        released
+    }
+
+    /// Makes every `exec` and `run` fail with `OutOfFuel` after `max_steps`
+    /// instructions, instead of looping forever on a diverging program.
+    pub fn with_limits(mut self, max_steps: u64) -> Self {
+        self.max_steps = Some(max_steps);
+        self
+    }
+
+    /// Makes int operations which overflow wrap around instead of failing.
+    pub fn with_arithmetic(mut self, arithmetic: IntArithmetic) -> Self {
+        self.arithmetic = arithmetic;
+        self
+    }
+
+    /// Makes the machine count the instructions it executes, see `profile`.
+    pub fn with_profiling(mut self) -> Self {
+        self.profile = Some(Profile::default());
+        self
+    }
+
+    /// Makes the machine compile the functions of ints it calls often to
+    /// native code, see the `jit` module. Steps are counted as before, but
+    /// native code isn't traced or profiled, so a machine which is doesn't
+    /// use it, and neither does one which can be cancelled. Does nothing on
+    /// hosts Cranelift doesn't support.
+    #[cfg(feature = "jit")]
+    pub fn with_jit(mut self) -> Self {
+        self.jit = jit::Jit::new().map(Box::new);
+        self
+    }
+
+    /// Makes the garbage collector run as `config` says.
+    pub fn with_gc(mut self, config: GcConfig) -> Self {
+        self.gc = config;
+        self
+    }
+
+    /// Makes `Print` write to `output` instead of the standard output, so that
+    /// embedders can show it elsewhere and tests can check it.
+    pub fn with_output<W: io::Write + 'p>(mut self, output: W) -> Self {
+        self.output = Output(Box::new(output));
+        self
+    }
+
+    /// Binds `name` in the top-level environment.
+    pub fn define(&mut self, name: Name, value: Value<'p>) {
+        let top = &mut self.environments[0];
+        match top.iter().position(|&(n, _)| n == name) {
+            Some(i) => top[i].1 = value,
+            None => top.push((name, value)),
+        }
+    }
+
+    /// Binds `name` in the top-level environment to a function of `arity`
+    /// arguments, at least one, which `fun` implements and values show as
+    /// `label`. Applied to fewer arguments, the function waits for the rest;
+    /// applied to all of them, it calls `fun` with them in order.
+    pub fn register_native<F>(&mut self, name: Name, label: &'static str, arity: usize, fun: F)
+        where F: Fn(&[Value<'p>]) -> Result<Value<'p>> + 'p
+    {
+        assert!(arity > 0, "a native function takes at least one argument");
+        self.natives.push(NativeImpl {
+            name: label,
+            arity: arity,
+            fun: Box::new(fun),
+        });
+        self.storage.push(Object::Native {
+            native: self.natives.len() - 1,
+            args: vec![],
+        });
+        let value = Value::Native(Native {
+            name: label,
+            index: self.storage.len() - 1,
+        });
+        self.define(name, value);
+    }
+
+    /// Starts recording up to `limit` executed instructions, see `take_trace`.
+    pub fn start_trace(&mut self, limit: usize) {
+        self.trace = Some(Trace {
+            instructions: Vec::new(),
+            omitted: 0,
+            limit: limit,
+        });
+    }
+
+    /// Stops tracing and returns the instructions executed since `start_trace`.
+    pub fn take_trace(&mut self) -> Option<Trace<'p>> {
+        self.trace.take()
+    }
+
+    /// Calls `tracer` before each instruction, until `remove_tracer`.
+    pub fn set_tracer<T: Tracer + 'p>(&mut self, tracer: T) {
+        self.tracer = Some(Box::new(tracer));
+    }
+
+    pub fn remove_tracer(&mut self) {
+        self.tracer = None;
+    }
+
+    /// Makes execution fail with a "Cancelled" error soon after `token` is cancelled.
+    pub fn set_cancel_token(&mut self, token: CancelToken) {
+        self.cancel = Some(token);
+    }
+
+    /// Number of instructions executed so far.
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// What a `Tracer` is shown of the machine, see `snapshot` for all of it.
+    pub fn shape(&self) -> MachineSnapshot {
+        MachineSnapshot {
+            steps: self.steps,
+            stack_depth: self.values.len(),
+            environments: self.environments.len(),
+        }
+    }
+
+    /// Number of objects alive, both on the heap and on the call stack.
+    pub fn heap_size(&self) -> usize {
+        self.storage.len() + self.environments.len()
+    }
+
+    /// Total time spent collecting garbage so far.
+    pub fn gc_time(&self) -> Duration {
+        self.gc_time
+    }
+
+    pub fn gc_stats(&self) -> GcStats {
+        self.gc_stats
+    }
+
+    /// The instructions executed so far, if the machine was built `with_profiling`.
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profile.as_ref()
+    }
+
+    pub fn exec(&mut self) -> Result<Value<'p>> {
+        if let Some(max_steps) = self.max_steps {
+            return self.exec_with_fuel(max_steps);
+        }
+        loop {
+            if let Some(result) = try!(self.advance()) {
+                return Ok(result);
+            }
+        }
+    }
+
+    /// Like `exec`, but fails with `OutOfFuel` if the program doesn't finish in
+    /// `max_steps` instructions. The machine can be resumed with more fuel afterwards.
+    pub fn exec_with_fuel(&mut self, max_steps: u64) -> Result<Value<'p>> {
+        self.step_limit = self.steps.saturating_add(max_steps);
+        let result = self.exec_until_limit();
+        self.step_limit = u64::MAX;
+        result
+    }
+
+    fn exec_until_limit(&mut self) -> Result<Value<'p>> {
+        loop {
+            // Whether instructions are left takes a look at every call, so it
+            // is only asked once the fuel is gone.
+            if self.steps >= self.step_limit && self.activations.iter().any(|act| !act.is_empty()) {
+                return Err(runtime_error(RuntimeErrorKind::OutOfFuel));
+            }
+            if let Some(result) = try!(self.advance()) {
+                return Ok(result);
+            }
+        }
+    }
+
+    /// Executes a single instruction, so that the machine can be inspected
+    /// between instructions, see `exec` for running a program to the end.
+    pub fn step(&mut self) -> Status<'p> {
+        match self.advance() {
+            Ok(None) => Status::Running,
+            Ok(Some(value)) => Status::Finished(value),
+            Err(e) => Status::Error(e),
+        }
+    }
+
+    /// Executes a single instruction. Returns the result once the program is finished.
+    fn advance(&mut self) -> Result<Option<Value<'p>>> {
+        let inst = match self.fetch_instruction() {
+            Some(inst) => inst,
+            None => return self.finish().map(Some),
+        };
+        if self.tracer.is_some() {
+            let snapshot = self.shape();
+            if let Some(ref mut tracer) = self.tracer {
+                tracer.before(inst, &snapshot);
+            }
+        }
+        self.steps += 1;
+        let depth = self.depth();
+        if let Some(ref mut trace) = self.trace {
+            if trace.instructions.len() < trace.limit {
+                trace.instructions.push((depth, inst));
+            } else {
+                trace.omitted += 1;
+            }
+        }
+        if self.profile.is_some() {
+            let position = self.position(slice::from_ref(inst));
+            if let Some(ref mut profile) = self.profile {
+                *profile.opcodes.entry(inst.opcode()).or_insert(0) += 1;
+                if let Some(position) = position {
+                    *profile.instructions.entry(position).or_insert(0) += 1;
+                }
+            }
+        }
+        try!(inst.exec(self));
+        if self.steps % 1024 == 0 && self.cancel.as_ref().map_or(false, |token| token.is_cancelled()) {
+            return Err(runtime_error(RuntimeErrorKind::Cancelled));
+        }
+        if self.should_collect() {
+            let major = !self.gc.generational || self.old >= 2 * self.old_after_major.max(32);
+            self.collect_garbage(major);
+        }
+        Ok(None)
+    }
+
+    /// Runs `gc` and counts it in the statistics.
+    fn collect_garbage(&mut self, major: bool) {
+        let stopwatch = Stopwatch::start();
+        let before = self.storage.len();
+        self.gc(major);
+        let elapsed = stopwatch.elapsed();
+        trace_gc(before, self.storage.len(), elapsed);
+        self.gc_time += elapsed;
+        self.gc_stats.collections += 1;
+        self.gc_stats.major_collections += major as u64;
+        self.gc_stats.reclaimed += (before - self.storage.len()) as u64;
+        self.gc_stats.max_pause = self.gc_stats.max_pause.max(elapsed);
+        self.heap_after_gc = self.heap_size();
+    }
+
+    fn should_collect(&self) -> bool {
+        match self.gc.trigger {
+            GcTrigger::Steps(n) => n > 0 && self.steps % n == 0,
+            GcTrigger::HeapSize(n) => self.heap_size() >= self.heap_after_gc + n,
+            GcTrigger::Never => false,
+        }
+    }
+
+    /// The instructions left to execute in the innermost function or in the
+    /// program, starting with the next one. They include the code of both
+    /// alternatives of a conditional, if the next instruction is a jump.
+    pub fn current_frame(&self) -> Option<&'p [Instruction]> {
+        self.activations.last().cloned().filter(|frame| !frame.is_empty())
+    }
+
+    /// The elements of a list.
+    pub fn list(&self, value: Value<'p>) -> Result<Vec<Value<'p>>> {
+        let mut items = Vec::new();
+        let mut list = value;
+        while let Value::Cons(_) = list {
+            let (head, tail) = try!(self.cell(list));
+            items.push(head);
+            list = tail;
+        }
+        match list {
+            Value::Nil => Ok(items),
+            _ => Err(fatal_error("runtime type error")),
+        }
+    }
+
+    /// The contents of a string.
+    pub fn string(&self, value: Value<'p>) -> Result<&str> {
+        match value {
+            Value::Str(index) => {
+                match self.storage[index] {
+                    Object::Str(ref s) => Ok(s),
+                    _ => Err(fatal_error("not a string")),
+                }
+            }
+            _ => Err(fatal_error("runtime type error")),
+        }
+    }
+
+    /// The tag, the name and the argument of a constructed value.
+    pub fn variant(&self, value: Value<'p>) -> Result<(usize, &'p str, Option<Value<'p>>)> {
+        match value {
+            Value::Variant(index) => {
+                match self.storage[index] {
+                    Object::Variant { tag, name, arg } => Ok((tag, name, arg)),
+                    _ => Err(fatal_error("not a variant")),
+                }
+            }
+            _ => Err(fatal_error("runtime type error")),
+        }
+    }
+
+    /// The fields of a record, sorted by name.
+    pub fn record(&self, value: Value<'p>) -> Result<&[(&'p str, Value<'p>)]> {
+        match value {
+            Value::Record(index) => {
+                match self.storage[index] {
+                    Object::Record(ref fields) => Ok(fields),
+                    _ => Err(fatal_error("not a record")),
+                }
+            }
+            _ => Err(fatal_error("runtime type error")),
+        }
+    }
+
+    /// The value held by a reference cell.
+    pub fn contents(&self, cell: Value<'p>) -> Result<Value<'p>> {
+        match cell {
+            Value::Ref(index) => {
+                match self.storage[index] {
+                    Object::Ref(value) => Ok(value),
+                    _ => Err(fatal_error("not a reference cell")),
+                }
+            }
+            _ => Err(fatal_error("runtime type error")),
+        }
+    }
+
+    /// Formats `value` like `Display` does, but with the elements of lists,
+    /// the constructors of variants, the fields of records, the contents of reference cells and of
+    /// strings, quoted.
+    pub fn show(&self, value: Value<'p>) -> String {
+        self.show_in(value, &mut Vec::new())
+    }
+
+    /// Like `show`, inside of the reference `cells`: a cell which holds
+    /// itself is shown as `<cycle>` the second time.
+    fn show_in(&self, value: Value<'p>, cells: &mut Vec<usize>) -> String {
+        if let Value::Str(_) = value {
+            return match self.string(value) {
+                Ok(s) => quote(s),
+                Err(_) => value.to_string(),
+            };
+        }
+        if let Value::Variant(_) = value {
+            return match self.variant(value) {
+                Ok((_, name, None)) => name.to_owned(),
+                Ok((_, name, Some(arg))) if self.is_applied(arg) => {
+                    format!("{} ({})", name, self.show_in(arg, cells))
+                }
+                Ok((_, name, Some(arg))) => format!("{} {}", name, self.show_in(arg, cells)),
+                Err(_) => value.to_string(),
+            };
+        }
+        if let Value::Record(_) = value {
+            let fields = match self.record(value) {
+                Ok(fields) => fields,
+                Err(_) => return value.to_string(),
+            };
+            let fields = fields.iter()
+                               .map(|&(name, value)| format!("{} = {}", name, self.show_in(value, cells)))
+                               .collect::<Vec<_>>();
+            return format!("{{{}}}", fields.join(", "));
+        }
+        if let Value::Ref(index) = value {
+            if cells.contains(&index) {
+                return "<cycle>".to_owned();
+            }
+            let contents = match self.contents(value) {
+                Ok(contents) => contents,
+                Err(_) => return value.to_string(),
+            };
+            cells.push(index);
+            let shown = self.show_in(contents, cells);
+            cells.pop();
+            return if self.is_applied(contents) {
+                format!("ref ({})", shown)
+            } else {
+                format!("ref {}", shown)
+            };
+        }
+        match self.list(value) {
+            Ok(items) => {
+                let items = items.into_iter().map(|item| self.show_in(item, cells)).collect::<Vec<_>>();
+                format!("[{}]", items.join(", "))
+            }
+            Err(_) => value.to_string(),
+        }
+    }
+
+    /// Whether `show` writes `value` as an application, like `Some 1` or
+    /// `ref 1`, which needs parentheses as an argument.
+    fn is_applied(&self, value: Value<'p>) -> bool {
+        match value {
+            Value::Ref(_) => true,
+            Value::Variant(_) => {
+                match self.variant(value) {
+                    Ok((_, _, arg)) => arg.is_some(),
+                    Err(_) => false,
+                }
+            }
+            _ => false,
+        }
+    }
+
+    pub fn values(&self) -> &[Value<'p>] {
+        &self.values
+    }
+
+    /// The variables visible to the next instruction.
+    pub fn env(&self) -> HashMap<Name, Value<'p>> {
+        self.current_env().iter().cloned().collect()
+    }
+
+    /// Number of active calls, the top level being zero.
+    pub fn depth(&self) -> usize {
+        self.environments.len() - 1
+    }
+
+    fn finish(&mut self) -> Result<Value<'p>> {
+        self.pop_value().and_then(|result| {
+            if !self.values.is_empty() {
+                return Err(fatal_error("more then one value on stack left"));
+            }
+            Ok(result)
+        })
+    }
+
+    /// The next instruction. A function body is left by `PopEnv` or
+    /// `TailCall`, so only the program itself runs out of instructions.
+    fn fetch_instruction(&mut self) -> Option<&'p Instruction> {
+        while let Some(act) = self.activations.pop() {
+            if let Some((inst, rest)) = act.split_first() {
+                self.activations.push(rest);
+                return Some(inst);
+            }
+        }
+        None
+    }
+
+    /// Where `code` starts in the programs given to `new` and `run`: which
+    /// of them, and at which instruction. All the code the machine executes
+    /// is a part of one of them, unless a closure from elsewhere was `define`d.
+    fn position(&self, code: &[Instruction]) -> Option<(usize, usize)> {
+        let size = mem::size_of::<Instruction>();
+        let start = code.as_ptr() as usize;
+        self.programs.iter().position(|program| {
+            let begin = program.as_ptr() as usize;
+            begin <= start && start + mem::size_of_val(code) <= begin + mem::size_of_val(&program[..])
+        }).map(|i| (i, (start - self.programs[i].as_ptr() as usize) / size))
+    }
+
+    fn switch_frame(&mut self, frame: &'p [Instruction]) {
+        self.activations.push(frame)
+    }
+
+    /// Takes the next `n` instructions of the current frame, which continues after them.
+    fn skip(&mut self, n: usize) -> Result<&'p [Instruction]> {
+        let act = match self.activations.pop() {
+            Some(act) => act,
+            None => return Err(fatal_error("no frame")),
+        };
+        if n > act.len() {
+            return Err(fatal_error("jump out of the frame"));
+        }
+        let (skipped, rest) = act.split_at(n);
+        self.activations.push(rest);
+        Ok(skipped)
+    }
+
+    fn push_int(&mut self, value: i64) {
+        self.push_value(Value::Int(value))
+    }
+
+    fn push_bool(&mut self, value: bool) {
+        self.push_value(Value::Bool(value))
+    }
+
+    fn push_value(&mut self, value: Value<'p>) {
+        self.values.push(value)
+    }
+
+    fn push_string(&mut self, value: String) {
+        self.storage.push(Object::Str(value));
+        let value = Value::Str(self.storage.len() - 1);
+        self.push_value(value)
+    }
+
+    fn pop_int(&mut self) -> Result<i64> {
+        self.pop_value().and_then(|v| v.into_int())
+    }
+
+    fn pop_bool(&mut self) -> Result<bool> {
+        self.pop_value().and_then(|v| v.into_bool())
+    }
+
+    fn pop_value(&mut self) -> Result<Value<'p>> {
+        self.values
+            .pop()
+            .ok_or(fatal_error("empty stack"))
+    }
+
+    fn lookup(&mut self, name: Name) -> Result<Value<'p>> {
+        self.current_env()
+            .iter()
+            .rev()
+            .find(|&&(n, _)| n == name)
+            .map(|&(_, value)| value)
+            .ok_or(runtime_error(RuntimeErrorKind::UndefinedVariable(name)))
+    }
+
+    fn slot(&self, slot: usize) -> Result<Value<'p>> {
+        let env = self.current_env();
+        if slot >= env.len() {
+            return Err(fatal_error("undefined variable"));
+        }
+        Ok(env[env.len() - 1 - slot].1)
+    }
+
+    /// The head and the tail of a non-empty list.
+    fn cell(&self, list: Value<'p>) -> Result<(Value<'p>, Value<'p>)> {
+        match list {
+            Value::Cons(index) => {
+                match self.storage[index] {
+                    Object::Cons(head, tail) => Ok((head, tail)),
+                    _ => Err(fatal_error("not a list cell")),
+                }
+            }
+            _ => Err(fatal_error("runtime type error")),
+        }
+    }
+
+    /// Calls `native` with `arg` after the arguments it already has, or
+    /// makes a new function waiting for the rest if there are still some left.
+    fn apply_native(&mut self, native: Native, arg: Value<'p>) -> Result<Value<'p>> {
+        let (index, mut args) = match self.storage[native.index] {
+            Object::Native { native, ref args } => (native, args.clone()),
+            _ => return Err(fatal_error("not a native function")),
+        };
+        args.push(arg);
+        let implementation = &self.natives[index];
+        if args.len() == implementation.arity {
+            return (implementation.fun)(&args);
+        }
+        self.storage.push(Object::Native {
+            native: index,
+            args: args,
+        });
+        Ok(Value::Native(Native {
+            name: native.name,
+            index: self.storage.len() - 1,
+        }))
+    }
+
+    fn current_env(&self) -> &Env<'p> {
+        self.environments.last().unwrap()
+    }
+
+    /// Calls `closure` with `arg` in native code, if the JIT is on and takes
+    /// it. Returns `None` if the machine has to make the call itself.
+    #[cfg(feature = "jit")]
+    fn call_native(&mut self, closure: value::Closure<'p>, arg: Value<'p>) -> Result<Option<Value<'p>>> {
+        if self.trace.is_some() || self.tracer.is_some() || self.profile.is_some() || self.cancel.is_some() {
+            return Ok(None);
+        }
+        let jit = match self.jit {
+            Some(ref mut jit) => jit,
+            None => return Ok(None),
+        };
+        let env = match self.storage[closure.env] {
+            Object::Env(ref env) => env,
+            _ => return Err(fatal_error("not an environment")),
+        };
+        let fuel = self.step_limit.saturating_sub(self.steps);
+        match jit.call(closure, env, arg, self.arithmetic, fuel) {
+            jit::Outcome::Interpret => Ok(None),
+            jit::Outcome::Finished(result, steps) => {
+                self.steps += steps;
+                Ok(Some(Value::Int(result)))
+            }
+            jit::Outcome::Failed(kind, steps) => {
+                self.steps += steps;
+                Err(runtime_error(kind))
+            }
+        }
+    }
+
+    /// Leaves the current function, dropping its environment and the rest of its code.
+    fn pop_env(&mut self) -> Result<()> {
+        if self.environments.len() == 0 || self.activations.is_empty() {
+            return Err(fatal_error("no environment"));
+        }
+        self.environments.pop();
+        self.activations.pop();
+        Ok(())
+    }
+
+    /// Moves the objects reachable from the stacks to the end of the old
+    /// ones, which a `major` collection moves as well. A minor one also
+    /// keeps what the `remembered` cells hold.
+    fn gc(&mut self, major: bool) {
+        let from = if major { 0 } else { self.old };
+        let mut young = self.storage.split_off(from);
+        let mut forward = vec![None; young.len()];
+        let mut remembered = Vec::new();
+        if !major {
+            for &index in &self.remembered {
+                if let Object::Ref(value) = self.storage[index] {
+                    remembered.push((index, value));
+                }
+            }
+        }
+
+        let survivors = {
+            let mut initial_work: Vec<&mut Value<'p>> = self.values.iter_mut().collect();
+            initial_work.extend(self.environments
+                                    .iter_mut()
+                                    .flat_map(|env| env.iter_mut().map(|&mut (_, ref mut value)| value)));
+            initial_work.extend(remembered.iter_mut().map(|&mut (_, ref mut value)| value));
+
+            let mut survivors = collect(initial_work, &mut forward, &mut young, from, from);
+            let mut done = 0;
+            loop {
+                let move_index = from + survivors.len();
+                let wave = {
+                    let work = survivors[done..].iter_mut().flat_map(Object::values_mut).collect();
+                    collect(work, &mut forward, &mut young, from, move_index)
+                };
+
+                if wave.is_empty() {
+                    break;
+                }
+                done = survivors.len();
+                survivors.extend(wave.into_iter());
+            }
+            survivors
+        };
+
+        assert!(survivors.len() <= young.len());
+
+        for (index, value) in remembered {
+            self.storage[index] = Object::Ref(value);
+        }
+        self.remembered.clear();
+        self.storage.extend(survivors);
+        self.old = self.storage.len();
+        if major {
+            self.old_after_major = self.old;
+        }
+    }
+}
+
+/// A string as it would be written in a program.
+fn quote(s: &str) -> String {
+    let mut result = String::with_capacity(s.len() + 2);
+    result.push('"');
+    for c in s.chars() {
+        match c {
+            '\n' => result.push_str("\\n"),
+            '\t' => result.push_str("\\t"),
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            c => result.push(c),
+        }
+    }
+    result.push('"');
+    result
+}
+
+#[cfg(feature = "tracing")]
+fn trace_gc(before: usize, after: usize, elapsed: Duration) {
+    tracing::trace!(target: "secd::gc",
+                    before = before as u64,
+                    after = after as u64,
+                    elapsed_us = elapsed.as_secs() * 1_000_000 + u64::from(elapsed.subsec_micros()),
+                    "collected garbage");
+}
+
+#[cfg(not(feature = "tracing"))]
+fn trace_gc(_before: usize, _after: usize, _elapsed: Duration) {}
+
+/// Where `value` lives on the heap, if it does.
+fn heap_index(value: Value) -> Option<usize> {
+    match value {
+        Value::Closure(closure) => Some(closure.env),
+        Value::Native(native) => Some(native.index),
+        Value::Cons(index) | Value::Str(index) | Value::Variant(index) | Value::Record(index) |
+        Value::Ref(index) => Some(index),
+        Value::Int(_) | Value::Float(_) | Value::Bool(_) | Value::Unit | Value::Nil => None,
+    }
+}
+
+/// Moves the objects `work` points to out of `objects`, which start at
+/// index `from`, and points `work` to where they will be once appended to the
+/// heap at `start_index`. `forward` remembers the objects already moved.
+fn collect<'p>(work: Vec<&mut Value<'p>>,
+               forward: &mut [Option<usize>],
+               objects: &mut [Object<'p>],
+               from: usize,
+               start_index: usize,
+) -> Vec<Object<'p>> {
+    let mut wave: Vec<Object<'p>> = vec![];
+    for value in work {
+        let index = match *value {
+            Value::Closure(ref mut closure) => &mut closure.env,
+            Value::Cons(ref mut index) => index,
+            Value::Str(ref mut index) => index,
+            Value::Variant(ref mut index) => index,
+            Value::Record(ref mut index) => index,
+            Value::Ref(ref mut index) => index,
+            Value::Native(ref mut native) => &mut native.index,
+            _ => continue,
+        };
+        if *index < from {
+            continue;
+        }
+        if let Some(new_index) = forward[*index - from] {
+            *index = new_index
+        } else {
+            let new_index = start_index + wave.len();
+            forward[*index - from] = Some(new_index);
+
+            let object = ::std::mem::replace(&mut objects[*index - from], Object::Env(Vec::new()));
+
+            *index = new_index;
+            wave.push(object);
+        }
+    }
+
+    wave
+}
+
+trait Exec {
+    fn exec<'p>(&'p self, state: &mut Machine<'p>) -> Result<()>;
+}
+
+impl Exec for Instruction {
+    fn exec<'p>(&'p self, machine: &mut Machine<'p>) -> Result<()> {
+        use self::program::Instruction::*;
+
+        match *self {
+            ArithInstruction(ref inst) => try!(inst.exec(machine)),
+            CmpInstruction(ref inst) => try!(inst.exec(machine)),
+            ListInstruction(ref inst) => try!(inst.exec(machine)),
+            PushInt(i) => machine.push_int(i),
+            PushFloat(x) => machine.push_value(Value::Float(x)),
+            PushBool(b) => machine.push_bool(b),
+            PushStr(ref s) => machine.push_string(s.clone()),
+            PushNil => machine.push_value(Value::Nil),
+            PushUnit => machine.push_value(Value::Unit),
+            Pop => {
+                try!(machine.pop_value());
+            }
+            Print => {
+                let value = try!(machine.pop_value());
+                let text = match value {
+                    Value::Str(_) => try!(machine.string(value)).to_owned(),
+                    _ => machine.show(value),
+                };
+                try!(writeln!(machine.output.0, "{}", text)
+                         .map_err(|e| runtime_error(RuntimeErrorKind::Output(e.to_string()))));
+                machine.push_value(Value::Unit);
+            }
+            Concat => {
+                let rhs = try!(machine.pop_value());
+                let lhs = try!(machine.pop_value());
+                let result = {
+                    let lhs = try!(machine.string(lhs));
+                    let rhs = try!(machine.string(rhs));
+                    let mut result = String::with_capacity(lhs.len() + rhs.len());
+                    result.push_str(lhs);
+                    result.push_str(rhs);
+                    result
+                };
+                machine.push_string(result);
+            }
+            IntOfFloat => {
+                let x = try!(try!(machine.pop_value()).into_float());
+                machine.push_int(x as i64);
+            }
+            FloatOfInt => {
+                let i = try!(machine.pop_int());
+                machine.push_value(Value::Float(i as f64));
+            }
+            Construct { tag, ref name, arg } => {
+                let arg = if arg {
+                    Some(try!(machine.pop_value()))
+                } else {
+                    None
+                };
+                machine.storage.push(Object::Variant {
+                    tag: tag,
+                    name: name,
+                    arg: arg,
+                });
+                let value = Value::Variant(machine.storage.len() - 1);
+                machine.push_value(value);
+            }
+            Case(ref offsets) => {
+                let value = try!(machine.pop_value());
+                let (tag, _, _) = try!(machine.variant(value));
+                match offsets.get(tag) {
+                    Some(&offset) => try!(machine.skip(offset)),
+                    None => return Err(fatal_error("no case for the constructor")),
+                };
+            }
+            Payload => {
+                let value = try!(machine.pop_value());
+                match try!(machine.variant(value)) {
+                    (_, _, Some(arg)) => machine.push_value(arg),
+                    (_, _, None) => return Err(fatal_error("the constructor has no argument")),
+                }
+            }
+            Record(ref names) => {
+                let mut fields = Vec::with_capacity(names.len());
+                for name in names.iter().rev() {
+                    fields.push((&name[..], try!(machine.pop_value())));
+                }
+                fields.sort_by_key(|&(name, _)| name);
+                machine.storage.push(Object::Record(fields));
+                let value = Value::Record(machine.storage.len() - 1);
+                machine.push_value(value);
+            }
+            Field(ref name) => {
+                let record = try!(machine.pop_value());
+                let value = match try!(machine.record(record)).iter().find(|&&(field, _)| field == name) {
+                    Some(&(_, value)) => value,
+                    None => return Err(fatal_error("no such field")),
+                };
+                machine.push_value(value);
+            }
+            Ref => {
+                let value = try!(machine.pop_value());
+                machine.storage.push(Object::Ref(value));
+                let cell = Value::Ref(machine.storage.len() - 1);
+                machine.push_value(cell);
+            }
+            Deref => {
+                let cell = try!(machine.pop_value());
+                let value = try!(machine.contents(cell));
+                machine.push_value(value);
+            }
+            Assign => {
+                let value = try!(machine.pop_value());
+                let index = match try!(machine.pop_value()) {
+                    Value::Ref(index) => index,
+                    _ => return Err(fatal_error("runtime type error")),
+                };
+                machine.storage[index] = Object::Ref(value);
+                let young = heap_index(value).map_or(false, |value| value >= machine.old);
+                if machine.gc.generational && index < machine.old && young {
+                    machine.remembered.push(index);
+                }
+                machine.push_value(Value::Unit);
+            }
+            Jump(n) => {
+                try!(machine.skip(n));
+            }
+            JumpIfFalse(n) => {
+                if !try!(machine.pop_bool()) {
+                    try!(machine.skip(n));
+                }
+            }
+            Var(name) => {
+                let value = try!(machine.lookup(name));
+                machine.push_value(value);
+            }
+            Slot(slot) => {
+                let value = try!(machine.slot(slot));
+                machine.push_value(value);
+            }
+            Closure { name, arg, len, ref captures } => {
+                let frame = try!(machine.skip(len));
+                let mut env = match *captures {
+                    None => machine.current_env().clone(),
+                    Some(ref names) => {
+                        let mut env = Vec::with_capacity(names.len() + 2);
+                        for &name in names {
+                            env.push((name, try!(machine.lookup(name))));
+                        }
+                        env
+                    }
+                };
+                let env_idx = machine.storage.len();
+
+                let value = Value::Closure(value::Closure {
+                    arg: arg,
+                    frame: frame,
+                    env: env_idx,
+                });
+                env.push((name, value));
+                machine.storage.push(Object::Env(env));
+                machine.push_value(value);
+            }
+            Fix(ref names) => {
+                let mut closures = Vec::with_capacity(names.len());
+                for _ in names {
+                    closures.push(try!(try!(machine.pop_value()).into_closure()));
+                }
+                closures.reverse();
+                // The environments are copied rather than patched, so that an
+                // old one never points to a younger closure.
+                let start = machine.storage.len();
+                let fixed = closures.iter()
+                                    .enumerate()
+                                    .map(|(i, closure)| {
+                                        Value::Closure(value::Closure { env: start + i, ..*closure })
+                                    })
+                                    .collect::<Vec<_>>();
+                for (closure, &value) in closures.iter().zip(&fixed) {
+                    let mut env = names.iter().cloned().zip(fixed.iter().cloned()).collect::<Env>();
+                    match machine.storage[closure.env] {
+                        Object::Env(ref captured) => env.extend_from_slice(captured),
+                        _ => return Err(fatal_error("not an environment")),
+                    }
+                    // `Closure` bound the function itself last, before it was fixed.
+                    env.last_mut().unwrap().1 = value;
+                    machine.storage.push(Object::Env(env));
+                }
+                match fixed.last() {
+                    Some(&value) => machine.push_value(value),
+                    None => return Err(fatal_error("empty recursive group")),
+                }
+            }
+            Call | TailCall => {
+                let arg_value = try!(machine.pop_value());
+                let fun = try!(machine.pop_value());
+                // The caller has nothing left to do, so its environment and code can go
+                if let TailCall = *self {
+                    try!(machine.pop_env());
+                }
+                if let Value::Native(native) = fun {
+                    let result = try!(machine.apply_native(native, arg_value));
+                    machine.push_value(result);
+                    return Ok(());
+                }
+                #[cfg(feature = "jit")]
+                {
+                    if let Value::Closure(closure) = fun {
+                        if let Some(result) = try!(machine.call_native(closure, arg_value)) {
+                            machine.push_value(result);
+                            return Ok(());
+                        }
+                    }
+                }
+                let value::Closure { arg, frame, env } = try!(fun.into_closure());
+                let mut env = match machine.storage[env] {
+                    Object::Env(ref env) => {
+                        let mut copy = Vec::with_capacity(env.len() + 1);
+                        copy.extend_from_slice(env);
+                        copy
+                    }
+                    _ => return Err(fatal_error("not an environment")),
+                };
+                env.push((arg, arg_value));
+                machine.environments.push(env);
+                machine.switch_frame(frame);
+            }
+            PopEnv => try!(machine.pop_env()),
+        }
+        Ok(())
+    }
+}
+
+impl Exec for ArithInstruction {
+    fn exec<'p>(&'p self, machine: &mut Machine<'p>) -> Result<()> {
+        use self::program::ArithInstruction::*;
+        let op2 = try!(machine.pop_value());
+        let op1 = try!(machine.pop_value());
+        if let Value::Float(op1) = op1 {
+            let op2 = try!(op2.into_float());
+            let ret = match *self {
+                Add => op1 + op2,
+                Sub => op1 - op2,
+                Mul => op1 * op2,
+                Div => op1 / op2,
+                Mod => op1 % op2,
+            };
+            machine.push_value(Value::Float(ret));
+            return Ok(());
+        }
+        let (op1, op2) = (try!(op1.into_int()), try!(op2.into_int()));
+        if op2 == 0 && (*self == Div || *self == Mod) {
+            return Err(runtime_error(RuntimeErrorKind::DivisionByZero));
+        }
+        let ret = match machine.arithmetic {
+            IntArithmetic::Checked => {
+                try!(self.checked(op1, op2).ok_or_else(|| runtime_error(RuntimeErrorKind::Overflow)))
+            }
+            IntArithmetic::Wrapping => self.wrapping(op1, op2),
+        };
+        machine.push_int(ret);
+        Ok(())
+    }
+}
+
+impl ArithInstruction {
+    /// `None` on overflow, of which `i64::MIN / -1` is the only case for
+    /// division.
+    fn checked(&self, op1: i64, op2: i64) -> Option<i64> {
+        use self::program::ArithInstruction::*;
+        match *self {
+            Add => op1.checked_add(op2),
+            Sub => op1.checked_sub(op2),
+            Mul => op1.checked_mul(op2),
+            Div => op1.checked_div(op2),
+            Mod => op1.checked_rem(op2),
+        }
+    }
+
+    fn wrapping(&self, op1: i64, op2: i64) -> i64 {
+        use self::program::ArithInstruction::*;
+        match *self {
+            Add => op1.wrapping_add(op2),
+            Sub => op1.wrapping_sub(op2),
+            Mul => op1.wrapping_mul(op2),
+            Div => op1.wrapping_div(op2),
+            Mod => op1.wrapping_rem(op2),
+        }
+    }
+}
+
+impl Exec for CmpInstruction {
+    fn exec<'p>(&'p self, machine: &mut Machine<'p>) -> Result<()> {
+        use self::program::CmpInstruction::*;
+        let op2 = try!(machine.pop_value());
+        let op1 = try!(machine.pop_value());
+        if let (&Eq, Value::Str(_)) | (&Ne, Value::Str(_)) = (self, op1) {
+            let eq = try!(machine.string(op1)) == try!(machine.string(op2));
+            machine.push_bool(if *self == Eq { eq } else { !eq });
+            return Ok(());
+        }
+        let ret = if let Value::Float(op1) = op1 {
+            self.compare(op1, try!(op2.into_float()))
+        } else {
+            self.compare(try!(op1.into_int()), try!(op2.into_int()))
+        };
+        machine.push_bool(ret);
+        Ok(())
+    }
+}
+
+impl CmpInstruction {
+    fn compare<T: PartialOrd>(self, op1: T, op2: T) -> bool {
+        use self::program::CmpInstruction::*;
+        match self {
+            Lt => op1 < op2,
+            Le => op1 <= op2,
+            Eq => op1 == op2,
+            Ne => op1 != op2,
+            Gt => op1 > op2,
+            Ge => op1 >= op2,
+        }
+    }
+}
+
+impl Exec for ListInstruction {
+    fn exec<'p>(&'p self, machine: &mut Machine<'p>) -> Result<()> {
+        use self::program::ListInstruction::*;
+        let list = try!(machine.pop_value());
+        let result = match *self {
+            Cons => {
+                match list {
+                    Value::Nil | Value::Cons(_) => {}
+                    _ => return Err(fatal_error("runtime type error")),
+                }
+                let head = try!(machine.pop_value());
+                machine.storage.push(Object::Cons(head, list));
+                Value::Cons(machine.storage.len() - 1)
+            }
+            Head if list == Value::Nil => return Err(runtime_error(RuntimeErrorKind::HeadOfEmptyList)),
+            Tail if list == Value::Nil => return Err(runtime_error(RuntimeErrorKind::TailOfEmptyList)),
+            Head => try!(machine.cell(list)).0,
+            Tail => try!(machine.cell(list)).1,
+            IsEmpty => {
+                match list {
+                    Value::Nil => Value::Bool(true),
+                    Value::Cons(_) => Value::Bool(false),
+                    _ => return Err(fatal_error("runtime type error")),
+                }
+            }
+        };
+        machine.push_value(result);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn push_instr<V: Into<Value<'static>>>(v: V) -> Instruction {
+        match v.into() {
+            Value::Int(i) => Instruction::PushInt(i),
+            Value::Float(x) => Instruction::PushFloat(x),
+            Value::Bool(b) => Instruction::PushBool(b),
+            _ => unreachable!(),
+        }
+    }
+
+    fn construct(tag: usize, name: &str, arg: bool) -> Instruction {
+        Instruction::Construct {
+            tag: tag,
+            name: name.to_owned(),
+            arg: arg,
+        }
+    }
+
+    /// `if` as the compiler lays it out.
+    fn branch_code(tru: Frame, fls: Frame) -> Frame {
+        let mut result = vec![Instruction::JumpIfFalse(tru.len() + 1)];
+        result.extend(tru);
+        result.push(Instruction::Jump(fls.len()));
+        result.extend(fls);
+        result
+    }
+
+    /// `match` as the compiler lays it out: each arm but the last jumps to the end.
+    fn case_code(arms: Vec<Frame>) -> Frame {
+        let lens = arms.iter().map(|arm| arm.len() + 1).collect::<Vec<_>>();
+        let offsets = (0..arms.len()).map(|i| lens[..i].iter().sum()).collect();
+        let mut result = vec![Instruction::Case(offsets)];
+        let count = arms.len();
+        for (i, arm) in arms.into_iter().enumerate() {
+            result.extend(arm);
+            if i + 1 < count {
+                let rest: usize = lens[i + 1..].iter().sum();
+                result.push(Instruction::Jump(rest - 1));
+            }
+        }
+        result
+    }
+
+    fn closure_code(name: Name, arg: Name, body: Frame) -> Frame {
+        let mut result = vec![Instruction::Closure {
+                                  name: name,
+                                  arg: arg,
+                                  len: body.len(),
+                                  captures: None,
+                              }];
+        result.extend(body);
+        result
+    }
+
+    macro_rules! secd {
+        ( (do $($tt:tt)*) ) => { secd![$($tt)*] };
+        () => { Frame::new() };
+        ( $( $tt:tt )+ ) => {{
+            let mut frame: Frame = Vec::new();
+            $( frame.extend(secd_code!($tt)); )*
+            frame
+        }};
+    }
+
+    macro_rules! secd_code {
+        ( (case $($arm:tt)*) ) => { case_code(vec![ $( secd![$arm] ),* ]) };
+        ( (branch $tru:tt $fls:tt) ) => { branch_code(secd![$tru], secd![$fls]) };
+        ( (clos ($name:expr, $arg:expr) $body:tt) ) => { closure_code($name, $arg, secd![$body]) };
+        ( $tt:tt ) => { vec![secd_instr!($tt)] };
+    }
+
+    macro_rules! secd_instr {
+        ( call ) => { Instruction::Call };
+        ( tailcall ) => { Instruction::TailCall };
+        ( ret ) => { Instruction::PopEnv };
+        ( add ) => { Instruction::ArithInstruction(ArithInstruction::Add) };
+        ( sub ) => { Instruction::ArithInstruction(ArithInstruction::Sub) };
+        ( mul ) => { Instruction::ArithInstruction(ArithInstruction::Mul) };
+        ( div ) => { Instruction::ArithInstruction(ArithInstruction::Div) };
+        ( mod ) => { Instruction::ArithInstruction(ArithInstruction::Mod) };
+        ( lt ) => { Instruction::CmpInstruction(CmpInstruction::Lt) };
+        ( eq ) => { Instruction::CmpInstruction(CmpInstruction::Eq) };
+        ( gt ) => { Instruction::CmpInstruction(CmpInstruction::Gt) };
+        ( le ) => { Instruction::CmpInstruction(CmpInstruction::Le) };
+        ( ne ) => { Instruction::CmpInstruction(CmpInstruction::Ne) };
+        ( ge ) => { Instruction::CmpInstruction(CmpInstruction::Ge) };
+        ( nil ) => { Instruction::PushNil };
+        ( unit ) => { Instruction::PushUnit };
+        ( pop ) => { Instruction::Pop };
+        ( print ) => { Instruction::Print };
+        ( concat ) => { Instruction::Concat };
+        ( int_of_float ) => { Instruction::IntOfFloat };
+        ( float_of_int ) => { Instruction::FloatOfInt };
+        ( cons ) => { Instruction::ListInstruction(ListInstruction::Cons) };
+        ( head ) => { Instruction::ListInstruction(ListInstruction::Head) };
+        ( tail ) => { Instruction::ListInstruction(ListInstruction::Tail) };
+        ( empty ) => { Instruction::ListInstruction(ListInstruction::IsEmpty) };
+        ( payload ) => { Instruction::Payload };
+        ( ref ) => { Instruction::Ref };
+        ( deref ) => { Instruction::Deref };
+        ( assign ) => { Instruction::Assign };
+        ( (construct $tag:expr, $name:expr) ) => { construct($tag, $name, false) };
+        ( (construct_with_arg $tag:expr, $name:expr) ) => { construct($tag, $name, true) };
+        ( (push $e:expr) ) => { push_instr($e) };
+        ( (push_str $e:expr) ) => { Instruction::PushStr($e.to_owned()) };
+        ( (record $($name:expr),*) ) => { Instruction::Record(vec![$($name.to_owned()),*]) };
+        ( (field $name:expr) ) => { Instruction::Field($name.to_owned()) };
+        ( (fix $($name:expr),*) ) => { Instruction::Fix(vec![$($name),*]) };
+        ( (var $e:expr) ) => { Instruction::Var($e) };
+        ( (slot $e:expr) ) => { Instruction::Slot($e) };
+    }
+
+    /// Runs `program` as it is and with its variables `resolve`d.
+    fn assert_execs<V: Into<Value<'static>>>(expected: V, program: Frame) {
+        let expected = expected.into();
+        let mut resolved = program.clone();
+        resolve(&mut resolved);
+        assert_execs_once(expected, &program);
+        assert_execs_once(expected, &resolved);
+    }
+
+    fn assert_execs_once(expected: Value<'static>, program: &Frame) {
+        let mut machine = Machine::new(program);
+        match machine.exec() {
+            Ok(value) => {
+                assert!(value == expected,
+                        "Wrong answer\nExpected {:?}\nGot {:?}\nMachine {:#?}",
+                        expected,
+                        value,
+                        machine)
+            }
+            Err(e) => assert!(false, "Machine panicked with error {:?}\n{:#?}", e, machine),
+        }
+    }
+
+    fn assert_fails(expected_message: &str, program: Frame) {
+        let mut machine = Machine::new(&program);
+        match machine.exec() {
+            Ok(_) => {
+                assert!(false,
+                        "Machine should have failed with {}\n{:#?}",
+                        expected_message,
+                        machine)
+            }
+            Err(e) => {
+                assert!(e.to_string().contains(expected_message),
+                        "Wrong error message.\nExpected: {}\nGot:      {}\n{:#?}",
+                        expected_message,
+                        e,
+                        machine)
+            }
+        }
+    }
+
+    #[test]
+    fn basic() {
+        assert_execs(92, secd![ (push 92) ]);
+        assert_fails("Fatal: empty stack :(", secd![]);
+        assert_fails("Fatal: more then one value on stack left :(",
+                     secd![(push 1)
+                           (push 2)]);
+        assert_execs(92, secd![(push 1) pop (push 92)]);
+        assert_fails("Fatal: empty stack :(", secd![unit pop pop]);
+    }
+
+    #[test]
+    fn arith() {
+        assert_execs(92, secd![(push 90) (push 2) add]);
+        assert_execs(92, secd![(push 94) (push 2) sub]);
+        assert_execs(92, secd![(push 46) (push 2) mul]);
+        assert_execs(92, secd![(push 184) (push 2) div]);
+        assert_fails("Division by zero", secd![(push 1) (push 0) div]);
+        assert_execs(2, secd![(push 92) (push 10) mod]);
+        assert_execs(-2, secd![(push -92) (push 10) mod]);
+        assert_fails("Division by zero", secd![(push 1) (push 0) mod]);
+        assert_fails("Fatal: empty stack :(", secd![add]);
+        assert_fails("Fatal: runtime type error :(",
+                     secd![(push 1) (push true) add]);
+
+        assert_execs(92.5, secd![(push 90.25) (push 2.25) add]);
+        assert_execs(0.5, secd![(push 1.0) (push 2.0) div]);
+        assert_execs(f64::INFINITY, secd![(push 1.0) (push 0.0) div]);
+        assert_execs(1.5, secd![(push 5.5) (push 2.0) mod]);
+        assert_fails("Fatal: runtime type error :(",
+                     secd![(push 1.0) (push 1) add]);
+    }
+
+    #[test]
+    fn overflow() {
+        assert_fails("Integer overflow", secd![(push i64::MAX) (push 1) add]);
+        assert_fails("Integer overflow", secd![(push i64::MIN) (push 1) sub]);
+        assert_fails("Integer overflow", secd![(push i64::MAX) (push 2) mul]);
+        assert_fails("Integer overflow", secd![(push i64::MIN) (push -1) div]);
+        assert_fails("Integer overflow", secd![(push i64::MIN) (push -1) mod]);
+
+        let program = secd![(push i64::MAX) (push 1) add];
+        let mut machine = Machine::new(&program).with_arithmetic(IntArithmetic::Wrapping);
+        assert_eq!(machine.exec().unwrap(), Value::Int(i64::MIN));
+        let program = secd![(push i64::MIN) (push -1) mod];
+        let mut machine = Machine::new(&program).with_arithmetic(IntArithmetic::Wrapping);
+        assert_eq!(machine.exec().unwrap(), Value::Int(0));
+        let program = secd![(push 1) (push 0) div];
+        let mut machine = Machine::new(&program).with_arithmetic(IntArithmetic::Wrapping);
+        assert_eq!(machine.exec().unwrap_err().kind, RuntimeErrorKind::DivisionByZero);
+    }
+
+    #[test]
+    fn float_conversions() {
+        assert_execs(92, secd![(push 92.9) int_of_float]);
+        assert_execs(-92, secd![(push -92.9) int_of_float]);
+        assert_execs(i64::MAX, secd![(push 1e30) int_of_float]);
+        assert_execs(92.0, secd![(push 92) float_of_int]);
+        assert_fails("Fatal: runtime type error :(", secd![(push 92) int_of_float]);
+    }
+    #[test]
+    fn cmp() {
+        assert_execs(false, secd![(push 92) (push 62) lt]);
+        assert_execs(true, secd![(push 92) (push 62) gt]);
+        assert_execs(false, secd![(push 1) (push 2) eq]);
+        assert_execs(true, secd![(push 2) (push 2) eq]);
+        assert_execs(true, secd![(push 2) (push 2) le]);
+        assert_execs(false, secd![(push 1) (push 2) ge]);
+        assert_execs(true, secd![(push 1) (push 2) ne]);
+        assert_execs(true, secd![(push 0.5) (push 2.0) lt]);
+        assert_execs(false, secd![(push 0.5) (push 0.5) ne]);
+
+        assert_fails("Fatal: runtime type error :(",
+                     secd![(push 1) (push true) eq]);
+        assert_fails("Fatal: runtime type error :(",
+                     secd![(push true) (push false) eq]);
+    }
+    #[test]
+    fn branch() {
+        assert_execs(92,
+                     secd![(push true)
+                           (branch
+                               (push 92)
+                               (push 62))]);
+
+        assert_execs(62,
+                     secd![(push false)
+                           (branch
+                               (push 92)
+                               (push 62))]);
+
+        assert_execs(92,
+                     secd![(push true)
+                           (branch
+                               (push 41)
+                               (push 51))
+                           (push false)
+                           (branch
+                               (push 41)
+                               (push 51))
+                           add]);
+        assert_fails("Fatal: runtime type error :(",
+                     secd![(push 92)
+                           (branch
+                               (push true)
+                               (push false))]);
+    }
+
+    #[test]
+    fn lists() {
+        assert_execs(true, secd![nil empty]);
+        assert_execs(false, secd![(push 1) nil cons empty]);
+        assert_execs(2, secd![(push 1) (push 2) nil cons cons tail head]);
+        assert_fails("Head of an empty list", secd![nil head]);
+        assert_fails("Tail of an empty list", secd![nil tail]);
+        assert_fails("Fatal: runtime type error :(", secd![(push 1) (push 2) cons]);
+        assert_fails("Fatal: runtime type error :(", secd![(push 1) head]);
+
+        let program = secd![(push 1) (push true) nil cons cons];
+        let mut machine = Machine::new(&program);
+        let list = machine.exec().unwrap();
+        assert_eq!(machine.show(list), "[1, true]");
+        assert_eq!(machine.show(Value::Nil), "[]");
+        assert_eq!(list.to_string(), "<list>");
+    }
+
+    #[test]
+    fn lists_survive_gc() {
+        // Builds the list [0, 1, ..., 99] recursively. Each element is put into a
+        // temporary cell first, which becomes garbage right away.
+        let range = secd![
+            (clos (0, 1) (do
+                (var 1)
+                (push 0)
+                eq
+                (branch
+                    nil
+                    (do
+                        (push 100)
+                        (var 1)
+                        sub
+                        nil
+                        cons
+                        head
+                        (var 0)
+                        (var 1)
+                        (push 1)
+                        sub
+                        call
+                        cons))
+                ret))
+            (push 100)
+            call
+        ];
+        let mut machine = Machine::new(&range);
+        let list = machine.exec().unwrap();
+        assert!(machine.steps() > 92 * 10);
+        let items = machine.list(list).unwrap();
+        assert_eq!(items.len(), 100);
+        assert_eq!(items[0], Value::Int(0));
+        assert_eq!(items[99], Value::Int(99));
+        assert!(machine.heap_size() < 150, "garbage wasn't collected: {}", machine.heap_size());
+    }
+
+    #[test]
+    fn variants() {
+        assert_execs(92, secd![(push 92) (construct_with_arg 1, "Some") payload]);
+        assert_execs(62,
+                     secd![(construct 0, "None")
+                           (case
+                               (push 62)
+                               (push 92))]);
+        assert_execs(92,
+                     secd![(push 1)
+                           (construct_with_arg 1, "Some")
+                           (case
+                               (push 62)
+                               (push 92))]);
+        assert_fails("Fatal: the constructor has no argument :(",
+                     secd![(construct 0, "None") payload]);
+        assert_fails("Fatal: no case for the constructor :(",
+                     secd![(construct 2, "Other") (case (push 62) (push 92))]);
+        assert_fails("Fatal: runtime type error :(", secd![(push 1) payload]);
+
+        let program = secd![(push 1)
+                            nil
+                            cons
+                            (construct_with_arg 1, "Some")
+                            (construct_with_arg 1, "Some")];
+        let mut machine = Machine::new(&program);
+        let value = machine.exec().unwrap();
+        assert_eq!(machine.show(value), "Some (Some [1])");
+        assert_eq!(value.to_string(), "<variant>");
+    }
+
+    #[test]
+    fn records() {
+        assert_execs(92, secd![(push 92) (push true) (record "x", "y") (field "x")]);
+        assert_execs(true, secd![(push true) (push 92) (record "y", "x") (field "y")]);
+        assert_fails("Fatal: no such field :(", secd![(push 92) (record "x") (field "y")]);
+        assert_fails("Fatal: runtime type error :(", secd![(push 92) (field "x")]);
+
+        let program = secd![(push 1) nil cons (push_str "a") (construct 0, "None") (record "z", "a", "b")];
+        let mut machine = Machine::new(&program);
+        let value = machine.exec().unwrap();
+        assert_eq!(machine.show(value), r#"{a = "a", b = None, z = [1]}"#);
+        assert_eq!(value.to_string(), "<record>");
+    }
+
+    #[test]
+    fn records_survive_gc() {
+        // Counts down from 100 in the field of a record, making a new record
+        // at each step.
+        let program = secd![
+            (clos (0, 1) (do
+                (var 1)
+                (field "n")
+                (push 0)
+                eq
+                (branch
+                    (var 1)
+                    (do
+                        (var 0)
+                        (var 1)
+                        (field "n")
+                        (push 1)
+                        sub
+                        (record "n")
+                        call))
+                ret))
+            (push 100)
+            (record "n")
+            call
+        ];
+        let mut machine = Machine::new(&program);
+        let record = machine.exec().unwrap();
+        assert!(machine.steps() > 92 * 10);
+        assert_eq!(machine.record(record).unwrap(), &[("n", Value::Int(0))]);
+        assert!(machine.heap_size() < 150, "garbage wasn't collected: {}", machine.heap_size());
+    }
+
+    /// `even` and `odd`, calling each other, with `odd` left on the stack.
+    fn even_odd() -> Frame {
+        secd![
+            (clos (0, 2) (do
+                (var 2) (push 0) eq
+                (branch (push true) (do (var 1) (var 2) (push 1) sub call))
+                ret))
+            (clos (1, 3) (do
+                (var 3) (push 0) eq
+                (branch (push false) (do (var 0) (var 3) (push 1) sub call))
+                ret))
+            (fix 0, 1)
+        ]
+    }
+
+    #[test]
+    fn mutual_recursion() {
+        let mut program = even_odd();
+        program.extend(secd![(push 7) call]);
+        assert_execs(true, program);
+        let mut program = even_odd();
+        program.extend(secd![(push 92) call]);
+        assert_execs(false, program);
+
+        let mut program = even_odd();
+        program.extend(secd![(push 500) call]);
+        resolve(&mut program);
+        for &generational in &[false, true] {
+            let config = GcConfig {
+                trigger: GcTrigger::Steps(1),
+                generational: generational,
+            };
+            let mut machine = Machine::new(&program).with_gc(config);
+            assert_eq!(machine.exec().unwrap(), Value::Bool(false));
+        }
+    }
+
+    #[test]
+    fn snapshot() {
+        // The result of `odd 9` in a `Some` in a record.
+        let mut program = even_odd();
+        program.extend(secd![(push 9) call (construct_with_arg 1, "Some") (record "x")]);
+        resolve(&mut program);
+        let mut machine = Machine::new(&program);
+        for _ in 0..20 {
+            assert!(is_running(machine.step()));
+        }
+        let state = machine.snapshot().unwrap();
+        assert_eq!(state.steps(), 20);
+        let result = machine.exec().unwrap();
+        assert_eq!(machine.show(result), "{x = Some true}");
+
+        machine.restore(&state).unwrap();
+        assert_eq!(machine.steps(), 20);
+        let result = machine.exec().unwrap();
+        assert_eq!(machine.show(result), "{x = Some true}");
+
+        let mut machine = Machine::new(&program);
+        machine.restore(&state).unwrap();
+        let result = machine.exec().unwrap();
+        assert_eq!(machine.show(result), "{x = Some true}");
+
+        let other = secd![(push 92)];
+        let mut machine = Machine::new(&other);
+        assert!(machine.restore(&state).is_err());
+
+        let other = secd![(clos (0, 1) (do (var 1) ret))];
+        let closure = Machine::new(&other).exec().unwrap();
+        let mut machine = Machine::new(&program);
+        machine.define(0, closure);
+        assert_eq!(machine.snapshot().unwrap_err().to_string(),
+                   "Fatal: code outside of the programs of the machine :(");
+    }
+
+    #[test]
+    fn profile() {
+        // `odd 3`, which calls `even 2`, `odd 1` and `even 0`.
+        let mut program = even_odd();
+        program.extend(secd![(push 3) call]);
+        let mut machine = Machine::new(&program).with_profiling();
+        assert_eq!(machine.exec().unwrap(), Value::Bool(true));
+        let profile = machine.profile().unwrap();
+        assert_eq!(profile.steps(), machine.steps());
+        assert_eq!(profile.opcodes["call"], 4);
+        let functions = profile.functions(0, &program);
+        assert_eq!(functions[&None], 5);
+        assert_eq!((functions[&Some(0)], functions[&Some(1)]), (17, 20));
+        assert_eq!(functions.values().sum::<u64>(), machine.steps());
+
+        assert!(Machine::new(&program).profile().is_none());
+    }
+
+    #[test]
+    fn refs() {
+        assert_execs(92, secd![(push 90) ref deref (push 2) add]);
+        // (fun f(r) is r := 46; !r + !r) (ref 0)
+        assert_execs(92,
+                     secd![(clos (0, 1) (do (var 1) (push 46) assign pop (var 1) deref (var 1) deref add ret))
+                           (push 0)
+                           ref
+                           call]);
+        assert_fails("Fatal: runtime type error :(", secd![(push 1) deref]);
+        assert_fails("Fatal: runtime type error :(", secd![(push 1) (push 2) assign]);
+        let program = secd![(push 0) ref (push 1) assign];
+        let mut machine = Machine::new(&program);
+        let unit = machine.exec().unwrap();
+        assert_eq!(unit, Value::Unit);
+        assert_eq!(machine.show(unit), "()");
+
+        let program = secd![(push 1) (construct_with_arg 1, "Some") ref ref (push 2) ref nil cons cons];
+        let mut machine = Machine::new(&program);
+        let list = machine.exec().unwrap();
+        assert_eq!(machine.show(list), "[ref (ref (Some 1)), ref 2]");
+        assert_eq!(machine.list(list).unwrap()[1].to_string(), "<ref>");
+
+        // (fun f(r) is r := Node r; !r) (ref 0)
+        let program = secd![(clos (0, 1) (do (var 1) (var 1) (construct_with_arg 0, "Node") assign pop (var 1) deref ret))
+                            (push 0)
+                            ref
+                            call];
+        let mut machine = Machine::new(&program);
+        let node = machine.exec().unwrap();
+        assert_eq!(machine.show(node), "Node (ref (Node (<cycle>)))");
+    }
+
+    #[test]
+    fn print() {
+        let mut output = Vec::new();
+        {
+            let program = secd![(push_str "hello") print pop (push 1) nil cons print pop (push 92) print];
+            let mut machine = Machine::new(&program).with_output(&mut output);
+            assert_eq!(machine.exec().unwrap(), Value::Unit);
+        }
+        assert_eq!(String::from_utf8(output).unwrap(), "hello\n[1]\n92\n");
+
+        struct Closed;
+
+        impl io::Write for Closed {
+            fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+                Err(io::Error::new(io::ErrorKind::BrokenPipe, "closed"))
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let program = secd![(push 1) print];
+        let mut machine = Machine::new(&program).with_output(Closed);
+        assert_eq!(machine.exec().unwrap_err().to_string(), "Can't write output: closed");
+    }
+
+    #[test]
+    fn natives() {
+        fn add<'p>(args: &[Value<'p>]) -> Result<Value<'p>> {
+            Ok(Value::Int(try!(args[0].into_int()) + try!(args[1].into_int())))
+        }
+
+        let calls = ::std::cell::Cell::new(0);
+        // let inc = add 1 in inc (inc 90)
+        let program = secd![(clos (1, 2) (do (var 2) (var 2) (push 90) call call ret))
+                            (var 0)
+                            (push 1)
+                            call
+                            call];
+        for &generational in &[false, true] {
+            let config = GcConfig {
+                trigger: GcTrigger::Steps(1),
+                generational: generational,
+            };
+            let mut machine = Machine::new(&program).with_gc(config);
+            machine.register_native(0, "add", 2, |args| {
+                calls.set(calls.get() + 1);
+                add(args)
+            });
+            assert_eq!(machine.exec().unwrap(), Value::Int(92));
+        }
+        assert_eq!(calls.get(), 4);
+
+        let program = secd![(var 0) (push 1) call];
+        let mut machine = Machine::new(&program);
+        machine.register_native(0, "add", 2, add);
+        let inc = machine.exec().unwrap();
+        assert_eq!(machine.show(inc), "<builtin add>");
+
+        let program = secd![(var 0) (push 1) call];
+        let mut machine = Machine::new(&program);
+        machine.register_native(0, "fail", 1, |_| Err(RuntimeErrorKind::Native("no".to_owned()).into()));
+        assert_eq!(machine.exec().unwrap_err().kind, RuntimeErrorKind::Native("no".to_owned()));
+    }
+
+    #[test]
+    fn strings() {
+        assert_execs(true, secd![(push_str "ab") (push_str "a") (push_str "b") concat eq]);
+        assert_execs(false, secd![(push_str "a") (push_str "b") eq]);
+        assert_execs(true, secd![(push_str "a") (push_str "b") ne]);
+        assert_fails("Fatal: runtime type error :(", secd![(push_str "a") (push 1) concat]);
+        assert_fails("Fatal: runtime type error :(", secd![(push_str "a") (push 1) eq]);
+        assert_fails("Fatal: runtime type error :(", secd![(push_str "a") (push_str "b") lt]);
+
+        let program = secd![(push_str "say \"hi\"") (push_str "\n") concat nil cons];
+        let mut machine = Machine::new(&program);
+        let list = machine.exec().unwrap();
+        assert_eq!(machine.show(list), r#"["say \"hi\"\n"]"#);
+        let string = machine.list(list).unwrap()[0];
+        assert_eq!(machine.string(string).unwrap(), "say \"hi\"\n");
+        assert_eq!(string.to_string(), "<string>");
+    }
+
+    #[test]
+    fn strings_survive_gc() {
+        let program = repeat_a(100);
+        let mut machine = Machine::new(&program);
+        let string = machine.exec().unwrap();
+        assert!(machine.steps() > 92 * 10);
+        assert_eq!(machine.string(string).unwrap(), &"a".repeat(100)[..]);
+        assert!(machine.heap_size() < 50, "garbage wasn't collected: {}", machine.heap_size());
+    }
+
+    #[test]
+    fn gc_config() {
+        let program = repeat_a(100);
+        let config = GcConfig { trigger: GcTrigger::Never, ..GcConfig::default() };
+        let mut machine = Machine::new(&program).with_gc(config);
+        machine.exec().unwrap();
+        assert_eq!(machine.gc_stats(), GcStats::default());
+        assert!(machine.heap_size() > 200);
+
+        let config = GcConfig { trigger: GcTrigger::Steps(10), ..GcConfig::default() };
+        let mut machine = Machine::new(&program).with_gc(config);
+        machine.exec().unwrap();
+        let stats = machine.gc_stats();
+        assert_eq!(stats.collections, machine.steps() / 10);
+        assert_eq!(stats.major_collections, stats.collections);
+        assert!(stats.reclaimed > 100, "{:?}", stats);
+
+        let config = GcConfig { trigger: GcTrigger::HeapSize(20), ..GcConfig::default() };
+        let mut machine = Machine::new(&program).with_gc(config);
+        let string = machine.exec().unwrap();
+        assert_eq!(machine.string(string).unwrap(), &"a".repeat(100)[..]);
+        assert!(machine.gc_stats().collections > 0);
+        assert!(machine.heap_size() < 150, "garbage wasn't collected: {}", machine.heap_size());
+    }
+
+    #[test]
+    fn generational_gc() {
+        let program = repeat_a(1000);
+        let config = GcConfig { generational: true, ..GcConfig::default() };
+        let mut machine = Machine::new(&program).with_gc(config);
+        let string = machine.exec().unwrap();
+        assert_eq!(machine.string(string).unwrap(), &"a".repeat(1000)[..]);
+        let stats = machine.gc_stats();
+        // The shorter strings die young, so they never reach a major collection.
+        assert_eq!(stats.major_collections, 0);
+        assert!(stats.reclaimed > 1000, "{:?}", stats);
+        assert!(machine.heap_size() < 100, "garbage wasn't collected: {}", machine.heap_size());
+    }
+
+    #[test]
+    fn generational_gc_with_refs() {
+        // Appends "a" to the string in a cell `n` times. The cell gets old
+        // early, and then holds a young string at every collection.
+        let program = secd![
+            (clos (0, 1) (do
+                (clos (2, 3) (do
+                    (var 3)
+                    (push 0)
+                    eq
+                    (branch
+                        (do (var 1) deref ret)
+                        (do
+                            (clos (4, 5) (do (var 2) (var 3) (push 1) sub tailcall))
+                            (var 1)
+                            (var 1)
+                            deref
+                            (push_str "a")
+                            concat
+                            assign
+                            tailcall))))
+                (push 1000)
+                tailcall))
+            (push_str "")
+            ref
+            call
+        ];
+        let config = GcConfig { generational: true, ..GcConfig::default() };
+        let mut machine = Machine::new(&program).with_gc(config);
+        let string = machine.exec().unwrap();
+        assert_eq!(machine.string(string).unwrap(), &"a".repeat(1000)[..]);
+        let stats = machine.gc_stats();
+        assert!(stats.collections > stats.major_collections + 100, "{:?}", stats);
+    }
+
+    /// Appends "a" to a string `n` times, leaving the shorter strings as garbage.
+    fn repeat_a(n: i64) -> Frame {
+        secd![
+            (clos (0, 1) (do
+                (var 1)
+                (push 0)
+                eq
+                (branch
+                    (push_str "")
+                    (do
+                        (var 0)
+                        (var 1)
+                        (push 1)
+                        sub
+                        call
+                        (push_str "a")
+                        concat))
+                ret))
+            (push n)
+            call
+        ]
+    }
+
+    /// Counts down from `n` to zero, ending each step with `call`.
+    fn count_down(n: i64, call: Frame) -> Frame {
+        let mut step = secd![(var 0) (var 1) (push 1) sub];
+        step.extend(call);
+        let mut body = secd![(var 1) (push 0) eq];
+        body.extend(branch_code(secd![(push 92) ret], step));
+        let mut program = closure_code(0, 1, body);
+        program.extend(secd![(push n) call]);
+        program
+    }
+
+    fn max_depth(program: &Frame) -> usize {
+        let mut machine = Machine::new(program);
+        let mut max_depth = 0;
+        loop {
+            max_depth = max_depth.max(machine.depth());
+            if let Status::Finished(result) = machine.step() {
+                assert_eq!(result, Value::Int(92));
+                return max_depth;
+            }
+        }
+    }
+
+    #[test]
+    fn tail_calls() {
+        assert_eq!(max_depth(&count_down(1000, secd![call ret])), 1001);
+        assert_eq!(max_depth(&count_down(100000, secd![tailcall])), 1);
+        assert_execs(92, count_down(0, secd![tailcall]));
+        assert_execs(6,
+                     secd![(clos (0, 1) (do (var 1) (push 3) tailcall))
+                           (clos (2, 3) (do (var 3) (push 2) mul ret))
+                           call]);
+    }
+
+    #[test]
+    fn step() {
+        let program = secd![(clos (0, 1) (do (var 1) ret)) (push 92) call];
+        let mut machine = Machine::new(&program);
+        for _ in 0..3 {
+            assert!(is_running(machine.step()));
+        }
+        assert_eq!(machine.depth(), 1);
+        assert_eq!(machine.env()[&1], Value::Int(92));
+        assert_eq!(format!("{}", machine.current_frame().unwrap()[0]), "var 1");
+        assert!(is_running(machine.step()));
+        assert_eq!(machine.values(), &[Value::Int(92)]);
+        assert!(is_running(machine.step()));
+        assert_eq!(machine.depth(), 0);
+        match machine.step() {
+            Status::Finished(value) => assert_eq!(value, Value::Int(92)),
+            status => panic!("expected a result, got {:?}", status),
+        }
+
+        let program = secd![(push 1) (push 0) div];
+        let mut machine = Machine::new(&program);
+        assert!(is_running(machine.step()) && is_running(machine.step()));
+        match machine.step() {
+            Status::Error(e) => assert_eq!(e.kind, RuntimeErrorKind::DivisionByZero),
+            status => panic!("expected an error, got {:?}", status),
+        }
+    }
+
+    fn is_running(status: Status) -> bool {
+        match status {
+            Status::Running => true,
+            _ => false,
+        }
+    }
+
+    #[test]
+    fn run() {
+        let define = secd![(push 90)];
+        let fail = secd![(push 1) (push 0) div];
+        let use_defined = secd![(var 0) (push 2) add];
+        let mut machine = Machine::empty();
+        let value = machine.run(&define).unwrap();
+        machine.define(0, value);
+        assert!(machine.run(&fail).is_err());
+        assert_eq!(machine.run(&use_defined).unwrap(), Value::Int(92));
+    }
+
+    #[test]
+    fn release_programs() {
+        let closure = secd![(clos (0, 1) (do (var 1) ret))];
+        let variant = secd![(construct 0, "None")];
+        let result = secd![(push 92)];
+        let call = secd![(var 0) (var 1) call];
+        let mut machine = Machine::empty();
+        let value = machine.run(&closure).unwrap();
+        machine.define(0, value);
+        let value = machine.run(&variant).unwrap();
+        machine.define(1, value);
+        assert_eq!(machine.run(&result).unwrap(), Value::Int(92));
+        assert_eq!(machine.release_programs(), vec![&result as *const Frame]);
+
+        let value = machine.run(&call).unwrap();
+        assert_eq!(machine.show(value), "None");
+        machine.define(0, Value::Unit);
+        assert_eq!(machine.release_programs(), vec![&closure as *const Frame, &call as *const Frame]);
+        assert!(machine.release_programs().is_empty());
+    }
+
+    #[test]
+    fn trace() {
+        let program = secd![(clos (0, 1) (do (var 1) ret)) (push 92) call];
+        let mut machine = Machine::new(&program);
+        machine.start_trace(4);
+        machine.exec().unwrap();
+        let trace = machine.take_trace().unwrap();
+        let trace = trace.instructions
+                         .iter()
+                         .map(|&(depth, inst)| format!("{} {}", depth, inst))
+                         .collect::<Vec<_>>();
+        assert_eq!(trace, ["0 clos (0, 1) [2]", "0 push 92", "0 call", "1 var 1"]);
+        assert_eq!(machine.take_trace().map(|trace| trace.omitted), None);
+    }
+
+    #[test]
+    fn disassemble() {
+        let program = secd![
+            (clos (0, 1) (do (var 1) (push 0) eq (branch (push 1) (push 2)) ret))
+            (push 92)
+            call
+        ];
+        assert_eq!(super::disassemble(&program), "   0 clos (0, 1) [8]
+     fun 0(1):
+   1     var 1
+   2     push 0
+   3     eq
+   4     jumpf +2  -> 7
+   5     push 1
+   6     jump +1  -> 8
+   7     push 2
+   8     ret
+   9 push 92
+  10 call");
+    }
+
+    #[test]
+    fn tracer() {
+        let program = secd![(clos (0, 1) (do (var 1) ret)) (push 92) call];
+        let mut seen = Vec::new();
+        {
+            let mut machine = Machine::new(&program);
+            machine.set_tracer(|inst: &Instruction, s: &MachineSnapshot| {
+                seen.push(format!("{} {} {} {}", s.steps, s.stack_depth, s.environments, inst))
+            });
+            assert_eq!(machine.exec().unwrap(), Value::Int(92));
+        }
+        assert_eq!(seen, ["0 0 1 clos (0, 1) [2]", "1 1 1 push 92", "2 2 1 call", "3 0 2 var 1", "4 1 2 ret"]);
+    }
+
+    #[test]
+    fn fuel() {
+        // `let fun loop(x: int): int is loop x in loop 0`
+        let program = secd![(clos (0, 1) (do (var 0) (var 1) tailcall)) (push 0) call];
+        let mut machine = Machine::new(&program);
+        assert_eq!(machine.exec_with_fuel(1000).unwrap_err().kind, RuntimeErrorKind::OutOfFuel);
+        assert_eq!(machine.steps(), 1000);
+
+        let program = secd![(push 90) (push 2) add];
+        let mut machine = Machine::new(&program).with_limits(3);
+        assert_eq!(machine.exec().unwrap(), Value::Int(92));
+        let mut machine = Machine::new(&program).with_limits(2);
+        assert_eq!(machine.exec().unwrap_err().kind, RuntimeErrorKind::OutOfFuel);
+        assert_eq!(machine.exec_with_fuel(10).unwrap(), Value::Int(92));
+    }
+
+    #[test]
+    fn vars() {
+        assert_execs(92,
+                     secd![(clos (0, 1) (var 1))
+                               (push 92)
+                               call]);
+
+        assert_fails("Undefined variable v92", secd![(var 92)]);
+        let error = Machine::new(&secd![(var 92)]).exec().unwrap_err();
+        assert_eq!(error.kind, RuntimeErrorKind::UndefinedVariable(92));
+        let error = error.with_names(|name| if name == 92 { Some("even".to_string()) } else { None });
+        assert_eq!(error.to_string(), "Undefined variable 'even'");
+    }
+
+    #[test]
+    fn slots() {
+        // (fun f(x) is fun g(y) is x + y + z) 40 2
+        let mut program = secd![
+            (clos (0, 1) (do (clos (2, 3) (do (var 1) (var 3) add (var 5) add ret)) ret))
+            (push 40)
+            call
+            (push 2)
+            call
+        ];
+        resolve(&mut program);
+        assert_eq!(super::disassemble(&program), "   0 clos (0, 1) [8] {5}
+     fun 0(1):
+   1     clos (2, 3) [6] {1, 5}
+         fun 2(3):
+   2         slot 3
+   3         slot 0
+   4         add
+   5         slot 2
+   6         add
+   7         ret
+   8     ret
+   9 push 40
+  10 call
+  11 push 2
+  12 call");
+        let mut machine = Machine::new(&program);
+        machine.define(5, Value::Int(50));
+        assert_eq!(machine.exec().unwrap(), Value::Int(92));
+
+        assert_fails("Fatal: undefined variable :(", secd![(slot 0)]);
+    }
+
+    #[test]
+    fn factorial() {
+        let factorial = secd![
+            (clos (0, 1) (do
+                (push 0)
+                (var 1)
+                eq
+                (branch
+                    (push 1)
+                    (do
+                        (var 1)
+                        (var 0)
+                        (var 1)
+                        (push 1)
+                        sub
+                        call
+                        mul))
+                ret))
+            (push 5)
+            call
+        ];
+        assert_execs(120, factorial);
+    }
+
+    #[test]
+    fn hof() {
+        let apply_twice = secd![
+            (clos (0, 1) (do
+                (clos (2, 3) (do
+                    (var 1)
+                    (var 1)
+                    (var 3)
+                    call
+                    call
+                    ret))
+                ret))
+            (clos (0, 1) (do
+                (var 1)
+                (var 1)
+                add
+                ret))
+            call
+            (push 23)
+            call
+        ];
+
+        assert_execs(92, apply_twice);
+    }
+
+    /// `fib n`, which calls itself twice.
+    #[cfg(feature = "jit")]
+    fn fib(n: i64) -> Frame {
+        secd![
+            (clos (0, 1) (do
+                (var 1) (push 2) lt
+                (branch
+                    (var 1)
+                    (do (var 0) (var 1) (push 1) sub call (var 0) (var 1) (push 2) sub call add))
+                ret))
+            (push n)
+            call
+        ]
+    }
+
+    /// Runs `program` with the JIT, as it is and with its variables
+    /// `resolve`d, and checks that it comes to the same result in as many
+    /// steps as without, having compiled `compiled` functions.
+    #[cfg(feature = "jit")]
+    fn assert_jit_agrees(program: Frame, arithmetic: IntArithmetic, compiled: usize) {
+        let mut resolved = program.clone();
+        resolve(&mut resolved);
+        for program in &[program, resolved] {
+            let mut machine = Machine::new(program).with_arithmetic(arithmetic);
+            let expected = machine.exec().map_err(|e| e.kind);
+            let mut jit = Machine::new(program).with_arithmetic(arithmetic).with_jit();
+            assert_eq!(jit.exec().map_err(|e| e.kind), expected);
+            assert_eq!(jit.steps(), machine.steps());
+            assert_eq!(jit.jit.as_ref().unwrap().compiled(), compiled, "{}", ::disassemble(program));
+        }
+    }
+
+    #[cfg(feature = "jit")]
+    #[test]
+    fn jit() {
+        assert_jit_agrees(fib(20), IntArithmetic::Checked, 1);
+
+        // `tak 18 12 6`, with the arguments one by one.
+        let tak = secd![
+            (clos (0, 1) (do
+                (clos (2, 3) (do
+                    (clos (4, 5) (do
+                        (var 3) (var 1) lt
+                        (branch
+                            (do
+                                (var 0) (var 0) (var 1) (push 1) sub call (var 3) call (var 5) call call
+                                (var 0) (var 3) (push 1) sub call (var 5) call (var 1) call call
+                                (var 0) (var 5) (push 1) sub call (var 1) call (var 3) call call)
+                            (var 5))
+                        ret))
+                    ret))
+                ret))
+            (push 18) call (push 12) call (push 6) call
+        ];
+        assert_jit_agrees(tak, IntArithmetic::Checked, 1);
+
+        // `fact 100`, which overflows, or wraps around to 0.
+        let fact = secd![
+            (clos (0, 1) (do
+                (var 1) (push 0) eq
+                (branch (push 1) (do (var 1) (var 0) (var 1) (push 1) sub call mul))
+                ret))
+            (push 100)
+            call
+        ];
+        assert_jit_agrees(fact.clone(), IntArithmetic::Checked, 1);
+        assert_jit_agrees(fact, IntArithmetic::Wrapping, 1);
+
+        // A loop which divides by zero at the end.
+        let countdown = secd![
+            (clos (0, 1) (do
+                (var 1) (push 0) eq
+                (branch (do (push 1) (var 1) div) (do (var 0) (var 1) (push 1) sub tailcall))
+                ret))
+            (push 1000)
+            call
+        ];
+        assert_jit_agrees(countdown, IntArithmetic::Checked, 1);
+
+        // Too deep for native code, which leaves it to the machine.
+        let sum = secd![
+            (clos (0, 1) (do
+                (var 1) (push 0) eq
+                (branch (push 0) (do (var 1) (var 0) (var 1) (push 1) sub call add))
+                ret))
+            (push 5000)
+            call
+        ];
+        assert_jit_agrees(sum, IntArithmetic::Checked, 0);
+
+        // Floats are left to the machine as well.
+        let countdown = secd![
+            (clos (0, 1) (do
+                (var 1) (push 0.0) le
+                (branch (var 1) (do (var 0) (var 1) (push 1.0) sub tailcall))
+                ret))
+            (push 100.0)
+            call
+        ];
+        assert_jit_agrees(countdown, IntArithmetic::Checked, 0);
+    }
+
+    #[cfg(feature = "jit")]
+    #[test]
+    fn jit_with_fuel() {
+        let program = fib(20);
+        let steps = {
+            let mut machine = Machine::new(&program);
+            machine.exec().unwrap();
+            machine.steps()
+        };
+        for &fuel in &[1000, steps - 1] {
+            let mut machine = Machine::new(&program).with_jit();
+            assert_eq!(machine.exec_with_fuel(fuel).unwrap_err().kind, RuntimeErrorKind::OutOfFuel);
+            assert_eq!(machine.steps(), fuel);
+        }
+        let mut machine = Machine::new(&program).with_jit();
+        assert_eq!(machine.exec_with_fuel(steps).unwrap(), Value::Int(6765));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde() {
+        let frame = vec![
+            construct(1, "Some", true),
+            Instruction::Closure {
+                name: 0,
+                arg: 2,
+                len: 1,
+                captures: Some(vec![4]),
+            },
+            Instruction::ArithInstruction(ArithInstruction::Add),
+            Instruction::PushFloat(1.5),
+            Instruction::Fix(vec![0, 1]),
+        ];
+        let json = ::serde_json::to_string(&frame).unwrap();
+        assert!(json.contains(r#"{"Fix":[0,1]}"#), "{}", json);
+        assert_eq!(::serde_json::from_str::<Frame>(&json).unwrap(), frame);
+
+        let mut program = even_odd();
+        program.extend(secd![(push 9) call]);
+        let mut machine = Machine::new(&program);
+        for _ in 0..20 {
+            machine.step();
+        }
+        let json = ::serde_json::to_string(&machine.snapshot().unwrap()).unwrap();
+        let mut machine = Machine::new(&program);
+        machine.restore(&::serde_json::from_str(&json).unwrap()).unwrap();
+        assert_eq!(machine.exec().unwrap(), Value::Bool(true));
+
+        // An edited state which would make the next collection look past the heap.
+        let edits = vec![("old", ::serde_json::Value::from(1000)),
+                         ("old_after_major", ::serde_json::Value::from(1000)),
+                         ("remembered", ::serde_json::Value::from(vec![1000]))];
+        for (field, value) in edits {
+            let mut state: ::serde_json::Value = ::serde_json::from_str(&json).unwrap();
+            state[field] = value;
+            let state = ::serde_json::from_value(state).unwrap();
+            assert_eq!(machine.restore(&state).unwrap_err().to_string(), "Fatal: dangling pointer :(");
+        }
+    }
+}
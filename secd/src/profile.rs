@@ -0,0 +1,46 @@
+use std::collections::HashMap;
+
+use program::{Instruction, Name};
+
+/// What a machine built `with_profiling` has executed. The counts are the
+/// same on every run of a program, unlike timings.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Profile {
+    /// Steps by opcode, like `add` or `call`.
+    pub opcodes: HashMap<&'static str, u64>,
+    /// Steps by instruction: the index of its program among those given to
+    /// the machine, and its position in it.
+    pub instructions: HashMap<(usize, usize), u64>,
+}
+
+impl Profile {
+    pub fn steps(&self) -> u64 {
+        self.opcodes.values().sum()
+    }
+
+    /// Steps spent in the body of each function of `program`, the `index`th
+    /// one given to the machine, by the name of the function, and under
+    /// `None` outside of them. The steps of a function defined inside
+    /// another one are only counted for the inner one.
+    pub fn functions(&self, index: usize, program: &[Instruction]) -> HashMap<Option<Name>, u64> {
+        let mut owners = Vec::with_capacity(program.len());
+        // The end and the name of the closures around the instruction.
+        let mut open: Vec<(usize, Name)> = Vec::new();
+        for (i, inst) in program.iter().enumerate() {
+            while open.last().map_or(false, |&(end, _)| end <= i) {
+                open.pop();
+            }
+            owners.push(open.last().map(|&(_, name)| name));
+            if let Instruction::Closure { name, len, .. } = *inst {
+                open.push((i + 1 + len, name));
+            }
+        }
+        let mut functions = HashMap::new();
+        for (&(program, position), &steps) in &self.instructions {
+            if program == index && position < owners.len() {
+                *functions.entry(owners[position]).or_insert(0) += steps;
+            }
+        }
+        functions
+    }
+}
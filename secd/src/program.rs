@@ -0,0 +1,438 @@
+use std::collections::HashMap;
+use std::fmt;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// A whole program. The bodies of functions and the alternatives of
+/// conditionals are part of the same list of instructions, and are found
+/// by counting instructions forward.
+pub type Frame = Vec<Instruction>;
+
+#[derive(PartialEq, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Instruction {
+    ArithInstruction(ArithInstruction),
+    CmpInstruction(CmpInstruction),
+    ListInstruction(ListInstruction),
+    PushInt(i64),
+    PushFloat(f64),
+    PushBool(bool),
+    PushStr(String),
+    PushNil,
+    /// Pushes `()`, the only value of type `unit`.
+    PushUnit,
+    /// Pops a value and drops it.
+    Pop,
+    /// Pops a value, writes it and a newline to the output of the machine and
+    /// pushes `()`. Strings are written as they are, other values as `show`s them.
+    Print,
+    /// Pops two strings and pushes them joined.
+    Concat,
+    /// Pops a float and pushes it rounded towards zero, saturating at the
+    /// bounds of an int.
+    IntOfFloat,
+    /// Pops an int and pushes the nearest float.
+    FloatOfInt,
+    /// Pops the argument if there is one and pushes the `tag`th constructor
+    /// of a type applied to it. The `name` is only used to show the value.
+    Construct {
+        tag: usize,
+        name: String,
+        arg: bool,
+    },
+    /// Pops a constructed value and skips as many instructions as its tag
+    /// says, to the code for that constructor.
+    Case(Vec<usize>),
+    /// Pops a constructed value and pushes its argument.
+    Payload,
+    /// Pops a value for each of the names, the last one first, and pushes a
+    /// record with these fields.
+    Record(Vec<String>),
+    /// Pops a record and pushes the value of its field with the name.
+    Field(String),
+    /// Pops a value and pushes a new reference cell holding it.
+    Ref,
+    /// Pops a reference cell and pushes the value it holds.
+    Deref,
+    /// Pops a value and a reference cell, stores the value in the cell and
+    /// pushes `()`.
+    Assign,
+    /// Skips the next `n` instructions.
+    Jump(usize),
+    /// Pops a bool and skips the next `n` instructions if it is false.
+    JumpIfFalse(usize),
+    Var(Name),
+    /// Pushes the variable `n` places from the end of the environment: the
+    /// argument of the current function is 0, the function itself 1, and its
+    /// captured variables follow in reverse order.
+    Slot(usize),
+    /// Pushes a function whose body is the next `len` instructions, and
+    /// skips them. The function keeps the variables named in `captures`, or
+    /// the whole environment if there are none, see `resolve`.
+    Closure {
+        name: Name,
+        arg: Name,
+        len: usize,
+        captures: Option<Vec<Name>>,
+    },
+    /// Pops a closure for each of the names, the last one first, and pushes
+    /// the last one back, with the names bound to all of them in each of
+    /// their environments, so that they can call each other. The closures
+    /// must not capture the names themselves.
+    Fix(Vec<Name>),
+    Call,
+    /// Like `Call` followed by `PopEnv`, but the callee replaces the
+    /// environment of the caller instead of growing the call stack.
+    TailCall,
+    PopEnv,
+}
+
+impl Instruction {
+    /// The name of the instruction without its operands, like `push` or
+    /// `add`, as `Display` starts with.
+    pub fn opcode(&self) -> &'static str {
+        use self::Instruction::*;
+        match *self {
+            ArithInstruction(inst) => inst.opcode(),
+            CmpInstruction(inst) => inst.opcode(),
+            ListInstruction(inst) => inst.opcode(),
+            PushInt(_) | PushFloat(_) | PushBool(_) | PushStr(_) | PushNil | PushUnit => "push",
+            Pop => "pop",
+            Print => "print",
+            Concat => "concat",
+            IntOfFloat => "int_of_float",
+            FloatOfInt => "float_of_int",
+            Construct { .. } => "construct",
+            Case(_) => "case",
+            Payload => "payload",
+            Record(_) => "record",
+            Field(_) => "field",
+            Ref => "ref",
+            Deref => "deref",
+            Assign => "assign",
+            Jump(_) => "jump",
+            JumpIfFalse(_) => "jumpf",
+            Var(_) => "var",
+            Slot(_) => "slot",
+            Closure { .. } => "clos",
+            Fix(_) => "fix",
+            Call => "call",
+            TailCall => "tailcall",
+            PopEnv => "ret",
+        }
+    }
+}
+
+/// One line per instruction, the bodies of closures are shown by their length
+/// only, see `disassemble` for a whole program.
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::Instruction::*;
+        match *self {
+            ArithInstruction(ref inst) => inst.fmt(f),
+            CmpInstruction(ref inst) => inst.fmt(f),
+            ListInstruction(ref inst) => inst.fmt(f),
+            PushInt(i) => write!(f, "push {}", i),
+            PushFloat(x) => write!(f, "push {:?}", x),
+            PushBool(b) => write!(f, "push {}", b),
+            PushStr(ref s) => write!(f, "push {:?}", s),
+            PushNil => f.write_str("push []"),
+            PushUnit => f.write_str("push ()"),
+            Pop => f.write_str("pop"),
+            Print => f.write_str("print"),
+            Concat => f.write_str("concat"),
+            IntOfFloat => f.write_str("int_of_float"),
+            FloatOfInt => f.write_str("float_of_int"),
+            Construct { tag, ref name, arg } => {
+                try!(write!(f, "construct {}#{}", name, tag));
+                if arg {
+                    try!(f.write_str(" _"));
+                }
+                Ok(())
+            }
+            Case(ref offsets) => {
+                try!(f.write_str("case"));
+                for offset in offsets {
+                    try!(write!(f, " +{}", offset));
+                }
+                Ok(())
+            }
+            Payload => f.write_str("payload"),
+            Record(ref names) => write!(f, "record {{{}}}", names.join(", ")),
+            Field(ref name) => write!(f, "field {}", name),
+            Ref => f.write_str("ref"),
+            Deref => f.write_str("deref"),
+            Assign => f.write_str("assign"),
+            Jump(n) => write!(f, "jump +{}", n),
+            JumpIfFalse(n) => write!(f, "jumpf +{}", n),
+            Var(name) => write!(f, "var {}", name),
+            Slot(n) => write!(f, "slot {}", n),
+            Closure { name, arg, len, ref captures } => {
+                try!(write!(f, "clos ({}, {}) [{}]", name, arg, len));
+                if let Some(ref captures) = *captures {
+                    let names = captures.iter().map(|name| name.to_string()).collect::<Vec<_>>();
+                    try!(write!(f, " {{{}}}", names.join(", ")));
+                }
+                Ok(())
+            }
+            Fix(ref names) => {
+                let names = names.iter().map(|name| name.to_string()).collect::<Vec<_>>();
+                write!(f, "fix {{{}}}", names.join(", "))
+            }
+            Call => f.write_str("call"),
+            TailCall => f.write_str("tailcall"),
+            PopEnv => f.write_str("ret"),
+        }
+    }
+}
+
+/// Turns each closure into one which captures only the variables its body
+/// uses, unless it already has its `captures`, and each `Var` inside a
+/// closure into the `Slot` the variable will be found at. Variables outside
+/// of closures are left to be looked up by name.
+pub fn resolve(frame: &mut [Instruction]) {
+    resolve_in(frame, None)
+}
+
+/// `env` names the variables of the closure `frame` is the body of, in the
+/// order they are in its environment.
+fn resolve_in(frame: &mut [Instruction], env: Option<&[Name]>) {
+    let groups = groups(frame);
+    let mut i = 0;
+    while i < frame.len() {
+        i += 1;
+        let (name, arg, len, captures) = match frame[i - 1] {
+            Instruction::Var(var) => {
+                let slot = env.and_then(|env| env.iter().rev().position(|&name| name == var));
+                if let Some(slot) = slot {
+                    frame[i - 1] = Instruction::Slot(slot);
+                }
+                continue;
+            }
+            Instruction::Closure { name, arg, len, ref captures } => (name, arg, len, captures.clone()),
+            _ => continue,
+        };
+        let end = frame.len().min(i + len);
+        // `Fix` binds the names of a group in front of the captured variables.
+        let group = groups.get(&(i - 1)).cloned().unwrap_or_default();
+        let captures = captures.unwrap_or_else(|| {
+            free_vars(&frame[i..end], name, arg).into_iter().filter(|var| !group.contains(var)).collect()
+        });
+        let mut body_env = group;
+        body_env.extend_from_slice(&captures);
+        body_env.push(name);
+        body_env.push(arg);
+        resolve_in(&mut frame[i..end], Some(&body_env));
+        frame[i - 1] = Instruction::Closure {
+            name: name,
+            arg: arg,
+            len: len,
+            captures: Some(captures),
+        };
+        i = end;
+    }
+}
+
+/// The variables used by the body of the closure `fun name(arg)`, other than
+/// `name` and `arg`, in the order of their first use.
+fn free_vars(body: &[Instruction], name: Name, arg: Name) -> Vec<Name> {
+    let groups = groups(body);
+    let mut result = Vec::new();
+    let mut i = 0;
+    while i < body.len() {
+        i += 1;
+        let used = match body[i - 1] {
+            Instruction::Var(var) => vec![var],
+            Instruction::Closure { name, arg, len, ref captures } => {
+                let end = body.len().min(i + len);
+                let mut used = captures.clone().unwrap_or_else(|| free_vars(&body[i..end], name, arg));
+                if let Some(group) = groups.get(&(i - 1)) {
+                    used.retain(|var| !group.contains(var));
+                }
+                i = end;
+                used
+            }
+            _ => continue,
+        };
+        for var in used {
+            if var != name && var != arg && !result.contains(&var) {
+                result.push(var);
+            }
+        }
+    }
+    result
+}
+
+/// The names of the recursive group of each closure in `frame`, outside of
+/// the bodies of closures, by its index. A group is the closures right before
+/// a `Fix`.
+fn groups(frame: &[Instruction]) -> HashMap<usize, Vec<Name>> {
+    let mut result = HashMap::new();
+    let mut closures = Vec::new();
+    let mut i = 0;
+    while i < frame.len() {
+        match frame[i] {
+            Instruction::Closure { len, .. } => {
+                closures.push(i);
+                i += len;
+            }
+            Instruction::Fix(ref names) => {
+                let start = closures.len().saturating_sub(names.len());
+                for &closure in &closures[start..] {
+                    result.insert(closure, names.clone());
+                }
+                closures.clear();
+            }
+            _ => closures.clear(),
+        }
+        i += 1;
+    }
+    result
+}
+
+/// Shows `frame` one numbered instruction per line, with the body of each
+/// closure indented under a `fun name(arg):` label, and the index each jump
+/// lands on after a `->`.
+pub fn disassemble(frame: &[Instruction]) -> String {
+    let mut lines = Vec::new();
+    disassemble_into(frame, 0, 0, &mut lines);
+    lines.join("\n")
+}
+
+fn disassemble_into(frame: &[Instruction], start: usize, depth: usize, lines: &mut Vec<String>) {
+    let indent = "    ".repeat(depth);
+    let mut i = 0;
+    while i < frame.len() {
+        let index = start + i;
+        let mut line = format!("{:4} {}{}", index, indent, frame[i]);
+        match frame[i] {
+            Instruction::Jump(n) | Instruction::JumpIfFalse(n) => {
+                line.push_str(&format!("  -> {}", index + 1 + n))
+            }
+            Instruction::Case(ref offsets) => {
+                let targets = offsets.iter().map(|n| (index + 1 + n).to_string()).collect::<Vec<_>>();
+                line.push_str(&format!("  -> {}", targets.join(", ")));
+            }
+            _ => {}
+        }
+        lines.push(line);
+        i += 1;
+        if let Instruction::Closure { name, arg, len, .. } = frame[i - 1] {
+            lines.push(format!("     {}fun {}({}):", indent, name, arg));
+            let end = frame.len().min(i + len);
+            disassemble_into(&frame[i..end], start + i, depth + 1, lines);
+            i = end;
+        }
+    }
+}
+
+pub type Name = usize;
+
+/// Pops two ints, or two floats, and pushes the result of the same kind.
+/// Float division by zero is infinite or NaN rather than an error.
+#[derive(PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum ArithInstruction {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    /// The remainder of a division, with the sign of the dividend.
+    Mod,
+}
+
+impl ArithInstruction {
+    pub fn opcode(&self) -> &'static str {
+        use self::ArithInstruction::*;
+        match *self {
+            Add => "add",
+            Sub => "sub",
+            Mul => "mul",
+            Div => "div",
+            Mod => "mod",
+        }
+    }
+}
+
+impl fmt::Display for ArithInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.opcode())
+    }
+}
+
+impl fmt::Debug for ArithInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        <ArithInstruction as fmt::Display>::fmt(self, f)
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum CmpInstruction {
+    Lt,
+    Le,
+    Eq,
+    Ne,
+    Gt,
+    Ge,
+}
+
+impl CmpInstruction {
+    pub fn opcode(&self) -> &'static str {
+        use self::CmpInstruction::*;
+        match *self {
+            Lt => "lt",
+            Le => "le",
+            Eq => "eq",
+            Ne => "ne",
+            Gt => "gt",
+            Ge => "ge",
+        }
+    }
+}
+
+impl fmt::Display for CmpInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.opcode())
+    }
+}
+
+impl fmt::Debug for CmpInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        <CmpInstruction as fmt::Display>::fmt(self, f)
+    }
+}
+
+/// `Cons` pops a tail and a head and pushes a new list, the others pop a list.
+#[derive(PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum ListInstruction {
+    Cons,
+    Head,
+    Tail,
+    IsEmpty,
+}
+
+impl ListInstruction {
+    pub fn opcode(&self) -> &'static str {
+        use self::ListInstruction::*;
+        match *self {
+            Cons => "cons",
+            Head => "head",
+            Tail => "tail",
+            IsEmpty => "empty",
+        }
+    }
+}
+
+impl fmt::Display for ListInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.opcode())
+    }
+}
+
+impl fmt::Debug for ListInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        <ListInstruction as fmt::Display>::fmt(self, f)
+    }
+}
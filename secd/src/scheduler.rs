@@ -0,0 +1,208 @@
+//! Runs many programs on one thread, taking turns.
+
+use std::collections::VecDeque;
+
+use {Machine, Result, RuntimeErrorKind, Status, Value, runtime_error};
+use program::Frame;
+
+/// Identifies a program spawned on a `Scheduler`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ProgramId(usize);
+
+struct Task<'p> {
+    id: ProgramId,
+    machine: Machine<'p>,
+    /// Instructions the program may still execute, if limited.
+    budget: Option<u64>,
+}
+
+/// Interleaves several machines round-robin: each program in turn executes
+/// up to `quantum` instructions before yielding to the next one, so a long
+/// or looping program can't starve the others. A program can be given a
+/// budget of instructions, after which it fails with `OutOfFuel`.
+///
+/// ```
+/// use secd::{Instruction, RuntimeErrorKind, Scheduler, Value};
+///
+/// // A function calling itself forever, and a constant.
+/// let forever = vec![
+///     Instruction::Closure { name: 0, arg: 1, len: 4, captures: None },
+///     Instruction::Var(0),
+///     Instruction::Var(1),
+///     Instruction::Call,
+///     Instruction::PopEnv,
+///     Instruction::PushInt(0),
+///     Instruction::Call,
+/// ];
+/// let answer = vec![Instruction::PushInt(92)];
+///
+/// let mut scheduler = Scheduler::new(100);
+/// let looping = scheduler.spawn_with_budget(&forever, 1000);
+/// let quick = scheduler.spawn(&answer);
+/// let results = scheduler.run();
+/// assert_eq!(results[0].0, quick);
+/// assert_eq!(*results[0].1.as_ref().unwrap(), Value::Int(92));
+/// assert_eq!(results[1].0, looping);
+/// assert_eq!(results[1].1.as_ref().unwrap_err().kind, RuntimeErrorKind::OutOfFuel);
+/// ```
+pub struct Scheduler<'p> {
+    tasks: VecDeque<Task<'p>>,
+    quantum: u64,
+    next_id: usize,
+}
+
+impl<'p> Scheduler<'p> {
+    pub fn new(quantum: u64) -> Scheduler<'p> {
+        assert!(quantum > 0);
+        Scheduler {
+            tasks: VecDeque::new(),
+            quantum: quantum,
+            next_id: 0,
+        }
+    }
+
+    /// Adds `program` to the end of the queue.
+    pub fn spawn(&mut self, program: &'p Frame) -> ProgramId {
+        self.push(Machine::new(program), None)
+    }
+
+    /// Adds `program`, which may execute at most `budget` instructions in total.
+    pub fn spawn_with_budget(&mut self, program: &'p Frame, budget: u64) -> ProgramId {
+        self.push(Machine::new(program), Some(budget))
+    }
+
+    /// Adds an already set up machine, for example one with a cancel token.
+    pub fn spawn_machine(&mut self, machine: Machine<'p>, budget: Option<u64>) -> ProgramId {
+        self.push(machine, budget)
+    }
+
+    /// Removes a program that hasn't finished yet. Returns whether it was found.
+    pub fn cancel(&mut self, id: ProgramId) -> bool {
+        let len = self.tasks.len();
+        self.tasks.retain(|task| task.id != id);
+        self.tasks.len() < len
+    }
+
+    /// Number of programs that haven't finished yet.
+    pub fn len(&self) -> usize {
+        self.tasks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tasks.is_empty()
+    }
+
+    /// Gives the next program its turn. Returns its result if it finished.
+    pub fn step(&mut self) -> Option<(ProgramId, Result<Value<'p>>)> {
+        let mut task = match self.tasks.pop_front() {
+            Some(task) => task,
+            None => return None,
+        };
+        let turn = match task.budget {
+            Some(budget) => budget.min(self.quantum),
+            None => self.quantum,
+        };
+        for _ in 0..turn {
+            match task.machine.step() {
+                Status::Running => {}
+                Status::Finished(value) => return Some((task.id, Ok(value))),
+                Status::Error(e) => return Some((task.id, Err(e))),
+            }
+        }
+        if let Some(ref mut budget) = task.budget {
+            *budget -= turn;
+            if *budget == 0 {
+                return Some((task.id, Err(runtime_error(RuntimeErrorKind::OutOfFuel))));
+            }
+        }
+        self.tasks.push_back(task);
+        None
+    }
+
+    /// Runs every program to completion, returning the results in the order
+    /// the programs finished.
+    pub fn run(&mut self) -> Vec<(ProgramId, Result<Value<'p>>)> {
+        let mut results = Vec::new();
+        while !self.is_empty() {
+            results.extend(self.step());
+        }
+        results
+    }
+
+    fn push(&mut self, machine: Machine<'p>, budget: Option<u64>) -> ProgramId {
+        let id = ProgramId(self.next_id);
+        self.next_id += 1;
+        self.tasks.push_back(Task {
+            id: id,
+            machine: machine,
+            budget: budget,
+        });
+        id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use program::{ArithInstruction, Instruction};
+
+    /// Counts down from `n` to zero, executing 9 instructions per iteration.
+    fn countdown(n: i64) -> Frame {
+        use program::CmpInstruction;
+        vec![
+            Instruction::Closure {
+                name: 0,
+                arg: 1,
+                len: 12,
+                captures: None,
+            },
+            Instruction::Var(1),
+            Instruction::PushInt(0),
+            Instruction::CmpInstruction(CmpInstruction::Eq),
+            Instruction::JumpIfFalse(2),
+            Instruction::Var(1),
+            Instruction::Jump(5),
+            Instruction::Var(0),
+            Instruction::Var(1),
+            Instruction::PushInt(1),
+            Instruction::ArithInstruction(ArithInstruction::Sub),
+            Instruction::Call,
+            Instruction::PopEnv,
+            Instruction::PushInt(n),
+            Instruction::Call,
+        ]
+    }
+
+    #[test]
+    fn test_round_robin() {
+        let (long, short) = (countdown(1000), countdown(10));
+        let mut scheduler = Scheduler::new(50);
+        let a = scheduler.spawn(&long);
+        let b = scheduler.spawn(&short);
+        let c = scheduler.spawn(&long);
+        assert_eq!(scheduler.len(), 3);
+        // The short program finishes first even though it was queued after a long one.
+        let order = scheduler.run()
+                             .into_iter()
+                             .map(|(id, result)| (id, result.unwrap()))
+                             .collect::<Vec<_>>();
+        assert_eq!(order, vec![(b, Value::Int(0)), (a, Value::Int(0)), (c, Value::Int(0))]);
+        assert!(scheduler.step().is_none());
+    }
+
+    #[test]
+    fn test_budget_and_cancel() {
+        let (long, short) = (countdown(1000), countdown(3));
+        let mut scheduler = Scheduler::new(7);
+        let a = scheduler.spawn_with_budget(&long, 100);
+        let b = scheduler.spawn_with_budget(&short, 1000);
+        let c = scheduler.spawn(&long);
+        assert!(scheduler.cancel(c));
+        assert!(!scheduler.cancel(c));
+        let results = scheduler.run();
+        assert_eq!(results.len(), 2);
+        assert_eq!((results[0].0, results[0].1.as_ref().unwrap()), (b, &Value::Int(0)));
+        assert_eq!((results[1].0, &results[1].1.as_ref().unwrap_err().kind),
+                   (a, &RuntimeErrorKind::OutOfFuel));
+    }
+}
@@ -0,0 +1,340 @@
+use std::collections::HashMap;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use program::{Frame, Instruction, Name};
+use value::{Closure, Native, Value};
+use {Env, Machine, NativeImpl, Object, Result, fatal_error};
+
+/// Everything a `Machine` is in the middle of: its stack, environments,
+/// heap and the code left to execute, see `Machine::snapshot`. Code is kept
+/// as positions in the programs the machine was given rather than as
+/// references, so that a state can be saved and outlive the machine.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct MachineState {
+    values: Vec<SavedValue>,
+    environments: Vec<Vec<(Name, SavedValue)>>,
+    storage: Vec<SavedObject>,
+    activations: Vec<Code>,
+    steps: u64,
+    heap_after_gc: usize,
+    old: usize,
+    remembered: Vec<usize>,
+    old_after_major: usize,
+}
+
+impl MachineState {
+    /// Number of instructions the machine had executed.
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+}
+
+/// The instructions from `start` to `start + len` of the `program`th
+/// program given to the machine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+struct Code {
+    program: usize,
+    start: usize,
+    len: usize,
+}
+
+/// A `Value` with its code and the name of its native function left out.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+enum SavedValue {
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Unit,
+    Str(usize),
+    Closure {
+        arg: Name,
+        code: Code,
+        env: usize,
+    },
+    Native(usize),
+    Nil,
+    Cons(usize),
+    Variant(usize),
+    Record(usize),
+    Ref(usize),
+}
+
+/// An `Object` with its own copies of the names it shares with the code.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+enum SavedObject {
+    Env(Vec<(Name, SavedValue)>),
+    Cons(SavedValue, SavedValue),
+    Str(String),
+    Variant {
+        tag: usize,
+        name: String,
+        arg: Option<SavedValue>,
+    },
+    Record(Vec<(String, SavedValue)>),
+    Ref(SavedValue),
+    Native {
+        native: usize,
+        args: Vec<SavedValue>,
+    },
+}
+
+impl<'p> Machine<'p> {
+    /// Saves the state of the machine, to `restore` it later, for example to
+    /// go back in a debugger or to resume a long computation in another
+    /// process. Fails if a closure was `define`d from code the machine wasn't
+    /// given.
+    pub fn snapshot(&self) -> Result<MachineState> {
+        let env = |env: &Env<'p>| -> Result<Vec<(Name, SavedValue)>> {
+            env.iter().map(|&(name, value)| self.save(value).map(|value| (name, value))).collect()
+        };
+        let mut storage = Vec::with_capacity(self.storage.len());
+        for object in &self.storage {
+            let object = match *object {
+                Object::Env(ref values) => SavedObject::Env(try!(env(values))),
+                Object::Cons(head, tail) => SavedObject::Cons(try!(self.save(head)), try!(self.save(tail))),
+                Object::Str(ref s) => SavedObject::Str(s.clone()),
+                Object::Variant { tag, name, arg } => {
+                    SavedObject::Variant {
+                        tag: tag,
+                        name: name.to_owned(),
+                        arg: match arg {
+                            Some(arg) => Some(try!(self.save(arg))),
+                            None => None,
+                        },
+                    }
+                }
+                Object::Record(ref fields) => {
+                    let mut values = Vec::with_capacity(fields.len());
+                    for &(name, value) in fields {
+                        values.push((name.to_owned(), try!(self.save(value))));
+                    }
+                    SavedObject::Record(values)
+                }
+                Object::Ref(value) => SavedObject::Ref(try!(self.save(value))),
+                Object::Native { native, ref args } => {
+                    SavedObject::Native {
+                        native: native,
+                        args: try!(args.iter().map(|&arg| self.save(arg)).collect()),
+                    }
+                }
+            };
+            storage.push(object);
+        }
+        Ok(MachineState {
+            values: try!(self.values.iter().map(|&value| self.save(value)).collect()),
+            environments: try!(self.environments.iter().map(|values| env(values)).collect()),
+            storage: storage,
+            activations: try!(self.activations.iter().map(|&code| self.code(code)).collect()),
+            steps: self.steps,
+            heap_after_gc: self.heap_after_gc,
+            old: self.old,
+            remembered: self.remembered.clone(),
+            old_after_major: self.old_after_major,
+        })
+    }
+
+    /// Puts the machine back in `state`. The machine has to have been given
+    /// the same programs in the same order, by `new` and `run`, as the one
+    /// `state` was saved from, and the same native functions. Its
+    /// configuration, like the limits and the output, stays as it is.
+    pub fn restore(&mut self, state: &MachineState) -> Result<()> {
+        // A collection splits the heap at `old`, and looks into the
+        // `remembered` cells below it.
+        let heap = state.storage.len();
+        if state.old > heap || state.old_after_major > heap ||
+           state.remembered.iter().any(|&index| index >= state.old) {
+            return Err(fatal_error("dangling pointer"));
+        }
+        let (values, environments, storage, activations) = {
+            let loader = Loader::new(&self.programs, &self.natives, &state.storage);
+            let env = |env: &[(Name, SavedValue)]| -> Result<Env<'p>> {
+                env.iter().map(|&(name, value)| loader.value(value).map(|value| (name, value))).collect()
+            };
+            let values = try!(state.values.iter().map(|&value| loader.value(value)).collect());
+            let environments = try!(state.environments.iter().map(|values| env(values)).collect());
+            let mut storage = Vec::with_capacity(state.storage.len());
+            for object in &state.storage {
+                let object = match *object {
+                    SavedObject::Env(ref values) => Object::Env(try!(env(values))),
+                    SavedObject::Cons(head, tail) => {
+                        Object::Cons(try!(loader.value(head)), try!(loader.value(tail)))
+                    }
+                    SavedObject::Str(ref s) => Object::Str(s.clone()),
+                    SavedObject::Variant { tag, ref name, arg } => {
+                        Object::Variant {
+                            tag: tag,
+                            name: try!(loader.name(name)),
+                            arg: match arg {
+                                Some(arg) => Some(try!(loader.value(arg))),
+                                None => None,
+                            },
+                        }
+                    }
+                    SavedObject::Record(ref fields) => {
+                        let mut values = Vec::with_capacity(fields.len());
+                        for &(ref name, value) in fields {
+                            values.push((try!(loader.name(name)), try!(loader.value(value))));
+                        }
+                        Object::Record(values)
+                    }
+                    SavedObject::Ref(value) => Object::Ref(try!(loader.value(value))),
+                    SavedObject::Native { native, ref args } => {
+                        if native >= self.natives.len() {
+                            return Err(fatal_error("no such native function"));
+                        }
+                        Object::Native {
+                            native: native,
+                            args: try!(args.iter().map(|&arg| loader.value(arg)).collect()),
+                        }
+                    }
+                };
+                storage.push(object);
+            }
+            let activations = try!(state.activations.iter().map(|&code| loader.code(code)).collect());
+            (values, environments, storage, activations)
+        };
+        self.values = values;
+        self.environments = environments;
+        self.storage = storage;
+        self.activations = activations;
+        self.steps = state.steps;
+        self.heap_after_gc = state.heap_after_gc;
+        self.old = state.old;
+        self.remembered = state.remembered.clone();
+        self.old_after_major = state.old_after_major;
+        Ok(())
+    }
+
+    fn save(&self, value: Value<'p>) -> Result<SavedValue> {
+        let value = match value {
+            Value::Int(i) => SavedValue::Int(i),
+            Value::Float(x) => SavedValue::Float(x),
+            Value::Bool(b) => SavedValue::Bool(b),
+            Value::Unit => SavedValue::Unit,
+            Value::Str(index) => SavedValue::Str(index),
+            Value::Closure(closure) => {
+                SavedValue::Closure {
+                    arg: closure.arg,
+                    code: try!(self.code(closure.frame)),
+                    env: closure.env,
+                }
+            }
+            Value::Native(native) => SavedValue::Native(native.index),
+            Value::Nil => SavedValue::Nil,
+            Value::Cons(index) => SavedValue::Cons(index),
+            Value::Variant(index) => SavedValue::Variant(index),
+            Value::Record(index) => SavedValue::Record(index),
+            Value::Ref(index) => SavedValue::Ref(index),
+        };
+        Ok(value)
+    }
+
+    fn code(&self, code: &'p [Instruction]) -> Result<Code> {
+        let (program, start) = try!(self.position(code)
+            .ok_or(fatal_error("code outside of the programs of the machine")));
+        Ok(Code {
+            program: program,
+            start: start,
+            len: code.len(),
+        })
+    }
+}
+
+/// Turns a `MachineState` back into the values of a machine.
+struct Loader<'a, 'p: 'a> {
+    programs: &'a [&'p Frame],
+    natives: &'a [NativeImpl<'p>],
+    storage: &'a [SavedObject],
+    /// The names of constructors and fields in the programs, which objects
+    /// borrow from there.
+    names: HashMap<&'p str, &'p str>,
+}
+
+impl<'a, 'p> Loader<'a, 'p> {
+    fn new(programs: &'a [&'p Frame],
+           natives: &'a [NativeImpl<'p>],
+           storage: &'a [SavedObject])
+           -> Loader<'a, 'p> {
+        let mut names = HashMap::new();
+        for &program in programs {
+            for inst in program.iter() {
+                match *inst {
+                    Instruction::Construct { ref name, .. } => {
+                        names.insert(name.as_str(), name.as_str());
+                    }
+                    Instruction::Record(ref fields) => {
+                        for name in fields {
+                            names.insert(name.as_str(), name.as_str());
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Loader {
+            programs: programs,
+            natives: natives,
+            storage: storage,
+            names: names,
+        }
+    }
+
+    fn value(&self, value: SavedValue) -> Result<Value<'p>> {
+        let value = match value {
+            SavedValue::Int(i) => Value::Int(i),
+            SavedValue::Float(x) => Value::Float(x),
+            SavedValue::Bool(b) => Value::Bool(b),
+            SavedValue::Unit => Value::Unit,
+            SavedValue::Str(index) => Value::Str(try!(self.index(index))),
+            SavedValue::Closure { arg, code, env } => {
+                Value::Closure(Closure {
+                    arg: arg,
+                    frame: try!(self.code(code)),
+                    env: try!(self.index(env)),
+                })
+            }
+            SavedValue::Native(index) => {
+                let name = match self.storage.get(index) {
+                    Some(&SavedObject::Native { native, .. }) => {
+                        self.natives.get(native).map(|native| native.name)
+                    }
+                    _ => None,
+                };
+                match name {
+                    Some(name) => Value::Native(Native { name: name, index: index }),
+                    None => return Err(fatal_error("no such native function")),
+                }
+            }
+            SavedValue::Nil => Value::Nil,
+            SavedValue::Cons(index) => Value::Cons(try!(self.index(index))),
+            SavedValue::Variant(index) => Value::Variant(try!(self.index(index))),
+            SavedValue::Record(index) => Value::Record(try!(self.index(index))),
+            SavedValue::Ref(index) => Value::Ref(try!(self.index(index))),
+        };
+        Ok(value)
+    }
+
+    fn index(&self, index: usize) -> Result<usize> {
+        if index < self.storage.len() {
+            Ok(index)
+        } else {
+            Err(fatal_error("dangling pointer"))
+        }
+    }
+
+    fn code(&self, code: Code) -> Result<&'p [Instruction]> {
+        self.programs
+            .get(code.program)
+            .and_then(|&program| program.get(code.start..code.start + code.len))
+            .ok_or(fatal_error("code outside of the programs of the machine"))
+    }
+
+    fn name(&self, name: &str) -> Result<&'p str> {
+        self.names.get(name).cloned().ok_or(fatal_error("name outside of the programs of the machine"))
+    }
+}
@@ -0,0 +1,33 @@
+use std::fmt;
+
+use program::Instruction;
+
+/// Watches a `Machine` run, see `Machine::set_tracer`. Closures taking an
+/// instruction and a snapshot are tracers too.
+pub trait Tracer {
+    /// Called before `inst` executes, with the state of the machine at that moment.
+    fn before(&mut self, inst: &Instruction, snapshot: &MachineSnapshot);
+}
+
+impl<F: FnMut(&Instruction, &MachineSnapshot)> Tracer for F {
+    fn before(&mut self, inst: &Instruction, snapshot: &MachineSnapshot) {
+        self(inst, snapshot)
+    }
+}
+
+impl<'a> fmt::Debug for dyn Tracer + 'a {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("<tracer>")
+    }
+}
+
+/// The shape of a `Machine` between two instructions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MachineSnapshot {
+    /// Instructions executed so far.
+    pub steps: u64,
+    /// Number of values on the stack.
+    pub stack_depth: usize,
+    /// Number of environments, one per active call plus the top level.
+    pub environments: usize,
+}
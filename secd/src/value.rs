@@ -0,0 +1,121 @@
+use std::fmt;
+
+use {Result, fatal_error};
+use program::{Name, Instruction};
+
+#[derive(PartialEq, Clone, Copy)]
+pub enum Value<'p> {
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    /// `()`, the only value of type `unit`.
+    Unit,
+    /// A string, the index of its contents on the machine's heap.
+    Str(usize),
+    Closure(Closure<'p>),
+    Native(Native),
+    /// The empty list.
+    Nil,
+    /// A non-empty list, the index of its first cell on the machine's heap.
+    Cons(usize),
+    /// A value built by `Construct`, the index of its tag and argument on the heap.
+    Variant(usize),
+    /// A record, the index of its fields on the heap.
+    Record(usize),
+    /// A reference cell, the index of the value it holds on the heap.
+    Ref(usize),
+}
+
+#[derive(PartialEq, Clone, Copy)]
+pub struct Closure<'p> {
+    pub arg: Name,
+    pub frame: &'p [Instruction],
+    pub env: usize,
+}
+
+/// A function implemented in Rust, see `Machine::register_native`.
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub struct Native {
+    pub name: &'static str,
+    /// The index on the machine's heap of the function and of the arguments
+    /// it has been applied to so far.
+    pub index: usize,
+}
+
+/// A function of one argument, like the builtins of `miniml::Plugins`.
+pub type NativeFn = for<'p> fn(Value<'p>) -> ::std::result::Result<Value<'p>, String>;
+
+impl<'p> Value<'p> {
+    pub fn into_int(self) -> Result<i64> {
+        match self {
+            Value::Int(i) => Ok(i),
+            _ => Err(fatal_error("runtime type error")),
+        }
+    }
+
+    pub fn into_float(self) -> Result<f64> {
+        match self {
+            Value::Float(x) => Ok(x),
+            _ => Err(fatal_error("runtime type error")),
+        }
+    }
+
+    pub fn into_bool(self) -> Result<bool> {
+        match self {
+            Value::Bool(b) => Ok(b),
+            _ => Err(fatal_error("runtime type error")),
+        }
+    }
+
+    pub fn into_closure(self) -> Result<Closure<'p>> {
+        match self {
+            Value::Closure(c) => Ok(c),
+            _ => Err(fatal_error("runtime type error")),
+        }
+    }
+}
+
+impl From<i64> for Value<'static> {
+    fn from(i: i64) -> Self {
+        Value::Int(i)
+    }
+}
+
+impl From<f64> for Value<'static> {
+    fn from(x: f64) -> Self {
+        Value::Float(x)
+    }
+}
+
+impl From<bool> for Value<'static> {
+    fn from(b: bool) -> Self {
+        Value::Bool(b)
+    }
+}
+
+impl<'p> fmt::Display for Value<'p> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Value::Int(i) => i.fmt(f),
+            // With a point even if it is whole, to tell it from an int.
+            Value::Float(x) => write!(f, "{:?}", x),
+            Value::Bool(b) => b.fmt(f),
+            Value::Unit => "()".fmt(f),
+            Value::Str(_) => "<string>".fmt(f),
+            Value::Closure(_) => "<closure>".fmt(f),
+            Value::Native(ref native) => write!(f, "<builtin {}>", native.name),
+            Value::Nil => "[]".fmt(f),
+            // The elements are on the heap, see `Machine::show`.
+            Value::Cons(_) => "<list>".fmt(f),
+            Value::Variant(_) => "<variant>".fmt(f),
+            Value::Record(_) => "<record>".fmt(f),
+            Value::Ref(_) => "<ref>".fmt(f),
+        }
+    }
+}
+
+impl<'p> fmt::Debug for Value<'p> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        <Value as fmt::Display>::fmt(self, f)
+    }
+}
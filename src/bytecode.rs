@@ -0,0 +1,43 @@
+//! Compiled programs in the binary format of `secd::encode`, conventionally
+//! stored in `.mlbc` files.
+//!
+//! Programs are tagged with the version of miniml that compiled them. The
+//! code of another version may rely on different conventions, like the
+//! numbering of names, even if the format is the same, so `load` rejects
+//! it and a cache should fall back to recompiling the source.
+
+use secd::{self, DecodeError, Frame};
+
+/// The compiler recorded in every program `save` writes.
+pub const COMPILER: &'static str = concat!("miniml ", env!("CARGO_PKG_VERSION"));
+
+pub fn save(frame: &Frame) -> Vec<u8> {
+    secd::encode(frame, COMPILER)
+}
+
+/// Loads a program saved by this version of miniml.
+pub fn load(bytes: &[u8]) -> Result<Frame, DecodeError> {
+    let (header, frame) = try!(secd::decode(bytes));
+    if header.compiler != COMPILER {
+        return Err(DecodeError {
+            message: format!("Compiled by {}, but this is {}, recompile the source",
+                             header.compiler,
+                             COMPILER),
+        });
+    }
+    Ok(frame)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use secd::Instruction;
+
+    #[test]
+    fn test_versions() {
+        let frame = vec![Instruction::PushInt(92)];
+        assert_eq!(load(&save(&frame)), Ok(vec![Instruction::PushInt(92)]));
+        assert_eq!(load(&secd::encode(&frame, "miniml 0.0.1")).unwrap_err().message,
+                   format!("Compiled by miniml 0.0.1, but this is {}, recompile the source", COMPILER));
+    }
+}
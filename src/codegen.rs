@@ -0,0 +1,259 @@
+//! Compiles the IR to the source of a standalone Rust program, which prints
+//! the value of the miniml program as `miniml` does, so that it can be built
+//! into a native executable with `rustc -O`.
+//!
+//! Values are a `Value` enum, in `codegen/runtime.rs`, which is copied in
+//! front of the generated code: ints, floats and bools are Rust ones, and
+//! functions are boxed closures taking one `Value`. Each variable becomes a
+//! Rust variable, named after its source identifier where there is one, and
+//! closures get clones of the variables they capture. Recursive functions
+//! read themselves and the rest of their group from cells, which are set once
+//! all the closures are made, and which keep each other alive until the
+//! program exits. Calls aren't tail calls, so the program runs on a thread
+//! with a large stack.
+//!
+//! ```
+//! let (ir, names) = miniml::desugar_with_names(&miniml::parse("let x = 1 in x + 1").unwrap());
+//! assert!(miniml::codegen::rust(&ir, &names).ends_with("fn program() -> Value {
+//!     {
+//!         let x_0 = Value::Int(1);
+//!         add(x_0.clone(), Value::Int(1))
+//!     }
+//! }
+//! "));
+//! ```
+
+use std::slice;
+
+use compile::free_vars;
+use ir::{Ir, BinOpKind, UnOpKind, Fun, Name, NameTable, ANONYMOUS};
+
+/// The definitions the generated code uses.
+const RUNTIME: &'static str = include_str!("codegen/runtime.rs");
+
+/// The Rust program computing `ir`; `names` are the source identifiers of
+/// its variables, which name the Rust ones.
+pub fn rust(ir: &Ir, names: &NameTable) -> String {
+    let codegen = Codegen { names: names };
+    format!("{}\nfn program() -> Value {{\n    {}\n}}\n", RUNTIME, indent(&codegen.expr(ir)))
+}
+
+struct Codegen<'a> {
+    names: &'a NameTable,
+}
+
+impl<'a> Codegen<'a> {
+    /// The Rust variable of `name`, like `count_4`, or `v4` if the source
+    /// identifier isn't one in Rust. The number keeps it apart from
+    /// keywords and from the functions of the runtime.
+    fn var(&self, name: Name) -> String {
+        match self.names.symbol(name).map(|symbol| symbol.to_string()) {
+            Some(ref symbol) if symbol.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') => {
+                format!("{}_{}", symbol, name)
+            }
+            _ => format!("v{}", name),
+        }
+    }
+
+    /// The cell a recursive function `name` is read from.
+    fn cell(&self, name: Name) -> String {
+        format!("cell_{}", self.var(name))
+    }
+
+    fn expr(&self, ir: &Ir) -> String {
+        match *ir {
+            Ir::Var(name) => format!("{}.clone()", self.var(name)),
+            Ir::IntLiteral(i) => format!("Value::Int({})", int(i)),
+            Ir::FloatLiteral(x) => format!("Value::Float({})", float(x)),
+            Ir::BoolLiteral(b) => format!("Value::Bool({})", b),
+            Ir::StrLiteral(ref s) => format!("Value::Str(Rc::from({:?}))", s),
+            Ir::Unit => "Value::Unit".to_owned(),
+            Ir::Nil => "Value::Nil".to_owned(),
+            Ir::BinOp(ref op) => {
+                let function = match op.kind {
+                    BinOpKind::Add => "add",
+                    BinOpKind::Sub => "sub",
+                    BinOpKind::Mul => "mul",
+                    BinOpKind::Div => "div",
+                    BinOpKind::Mod => "rem",
+                    BinOpKind::Lt => "lt",
+                    BinOpKind::Le => "le",
+                    BinOpKind::Eq => "eq",
+                    BinOpKind::Ne => "ne",
+                    BinOpKind::Gt => "gt",
+                    BinOpKind::Ge => "ge",
+                    BinOpKind::Cons => "cons",
+                    BinOpKind::Concat => "concat",
+                    BinOpKind::Assign => "assign",
+                };
+                format!("{}({}, {})", function, self.expr(&op.lhs), self.expr(&op.rhs))
+            }
+            Ir::UnOp(ref op) => {
+                let function = match op.kind {
+                    UnOpKind::Head => "head",
+                    UnOpKind::Tail => "tail",
+                    UnOpKind::IsEmpty => "is_empty",
+                    UnOpKind::Payload => "payload",
+                    UnOpKind::Ref => "new_ref",
+                    UnOpKind::Deref => "deref",
+                    UnOpKind::Print => "print",
+                    UnOpKind::IntOfFloat => "int_of_float",
+                    UnOpKind::FloatOfInt => "float_of_int",
+                };
+                format!("{}({})", function, self.expr(&op.arg))
+            }
+            Ir::Construct(ref c) => {
+                match c.arg {
+                    Some(ref arg) => {
+                        format!("Value::Variant({}, {:?}, Some(Rc::new({})))", c.tag, c.name, self.expr(arg))
+                    }
+                    None => format!("Value::Variant({}, {:?}, None)", c.tag, c.name),
+                }
+            }
+            Ir::Record(ref record) => {
+                let fields = record.fields
+                                   .iter()
+                                   .map(|&(ref name, ref value)| {
+                                       format!("({:?}, {})", name, self.expr(value))
+                                   })
+                                   .collect::<Vec<_>>();
+                format!("record(vec![{}])", fields.join(", "))
+            }
+            Ir::Field(ref field) => format!("field({}, {:?})", self.expr(&field.record), field.name),
+            Ir::Case(ref case) => {
+                let mut arms = case.arms
+                                   .iter()
+                                   .enumerate()
+                                   .map(|(tag, arm)| format!("{} => {},", tag, self.expr(arm)))
+                                   .collect::<Vec<_>>();
+                arms.push("_ => unreachable!(),".to_owned());
+                format!("match tag({}) {}", self.expr(&case.scrutinee), block(&arms, None))
+            }
+            Ir::If(ref if_) => {
+                format!("if bool({}) {} else {}",
+                        self.expr(&if_.cond),
+                        block(&[], Some(&self.expr(&if_.tru))),
+                        block(&[], Some(&self.expr(&if_.fls))))
+            }
+            Ir::Seq(ref seq) => {
+                block(&[format!("{};", self.expr(&seq.first))], Some(&self.expr(&seq.second)))
+            }
+            // A `let`, which binds a variable rather than making a closure.
+            Ir::Apply(ref apply) if is_anonymous(&apply.fun) => {
+                let fun = match apply.fun {
+                    Ir::Fun(ref fun) => fun,
+                    _ => unreachable!(),
+                };
+                let binding = format!("let {} = {};", self.var(fun.arg_name), self.expr(&apply.arg));
+                block(&[binding], Some(&self.expr(&fun.body)))
+            }
+            Ir::Apply(ref apply) => format!("apply({}, {})", self.expr(&apply.fun), self.expr(&apply.arg)),
+            Ir::Fun(ref fun) => self.group(slice::from_ref(&**fun), &Ir::Var(fun.fun_name)),
+            Ir::LetRec(ref let_rec) => self.group(&let_rec.funs, &let_rec.body),
+        }
+    }
+
+    /// Makes the closures of the functions `funs`, which can call each
+    /// other, and evaluates `body` where they are bound.
+    fn group(&self, funs: &[Fun], body: &Ir) -> String {
+        let group = funs.iter().map(|fun| fun.fun_name).collect::<Vec<_>>();
+        let recursive = funs.iter().any(|fun| !self.captures(fun, &group).1.is_empty());
+        if !recursive {
+            if let Ir::Var(name) = *body {
+                if funs.len() == 1 && name == funs[0].fun_name {
+                    return self.closure(&funs[0], &group);
+                }
+            }
+            let bindings = funs.iter()
+                               .map(|fun| {
+                                   format!("let {} = {};", self.var(fun.fun_name), self.closure(fun, &group))
+                               })
+                               .collect::<Vec<_>>();
+            return block(&bindings, Some(&self.expr(body)));
+        }
+        let mut statements = Vec::new();
+        for &name in &group {
+            statements.push(format!("let {} = cell();", self.cell(name)));
+        }
+        for fun in funs {
+            let closure = self.closure(fun, &group);
+            statements.push(format!("*{}.borrow_mut() = {};", self.cell(fun.fun_name), closure));
+        }
+        for &name in &group {
+            statements.push(format!("let {} = {}.borrow().clone();", self.var(name), self.cell(name)));
+        }
+        block(&statements, Some(&self.expr(body)))
+    }
+
+    /// A closure of `fun`, one of the functions `group`.
+    fn closure(&self, fun: &Fun, group: &[Name]) -> String {
+        let (captures, recursive) = self.captures(fun, group);
+        let mut statements = Vec::new();
+        for &name in &captures {
+            statements.push(format!("let {0} = {0}.clone();", self.var(name)));
+        }
+        for &name in &recursive {
+            statements.push(format!("let {0} = {0}.clone();", self.cell(name)));
+        }
+        let mut body = Vec::new();
+        for &name in &recursive {
+            body.push(format!("let {} = {}.borrow().clone();", self.var(name), self.cell(name)));
+        }
+        let body = block(&body, Some(&self.expr(&fun.body)));
+        let closure = format!("fun(move |{}: Value| {})", self.var(fun.arg_name), body);
+        if statements.is_empty() {
+            closure
+        } else {
+            block(&statements, Some(&closure))
+        }
+    }
+
+    /// The variables the body of `fun` uses from outside, apart from the
+    /// functions of `group`, and the functions of `group` it calls.
+    fn captures(&self, fun: &Fun, group: &[Name]) -> (Vec<Name>, Vec<Name>) {
+        let mut free = Vec::new();
+        free_vars(&fun.body, &mut vec![fun.arg_name], &mut free);
+        free.into_iter().partition(|name| !group.contains(name))
+    }
+}
+
+fn is_anonymous(ir: &Ir) -> bool {
+    match *ir {
+        Ir::Fun(ref fun) => fun.fun_name == ANONYMOUS,
+        _ => false,
+    }
+}
+
+/// `i64::MIN` can't be written as a literal.
+fn int(i: i64) -> String {
+    if i == i64::min_value() {
+        "i64::min_value()".to_owned()
+    } else {
+        i.to_string()
+    }
+}
+
+fn float(x: f64) -> String {
+    if x.is_nan() {
+        "::std::f64::NAN".to_owned()
+    } else if x.is_infinite() {
+        (if x > 0.0 { "::std::f64::INFINITY" } else { "::std::f64::NEG_INFINITY" }).to_owned()
+    } else {
+        format!("{:?}", x)
+    }
+}
+
+/// A block of `statements`, ending with the expression `value`.
+fn block(statements: &[String], value: Option<&str>) -> String {
+    let mut result = "{".to_owned();
+    for line in statements.iter().map(String::as_str).chain(value) {
+        result.push_str("\n    ");
+        result.push_str(&indent(line));
+    }
+    result.push_str("\n}");
+    result
+}
+
+fn indent(code: &str) -> String {
+    code.replace('\n', "\n    ")
+}
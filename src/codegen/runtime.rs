@@ -0,0 +1,328 @@
+// The values and operations programs compiled to Rust by miniml use. Not a
+// module of miniml: `codegen` puts this in front of every generated program.
+
+#![allow(unused, non_snake_case)]
+
+use std::cell::RefCell;
+use std::fmt;
+use std::io::{self, Write};
+use std::process;
+use std::rc::Rc;
+use std::thread;
+
+#[derive(Clone)]
+enum Value {
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Unit,
+    Str(Rc<str>),
+    Nil,
+    Cons(Rc<(Value, Value)>),
+    /// The tag, the name and the argument of a constructor.
+    Variant(usize, &'static str, Option<Rc<Value>>),
+    /// The fields, sorted by name.
+    Record(Rc<Vec<(&'static str, Value)>>),
+    Ref(Rc<RefCell<Value>>),
+    Fun(Rc<dyn Fn(Value) -> Value>),
+}
+
+/// Shows a value like `miniml` does.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Value::Int(i) => i.fmt(f),
+            Value::Float(x) => write!(f, "{:?}", x),
+            Value::Bool(b) => b.fmt(f),
+            Value::Unit => f.write_str("()"),
+            Value::Str(ref s) => {
+                f.write_str("\"")?;
+                for c in s.chars() {
+                    match c {
+                        '\n' => f.write_str("\\n")?,
+                        '\t' => f.write_str("\\t")?,
+                        '"' => f.write_str("\\\"")?,
+                        '\\' => f.write_str("\\\\")?,
+                        c => write!(f, "{}", c)?,
+                    }
+                }
+                f.write_str("\"")
+            }
+            Value::Nil | Value::Cons(_) => {
+                f.write_str("[")?;
+                let mut list = self;
+                while let Value::Cons(ref cell) = *list {
+                    if !std::ptr::eq(list, self) {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{}", cell.0)?;
+                    list = &cell.1;
+                }
+                f.write_str("]")
+            }
+            Value::Variant(_, name, None) => f.write_str(name),
+            Value::Variant(_, name, Some(ref arg)) => write!(f, "{} {}", name, Arg(arg)),
+            Value::Record(ref fields) => {
+                f.write_str("{")?;
+                for (i, &(name, ref value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{} = {}", name, value)?;
+                }
+                f.write_str("}")
+            }
+            Value::Ref(ref cell) => {
+                // The cell is borrowed while it is shown, so a cell which
+                // holds itself is found.
+                match cell.try_borrow_mut() {
+                    Ok(contents) => write!(f, "ref {}", Arg(&contents)),
+                    Err(_) => f.write_str("<cycle>"),
+                }
+            }
+            Value::Fun(_) => f.write_str("<closure>"),
+        }
+    }
+}
+
+/// Applications like `Some 1` and `ref 1` are parenthesized as arguments.
+struct Arg<'a>(&'a Value);
+
+impl<'a> fmt::Display for Arg<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self.0 {
+            Value::Variant(_, _, Some(_)) | Value::Ref(_) => write!(f, "({})", self.0),
+            ref value => value.fmt(f),
+        }
+    }
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("Runtime error: {}", message);
+    process::exit(4)
+}
+
+fn int(value: Value) -> i64 {
+    match value {
+        Value::Int(i) => i,
+        _ => unreachable!(),
+    }
+}
+
+fn float(value: Value) -> f64 {
+    match value {
+        Value::Float(x) => x,
+        _ => unreachable!(),
+    }
+}
+
+fn bool(value: Value) -> bool {
+    match value {
+        Value::Bool(b) => b,
+        _ => unreachable!(),
+    }
+}
+
+fn string(value: Value) -> Rc<str> {
+    match value {
+        Value::Str(s) => s,
+        _ => unreachable!(),
+    }
+}
+
+fn arith(lhs: Value,
+         rhs: Value,
+         int: fn(i64, i64) -> Option<i64>,
+         float: fn(f64, f64) -> f64)
+         -> Value {
+    match (lhs, rhs) {
+        (Value::Float(lhs), Value::Float(rhs)) => Value::Float(float(lhs, rhs)),
+        (Value::Int(lhs), Value::Int(rhs)) => {
+            match int(lhs, rhs) {
+                Some(i) => Value::Int(i),
+                None => fail("Integer overflow"),
+            }
+        }
+        _ => unreachable!(),
+    }
+}
+
+fn add(lhs: Value, rhs: Value) -> Value {
+    arith(lhs, rhs, i64::checked_add, |lhs, rhs| lhs + rhs)
+}
+
+fn sub(lhs: Value, rhs: Value) -> Value {
+    arith(lhs, rhs, i64::checked_sub, |lhs, rhs| lhs - rhs)
+}
+
+fn mul(lhs: Value, rhs: Value) -> Value {
+    arith(lhs, rhs, i64::checked_mul, |lhs, rhs| lhs * rhs)
+}
+
+fn div(lhs: Value, rhs: Value) -> Value {
+    if let Value::Int(0) = rhs {
+        fail("Division by zero");
+    }
+    arith(lhs, rhs, i64::checked_div, |lhs, rhs| lhs / rhs)
+}
+
+fn rem(lhs: Value, rhs: Value) -> Value {
+    if let Value::Int(0) = rhs {
+        fail("Division by zero");
+    }
+    arith(lhs, rhs, i64::checked_rem, |lhs, rhs| lhs % rhs)
+}
+
+fn compare(lhs: Value, rhs: Value) -> Option<std::cmp::Ordering> {
+    match (lhs, rhs) {
+        (Value::Int(lhs), Value::Int(rhs)) => lhs.partial_cmp(&rhs),
+        (Value::Float(lhs), Value::Float(rhs)) => lhs.partial_cmp(&rhs),
+        (Value::Str(lhs), Value::Str(rhs)) => lhs.partial_cmp(&rhs),
+        _ => unreachable!(),
+    }
+}
+
+fn lt(lhs: Value, rhs: Value) -> Value {
+    Value::Bool(compare(lhs, rhs) == Some(std::cmp::Ordering::Less))
+}
+
+fn le(lhs: Value, rhs: Value) -> Value {
+    let ordering = compare(lhs, rhs);
+    Value::Bool(ordering.is_some() && ordering != Some(std::cmp::Ordering::Greater))
+}
+
+fn eq(lhs: Value, rhs: Value) -> Value {
+    Value::Bool(compare(lhs, rhs) == Some(std::cmp::Ordering::Equal))
+}
+
+fn ne(lhs: Value, rhs: Value) -> Value {
+    Value::Bool(compare(lhs, rhs) != Some(std::cmp::Ordering::Equal))
+}
+
+fn gt(lhs: Value, rhs: Value) -> Value {
+    Value::Bool(compare(lhs, rhs) == Some(std::cmp::Ordering::Greater))
+}
+
+fn ge(lhs: Value, rhs: Value) -> Value {
+    let ordering = compare(lhs, rhs);
+    Value::Bool(ordering.is_some() && ordering != Some(std::cmp::Ordering::Less))
+}
+
+fn cons(head: Value, tail: Value) -> Value {
+    Value::Cons(Rc::new((head, tail)))
+}
+
+fn concat(lhs: Value, rhs: Value) -> Value {
+    Value::Str(Rc::from(format!("{}{}", string(lhs), string(rhs))))
+}
+
+fn assign(cell: Value, value: Value) -> Value {
+    match cell {
+        Value::Ref(cell) => *cell.borrow_mut() = value,
+        _ => unreachable!(),
+    }
+    Value::Unit
+}
+
+fn head(list: Value) -> Value {
+    match list {
+        Value::Cons(cell) => cell.0.clone(),
+        _ => fail("Head of an empty list"),
+    }
+}
+
+fn tail(list: Value) -> Value {
+    match list {
+        Value::Cons(cell) => cell.1.clone(),
+        _ => fail("Tail of an empty list"),
+    }
+}
+
+fn is_empty(list: Value) -> Value {
+    Value::Bool(match list {
+        Value::Nil => true,
+        _ => false,
+    })
+}
+
+fn tag(value: Value) -> usize {
+    match value {
+        Value::Variant(tag, _, _) => tag,
+        _ => unreachable!(),
+    }
+}
+
+fn payload(value: Value) -> Value {
+    match value {
+        Value::Variant(_, _, Some(arg)) => (*arg).clone(),
+        _ => unreachable!(),
+    }
+}
+
+fn new_ref(value: Value) -> Value {
+    Value::Ref(Rc::new(RefCell::new(value)))
+}
+
+fn deref(cell: Value) -> Value {
+    match cell {
+        Value::Ref(cell) => cell.borrow().clone(),
+        _ => unreachable!(),
+    }
+}
+
+fn print(value: Value) -> Value {
+    let result = match value {
+        Value::Str(s) => writeln!(io::stdout(), "{}", s),
+        value => writeln!(io::stdout(), "{}", value),
+    };
+    if let Err(e) = result {
+        fail(&format!("Can't write output: {}", e));
+    }
+    Value::Unit
+}
+
+fn int_of_float(value: Value) -> Value {
+    Value::Int(float(value) as i64)
+}
+
+fn float_of_int(value: Value) -> Value {
+    Value::Float(int(value) as f64)
+}
+
+fn record(mut fields: Vec<(&'static str, Value)>) -> Value {
+    fields.sort_by_key(|&(name, _)| name);
+    Value::Record(Rc::new(fields))
+}
+
+fn field(record: Value, name: &str) -> Value {
+    match record {
+        Value::Record(fields) => fields.iter().find(|field| field.0 == name).unwrap().1.clone(),
+        _ => unreachable!(),
+    }
+}
+
+fn fun<F: Fn(Value) -> Value + 'static>(f: F) -> Value {
+    Value::Fun(Rc::new(f))
+}
+
+fn apply(fun: Value, arg: Value) -> Value {
+    match fun {
+        Value::Fun(fun) => fun(arg),
+        _ => unreachable!(),
+    }
+}
+
+/// A variable of a recursive function, set once the function is made.
+fn cell() -> Rc<RefCell<Value>> {
+    Rc::new(RefCell::new(Value::Unit))
+}
+
+// Calls don't reuse their frame as on the machine, so programs get a large
+// stack to recurse on.
+fn main() {
+    let program = thread::Builder::new().stack_size(1 << 30).spawn(|| program().to_string());
+    match program.unwrap().join() {
+        Ok(value) => println!("{}", value),
+        Err(_) => process::exit(101),
+    }
+}
@@ -1,78 +1,389 @@
-use ast::Expr;
-use machine::{Frame, Name, Instruction};
-use ir::{Ir, BinOp, If, Apply, Fun, desugar};
+use ast::{Expr, Program};
+use secd::{CancelToken, Cancelled, Frame, Name, Instruction, resolve};
+use ir::{Ir, BinOp, UnOp, Construct, Record, Field, Case, If, Seq, Apply, Fun, LetRec, ANONYMOUS, desugar,
+         desugar_program, desugar_program_cancellable};
+use opt::{OptConfig, optimize};
 
 
 pub fn compile(expr: &Expr) -> Frame {
-    let expr = desugar(expr);
-    expr.compile()
+    compile_ir(&desugar(expr))
 }
 
+pub fn compile_program(program: &Program) -> Frame {
+    compile_ir(&desugar_program(program))
+}
+
+/// Like `compile_program`, but runs the optimizations `config` enables on the IR.
+pub fn compile_program_optimized(program: &Program, config: &OptConfig) -> Frame {
+    compile_ir(&optimize(desugar_program(program), config))
+}
+
+/// Like `compile_program`, but gives up once `token` is cancelled, which
+/// desugaring and compiling check at each node of the program.
+pub fn compile_program_cancellable(program: &Program,
+                                   token: &CancelToken)
+                                   -> Result<Frame, Cancelled> {
+    let ir = try!(desugar_program_cancellable(program, token));
+    let mut frame = ir.compile(Some(token));
+    if token.is_cancelled() {
+        return Err(Cancelled);
+    }
+    resolve(&mut frame);
+    Ok(frame)
+}
+
+/// Compiles `ir` with the variables inside functions read by their slots, see `secd::resolve`.
+pub fn compile_ir(ir: &Ir) -> Frame {
+    let mut frame = ir.compile(None);
+    resolve(&mut frame);
+    frame
+}
+
+fn is_cancelled(cancel: Option<&CancelToken>) -> bool {
+    cancel.map_or(false, CancelToken::is_cancelled)
+}
+
+/// Compiling gives up once `cancel` is cancelled, which it checks at each
+/// node: the rest compiles to nothing, as the frame is thrown away.
 trait Compile {
-    fn compile(&self) -> Frame;
+    fn compile(&self, cancel: Option<&CancelToken>) -> Frame;
 }
 
 impl Compile for Ir {
-    fn compile(&self) -> Frame {
+    fn compile(&self, cancel: Option<&CancelToken>) -> Frame {
+        if is_cancelled(cancel) {
+            return Frame::new();
+        }
         match *self {
             Ir::Var(name) => vec![Instruction::Var(name)],
             Ir::IntLiteral(i) => vec![Instruction::PushInt(i)],
+            Ir::FloatLiteral(x) => vec![Instruction::PushFloat(x)],
             Ir::BoolLiteral(b) => vec![Instruction::PushBool(b)],
-            Ir::BinOp(ref op) => op.compile(),
-            Ir::If(ref if_) => if_.compile(),
-            Ir::Fun(ref fun) => fun.compile(),
-            Ir::Apply(ref apply) => apply.compile(),
+            Ir::StrLiteral(ref s) => vec![Instruction::PushStr(s.clone())],
+            Ir::Unit => vec![Instruction::PushUnit],
+            Ir::Nil => vec![Instruction::PushNil],
+            Ir::BinOp(ref op) => op.compile(cancel),
+            Ir::UnOp(ref op) => op.compile(cancel),
+            Ir::Construct(ref c) => c.compile(cancel),
+            Ir::Record(ref record) => record.compile(cancel),
+            Ir::Field(ref field) => field.compile(cancel),
+            Ir::Case(ref case) => case.compile(cancel),
+            Ir::If(ref if_) => if_.compile(cancel),
+            Ir::Seq(ref seq) => seq.compile(cancel),
+            Ir::Fun(ref fun) => fun.compile(cancel),
+            Ir::LetRec(ref let_rec) => let_rec.compile(cancel),
+            Ir::Apply(ref apply) => apply.compile(cancel),
         }
     }
 }
 
 impl Compile for BinOp {
-    fn compile(&self) -> Frame {
+    fn compile(&self, cancel: Option<&CancelToken>) -> Frame {
         use ir::BinOpKind::*;
-        use machine::{ArithInstruction, CmpInstruction};
-        let mut result = self.lhs.compile();
-        result.extend(self.rhs.compile());
+        use secd::{ArithInstruction, CmpInstruction, ListInstruction};
+        let mut result = self.lhs.compile(cancel);
+        result.extend(self.rhs.compile(cancel));
         result.push(match self.kind {
             Add => Instruction::ArithInstruction(ArithInstruction::Add),
             Sub => Instruction::ArithInstruction(ArithInstruction::Sub),
             Mul => Instruction::ArithInstruction(ArithInstruction::Mul),
             Div => Instruction::ArithInstruction(ArithInstruction::Div),
+            Mod => Instruction::ArithInstruction(ArithInstruction::Mod),
             Lt => Instruction::CmpInstruction(CmpInstruction::Lt),
+            Le => Instruction::CmpInstruction(CmpInstruction::Le),
             Eq => Instruction::CmpInstruction(CmpInstruction::Eq),
+            Ne => Instruction::CmpInstruction(CmpInstruction::Ne),
             Gt => Instruction::CmpInstruction(CmpInstruction::Gt),
+            Ge => Instruction::CmpInstruction(CmpInstruction::Ge),
+            Cons => Instruction::ListInstruction(ListInstruction::Cons),
+            Concat => Instruction::Concat,
+            Assign => Instruction::Assign,
+        });
+        result
+    }
+}
+
+impl Compile for UnOp {
+    fn compile(&self, cancel: Option<&CancelToken>) -> Frame {
+        use ir::UnOpKind::*;
+        use secd::ListInstruction;
+        let mut result = self.arg.compile(cancel);
+        result.push(match self.kind {
+            Head => Instruction::ListInstruction(ListInstruction::Head),
+            Tail => Instruction::ListInstruction(ListInstruction::Tail),
+            IsEmpty => Instruction::ListInstruction(ListInstruction::IsEmpty),
+            Payload => Instruction::Payload,
+            Ref => Instruction::Ref,
+            Deref => Instruction::Deref,
+            Print => Instruction::Print,
+            IntOfFloat => Instruction::IntOfFloat,
+            FloatOfInt => Instruction::FloatOfInt,
         });
         result
     }
 }
 
+impl Compile for Record {
+    fn compile(&self, cancel: Option<&CancelToken>) -> Frame {
+        let mut result = Frame::new();
+        for &(_, ref value) in &self.fields {
+            result.extend(value.compile(cancel));
+        }
+        result.push(Instruction::Record(self.fields.iter().map(|field| field.0.clone()).collect()));
+        result
+    }
+}
+
+impl Compile for Field {
+    fn compile(&self, cancel: Option<&CancelToken>) -> Frame {
+        let mut result = self.record.compile(cancel);
+        result.push(Instruction::Field(self.name.clone()));
+        result
+    }
+}
+
+impl Compile for Construct {
+    fn compile(&self, cancel: Option<&CancelToken>) -> Frame {
+        let mut result = match self.arg {
+            Some(ref arg) => arg.compile(cancel),
+            None => vec![],
+        };
+        result.push(Instruction::Construct {
+            tag: self.tag,
+            name: self.name.clone(),
+            arg: self.arg.is_some(),
+        });
+        result
+    }
+}
+
+impl Compile for Case {
+    fn compile(&self, cancel: Option<&CancelToken>) -> Frame {
+        let mut result = self.scrutinee.compile(cancel);
+        result.extend(case(self.arms.iter().map(|arm| arm.compile(cancel)).collect(), true));
+        result
+    }
+}
+
 impl Compile for If {
-    fn compile(&self) -> Frame {
-        let mut result = self.cond.compile();
-        result.push(Instruction::Branch(self.tru.compile(), self.fls.compile()));
+    fn compile(&self, cancel: Option<&CancelToken>) -> Frame {
+        let mut result = self.cond.compile(cancel);
+        result.extend(branch(self.tru.compile(cancel), self.fls.compile(cancel), true));
+        result
+    }
+}
+
+impl Compile for Seq {
+    fn compile(&self, cancel: Option<&CancelToken>) -> Frame {
+        let mut result = self.first.compile(cancel);
+        result.push(Instruction::Pop);
+        result.extend(self.second.compile(cancel));
         result
     }
 }
 
-fn make_closue(fun_name: Name, arg_name: Name, body: &Ir) -> Instruction {
-    let mut frame = body.compile();
-    frame.push(Instruction::PopEnv);
-    Instruction::Closure {
-        name: fun_name,
-        arg: arg_name,
-        frame: frame,
+/// Lays out the alternatives of an `if` one after another. Unless `jump`
+/// is false because it leaves the function anyway, `tru` jumps over `fls`.
+fn branch(tru: Frame, fls: Frame, jump: bool) -> Frame {
+    let mut result = vec![Instruction::JumpIfFalse(tru.len() + jump as usize)];
+    result.extend(tru);
+    if jump {
+        result.push(Instruction::Jump(fls.len()));
+    }
+    result.extend(fls);
+    result
+}
+
+/// Lays out the arms of a `case` like `branch` does, with each arm but the
+/// last jumping to the end of the last one.
+fn case(arms: Vec<Frame>, jump: bool) -> Frame {
+    let mut offsets = Vec::new();
+    let mut end = 0;
+    for arm in &arms {
+        offsets.push(end);
+        end += arm.len() + jump as usize;
+    }
+    let end = end - jump as usize;
+    let count = arms.len();
+    let mut result = vec![Instruction::Case(offsets)];
+    for (i, arm) in arms.into_iter().enumerate() {
+        result.extend(arm);
+        if jump && i + 1 < count {
+            let skip = end - result.len();
+            result.push(Instruction::Jump(skip));
+        }
+    }
+    result
+}
+
+/// A closure followed by its body. The closure captures only the free
+/// variables of the function, so that it doesn't keep the rest alive, and
+/// leaves the names of its recursive `group` to `Instruction::Fix`.
+fn make_closue(fun_name: Name,
+               arg_name: Name,
+               body: &Ir,
+               group: &[Name],
+               cancel: Option<&CancelToken>)
+               -> Frame {
+    if is_cancelled(cancel) {
+        return Frame::new();
+    }
+    let mut captures = Vec::new();
+    let mut bound = group.to_vec();
+    bound.push(fun_name);
+    bound.push(arg_name);
+    free_vars(body, &mut bound, &mut captures);
+    let body = compile_body(body, cancel);
+    let mut result = vec![Instruction::Closure {
+                              name: fun_name,
+                              arg: arg_name,
+                              len: body.len(),
+                              captures: Some(captures),
+                          }];
+    result.extend(body);
+    result
+}
+
+/// Adds the variables of `ir` which are not `bound` to `free`, in the order of their first use.
+pub fn free_vars(ir: &Ir, bound: &mut Vec<Name>, free: &mut Vec<Name>) {
+    match *ir {
+        Ir::Var(name) => {
+            if !bound.contains(&name) && !free.contains(&name) {
+                free.push(name);
+            }
+        }
+        Ir::IntLiteral(_) | Ir::FloatLiteral(_) | Ir::BoolLiteral(_) | Ir::StrLiteral(_) | Ir::Unit |
+        Ir::Nil => {}
+        Ir::BinOp(ref op) => {
+            free_vars(&op.lhs, bound, free);
+            free_vars(&op.rhs, bound, free);
+        }
+        Ir::UnOp(ref op) => free_vars(&op.arg, bound, free),
+        Ir::Construct(ref c) => {
+            if let Some(ref arg) = c.arg {
+                free_vars(arg, bound, free);
+            }
+        }
+        Ir::Record(ref record) => {
+            for &(_, ref value) in &record.fields {
+                free_vars(value, bound, free);
+            }
+        }
+        Ir::Field(ref field) => free_vars(&field.record, bound, free),
+        Ir::Case(ref case) => {
+            free_vars(&case.scrutinee, bound, free);
+            for arm in &case.arms {
+                free_vars(arm, bound, free);
+            }
+        }
+        Ir::If(ref if_) => {
+            free_vars(&if_.cond, bound, free);
+            free_vars(&if_.tru, bound, free);
+            free_vars(&if_.fls, bound, free);
+        }
+        Ir::Seq(ref seq) => {
+            free_vars(&seq.first, bound, free);
+            free_vars(&seq.second, bound, free);
+        }
+        Ir::Fun(ref fun) => {
+            bound.push(fun.fun_name);
+            bound.push(fun.arg_name);
+            free_vars(&fun.body, bound, free);
+            bound.truncate(bound.len() - 2);
+        }
+        Ir::LetRec(ref let_rec) => {
+            let outer = bound.len();
+            bound.extend(let_rec.funs.iter().map(|fun| fun.fun_name));
+            for fun in &let_rec.funs {
+                bound.push(fun.arg_name);
+                free_vars(&fun.body, bound, free);
+                bound.pop();
+            }
+            free_vars(&let_rec.body, bound, free);
+            bound.truncate(outer);
+        }
+        Ir::Apply(ref apply) => {
+            free_vars(&apply.fun, bound, free);
+            free_vars(&apply.arg, bound, free);
+        }
+    }
+}
+
+/// Compiles `body` so that every path through it leaves the function, with
+/// a `TailCall` if it ends with an application and with a `PopEnv` otherwise.
+fn compile_body(body: &Ir, cancel: Option<&CancelToken>) -> Frame {
+    match *body {
+        Ir::If(ref if_) => {
+            let mut result = if_.cond.compile(cancel);
+            result.extend(branch(compile_body(&if_.tru, cancel), compile_body(&if_.fls, cancel), false));
+            result
+        }
+        Ir::Case(ref case_) => {
+            let mut result = case_.scrutinee.compile(cancel);
+            result.extend(case(case_.arms.iter().map(|arm| compile_body(arm, cancel)).collect(), false));
+            result
+        }
+        Ir::Seq(ref seq) => {
+            let mut result = seq.first.compile(cancel);
+            result.push(Instruction::Pop);
+            result.extend(compile_body(&seq.second, cancel));
+            result
+        }
+        Ir::Apply(ref apply) => {
+            let mut result = apply.fun.compile(cancel);
+            result.extend(apply.arg.compile(cancel));
+            result.push(Instruction::TailCall);
+            result
+        }
+        Ir::LetRec(ref let_rec) => {
+            let mut result = fix(let_rec, cancel);
+            result.push(Instruction::TailCall);
+            result
+        }
+        _ => {
+            let mut result = body.compile(cancel);
+            result.push(Instruction::PopEnv);
+            result
+        }
     }
 }
 
 impl Compile for Fun {
-    fn compile(&self) -> Frame {
-        vec![make_closue(self.fun_name, self.arg_name, &self.body)]
+    fn compile(&self, cancel: Option<&CancelToken>) -> Frame {
+        make_closue(self.fun_name, self.arg_name, &self.body, &[], cancel)
+    }
+}
+
+impl Compile for LetRec {
+    fn compile(&self, cancel: Option<&CancelToken>) -> Frame {
+        let mut result = fix(self, cancel);
+        result.push(Instruction::Call);
+        result
+    }
+}
+
+/// The functions of `let_rec` and one more, evaluating its body, as a group
+/// which can call each other, followed by the `()` to call the last one with.
+/// The body is a function too, as the group is only bound in the environments
+/// of its functions. It is called `ANONYMOUS`.
+fn fix(let_rec: &LetRec, cancel: Option<&CancelToken>) -> Frame {
+    let body_name = ANONYMOUS;
+    let mut names = let_rec.funs.iter().map(|fun| fun.fun_name).collect::<Vec<_>>();
+    names.push(body_name);
+    let mut result = Frame::new();
+    for fun in &let_rec.funs {
+        result.extend(make_closue(fun.fun_name, fun.arg_name, &fun.body, &names, cancel));
     }
+    result.extend(make_closue(body_name, body_name, &let_rec.body, &names, cancel));
+    result.push(Instruction::Fix(names));
+    result.push(Instruction::PushUnit);
+    result
 }
 
 impl Compile for Apply {
-    fn compile(&self) -> Frame {
-        let mut result = self.fun.compile();
-        result.extend(self.arg.compile());
+    fn compile(&self, cancel: Option<&CancelToken>) -> Frame {
+        let mut result = self.fun.compile(cancel);
+        result.extend(self.arg.compile(cancel));
         result.push(Instruction::Call);
         result
     }
@@ -1,25 +1,215 @@
-use ast::Ident;
-use typecheck::Type;
+use ast::{Ident, Span, Variant};
+use secd::CancelToken;
+use typecheck::{Type, TypeError};
 
-pub struct TypeContext<'a>(Vec<(&'a Ident, Type)>);
+pub struct TypeContext<'a> {
+    bindings: Vec<(&'a Ident, Type)>,
+    /// The types declared around, with the levels of their declarations, see `with_type`.
+    types: Vec<(&'a Ident, &'a [Variant], u32)>,
+    aliases: Vec<(&'a Ident, Type)>,
+    /// The type parameters of the signatures around, like `'a`, with the
+    /// variables they stand for.
+    type_params: Vec<(Ident, Type)>,
+    cancel: Option<CancelToken>,
+    /// The errors found so far, if typechecking goes on after them.
+    errors: Option<Vec<TypeError>>,
+    /// The type of each expression parsed from source, if an editor asked for them.
+    annotations: Option<Vec<(Span, Type)>>,
+    /// What is known of each `Type::Var`.
+    vars: Vec<TypeVar>,
+    /// How many `let` values being checked are around, see `nested`.
+    level: u32,
+}
+
+/// What is known of a type variable so far.
+pub enum TypeVar {
+    /// Nothing: it can be unified with any type. The level is the lowest of
+    /// those of the `let` values mentioning it.
+    Unbound(u32),
+    Bound(Type),
+}
 
 impl<'a> TypeContext<'a> {
     pub fn empty() -> Self {
-        TypeContext(Vec::new())
+        TypeContext {
+            bindings: Vec::new(),
+            types: Vec::new(),
+            aliases: Vec::new(),
+            type_params: Vec::new(),
+            cancel: None,
+            errors: None,
+            annotations: None,
+            vars: Vec::new(),
+            level: 0,
+        }
+    }
+
+    /// A context which keeps the errors it is given to `report` and goes on.
+    pub fn collecting_errors() -> Self {
+        TypeContext { errors: Some(Vec::new()), ..TypeContext::empty() }
+    }
+
+    /// A context which collects errors, and the types of expressions given to `annotate`.
+    pub fn annotating() -> Self {
+        TypeContext { annotations: Some(Vec::new()), ..TypeContext::collecting_errors() }
+    }
+
+    pub fn with_cancel_token(token: &CancelToken) -> Self {
+        TypeContext { cancel: Some(token.clone()), ..TypeContext::empty() }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.as_ref().map_or(false, |token| token.is_cancelled())
+    }
+
+    /// Keeps `error` if the context collects errors, and gives it back otherwise.
+    pub fn report(&mut self, error: TypeError) -> Result<(), TypeError> {
+        match self.errors {
+            Some(ref mut errors) => {
+                errors.push(error);
+                Ok(())
+            }
+            None => Err(error),
+        }
+    }
+
+    pub fn error_count(&self) -> usize {
+        self.errors.as_ref().map_or(0, Vec::len)
+    }
+
+    /// Replaces each error kept after the first `start` ones with `f` of it.
+    pub fn map_errors_from<F: FnMut(TypeError) -> TypeError>(&mut self, start: usize, f: F) {
+        if let Some(ref mut errors) = self.errors {
+            let later = errors.split_off(start);
+            errors.extend(later.into_iter().map(f));
+        }
+    }
+
+    pub fn into_errors(self) -> Vec<TypeError> {
+        self.errors.unwrap_or_default()
+    }
+
+    /// Keeps the type of the expression at `span` if the context annotates. Expressions built by
+    /// hand have no place to annotate, and ones with errors have no type to show.
+    pub fn annotate(&mut self, span: Span, type_: &Type) {
+        if let Some(ref mut annotations) = self.annotations {
+            if !span.is_empty() && *type_ != Type::Error {
+                annotations.push((span, type_.clone()));
+            }
+        }
+    }
+
+    /// The annotations kept so far, which the context forgets.
+    pub fn take_annotations(&mut self) -> Vec<(Span, Type)> {
+        self.annotations.as_mut().map_or(Vec::new(), |annotations| annotations.split_off(0))
+    }
+
+    /// A new type variable, at the current level.
+    pub fn fresh(&mut self) -> Type {
+        self.vars.push(TypeVar::Unbound(self.level));
+        Type::Var(self.vars.len() as u32 - 1)
+    }
+
+    pub fn type_var(&self, var: u32) -> &TypeVar {
+        &self.vars[var as usize]
+    }
+
+    pub fn set_type_var(&mut self, var: u32, value: TypeVar) {
+        self.vars[var as usize] = value;
+    }
+
+    pub fn level(&self) -> u32 {
+        self.level
+    }
+
+    /// Checks the value of a `let` with `f` one level deeper, so that the type
+    /// variables created meanwhile and still only mentioned by it are above the
+    /// current level afterwards, and can be generalized.
+    pub fn nested<R, F>(&mut self, f: F) -> R
+        where F: FnOnce(&mut TypeContext<'a>) -> R
+    {
+        self.level += 1;
+        let result = f(self);
+        self.level -= 1;
+        result
     }
 
     pub fn lookup(&self, name: &Ident) -> Option<&Type> {
-        self.0.iter().rev().find(|&&(ident, _)| ident == name).map(|&(_, ref val)| val)
+        self.bindings.iter().rev().find(|&&(ident, _)| ident == name).map(|&(_, ref val)| val)
+    }
+
+    /// The constructors of the type `name`.
+    pub fn lookup_type(&self, name: &Ident) -> Option<&'a [Variant]> {
+        self.types.iter().rev().find(|&&(ident, _, _)| ident == name).map(|&(_, variants, _)| variants)
+    }
+
+    /// The types declared at levels above `level`, which the type variables of
+    /// `level` predate.
+    pub fn types_above(&self, level: u32) -> Vec<Ident> {
+        self.types.iter().filter(|&&(_, _, l)| l > level).map(|&(&name, _, _)| name).collect()
+    }
+
+    /// The type the alias `name` stands for.
+    pub fn lookup_alias(&self, name: &Ident) -> Option<&Type> {
+        self.aliases.iter().rev().find(|&&(ident, _)| ident == name).map(|&(_, ref type_)| type_)
+    }
+
+    /// The variable the type parameter `name` stands for.
+    pub fn lookup_type_param(&self, name: &Ident) -> Option<&Type> {
+        self.type_params.iter().rev().find(|&&(ident, _)| ident == *name).map(|&(_, ref type_)| type_)
+    }
+
+    /// The type `name` is a constructor of, and its position in that type.
+    pub fn lookup_constructor(&self, name: &Ident) -> Option<(&'a Ident, usize, &'a Variant)> {
+        for &(type_name, variants, _) in self.types.iter().rev() {
+            if let Some(tag) = variants.iter().position(|variant| variant.name == *name) {
+                return Some((type_name, tag, &variants[tag]));
+            }
+        }
+        None
+    }
+
+    /// Checks `f` with the type `name` declared one level deeper, so that the
+    /// type variables from before the declaration are below it, and can't
+    /// stand for a type mentioning `name` outside of its scope.
+    pub fn with_type<R, F>(&mut self, name: &'a Ident, variants: &'a [Variant], f: F) -> R
+        where F: FnOnce(&mut TypeContext<'a>) -> R
+    {
+        self.level += 1;
+        self.types.push((name, variants, self.level));
+        let result = f(self);
+        self.types.pop();
+        self.level -= 1;
+        result
+    }
+
+    pub fn with_alias<R, F>(&mut self, name: &'a Ident, type_: Type, f: F) -> R
+        where F: FnOnce(&mut TypeContext<'a>) -> R
+    {
+        self.aliases.push((name, type_));
+        let result = f(self);
+        self.aliases.pop();
+        result
+    }
+
+    pub fn with_type_params<R, F>(&mut self, params: Vec<(Ident, Type)>, f: F) -> R
+        where F: FnOnce(&mut TypeContext<'a>) -> R
+    {
+        let old_params = self.type_params.len();
+        self.type_params.extend(params);
+        let result = f(self);
+        self.type_params.truncate(old_params);
+        result
     }
 
     pub fn with_bindings<R, F, I>(&mut self, bindings: I, f: F) -> R
         where F: FnOnce(&mut TypeContext<'a>) -> R,
               I: IntoIterator<Item = (&'a Ident, Type)>
     {
-        let old_bindings = self.0.len();
-        self.0.extend(bindings.into_iter());
+        let old_bindings = self.bindings.len();
+        self.bindings.extend(bindings.into_iter());
         let result = f(self);
-        self.0.truncate(old_bindings);
+        self.bindings.truncate(old_bindings);
         result
     }
 }
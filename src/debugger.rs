@@ -0,0 +1,182 @@
+use ast::Symbol;
+use miniml::{Frame, Instruction, Machine, Name, NameTable, Status};
+use rustyline::DefaultEditor;
+
+const HELP: &'static str = "Commands:
+  step (s)        execute one instruction
+  next (n)        execute one instruction, stepping over calls
+  continue (c)    run until a breakpoint or the end of the program
+  break <fn> (b)  stop whenever `fn` is called
+  stack           show the value stack
+  env             show the variables in scope
+  disas           show the instructions left in the current frame
+  quit (q)";
+
+pub fn run(program: &Frame, names: &NameTable) {
+    let mut editor = match DefaultEditor::new() {
+        Ok(editor) => editor,
+        Err(e) => return eprintln!("Error: can't start the debugger: {}", e),
+    };
+    let mut debugger = Debugger {
+        program: program,
+        machine: Machine::new(program),
+        names: names,
+        breakpoints: Vec::new(),
+        finished: false,
+    };
+    println!("{}", HELP);
+    debugger.show_next();
+    let mut last_command = String::new();
+    loop {
+        let line = match editor.readline("(debug) ") {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+        // Like gdb, an empty line repeats the previous command.
+        if !line.trim().is_empty() {
+            last_command = line.trim().to_owned();
+        }
+        let mut words = last_command.split_whitespace();
+        match (words.next(), words.next()) {
+            (Some("step"), None) | (Some("s"), None) => debugger.step(),
+            (Some("next"), None) | (Some("n"), None) => debugger.next(),
+            (Some("continue"), None) | (Some("c"), None) => debugger.continue_(),
+            (Some("break"), Some(fun)) | (Some("b"), Some(fun)) => debugger.set_breakpoint(fun),
+            (Some("stack"), None) => debugger.show_stack(),
+            (Some("env"), None) => debugger.show_env(),
+            (Some("disas"), None) => debugger.disassemble(),
+            (Some("quit"), None) | (Some("q"), None) => break,
+            (None, _) => {}
+            _ => println!("{}", HELP),
+        }
+    }
+}
+
+struct Debugger<'p> {
+    program: &'p Frame,
+    machine: Machine<'p>,
+    names: &'p NameTable,
+    breakpoints: Vec<(String, &'p [Instruction])>,
+    finished: bool,
+}
+
+impl<'p> Debugger<'p> {
+    fn step(&mut self) {
+        if self.advance() {
+            self.show_next();
+        }
+    }
+
+    fn next(&mut self) {
+        let depth = self.machine.depth();
+        while self.advance() && self.machine.depth() > depth && !self.at_breakpoint() {}
+        if !self.finished {
+            self.show_next();
+        }
+    }
+
+    fn continue_(&mut self) {
+        while self.advance() && !self.at_breakpoint() {}
+        if !self.finished {
+            self.show_next();
+        }
+    }
+
+    /// Executes one instruction, returns `false` once there is nothing more to execute.
+    fn advance(&mut self) -> bool {
+        if self.finished {
+            println!("The program has finished");
+            return false;
+        }
+        let result = match self.machine.step() {
+            Status::Running => return true,
+            Status::Finished(value) => format!("Result: {}", self.machine.show(value)),
+            Status::Error(e) => format!("Runtime error: {}", self.names.name_error(e)),
+        };
+        self.finished = true;
+        println!("{}", result);
+        false
+    }
+
+    fn at_breakpoint(&self) -> bool {
+        let frame = match self.machine.current_frame() {
+            Some(frame) => frame,
+            None => return false,
+        };
+        match self.breakpoints.iter().find(|&&(_, entry)| same_frame(entry, frame)) {
+            Some(&(ref fun, _)) => {
+                println!("Breakpoint: {}", fun);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn set_breakpoint(&mut self, fun: &str) {
+        let mut entries = Vec::new();
+        if let Some(name) = self.names.name(Symbol::intern(fun)) {
+            closure_frames(self.program, name, &mut entries);
+        }
+        if entries.is_empty() {
+            return println!("No function `{}`", fun);
+        }
+        for entry in entries {
+            self.breakpoints.push((fun.to_owned(), entry));
+        }
+        println!("Breakpoint set on `{}`", fun);
+    }
+
+    fn show_next(&self) {
+        match self.machine.current_frame() {
+            Some(frame) => println!("[{}] {}", self.machine.depth(), frame[0]),
+            None => println!("[{}] <end of program>", self.machine.depth()),
+        }
+    }
+
+    fn show_stack(&self) {
+        if self.machine.values().is_empty() {
+            return println!("  <empty>");
+        }
+        for (i, value) in self.machine.values().iter().enumerate().rev() {
+            println!("  {}: {}", i, self.machine.show(*value));
+        }
+    }
+
+    fn show_env(&self) {
+        let mut vars = self.machine.env().into_iter().collect::<Vec<_>>();
+        vars.sort_by_key(|&(name, _)| name);
+        for (name, value) in vars {
+            println!("  {} = {}", self.display_name(name), self.machine.show(value));
+        }
+    }
+
+    fn disassemble(&self) {
+        let frame = self.machine.current_frame().unwrap_or(&[]);
+        for (i, inst) in frame.iter().enumerate() {
+            println!("{} {}", if i == 0 { "=>" } else { "  " }, inst);
+        }
+    }
+
+    /// Source name of a variable, or its number for names synthesised by desugaring.
+    fn display_name(&self, name: Name) -> String {
+        match self.names.symbol(name) {
+            Some(symbol) => symbol.to_string(),
+            None => format!("#{}", name),
+        }
+    }
+}
+
+fn same_frame(a: &[Instruction], b: &[Instruction]) -> bool {
+    a.as_ptr() == b.as_ptr() && a.len() == b.len()
+}
+
+/// Collects the bodies of all closures called `name` in `frame`.
+fn closure_frames<'p>(frame: &'p [Instruction], name: Name, result: &mut Vec<&'p [Instruction]>) {
+    for (i, inst) in frame.iter().enumerate() {
+        if let Instruction::Closure { name: fun_name, len, .. } = *inst {
+            if fun_name == name {
+                result.push(&frame[i + 1..i + 1 + len]);
+            }
+        }
+    }
+}
@@ -0,0 +1,245 @@
+use std::env;
+use std::io::{self, IsTerminal};
+
+use ast::Span;
+use miniml::Type;
+
+use {Error, Options};
+
+const ERROR: &'static str = "1;31";
+const TYPE: &'static str = "36";
+
+#[derive(Clone, Copy)]
+pub enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    pub fn from_name(name: &str) -> Result<ColorChoice, Error> {
+        match name {
+            "auto" => Ok(ColorChoice::Auto),
+            "always" => Ok(ColorChoice::Always),
+            "never" => Ok(ColorChoice::Never),
+            _ => {
+                Err(Error::Usage(format!("unknown color choice `{}`, expected `auto`, `always` or \
+                                          `never`",
+                                         name)))
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum MessageFormat {
+    Human,
+    /// One JSON object per error, for tools.
+    Json,
+}
+
+impl MessageFormat {
+    pub fn from_name(name: &str) -> Result<MessageFormat, Error> {
+        match name {
+            "human" => Ok(MessageFormat::Human),
+            "json" => Ok(MessageFormat::Json),
+            _ => {
+                Err(Error::Usage(format!("unknown message format `{}`, expected `human` or \
+                                          `json`",
+                                         name)))
+            }
+        }
+    }
+}
+
+/// Where in the source an error is, with the line it starts on.
+pub struct Location {
+    span: Span,
+    line: usize,
+    column: usize,
+    /// How many characters of the span are on its first line.
+    width: usize,
+    text: String,
+}
+
+impl Location {
+    pub fn new(span: Span, src: &str) -> Location {
+        let (line, column) = span.line_col(src);
+        let width = src.get(span.start as usize..span.end as usize)
+            .map_or(0, |text| text.chars().take_while(|&c| c != '\n').count());
+        Location {
+            span: span,
+            line: line,
+            column: column,
+            width: width,
+            text: src.lines().nth(line - 1).unwrap_or("").to_owned(),
+        }
+    }
+
+    /// `error at 3:14`, then the line with a caret under each character of
+    /// the span that is on it.
+    fn render(&self) -> String {
+        let before = self.text.chars().take(self.column - 1);
+        // Tabs are kept, so that the carets line up however wide they are
+        let indent = before.map(|c| if c == '\t' { '\t' } else { ' ' }).collect::<String>();
+        let width = ::std::cmp::max(1, self.width);
+        format!("error at {}:{}\n{}\n{}{}",
+                self.line,
+                self.column,
+                self.text,
+                indent,
+                "^".repeat(width))
+    }
+}
+
+/// Formats errors and types for the terminal, coloring them if asked to.
+pub struct Renderer {
+    color: bool,
+    format: MessageFormat,
+}
+
+impl Renderer {
+    pub fn stdout(options: &Options) -> Renderer {
+        Renderer::new(options.color, options.message_format, io::stdout().is_terminal())
+    }
+
+    pub fn stderr(options: &Options) -> Renderer {
+        Renderer::new(options.color, options.message_format, io::stderr().is_terminal())
+    }
+
+    /// `auto` colors only terminals and honors `NO_COLOR` (https://no-color.org),
+    /// which an explicit `always` overrides.
+    fn new(choice: ColorChoice, format: MessageFormat, is_terminal: bool) -> Renderer {
+        let color = match choice {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => {
+                is_terminal && env::var_os("NO_COLOR").map_or(true, |value| value.is_empty())
+            }
+        };
+        Renderer {
+            color: color && format == MessageFormat::Human,
+            format: format,
+        }
+    }
+
+    pub fn error(&self, error: &Error) -> String {
+        match self.format {
+            MessageFormat::Human => {
+                let message = format!("{}: {}", self.paint(ERROR, error.header()), error.message());
+                match error.location() {
+                    Some(location) => format!("{}\n{}", message, location.render()),
+                    None => message,
+                }
+            }
+            MessageFormat::Json => json(error, None),
+        }
+    }
+
+    /// An error in a file other than the one being run.
+    pub fn error_in(&self, file: &str, error: &Error) -> String {
+        match self.format {
+            MessageFormat::Human => format!("{}: {}", file, self.error(error)),
+            MessageFormat::Json => json(error, Some(file)),
+        }
+    }
+
+    pub fn type_(&self, type_: &Type) -> String {
+        self.paint(TYPE, &format!("{:?}", type_))
+    }
+
+    fn paint(&self, style: &str, text: &str) -> String {
+        if self.color {
+            format!("\x1b[{}m{}\x1b[0m", style, text)
+        } else {
+            text.to_owned()
+        }
+    }
+}
+
+/// Only type errors know where they happened, the `span` of others is `null`.
+fn json(error: &Error, file: Option<&str>) -> String {
+    let file = match file {
+        Some(file) => format!(r#","file":{}"#, json_string(file)),
+        None => String::new(),
+    };
+    let span = match error.location() {
+        Some(location) => {
+            format!(r#"{{"start":{},"end":{},"line":{},"column":{}}}"#,
+                    location.span.start,
+                    location.span.end,
+                    location.line,
+                    location.column)
+        }
+        None => "null".to_owned(),
+    };
+    format!(r#"{{"code":"{}","severity":"error","message":{}{},"span":{},"suggestions":[]}}"#,
+            error.code(),
+            json_string(&error.message()),
+            file,
+            span)
+}
+
+fn json_string(s: &str) -> String {
+    let mut result = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            c if (c as u32) < 0x20 => result.push_str(&format!("\\u{:04x}", c as u32)),
+            c => result.push(c),
+        }
+    }
+    result.push('"');
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ColorChoice, Location, MessageFormat, Renderer};
+    use ast::Span;
+    use Error;
+
+    #[test]
+    fn test_error() {
+        let error = Error::Type("Unbound variable: x".to_owned(), None);
+        let human = |choice, is_terminal| Renderer::new(choice, MessageFormat::Human, is_terminal);
+        assert_eq!(human(ColorChoice::Never, true).error(&error),
+                   "Type error: Unbound variable: x");
+        assert_eq!(human(ColorChoice::Always, false).error(&error),
+                   "\x1b[1;31mType error\x1b[0m: Unbound variable: x");
+        assert!(!human(ColorChoice::Auto, false).color);
+
+        let src = "let x = 1;;\n\tx + true";
+        let error = Error::Type("Expected int, got bool in true".to_owned(),
+                                Some(Location::new(Span::new(17, 21), src)));
+        assert_eq!(human(ColorChoice::Never, false).error(&error),
+                   "Type error: Expected int, got bool in true\nerror at 2:6\n\tx + true\n\t    ^^^^");
+
+        let src = "if x\n  else 2";
+        let error = Error::Parse("Expected `then` after if condition, found `else`".to_owned(),
+                                 Some(Location::new(Span::new(7, 11), src)));
+        assert_eq!(human(ColorChoice::Never, false).error(&error),
+                   "Parse error: Expected `then` after if condition, found `else`\nerror at 2:3\n  else 2\n  ^^^^");
+
+        let src = "let é = 1;;\nλ + é";
+        let error = Error::Type("Unbound variable: λ".to_owned(), Some(Location::new(Span::new(13, 15), src)));
+        assert_eq!(human(ColorChoice::Never, false).error(&error),
+                   "Type error: Unbound variable: λ\nerror at 2:1\nλ + é\n^");
+    }
+
+    #[test]
+    fn test_json() {
+        let json = Renderer::new(ColorChoice::Always, MessageFormat::Json, true);
+        let error = Error::Parse("expected \"in\"\n".to_owned(), None);
+        assert_eq!(json.error(&error),
+                   r#"{"code":"parse","severity":"error","message":"expected \"in\"\n","span":null,"suggestions":[]}"#);
+        assert_eq!(json.error_in("lib.ml", &Error::Runtime("Division by zero".to_owned())),
+                   r#"{"code":"runtime","severity":"error","message":"Division by zero","file":"lib.ml","span":null,"suggestions":[]}"#);
+        let error = Error::Type("Unbound variable: y".to_owned(),
+                                Some(Location::new(Span::new(4, 5), "1 + y")));
+        assert_eq!(json.error(&error),
+                   r#"{"code":"type","severity":"error","message":"Unbound variable: y","span":{"start":4,"end":5,"line":1,"column":5},"suggestions":[]}"#);
+    }
+}
@@ -0,0 +1,171 @@
+//! Errors of the library API. Each phase has an error with a `kind` to match
+//! on, and `Error` wraps whichever of them stopped a program.
+//!
+//! ```
+//! use miniml::{Error, RuntimeErrorKind, TypeErrorKind};
+//!
+//! match miniml::eval("1 / 0") {
+//!     Err(Error::Runtime(ref e)) => assert_eq!(e.kind, RuntimeErrorKind::DivisionByZero),
+//!     _ => panic!(),
+//! }
+//! match miniml::eval("1 + true") {
+//!     Err(Error::Type(ref e)) => match e.kind {
+//!         TypeErrorKind::Mismatch { .. } => {}
+//!         _ => panic!(),
+//!     },
+//!     _ => panic!(),
+//! }
+//! ```
+
+use std::{error, fmt};
+
+use ast::Span;
+use syntax;
+use syntax_ll;
+use secd::RuntimeError;
+use typecheck::TypeError;
+
+#[derive(Debug)]
+pub enum Error {
+    Parse(ParseError),
+    Type(TypeError),
+    Runtime(RuntimeError),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Parse(ref error) => write!(f, "Parse error: {}", error),
+            Error::Type(ref error) => write!(f, "Type error: {}", error),
+            Error::Runtime(ref error) => write!(f, "Runtime error: {}", error),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::Parse(ref error) => Some(error),
+            Error::Type(ref error) => Some(error),
+            Error::Runtime(ref error) => Some(error),
+        }
+    }
+}
+
+impl From<ParseError> for Error {
+    fn from(error: ParseError) -> Error {
+        Error::Parse(error)
+    }
+}
+
+impl From<TypeError> for Error {
+    fn from(error: TypeError) -> Error {
+        Error::Type(error)
+    }
+}
+
+impl From<RuntimeError> for Error {
+    fn from(error: RuntimeError) -> Error {
+        Error::Runtime(error)
+    }
+}
+
+#[derive(Debug)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    /// The offending token, or an empty span at the end of the input.
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseErrorKind {
+    /// The input has a character no token starts with.
+    InvalidToken,
+    UnexpectedToken {
+        found: String,
+        expected: Vec<String>,
+    },
+    UnexpectedEnd {
+        expected: Vec<String>,
+    },
+    /// A whole expression was parsed, but the input goes on.
+    ExtraToken {
+        found: String,
+    },
+    /// An error of the hand-written parser, which says what is wrong in words.
+    Message(String),
+}
+
+impl ParseError {
+    /// Converts an error of the parsers in `syntax`. The token itself is
+    /// looked up in `src`, which `strip_comments` keeps the offsets of.
+    pub fn from_lalrpop<T, E>(error: syntax::ParseError<usize, T, E>, src: &str) -> ParseError {
+        let (kind, span) = match error {
+            syntax::ParseError::InvalidToken { location } => {
+                (ParseErrorKind::InvalidToken, Span::new(location, location))
+            }
+            syntax::ParseError::UnrecognizedToken { token: Some((start, _, end)), expected } => {
+                let kind = ParseErrorKind::UnexpectedToken {
+                    found: src[start..end].to_owned(),
+                    expected: expected,
+                };
+                (kind, Span::new(start, end))
+            }
+            syntax::ParseError::UnrecognizedToken { token: None, expected } => {
+                (ParseErrorKind::UnexpectedEnd { expected: expected }, Span::new(src.len(), src.len()))
+            }
+            syntax::ParseError::ExtraToken { token: (start, _, end) } => {
+                let kind = ParseErrorKind::ExtraToken { found: src[start..end].to_owned() };
+                (kind, Span::new(start, end))
+            }
+            // The grammar has no fallible actions.
+            syntax::ParseError::User { .. } => unreachable!(),
+        };
+        ParseError {
+            kind: kind,
+            span: span,
+        }
+    }
+
+    /// Converts an error of the parser in `syntax_ll`, whose message already
+    /// names the token it expected and the one it found.
+    pub fn from_ll(error: syntax_ll::ParseError) -> ParseError {
+        ParseError {
+            kind: ParseErrorKind::Message(error.to_string()),
+            span: error.span(),
+        }
+    }
+}
+
+impl fmt::Display for ParseErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParseErrorKind::InvalidToken => f.write_str("Invalid token"),
+            ParseErrorKind::UnexpectedToken { ref found, ref expected } => {
+                try!(write!(f, "Unexpected `{}`", found));
+                write_expected(f, expected)
+            }
+            ParseErrorKind::UnexpectedEnd { ref expected } => {
+                try!(f.write_str("Unexpected end of input"));
+                write_expected(f, expected)
+            }
+            ParseErrorKind::ExtraToken { ref found } => write!(f, "Extra `{}` after the end", found),
+            ParseErrorKind::Message(ref message) => f.write_str(message),
+        }
+    }
+}
+
+fn write_expected(f: &mut fmt::Formatter, expected: &[String]) -> fmt::Result {
+    if expected.is_empty() {
+        return Ok(());
+    }
+    write!(f, ", expected one of {}", expected.join(" "))
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at {:?}", self.kind, self.span)
+    }
+}
+
+impl error::Error for ParseError {}
@@ -0,0 +1,108 @@
+use std::time::Duration;
+
+use ast::Program;
+use compile::compile_ir;
+use error::Error;
+use instrument;
+use parser::Parser;
+use ir::desugar_program_with_names;
+use secd::{Frame, Machine, RuntimeError, Stopwatch, Value};
+use typecheck::{Type, typecheck_program};
+use value::OwnedValue;
+
+/// How long each phase of `eval_program` took, and how many instructions it executed.
+#[derive(Debug, Default)]
+pub struct Stats {
+    pub phases: Vec<(&'static str, Duration)>,
+    pub instructions: u64,
+}
+
+impl Stats {
+    /// Runs `f`, recording how long it took as `phase`.
+    pub fn time<T, F: FnOnce() -> T>(&mut self, phase: &'static str, f: F) -> T {
+        let _span = instrument::phase(phase);
+        let stopwatch = Stopwatch::start();
+        let result = f();
+        let elapsed = stopwatch.elapsed();
+        instrument::phase_finished(phase, elapsed);
+        self.phases.push((phase, elapsed));
+        result
+    }
+}
+
+/// Parses, typechecks, compiles and runs the program `src`. The value is
+/// copied out of the machine, so the compiled code is freed afterwards.
+pub fn eval(src: &str) -> Result<OwnedValue, Error> {
+    eval_typed(src).map(|(value, _)| value)
+}
+
+/// Like `eval`, but returns the type of the result as well.
+pub fn eval_typed(src: &str) -> Result<(OwnedValue, Type), Error> {
+    let program = try!(parse(src));
+    eval_program(&program, &mut Stats::default())
+}
+
+/// Evaluates an already parsed program, recording the time of each phase in `stats`.
+pub fn eval_program(program: &Program, stats: &mut Stats) -> Result<(OwnedValue, Type), Error> {
+    eval_program_with(program, stats, OwnedValue::new)
+}
+
+/// Like `eval_program`, but returns the value as the REPL shows it.
+pub fn eval_program_to_string(program: &Program, stats: &mut Stats) -> Result<(String, Type), Error> {
+    eval_program_with(program, stats, |machine, value| machine.show(value))
+}
+
+/// Like `eval_program_to_string`, for a program the caller has typechecked
+/// already, so that it isn't checked twice.
+pub fn eval_checked_to_string(program: &Program, stats: &mut Stats) -> Result<String, Error> {
+    eval_checked_with(program, stats, |machine, value| machine.show(value))
+}
+
+/// Like `eval_typed`, but passes the value and the machine that computed it
+/// to `f` instead of returning it, so that the compiled code can be freed
+/// afterwards.
+pub fn eval_with<T, F>(src: &str, f: F) -> Result<(T, Type), Error>
+    where F: for<'p> FnOnce(&Machine<'p>, Value<'p>) -> T
+{
+    let program = try!(parse(src));
+    eval_program_with(&program, &mut Stats::default(), f)
+}
+
+fn eval_program_with<T, F>(program: &Program, stats: &mut Stats, f: F) -> Result<(T, Type), Error>
+    where F: for<'p> FnOnce(&Machine<'p>, Value<'p>) -> T
+{
+    let type_ = try!(stats.time("typecheck", || typecheck_program(program))
+                          .map_err(Error::Type));
+    let result = try!(eval_checked_with(program, stats, f));
+    Ok((result, type_))
+}
+
+fn eval_checked_with<T, F>(program: &Program, stats: &mut Stats, f: F) -> Result<T, Error>
+    where F: for<'p> FnOnce(&Machine<'p>, Value<'p>) -> T
+{
+    let (ir, names) = stats.time("desugar", || desugar_program_with_names(program));
+    let frame = stats.time("compile", || compile_ir(&ir));
+    exec(&frame, stats, f).map_err(|e| Error::Runtime(names.name_error(e)))
+}
+
+/// Runs a program compiled ahead of time, like one loaded by
+/// `bytecode::load`, and returns its value as the REPL shows it. Bytecode
+/// doesn't record types, so there is none to return.
+pub fn exec_to_string(frame: &Frame, stats: &mut Stats) -> Result<String, Error> {
+    exec(frame, stats, |machine, value| machine.show(value)).map_err(Error::Runtime)
+}
+
+pub fn parse(src: &str) -> Result<Program, Error> {
+    let _span = instrument::phase("parse");
+    Parser::default().parse_program(src).map_err(Error::Parse)
+}
+
+fn exec<'p, T, F>(frame: &'p Frame, stats: &mut Stats, f: F) -> Result<T, RuntimeError>
+    where F: FnOnce(&Machine<'p>, Value<'p>) -> T
+{
+    let mut machine = Machine::new(frame);
+    let result = stats.time("execute", || machine.exec());
+    stats.phases.push(("gc", machine.gc_time()));
+    stats.instructions = machine.steps();
+    result.map(|value| f(&machine, value))
+}
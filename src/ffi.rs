@@ -0,0 +1,204 @@
+//! C API for embedding the interpreter, declared in `include/miniml.h`.
+//!
+//! `miniml_eval` returns an owned `MinimlValue`, or null on error, in which
+//! case `miniml_last_error` describes what went wrong on this thread.
+
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int};
+use std::ptr;
+
+use eval::eval_with;
+use secd::{Machine, Value};
+
+pub const MINIML_INT: c_int = 0;
+pub const MINIML_BOOL: c_int = 1;
+pub const MINIML_FUNCTION: c_int = 2;
+pub const MINIML_LIST: c_int = 3;
+pub const MINIML_VARIANT: c_int = 4;
+pub const MINIML_STRING: c_int = 5;
+pub const MINIML_REF: c_int = 6;
+pub const MINIML_UNIT: c_int = 7;
+pub const MINIML_FLOAT: c_int = 8;
+pub const MINIML_RECORD: c_int = 9;
+
+/// A result of `miniml_eval`. Functions, lists, variants, records and references can only be shown,
+/// as they can't outlive the machine that made them.
+pub struct MinimlValue {
+    kind: c_int,
+    int: i64,
+    float: f64,
+    bool: bool,
+    string: Option<CString>,
+    display: CString,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = RefCell::new(None);
+}
+
+/// Evaluates the program `src`, a NUL-terminated UTF-8 string.
+/// The result must be freed with `miniml_value_free`.
+#[no_mangle]
+pub unsafe extern "C" fn miniml_eval(src: *const c_char) -> *mut MinimlValue {
+    if src.is_null() {
+        set_last_error("source is null".to_owned());
+        return ptr::null_mut();
+    }
+    let src = match CStr::from_ptr(src).to_str() {
+        Ok(src) => src,
+        Err(_) => {
+            set_last_error("source is not valid UTF-8".to_owned());
+            return ptr::null_mut();
+        }
+    };
+    match eval_with(src, to_owned) {
+        Ok((value, _)) => {
+            set_last_error_to(None);
+            Box::into_raw(Box::new(value))
+        }
+        Err(e) => {
+            set_last_error(e.to_string());
+            ptr::null_mut()
+        }
+    }
+}
+
+/// The error of the last failed `miniml_eval` on this thread, or null.
+/// The string is owned by the library and valid until the next `miniml_eval`.
+#[no_mangle]
+pub extern "C" fn miniml_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ref().map_or(ptr::null(), |e| e.as_ptr()))
+}
+
+/// One of `MINIML_INT`, `MINIML_FLOAT`, `MINIML_BOOL`, `MINIML_STRING`, `MINIML_FUNCTION`,
+/// `MINIML_LIST`, `MINIML_VARIANT`, `MINIML_RECORD`, `MINIML_REF` and `MINIML_UNIT`.
+#[no_mangle]
+pub unsafe extern "C" fn miniml_value_kind(value: *const MinimlValue) -> c_int {
+    (*value).kind
+}
+
+/// The value of an `int`, or 0 for other kinds.
+#[no_mangle]
+pub unsafe extern "C" fn miniml_value_int(value: *const MinimlValue) -> i64 {
+    (*value).int
+}
+
+/// The value of a `float`, or 0 for other kinds.
+#[no_mangle]
+pub unsafe extern "C" fn miniml_value_float(value: *const MinimlValue) -> f64 {
+    (*value).float
+}
+
+/// The value of a `bool`, or false for other kinds.
+#[no_mangle]
+pub unsafe extern "C" fn miniml_value_bool(value: *const MinimlValue) -> bool {
+    (*value).bool
+}
+
+/// The contents of a `string`, or null for other kinds. Valid as long as `value` is.
+#[no_mangle]
+pub unsafe extern "C" fn miniml_value_string(value: *const MinimlValue) -> *const c_char {
+    (*value).string.as_ref().map_or(ptr::null(), |s| s.as_ptr())
+}
+
+/// The value as the REPL shows it. Valid as long as `value` is.
+#[no_mangle]
+pub unsafe extern "C" fn miniml_value_to_string(value: *const MinimlValue) -> *const c_char {
+    (*value).display.as_ptr()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn miniml_value_free(value: *mut MinimlValue) {
+    if !value.is_null() {
+        drop(Box::from_raw(value));
+    }
+}
+
+fn to_owned<'p>(machine: &Machine<'p>, value: Value<'p>) -> MinimlValue {
+    let (kind, int, float, bool) = match value {
+        Value::Int(i) => (MINIML_INT, i, 0.0, false),
+        Value::Float(x) => (MINIML_FLOAT, 0, x, false),
+        Value::Bool(b) => (MINIML_BOOL, 0, 0.0, b),
+        Value::Unit => (MINIML_UNIT, 0, 0.0, false),
+        Value::Str(_) => (MINIML_STRING, 0, 0.0, false),
+        Value::Closure(_) | Value::Native(_) => (MINIML_FUNCTION, 0, 0.0, false),
+        Value::Nil | Value::Cons(_) => (MINIML_LIST, 0, 0.0, false),
+        Value::Variant(_) => (MINIML_VARIANT, 0, 0.0, false),
+        Value::Record(_) => (MINIML_RECORD, 0, 0.0, false),
+        Value::Ref(_) => (MINIML_REF, 0, 0.0, false),
+    };
+    MinimlValue {
+        kind: kind,
+        int: int,
+        float: float,
+        bool: bool,
+        // Like the source, strings can't contain NULs.
+        string: machine.string(value).ok().map(|s| CString::new(s).unwrap()),
+        display: CString::new(machine.show(value)).unwrap(),
+    }
+}
+
+fn set_last_error(message: String) {
+    // Messages quote the source, which can't contain NULs after `CStr`.
+    set_last_error_to(Some(CString::new(message).unwrap()));
+}
+
+fn set_last_error_to(error: Option<CString>) {
+    LAST_ERROR.with(|last| *last.borrow_mut() = error);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::{CStr, CString};
+
+    use super::*;
+
+    #[test]
+    fn test_eval() {
+        unsafe {
+            let src = CString::new("let x = 90;; x + 2").unwrap();
+            let value = miniml_eval(src.as_ptr());
+            assert_eq!(miniml_value_kind(value), MINIML_INT);
+            assert_eq!(miniml_value_int(value), 92);
+            assert!(miniml_last_error().is_null());
+            miniml_value_free(value);
+
+            let src = CString::new("float_of_int 3 / 2.0").unwrap();
+            let value = miniml_eval(src.as_ptr());
+            assert_eq!(miniml_value_kind(value), MINIML_FLOAT);
+            assert_eq!(miniml_value_float(value), 1.5);
+            miniml_value_free(value);
+
+            let src = CString::new("fun id(x: int): int is x").unwrap();
+            let value = miniml_eval(src.as_ptr());
+            assert_eq!(miniml_value_kind(value), MINIML_FUNCTION);
+            assert_eq!(CStr::from_ptr(miniml_value_to_string(value)).to_str(), Ok("<closure>"));
+            miniml_value_free(value);
+
+            let src = CString::new("[1, 2] :: []: int list list").unwrap();
+            let value = miniml_eval(src.as_ptr());
+            assert_eq!(miniml_value_kind(value), MINIML_LIST);
+            assert_eq!(CStr::from_ptr(miniml_value_to_string(value)).to_str(), Ok("[[1, 2]]"));
+            miniml_value_free(value);
+
+            let src = CString::new(r#""mini" ++ "ml\n""#).unwrap();
+            let value = miniml_eval(src.as_ptr());
+            assert_eq!(miniml_value_kind(value), MINIML_STRING);
+            assert_eq!(CStr::from_ptr(miniml_value_string(value)).to_str(), Ok("miniml\n"));
+            assert_eq!(CStr::from_ptr(miniml_value_to_string(value)).to_str(), Ok(r#""miniml\n""#));
+            miniml_value_free(value);
+
+            let src = CString::new("{y = true, x = 1}").unwrap();
+            let value = miniml_eval(src.as_ptr());
+            assert_eq!(miniml_value_kind(value), MINIML_RECORD);
+            assert_eq!(CStr::from_ptr(miniml_value_to_string(value)).to_str(), Ok("{x = 1, y = true}"));
+            miniml_value_free(value);
+
+            let src = CString::new("1 / 0").unwrap();
+            assert!(miniml_eval(src.as_ptr()).is_null());
+            assert_eq!(CStr::from_ptr(miniml_last_error()).to_str(),
+                       Ok("Runtime error: Division by zero"));
+        }
+    }
+}
@@ -0,0 +1,83 @@
+//! Snapshot tests for the compiler's dumps.
+//!
+//! For every `tests/golden/NAME.ml` the AST, IR and bytecode are printed
+//! and compared with `tests/golden/NAME.golden`. After an intended change,
+//! run the tests with `UPDATE_GOLDEN=1` to rewrite the snapshots and review
+//! the result with `git diff`.
+
+use std::env;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use syntax;
+use compile::compile_ir;
+use ir::desugar_program;
+use secd::{Frame, Instruction};
+
+fn golden_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("golden")
+}
+
+fn read(path: &Path) -> String {
+    let mut result = String::new();
+    File::open(path).and_then(|mut file| file.read_to_string(&mut result))
+                    .unwrap_or_else(|e| panic!("Failed to read {}: {}", path.display(), e));
+    result
+}
+
+fn dump(src: &str) -> String {
+    let program = syntax::parse_program(&syntax::strip_comments(src)).unwrap();
+    let ir = desugar_program(&program);
+    let frame = compile_ir(&ir);
+    let mut result = format!("-- ast\n{:?}\n\n-- ir\n{:?}\n\n-- bytecode\n", program, ir);
+    list(&frame, &mut result);
+    result
+}
+
+/// One instruction per line, with the bodies of closures indented below them.
+fn list(frame: &Frame, out: &mut String) {
+    let mut ends = Vec::new();
+    for (i, inst) in frame.iter().enumerate() {
+        while ends.last() == Some(&i) {
+            ends.pop();
+        }
+        out.push_str(&format!("{:2$}{}\n", "", inst, ends.len() * 4));
+        if let Instruction::Closure { len, .. } = *inst {
+            ends.push(i + 1 + len);
+        }
+    }
+}
+
+/// Compares `actual` with the contents of `path`, or overwrites `path`
+/// with it in update mode. Returns whether they differ.
+fn check(path: &Path, actual: &str, update: bool) -> bool {
+    let expected = if path.exists() { read(path) } else { String::new() };
+    if expected == actual {
+        return false;
+    }
+    if update {
+        File::create(path).and_then(|mut file| file.write_all(actual.as_bytes()))
+                          .unwrap_or_else(|e| panic!("Failed to write {}: {}", path.display(), e));
+        return false;
+    }
+    println!("{} differs, got:\n{}", path.display(), actual);
+    true
+}
+
+#[test]
+fn golden() {
+    let update = env::var_os("UPDATE_GOLDEN").is_some();
+    let mut sources = fs::read_dir(golden_dir()).unwrap()
+                                                .map(|entry| entry.unwrap().path())
+                                                .filter(|path| path.extension().map_or(false, |e| e == "ml"))
+                                                .collect::<Vec<_>>();
+    sources.sort();
+    assert!(!sources.is_empty());
+    let failed = sources.iter()
+                        .filter(|source| check(&source.with_extension("golden"), &dump(&read(source)), update))
+                        .count();
+    assert!(failed == 0,
+            "{} snapshots differ, rerun with UPDATE_GOLDEN=1 to update them",
+            failed);
+}
@@ -0,0 +1,634 @@
+//! What an editor shows of a program as it is written, for the language
+//! server in `lsp/`: the errors in it, the type of the expression at a place
+//! and where the name at a place is bound. Places are byte offsets in the
+//! text.
+//!
+//! The text is split into units, each declaration with its `;;` and the
+//! expression after the last one, which are parsed, typechecked and resolved
+//! on their own. An edit only parses the units it touches again. They are
+//! typechecked again with the units after them whose scope they change, that
+//! is, all of them when the names they bind or their types aren't the same
+//! as before.
+//!
+//! ```
+//! use miniml::Type;
+//! use miniml::ide::Analysis;
+//!
+//! let mut analysis = Analysis::new("let x = 1;;\nlet y = x + 1;;\ny");
+//! assert_eq!(analysis.type_at(20).map(|(_, t)| t), Some(&Type::Int));
+//! assert_eq!(analysis.definition(20).map(|span| (span.start, span.end)), Some((4, 5)));
+//!
+//! analysis.edit(24..25, "2.0");
+//! assert_eq!(analysis.text(), "let x = 1;;\nlet y = x + 2.0;;\ny");
+//! assert_eq!((analysis.reparsed(), analysis.rechecked()), (1, 1));
+//! assert_eq!(analysis.diagnostics().len(), 1);
+//! ```
+
+use std::collections::HashMap;
+use std::ops::Range;
+
+use ast::{Decl, Expr, ExprKind, Fun, Ident, Span};
+use parser::Parser;
+use syntax::strip_comments;
+use typecheck::{Annotations, Type, annotate_decl, annotate_in};
+
+/// An error in the program, for an editor to underline.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// Empty at the start of the declaration for the errors which aren't
+    /// about a part of it.
+    pub span: Span,
+    pub message: String,
+}
+
+/// A program being edited, with what was found in it.
+pub struct Analysis {
+    text: String,
+    /// Units cover the text, in order.
+    units: Vec<Unit>,
+    reparsed: usize,
+    rechecked: usize,
+}
+
+/// A declaration with its `;;`, or the expression after the last one. Spans
+/// in it are from its start.
+struct Unit {
+    start: usize,
+    text: String,
+    syntax: Result<Syntax, Diagnostic>,
+    /// The names the unit bound with their types when it was last checked,
+    /// which the units after it are checked with.
+    bindings: Option<Vec<(Ident, Type)>>,
+    /// What the last check found, unless the unit or its scope changed since.
+    annotations: Option<Annotations>,
+}
+
+/// What is known of a unit which parsed, without typechecking it.
+struct Syntax {
+    tree: Tree,
+    /// The names the unit binds, with where they are bound.
+    binders: Vec<(Ident, Option<Span>)>,
+    /// The variables referring to let-bound names of the unit, with where
+    /// the names are bound.
+    references: Vec<(Span, Span)>,
+    /// The variables referring to names bound before the unit.
+    free: Vec<(Span, Ident)>,
+}
+
+enum Tree {
+    Decl(Decl),
+    Body(Expr),
+}
+
+impl Analysis {
+    pub fn new(text: &str) -> Analysis {
+        let mut analysis = Analysis {
+            text: String::new(),
+            units: Vec::new(),
+            reparsed: 0,
+            rechecked: 0,
+        };
+        analysis.edit(0..0, text);
+        analysis
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Replaces the bytes in `range` of the text with `replacement`, and
+    /// analyzes the units which changed.
+    ///
+    /// # Panics
+    ///
+    /// If `range` isn't in the text or doesn't start and end at characters.
+    pub fn edit(&mut self, range: Range<usize>, replacement: &str) {
+        self.text.replace_range(range.clone(), replacement);
+        let inserted_end = range.start + replacement.len();
+        let mut old = self.units.drain(..).map(|unit| (unit.start, unit)).collect::<HashMap<_, _>>();
+        let mut units = Vec::new();
+        let mut parsed = Vec::new();
+        let chunks = split(&self.text);
+        for (i, chunk) in chunks.iter().enumerate() {
+            let text = &self.text[chunk.clone()];
+            // Where the unit started before the edit, unless the edit is in it.
+            let old_start = if chunk.end <= range.start {
+                Some(chunk.start)
+            } else if chunk.start >= inserted_end {
+                Some(chunk.start - inserted_end + range.end)
+            } else {
+                None
+            };
+            match old_start.and_then(|start| old.remove(&start)).filter(|unit| unit.text == text) {
+                Some(mut unit) => {
+                    unit.start = chunk.start;
+                    units.push(unit);
+                }
+                None => {
+                    parsed.push(units.len());
+                    units.push(Unit::parse(chunk.start, text, i == chunks.len() - 1));
+                }
+            }
+        }
+        // A unit changed in place is checked again, but the ones after it
+        // only if it binds other names or types. Any other change of units
+        // changes the scope of all the ones after it.
+        if old.len() == 1 && parsed.len() == 1 {
+            units[parsed[0]].bindings = old.into_iter().next().and_then(|(_, unit)| unit.bindings);
+        } else if parsed.is_empty() && !old.is_empty() {
+            if let Some(next) = units.iter_mut().find(|unit| unit.start >= range.start) {
+                next.bindings = None;
+                next.annotations = None;
+            }
+        }
+        self.units = units;
+        self.reparsed = parsed.len();
+        self.check();
+    }
+
+    /// Analyzes the new text of the program, as an edit of the part which
+    /// differs from the old text.
+    pub fn update(&mut self, text: &str) {
+        let old = self.text.as_bytes();
+        let new = text.as_bytes();
+        let mut prefix = old.iter().zip(new).take_while(|&(old, new)| old == new).count();
+        while !self.text.is_char_boundary(prefix) {
+            prefix -= 1;
+        }
+        let longest = ::std::cmp::min(old.len(), new.len()) - prefix;
+        let mut suffix = old.iter().rev().zip(new.iter().rev()).take(longest).take_while(|&(old, new)| old == new).count();
+        while !self.text.is_char_boundary(old.len() - suffix) {
+            suffix -= 1;
+        }
+        let range = prefix..old.len() - suffix;
+        self.edit(range, &text[prefix..new.len() - suffix]);
+    }
+
+    /// How many units the last change parsed again.
+    pub fn reparsed(&self) -> usize {
+        self.reparsed
+    }
+
+    /// How many units the last change typechecked again.
+    pub fn rechecked(&self) -> usize {
+        self.rechecked
+    }
+
+    /// Typechecks the units which changed or whose scope did. Nothing is
+    /// checked while a unit doesn't parse.
+    fn check(&mut self) {
+        self.rechecked = 0;
+        if !self.parses() {
+            return;
+        }
+        let mut env = Vec::new();
+        let mut env_changed = false;
+        for unit in &mut self.units {
+            if env_changed || unit.annotations.is_none() {
+                let (bindings, annotations) = match unit.syntax.as_ref().map(|syntax| &syntax.tree) {
+                    Ok(&Tree::Decl(ref decl)) => annotate_decl(&env, decl),
+                    Ok(&Tree::Body(ref expr)) => (Vec::new(), annotate_in(&env, expr)),
+                    Err(_) => unreachable!(),
+                };
+                env_changed = env_changed || unit.bindings.as_ref() != Some(&bindings);
+                unit.bindings = Some(bindings);
+                unit.annotations = Some(annotations);
+                self.rechecked += 1;
+            }
+            env.extend(unit.bindings.iter().flat_map(|bindings| bindings.iter().cloned()));
+        }
+    }
+
+    fn parses(&self) -> bool {
+        self.units.iter().all(|unit| unit.syntax.is_ok())
+    }
+
+    /// The parse errors, or all the type errors if there are none.
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        let parse_errors = self.units
+                               .iter()
+                               .filter_map(|unit| {
+                                   unit.syntax.as_ref().err().map(|error| {
+                                       Diagnostic {
+                                           span: shift(error.span, unit.start),
+                                           message: error.message.clone(),
+                                       }
+                                   })
+                               })
+                               .collect::<Vec<_>>();
+        if !parse_errors.is_empty() {
+            return parse_errors;
+        }
+        let mut result = Vec::new();
+        for unit in &self.units {
+            for error in unit.annotations.iter().flat_map(|annotations| annotations.errors.iter()) {
+                result.push(Diagnostic {
+                    span: shift(error.span.unwrap_or_default(), unit.start),
+                    message: error.to_string(),
+                });
+            }
+        }
+        result
+    }
+
+    /// The innermost expression at `offset` which has a type, with the type.
+    pub fn type_at(&self, offset: usize) -> Option<(Span, &Type)> {
+        if !self.parses() {
+            return None;
+        }
+        let unit = &self.units[self.unit_at(offset)];
+        let offset = offset - unit.start;
+        unit.annotations
+            .iter()
+            .flat_map(|annotations| annotations.types.iter())
+            .filter(|&&(span, _)| contains(span, offset))
+            .min_by_key(|&&(span, _)| span.end - span.start)
+            .map(|&(span, ref type_)| (shift(span, unit.start), type_))
+    }
+
+    /// Where the let-bound name used at `offset` is bound.
+    pub fn definition(&self, offset: usize) -> Option<Span> {
+        if !self.parses() {
+            return None;
+        }
+        let index = self.unit_at(offset);
+        let unit = &self.units[index];
+        let syntax = match unit.syntax {
+            Ok(ref syntax) => syntax,
+            Err(_) => return None,
+        };
+        let offset = offset - unit.start;
+        if let Some(&(_, binding)) = syntax.references.iter().find(|&&(reference, _)| contains(reference, offset)) {
+            return Some(shift(binding, unit.start));
+        }
+        let name = match syntax.free.iter().find(|&&(reference, _)| contains(reference, offset)) {
+            Some(&(_, name)) => name,
+            None => return None,
+        };
+        for unit in self.units[..index].iter().rev() {
+            if let Ok(ref syntax) = unit.syntax {
+                if let Some(&(_, binding)) = syntax.binders.iter().rev().find(|&&(ident, _)| ident == name) {
+                    return binding.map(|binding| shift(binding, unit.start));
+                }
+            }
+        }
+        None
+    }
+
+    /// The index of the unit `offset` is in.
+    fn unit_at(&self, offset: usize) -> usize {
+        self.units.iter().rposition(|unit| unit.start <= offset).unwrap_or(0)
+    }
+}
+
+impl Unit {
+    /// Parses the unit at `start`, which is the expression at the end of the
+    /// program if it is the `last` one.
+    fn parse(start: usize, text: &str, last: bool) -> Unit {
+        let parser = Parser::default();
+        let code = strip_comments(text);
+        let tree = if !last {
+            parser.parse_decl(text).map(Tree::Decl)
+        } else if code.trim().is_empty() {
+            // As in a program file, a program without an expression evaluates `main`.
+            Ok(Tree::Body(ExprKind::Var(Ident::from_str("main")).into()))
+        } else {
+            parser.parse(text).map(Tree::Body)
+        };
+        let syntax = tree.map(|tree| Syntax::new(tree, &code)).map_err(|e| {
+            Diagnostic {
+                span: e.span,
+                message: e.kind.to_string(),
+            }
+        });
+        Unit {
+            start: start,
+            text: text.to_owned(),
+            syntax: syntax,
+            bindings: None,
+            annotations: None,
+        }
+    }
+}
+
+impl Syntax {
+    /// Resolves the names in `tree`, whose code without comments is `code`.
+    fn new(tree: Tree, code: &str) -> Syntax {
+        let mut resolver = Resolver {
+            code: code,
+            scope: Vec::new(),
+            references: Vec::new(),
+            free: Vec::new(),
+        };
+        match tree {
+            Tree::Decl(ref decl) => resolver.decl(decl),
+            Tree::Body(ref expr) => resolver.expr(expr),
+        }
+        Syntax {
+            tree: tree,
+            binders: resolver.scope,
+            references: resolver.references,
+            free: resolver.free,
+        }
+    }
+}
+
+/// The ranges of the units of `text`: each declaration up to the end of its
+/// `;;`, and then the rest. A `;;` in a comment or a string doesn't end one.
+fn split(text: &str) -> Vec<Range<usize>> {
+    let mut result = Vec::new();
+    let mut start = 0;
+    let mut in_comment = false;
+    let mut in_string = false;
+    let mut escaped = false;
+    let mut chars = text.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        let next = chars.peek().map(|&(_, next)| next);
+        if c == '\n' {
+            in_comment = false;
+            in_string = false;
+        } else if in_string {
+            in_string = escaped || c != '"';
+            escaped = !escaped && c == '\\';
+        } else if !in_comment {
+            match (c, next) {
+                ('"', _) => {
+                    in_string = true;
+                    escaped = false;
+                }
+                ('-', Some('-')) => in_comment = true,
+                (';', Some(';')) => {
+                    chars.next();
+                    result.push(start..i + 2);
+                    start = i + 2;
+                }
+                _ => {}
+            }
+        }
+    }
+    result.push(start..text.len());
+    result
+}
+
+fn contains(span: Span, offset: usize) -> bool {
+    span.start as usize <= offset && offset < span.end as usize
+}
+
+fn shift(span: Span, by: usize) -> Span {
+    Span::new(span.start as usize + by, span.end as usize + by)
+}
+
+/// Finds the names variables of a unit refer to, with the scopes of the
+/// typechecker. The AST has no spans for names, so a let-bound name is taken
+/// to be where it is first written in its `let`.
+struct Resolver<'a> {
+    code: &'a str,
+    /// The names in scope, with where they are bound if they are let-bound.
+    scope: Vec<(Ident, Option<Span>)>,
+    references: Vec<(Span, Span)>,
+    free: Vec<(Span, Ident)>,
+}
+
+impl<'a> Resolver<'a> {
+    /// Resolves a declaration, leaving the names it binds in scope.
+    fn decl(&mut self, decl: &Decl) {
+        match *decl {
+            Decl::Fun(ref fun) => {
+                let binding = self.find(&fun.fun_name, 0);
+                self.fun(fun, binding);
+                self.scope.push((fun.fun_name, binding));
+            }
+            Decl::LetRec(ref funs) => self.group(funs, 0),
+            Decl::Value(ref name, ref value) => {
+                self.expr(value);
+                let binding = self.find(name, 0);
+                self.scope.push((*name, binding));
+            }
+        }
+    }
+
+    /// Where `name` is first written as a whole word after `start`.
+    fn find(&self, name: &Ident, start: usize) -> Option<Span> {
+        let name = name.as_ref();
+        self.code[start..]
+            .match_indices(name)
+            .map(|(i, _)| start + i)
+            .find(|&i| {
+                !self.code[..i].ends_with(is_ident_char) && !self.code[i + name.len()..].starts_with(is_ident_char)
+            })
+            .map(|i| Span::new(i, i + name.len()))
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        let scope = self.scope.len();
+        match expr.kind {
+            ExprKind::Var(ref name) => {
+                match self.scope.iter().rev().find(|&&(ident, _)| ident == *name) {
+                    Some(&(_, Some(binding))) => self.references.push((expr.span, binding)),
+                    Some(&(_, None)) => {}
+                    None => self.free.push((expr.span, *name)),
+                }
+            }
+            ExprKind::Literal(_) => {}
+            ExprKind::ArithBinOp(ref op) => self.exprs(&[&op.lhs, &op.rhs]),
+            ExprKind::CmpBinOp(ref op) => self.exprs(&[&op.lhs, &op.rhs]),
+            ExprKind::LogicBinOp(ref op) => self.exprs(&[&op.lhs, &op.rhs]),
+            ExprKind::Not(ref not) => self.expr(&not.arg),
+            ExprKind::List(ref list) => {
+                for item in &list.items {
+                    self.expr(item);
+                }
+            }
+            ExprKind::Cons(ref cons) => self.exprs(&[&cons.head, &cons.tail]),
+            ExprKind::Concat(ref concat) => self.exprs(&[&concat.lhs, &concat.rhs]),
+            ExprKind::ListUnOp(ref op) => self.expr(&op.arg),
+            ExprKind::RefUnOp(ref op) => self.expr(&op.arg),
+            ExprKind::PrintUnOp(ref op) => self.expr(&op.arg),
+            ExprKind::ConvertUnOp(ref op) => self.expr(&op.arg),
+            ExprKind::Assign(ref assign) => self.exprs(&[&assign.cell, &assign.value]),
+            ExprKind::Seq(ref seq) => self.exprs(&[&seq.first, &seq.second]),
+            ExprKind::Record(ref record) => {
+                for &(_, ref value) in &record.fields {
+                    self.expr(value);
+                }
+            }
+            ExprKind::Field(ref field) => self.expr(&field.record),
+            ExprKind::Construct(ref construct) => {
+                if let Some(ref arg) = construct.arg {
+                    self.expr(arg);
+                }
+            }
+            ExprKind::Match(ref match_) => {
+                self.expr(&match_.scrutinee);
+                for arm in &match_.arms {
+                    self.scope.extend(arm.binding.map(|binding| (binding, None)));
+                    self.expr(&arm.body);
+                    self.scope.truncate(scope);
+                }
+            }
+            ExprKind::If(ref if_) => self.exprs(&[&if_.cond, &if_.tru, &if_.fls]),
+            ExprKind::Fun(ref fun) => self.fun(fun, None),
+            ExprKind::Let(ref let_) => {
+                self.expr(&let_.value);
+                let binding = self.find(&let_.name, expr.span.start as usize);
+                self.scope.push((let_.name, binding));
+                self.expr(&let_.body);
+            }
+            ExprKind::LetFun(ref let_fun) => {
+                let binding = self.find(&let_fun.fun.fun_name, expr.span.start as usize);
+                self.fun(&let_fun.fun, binding);
+                self.scope.push((let_fun.fun.fun_name, binding));
+                self.expr(&let_fun.body);
+            }
+            ExprKind::LetRec(ref let_rec) => {
+                self.group(&let_rec.funs, expr.span.start as usize);
+                self.expr(&let_rec.body);
+            }
+            ExprKind::LetType(ref let_type) => self.expr(&let_type.body),
+            ExprKind::TypeAlias(ref alias) => self.expr(&alias.body),
+            ExprKind::Apply(ref apply) => self.exprs(&[&apply.fun, &apply.arg]),
+        }
+        self.scope.truncate(scope);
+    }
+
+    fn exprs(&mut self, exprs: &[&Expr]) {
+        for expr in exprs {
+            self.expr(expr);
+        }
+    }
+
+    /// Resolves the body of `fun`, which is bound at `binding`. As in the
+    /// typechecker, the name of the function shadows the first parameter.
+    fn fun(&mut self, fun: &Fun, binding: Option<Span>) {
+        let scope = self.scope.len();
+        self.scope.push((fun.args[0].0, None));
+        self.scope.push((fun.fun_name, binding));
+        self.scope.extend(fun.args[1..].iter().map(|arg| (arg.0, None)));
+        self.expr(&fun.body);
+        self.scope.truncate(scope);
+    }
+
+    /// Binds the functions of a `let rec` group written after `start`, and
+    /// resolves their bodies. Each function is named after the body of the
+    /// one before it.
+    fn group(&mut self, funs: &[Fun], start: usize) {
+        let mut start = start;
+        let mut bindings = Vec::new();
+        for fun in funs {
+            bindings.push(self.find(&fun.fun_name, start));
+            start = fun.body.span.end as usize;
+        }
+        self.scope.extend(funs.iter().map(|fun| fun.fun_name).zip(bindings.iter().cloned()));
+        for (fun, &binding) in funs.iter().zip(&bindings) {
+            self.fun(fun, binding);
+        }
+    }
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The text at `span`.
+    fn at(text: &str, span: Span) -> &str {
+        &text[span.start as usize..span.end as usize]
+    }
+
+    #[test]
+    fn diagnostics() {
+        let analysis = Analysis::new("let x = 1 +;;\nx");
+        let diagnostics = analysis.diagnostics();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].span, Span::new(11, 13));
+
+        let text = "let x = 1 + true;;\nlet y = if x then 1 else 2;;\ny + z";
+        let analysis = Analysis::new(text);
+        let spans = analysis.diagnostics().iter().map(|d| at(text, d.span).to_owned()).collect::<Vec<_>>();
+        assert_eq!(spans, vec!["true", "x", "z"]);
+    }
+
+    #[test]
+    fn types() {
+        let text = "let fun inc(n: int): int is n + 1;;\nlet xs = [1.0, 2.0];;\ninc (inc 1)";
+        let analysis = Analysis::new(text);
+        let type_at = |needle: &str| {
+            let (span, type_) = analysis.type_at(text.find(needle).unwrap()).unwrap();
+            (at(text, span), format!("{:?}", type_))
+        };
+        assert_eq!(type_at("n + 1"), ("n", "int".to_owned()));
+        assert_eq!(type_at("+ 1"), ("n + 1", "int".to_owned()));
+        assert_eq!(type_at("[1.0"), ("[1.0, 2.0]", "float list".to_owned()));
+        assert_eq!(type_at("inc (inc"), ("inc", "int -> int".to_owned()));
+        assert_eq!(analysis.type_at(text.find(";;").unwrap()), None);
+    }
+
+    #[test]
+    fn definitions() {
+        let text = "let x = 1;;\n\
+                    let rec fun even(n: int): bool is if n == 0 then true else odd (n - 1)\n\
+                    and fun odd(n: int): bool is if n == 0 then false else even (n - 1);;\n\
+                    let x = let y = x in let fun f(x: int): int is x + y in f x;;\n\
+                    -- x\n\
+                    odd x";
+        let analysis = Analysis::new(text);
+        let definition = |needle: &str, offset: usize| {
+            analysis.definition(text.find(needle).unwrap() + offset).map(|span| span.start as usize)
+        };
+        let odd = text.find("odd(n").unwrap();
+        assert_eq!(definition("odd (n - 1)", 0), Some(odd));
+        assert_eq!(definition("even (n - 1)", 0), text.find("even(n"));
+        assert_eq!(definition("y = x", 4), Some(4));
+        assert_eq!(definition("x + y", 4), text.find("y = x"));
+        // Parameters aren't let-bound, but still shadow.
+        assert_eq!(definition("x + y", 0), None);
+        assert_eq!(definition("f x", 0), text.find("f(x"));
+        assert_eq!(definition("odd x", 4), text.find("x = let"));
+        assert_eq!(definition("odd x", 0), Some(odd));
+    }
+
+    #[test]
+    fn units() {
+        let text = "let s = \"a;;\";; -- ;;\nlet t = s;;\ns";
+        let units = split(text).into_iter().map(|range| &text[range]).collect::<Vec<_>>();
+        assert_eq!(units, vec!["let s = \"a;;\";;", " -- ;;\nlet t = s;;", "\ns"]);
+        let analysis = Analysis::new(text);
+        assert_eq!(analysis.definition(text.len() - 1), Some(Span::new(4, 5)));
+    }
+
+    #[test]
+    fn edits() {
+        let text = "let x = 1;;\nlet y = x + 1;;\nlet z = y * 2;;\nz";
+        let mut analysis = Analysis::new(text);
+        assert_eq!((analysis.reparsed(), analysis.rechecked()), (4, 4));
+
+        // `y` is still an int, so what uses it needn't be checked again.
+        analysis.update(&text.replace("x + 1", "x + 10"));
+        assert_eq!((analysis.reparsed(), analysis.rechecked()), (1, 1));
+        analysis.update(&text.replace("x + 1", "1.0"));
+        assert_eq!((analysis.reparsed(), analysis.rechecked()), (1, 3));
+        assert_eq!(analysis.diagnostics().len(), 1);
+
+        // Without `y`, the units after it are in another scope.
+        let text = analysis.text().to_owned();
+        analysis.edit(text.find("\nlet y").unwrap()..text.find("\nlet z").unwrap(), "");
+        assert_eq!((analysis.reparsed(), analysis.rechecked()), (0, 2));
+        let messages = analysis.diagnostics().into_iter().map(|d| d.message).collect::<Vec<_>>();
+        assert_eq!(messages, vec!["Unbound variable: y"]);
+
+        // Nothing is checked while a unit doesn't parse.
+        let end = analysis.text().find(" 2;;").unwrap();
+        analysis.edit(end..end + 2, "");
+        assert_eq!((analysis.reparsed(), analysis.rechecked()), (1, 0));
+        assert_eq!(analysis.diagnostics().len(), 1);
+        assert_eq!(analysis.type_at(0), None);
+        analysis.edit(end..end, " 2");
+        assert_eq!((analysis.reparsed(), analysis.rechecked()), (1, 1));
+        assert_eq!(analysis.text(), "let x = 1;;\nlet z = y * 2;;\nz");
+        assert_eq!(analysis.type_at(0).map(|(span, _)| span), None);
+        assert_eq!(analysis.type_at(8).map(|(_, t)| t), Some(&Type::Int));
+    }
+}
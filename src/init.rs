@@ -0,0 +1,114 @@
+use std::env;
+use std::path::PathBuf;
+
+use miniml::Session;
+
+use diagnostics::{ColorChoice, Renderer};
+use script;
+use {Error, Frontend, Options, execute, read_file};
+
+/// Loads the REPL init file: `path`, or `~/.minimlrc` if it exists.
+///
+/// Lines like `:set prompt λ> ` change `prompt` and `options` (see `set`),
+/// everything else is definitions to seed `session` with.
+pub fn load(path: Option<&str>,
+            options: &mut Options,
+            prompt: &mut String,
+            session: &mut Session)
+            -> Result<(), Error> {
+    let path = match path.map(String::from).or_else(default_path) {
+        Some(path) => path,
+        None => return Ok(()),
+    };
+    let src = try!(read_file(&path));
+
+    let mut definitions = String::new();
+    for line in src.lines() {
+        if line.starts_with(":set ") {
+            try!(set(&line[":set ".len()..], options, prompt)
+                     .map_err(|e| Error::Usage(format!("{}: {}", path, e.message()))));
+            // Keep the line numbering of the definitions intact.
+            definitions.push('\n');
+        } else {
+            definitions.push_str(line);
+            definitions.push('\n');
+        }
+    }
+
+    session.set_timing(options.time);
+    let renderer = Renderer::stderr(options);
+    for statement in script::statements(&::miniml::strip_comments(&definitions)) {
+        if let Err(e) = execute(&statement, options, session) {
+            eprintln!("{}", renderer.error_in(&path, &e));
+        }
+    }
+    Ok(())
+}
+
+fn default_path() -> Option<String> {
+    let home = match env::var_os("HOME") {
+        Some(home) => home,
+        None => return None,
+    };
+    let path = PathBuf::from(home).join(".minimlrc");
+    if path.is_file() {
+        Some(path.to_string_lossy().into_owned())
+    } else {
+        None
+    }
+}
+
+/// Applies a `name value` setting. Settings mirror command line flags:
+/// `prompt`, `parser` (`--parser`), `color` (`--color`), `types` (`-t`) and `time` (`--time`).
+fn set(setting: &str, options: &mut Options, prompt: &mut String) -> Result<(), Error> {
+    let (name, value) = match setting.find(' ') {
+        Some(space) => (&setting[..space], &setting[space + 1..]),
+        None => return Err(Error::Usage(format!("expected `:set {} <value>`", setting))),
+    };
+    match name {
+        "prompt" => *prompt = value.to_owned(),
+        "parser" => options.frontend = try!(Frontend::from_name(value.trim())),
+        "color" => options.color = try!(ColorChoice::from_name(value.trim())),
+        "types" => options.show_type = try!(on_off(value.trim())),
+        "time" => options.time = try!(on_off(value.trim())),
+        _ => return Err(Error::Usage(format!("unknown setting `{}`", name))),
+    }
+    Ok(())
+}
+
+fn on_off(value: &str) -> Result<bool, Error> {
+    match value {
+        "on" => Ok(true),
+        "off" => Ok(false),
+        _ => Err(Error::Usage(format!("expected `on` or `off`, got `{}`", value))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::set;
+    use diagnostics::{ColorChoice, MessageFormat};
+    use miniml::OptConfig;
+    use {Frontend, Options};
+
+    #[test]
+    fn test_set() {
+        let mut options = Options {
+            frontend: Frontend::Lalrpop,
+            time: false,
+            show_type: false,
+            color: ColorChoice::Auto,
+            message_format: MessageFormat::Human,
+            quiet: false,
+            opt: OptConfig::none(),
+        };
+        let mut prompt = "> ".to_owned();
+        set("prompt λ> ", &mut options, &mut prompt).ok().unwrap();
+        set("types on", &mut options, &mut prompt).ok().unwrap();
+        assert_eq!(prompt, "λ> ");
+        assert!(options.show_type);
+        assert!(set("types maybe", &mut options, &mut prompt).is_err());
+        assert!(set("opt-level 3", &mut options, &mut prompt).is_err());
+        assert!(set("prompt", &mut options, &mut prompt).is_err());
+    }
+}
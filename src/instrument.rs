@@ -0,0 +1,35 @@
+//! Structured events through the [tracing](https://docs.rs/tracing) facade,
+//! for embedders who want to see what the interpreter is doing. Without the
+//! `tracing` feature these functions do nothing.
+
+use std::time::Duration;
+
+#[cfg(feature = "tracing")]
+pub use tracing::span::EnteredSpan as Guard;
+
+#[cfg(not(feature = "tracing"))]
+pub struct Guard;
+
+/// Enters a span for a phase of the pipeline, which lasts until the guard is dropped.
+#[cfg(feature = "tracing")]
+pub fn phase(phase: &'static str) -> Guard {
+    tracing::info_span!("phase", phase = phase).entered()
+}
+
+#[cfg(not(feature = "tracing"))]
+pub fn phase(_phase: &'static str) -> Guard {
+    Guard
+}
+
+#[cfg(feature = "tracing")]
+pub fn phase_finished(phase: &'static str, elapsed: Duration) {
+    tracing::debug!(phase = phase, elapsed_us = micros(elapsed), "phase finished");
+}
+
+#[cfg(not(feature = "tracing"))]
+pub fn phase_finished(_phase: &'static str, _elapsed: Duration) {}
+
+#[cfg(feature = "tracing")]
+fn micros(duration: Duration) -> u64 {
+    duration.as_secs() * 1_000_000 + u64::from(duration.subsec_micros())
+}
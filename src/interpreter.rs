@@ -0,0 +1,115 @@
+//! An interpreter for embedding miniml: it parses, typechecks, compiles and
+//! runs source text, and keeps the definitions made so far.
+
+use std::collections::HashMap;
+use std::io;
+use std::rc::Rc;
+
+use ast::{Ident, Symbol};
+use compile::compile_ir;
+use error::Error;
+use eval::parse;
+use ir::{Ir, NameTable, desugar_decl, desugar_in};
+use secd::{Frame, GcConfig, Machine, Name, Value};
+use typecheck::{Type, typecheck_decl, typecheck_in};
+use value::OwnedValue;
+
+/// Runs programs one after another, with the definitions of the previous
+/// ones in scope:
+///
+/// ```
+/// use miniml::{Interpreter, OwnedValue};
+///
+/// let mut interpreter = Interpreter::new().with_limits(1_000_000);
+/// interpreter.eval("let fun double(x: int): int is x * 2;;").unwrap();
+/// assert_eq!(interpreter.eval("double 46").unwrap(), OwnedValue::Int(92));
+/// assert_eq!(interpreter.eval("[double 1]").unwrap().to_string(), "[2]");
+/// ```
+pub struct Interpreter {
+    env: Vec<(Ident, Type)>,
+    names: HashMap<Symbol, Name>,
+    machine: Machine<'static>,
+    /// The code of the programs the machine still points into, like that of
+    /// the functions they define. It is declared after `machine`, so that it
+    /// is dropped after it.
+    frames: Vec<Rc<Frame>>,
+}
+
+impl Interpreter {
+    pub fn new() -> Interpreter {
+        Interpreter {
+            env: Vec::new(),
+            names: HashMap::new(),
+            machine: Machine::empty(),
+            frames: Vec::new(),
+        }
+    }
+
+    /// Makes each `eval` fail with `OutOfFuel` after `max_steps` instructions.
+    pub fn with_limits(self, max_steps: u64) -> Interpreter {
+        Interpreter { machine: self.machine.with_limits(max_steps), ..self }
+    }
+
+    /// Makes the garbage collector run as `config` says.
+    pub fn with_gc(self, config: GcConfig) -> Interpreter {
+        Interpreter { machine: self.machine.with_gc(config), ..self }
+    }
+
+    /// Makes `print` write to `output` instead of the standard output.
+    pub fn with_output<W: io::Write + 'static>(self, output: W) -> Interpreter {
+        Interpreter { machine: self.machine.with_output(output), ..self }
+    }
+
+    /// Runs the program `src`: its declarations, which stay defined for later
+    /// programs, and then its expression, if there is one. Returns the value
+    /// of the expression, or `()` for declarations only.
+    pub fn eval(&mut self, src: &str) -> Result<OwnedValue, Error> {
+        // The values of the earlier programs are all in the machine by now,
+        // so the code it doesn't point into can go.
+        let released = self.machine.release_programs();
+        self.frames.retain(|frame| !released.contains(&Rc::as_ptr(frame)));
+        let program = try!(parse(src));
+        for decl in &program.decls {
+            let bindings = try!(typecheck_decl(&self.env, decl).map_err(Error::Type));
+            let mut names = self.names.clone();
+            for (name, ir) in desugar_decl(&mut names, decl) {
+                let value = try!(self.exec(&ir));
+                self.machine.define(name, value);
+            }
+            self.names = names;
+            for (name, type_) in bindings {
+                self.env.retain(|&(ref bound, _)| bound != &name);
+                self.env.push((name, type_));
+            }
+        }
+        // Without an expression, the parser makes the body `main`, with an
+        // empty span as it isn't in the source.
+        if program.body.span.is_empty() {
+            return Ok(OwnedValue::Unit);
+        }
+        try!(typecheck_in(&self.env, &program.body).map_err(Error::Type));
+        // Desugaring binds the names of the variables in the expression,
+        // which mustn't outlive it.
+        let ir = desugar_in(&mut self.names.clone(), &program.body);
+        let value = try!(self.exec(&ir));
+        Ok(OwnedValue::new(&self.machine, value))
+    }
+
+    fn exec(&mut self, ir: &Ir) -> Result<Value<'static>, Error> {
+        // Closures defined by a program point into its code, so the code has
+        // to live as long as the machine refers to it. It does: `frames` is
+        // dropped after the machine, a frame is only dropped before once the
+        // machine has released it, and unlike a `Box`, an `Rc` may be moved
+        // while what it holds is borrowed.
+        let code = Rc::new(compile_ir(ir));
+        let frame: &'static Frame = unsafe { &*Rc::as_ptr(&code) };
+        self.frames.push(code);
+        self.machine.run(frame).map_err(|e| Error::Runtime(NameTable::new(&self.names).name_error(e)))
+    }
+}
+
+impl Default for Interpreter {
+    fn default() -> Interpreter {
+        Interpreter::new()
+    }
+}
@@ -1,21 +1,345 @@
+//! The desugared form of a program, which `compile` turns into machine code.
+//!
+//! There are only named recursive functions of one argument: `let`s become
+//! applications of functions, and the functions of a `let rec` group, which
+//! call each other, stay together in a `LetRec`. List literals become chains of `::`. Types
+//! declared with `type` are gone: constructors are numbered by their position
+//! in the declaration, and `match` becomes a `Case` on that number. Records
+//! keep the names of their fields, which are looked up when read. Variables
+//! are numbers rather than strings; source identifiers get even `Name`s and
+//! names made up by desugaring get odd ones, `ANONYMOUS` and `SCRUTINEE`.
+//! `desugar_with_names` also returns a `NameTable` of the identifiers behind
+//! the numbers, so that `Ir::with_names` shows the IR with the source names
+//! and runtime errors can say which variable they are about.
+//!
+//! The types here are stable: new kinds of nodes may be added, but existing
+//! ones keep their shape. Analyses are plain functions over `Ir`:
+//!
+//! ```
+//! use miniml::ir::Ir;
+//!
+//! fn size(ir: &Ir) -> usize {
+//!     1 + match *ir {
+//!         Ir::Var(_) | Ir::IntLiteral(_) | Ir::FloatLiteral(_) | Ir::BoolLiteral(_) | Ir::StrLiteral(_) |
+//!         Ir::Unit | Ir::Nil => 0,
+//!         Ir::BinOp(ref op) => size(&op.lhs) + size(&op.rhs),
+//!         Ir::UnOp(ref op) => size(&op.arg),
+//!         Ir::Construct(ref c) => c.arg.as_ref().map_or(0, size),
+//!         Ir::Record(ref record) => record.fields.iter().map(|field| size(&field.1)).sum::<usize>(),
+//!         Ir::Field(ref field) => size(&field.record),
+//!         Ir::Case(ref case) => size(&case.scrutinee) + case.arms.iter().map(size).sum::<usize>(),
+//!         Ir::If(ref if_) => size(&if_.cond) + size(&if_.tru) + size(&if_.fls),
+//!         Ir::Seq(ref seq) => size(&seq.first) + size(&seq.second),
+//!         Ir::Fun(ref fun) => size(&fun.body),
+//!         Ir::LetRec(ref let_rec) => {
+//!             let_rec.funs.iter().map(|fun| size(&fun.body)).sum::<usize>() + size(&let_rec.body)
+//!         }
+//!         Ir::Apply(ref apply) => size(&apply.fun) + size(&apply.arg),
+//!     }
+//! }
+//!
+//! let ir = miniml::desugar(&miniml::parse("if x < 0 then 0 else x").unwrap());
+//! assert_eq!(format!("{:?}", ir), "(if (< v0 0) 0 v0)");
+//! assert_eq!(size(&ir), 6);
+//! ```
+//!
+//! Transformations implement `Pass` and are run by `Plugins`; the ones that
+//! come with miniml are in `opt`.
+
 use std::collections::HashMap;
-use ast::{self, Expr};
+use std::fmt;
+use ast::{self, Expr, ExprKind, Symbol};
+use secd::{CancelToken, Cancelled, RuntimeError};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 
 pub type Name = usize;
 
+/// The name of functions made up by desugaring, which are never called by
+/// name: the inner functions of several parameters, the functions a `let`
+/// applies and the body of a `LetRec`.
+pub const ANONYMOUS: Name = 1;
+
+/// The name a `match` binds its scrutinee to, for the arms to take the
+/// argument of the constructor from.
+pub const SCRUTINEE: Name = 7;
+
+#[derive(Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Ir {
     Var(Name),
     IntLiteral(i64),
+    FloatLiteral(f64),
     BoolLiteral(bool),
+    StrLiteral(String),
+    /// `()`, the value of expressions evaluated for their effects.
+    Unit,
+    /// The empty list.
+    Nil,
     BinOp(Box<BinOp>),
+    UnOp(Box<UnOp>),
+    Construct(Box<Construct>),
+    Record(Box<Record>),
+    Field(Box<Field>),
+    Case(Box<Case>),
     If(Box<If>),
+    Seq(Box<Seq>),
     Fun(Box<Fun>),
+    LetRec(Box<LetRec>),
     Apply(Box<Apply>),
 }
 
+impl Ir {
+    /// Shows the IR with the source identifiers of the variables in `names`
+    /// instead of their numbers:
+    ///
+    /// ```
+    /// let (ir, names) = miniml::desugar_with_names(&miniml::parse("let y = 1 in x + y").unwrap());
+    /// assert_eq!(format!("{:?}", ir), "((λ v1 (v0) (+ v2 v0)) 1)");
+    /// assert_eq!(format!("{:?}", ir.with_names(&names)), "((λ v1 (y) (+ x y)) 1)");
+    /// ```
+    pub fn with_names<'a>(&'a self, names: &'a NameTable) -> WithNames<'a> {
+        WithNames {
+            ir: self,
+            names: names,
+        }
+    }
+}
+
+impl fmt::Debug for Ir {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.with_names(&NameTable::default()).fmt(f)
+    }
+}
+
+/// An `Ir` shown with source names, see `Ir::with_names`.
+pub struct WithNames<'a> {
+    ir: &'a Ir,
+    names: &'a NameTable,
+}
+
+impl<'a> WithNames<'a> {
+    fn name(&self, name: Name) -> String {
+        match self.names.symbol(name) {
+            Some(symbol) => symbol.to_string(),
+            None => format!("v{}", name),
+        }
+    }
+
+    fn fun(&self, fun: &Fun, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f,
+               "(λ {} ({}) {:?})",
+               self.name(fun.fun_name),
+               self.name(fun.arg_name),
+               fun.body.with_names(self.names))
+    }
+}
+
+impl<'a> fmt::Debug for WithNames<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let ir = |ir: &'a Ir| ir.with_names(self.names);
+        match *self.ir {
+            Ir::Var(name) => f.write_str(&self.name(name)),
+            Ir::IntLiteral(i) => i.fmt(f),
+            Ir::FloatLiteral(x) => write!(f, "{:?}", x),
+            Ir::BoolLiteral(b) => b.fmt(f),
+            Ir::StrLiteral(ref s) => write!(f, "\"{}\"", ast::escape(s)),
+            Ir::Unit => f.write_str("()"),
+            Ir::Nil => f.write_str("[]"),
+            Ir::BinOp(ref op) => write!(f, "({:?} {:?} {:?})", op.kind, ir(&op.lhs), ir(&op.rhs)),
+            Ir::UnOp(ref op) => write!(f, "({:?} {:?})", op.kind, ir(&op.arg)),
+            Ir::Construct(ref c) => {
+                match c.arg {
+                    Some(ref arg) => write!(f, "({}#{} {:?})", c.name, c.tag, ir(arg)),
+                    None => write!(f, "{}#{}", c.name, c.tag),
+                }
+            }
+            Ir::Record(ref record) => {
+                try!(f.write_str("{"));
+                for (i, field) in record.fields.iter().enumerate() {
+                    if i > 0 {
+                        try!(f.write_str(", "));
+                    }
+                    try!(write!(f, "{} = {:?}", field.0, ir(&field.1)));
+                }
+                f.write_str("}")
+            }
+            Ir::Field(ref field) => write!(f, "{:?}.{}", ir(&field.record), field.name),
+            Ir::Case(ref case) => {
+                try!(write!(f, "(case {:?}", ir(&case.scrutinee)));
+                for arm in &case.arms {
+                    try!(write!(f, " {:?}", ir(arm)));
+                }
+                f.write_str(")")
+            }
+            Ir::If(ref if_) => write!(f, "(if {:?} {:?} {:?})", ir(&if_.cond), ir(&if_.tru), ir(&if_.fls)),
+            Ir::Seq(ref seq) => write!(f, "(; {:?} {:?})", ir(&seq.first), ir(&seq.second)),
+            Ir::Fun(ref fun) => self.fun(fun, f),
+            Ir::LetRec(ref let_rec) => {
+                try!(f.write_str("(letrec ["));
+                for (i, fun) in let_rec.funs.iter().enumerate() {
+                    if i > 0 {
+                        try!(f.write_str(" "));
+                    }
+                    try!(self.fun(fun, f));
+                }
+                write!(f, "] {:?})", ir(&let_rec.body))
+            }
+            Ir::Apply(ref apply) => write!(f, "({:?} {:?})", ir(&apply.fun), ir(&apply.arg)),
+        }
+    }
+}
+
+/// Desugars a closed expression; its free variables are numbered from zero.
+///
+/// A `let` applies a function of the bound name, here made up `v1`, to the value:
+///
+/// ```
+/// let expr = miniml::parse("let fun inc(x: int): int is x + 1 in inc 1").unwrap();
+/// assert_eq!(format!("{:?}", miniml::desugar(&expr)),
+///            "((λ v1 (v0) (v0 1)) (λ v0 (v2) (+ v2 1)))");
+/// ```
 pub fn desugar(expr: &Expr) -> Ir {
+    desugar_with_names(expr).0
+}
+
+/// Also returns the number each source identifier was renamed to.
+pub fn desugar_with_names(expr: &Expr) -> (Ir, NameTable) {
+    let mut renamer = Renamer::empty();
+    let ir = expr.desugar(&mut renamer);
+    (ir, NameTable::new(&renamer.names))
+}
+
+/// Desugars the declarations of `program` into nested bindings around its body.
+pub fn desugar_program(program: &ast::Program) -> Ir {
+    desugar_program_with_names(program).0
+}
+
+/// Also returns the number each source identifier was renamed to.
+pub fn desugar_program_with_names(program: &ast::Program) -> (Ir, NameTable) {
     let mut renamer = Renamer::empty();
-    expr.desugar(&mut renamer)
+    let ir = desugar_decls(&program.decls, &program.body, &mut renamer);
+    (ir, NameTable::new(&renamer.names))
+}
+
+/// Like `desugar_program`, but gives up once `token` is cancelled, which it
+/// checks at each expression.
+pub fn desugar_program_cancellable(program: &ast::Program, token: &CancelToken) -> Result<Ir, Cancelled> {
+    let mut renamer = Renamer { cancel: Some(token.clone()), ..Renamer::empty() };
+    let ir = desugar_decls(&program.decls, &program.body, &mut renamer);
+    if token.is_cancelled() {
+        return Err(Cancelled);
+    }
+    Ok(ir)
+}
+
+/// The source identifiers behind the `Name`s of a program, to show them to
+/// the user. Names made up by desugaring aren't in it. Each identifier has
+/// one number, however many times it's bound, so the table works both ways.
+#[derive(Debug, Clone, Default)]
+pub struct NameTable {
+    names: HashMap<Symbol, Name>,
+    symbols: HashMap<Name, Symbol>,
+}
+
+impl NameTable {
+    /// The table of the numbers identifiers were given in `names`, as kept by
+    /// `desugar_in` and friends.
+    pub fn new(names: &HashMap<Symbol, Name>) -> NameTable {
+        NameTable {
+            names: names.clone(),
+            symbols: names.iter().map(|(&symbol, &name)| (name, symbol)).collect(),
+        }
+    }
+
+    pub fn name(&self, symbol: Symbol) -> Option<Name> {
+        self.names.get(&symbol).cloned()
+    }
+
+    pub fn symbol(&self, name: Name) -> Option<Symbol> {
+        self.symbols.get(&name).cloned()
+    }
+
+    /// Makes `error` say the source identifier of its variable, as in
+    /// `Undefined variable 'even'`.
+    pub fn name_error(&self, error: RuntimeError) -> RuntimeError {
+        error.with_names(|name| self.symbol(name).map(|symbol| symbol.to_string()))
+    }
+}
+
+/// Desugars `expr` in a scope where `names` are already bound, adding new names to `names`.
+pub fn desugar_in(names: &mut HashMap<Symbol, Name>, expr: &Expr) -> Ir {
+    let mut renamer = Renamer::with_names(names);
+    let ir = expr.desugar(&mut renamer);
+    *names = renamer.names;
+    ir
+}
+
+/// Desugars `program` in a scope where `names` are already bound, like `desugar_in`.
+pub fn desugar_program_in(names: &mut HashMap<Symbol, Name>, program: &ast::Program) -> Ir {
+    let mut renamer = Renamer::with_names(names);
+    let ir = desugar_decls(&program.decls, &program.body, &mut renamer);
+    *names = renamer.names;
+    ir
+}
+
+/// The name `name` is known by in the scope described by `names`, binding it if it is new.
+pub fn name_in(names: &mut HashMap<Symbol, Name>, name: Symbol) -> Name {
+    let mut renamer = Renamer::with_names(names);
+    let id = renamer.lookup(name);
+    *names = renamer.names;
+    id
+}
+
+/// Desugars a top-level declaration into the values it binds, like `desugar_in`.
+pub fn desugar_decl(names: &mut HashMap<Symbol, Name>, decl: &ast::Decl) -> Vec<(Name, Ir)> {
+    let mut renamer = Renamer::with_names(names);
+    let mut result = vec![];
+    match *decl {
+        ast::Decl::Fun(ref fun) => {
+            let fun = desugar_fun(fun, &mut renamer);
+            result.push((fun.fun_name, fun.into()));
+        }
+        ast::Decl::LetRec(ref funs) => {
+            // Each function gets its own copy of the whole group.
+            for fun in funs {
+                let name = renamer.lookup(fun.fun_name.symbol());
+                let group = funs.iter().map(|fun| desugar_fun(fun, &mut renamer)).collect();
+                result.push((name, let_rec(group, Ir::Var(name))));
+            }
+        }
+        ast::Decl::Value(ref name, ref value) => {
+            let value = value.desugar(&mut renamer);
+            result.push((renamer.lookup(name.symbol()), value));
+        }
+    }
+    *names = renamer.names;
+    result
+}
+
+fn desugar_decls(decls: &[ast::Decl],
+                 body: &Expr,
+                 renamer: &mut Renamer)
+                     -> Ir {
+    let (decl, rest) = match decls.split_first() {
+        Some(split) => split,
+        None => return body.desugar(renamer),
+    };
+    match *decl {
+        ast::Decl::Fun(ref fun) => {
+            let fun = desugar_fun(fun, renamer);
+            let_(fun, desugar_decls(rest, body, renamer))
+        }
+        ast::Decl::LetRec(ref funs) => {
+            let funs = funs.iter().map(|fun| desugar_fun(fun, renamer)).collect();
+            let_rec(funs, desugar_decls(rest, body, renamer))
+        }
+        ast::Decl::Value(ref name, ref value) => {
+            let value = value.desugar(renamer);
+            let name = renamer.lookup(name.symbol());
+            bind(name, value, desugar_decls(rest, body, renamer))
+        }
+    }
 }
 
 macro_rules! into_ir {
@@ -28,6 +352,8 @@ macro_rules! into_ir {
     }
 }
 
+#[derive(Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct BinOp {
     pub lhs: Ir,
     pub rhs: Ir,
@@ -36,16 +362,136 @@ pub struct BinOp {
 
 into_ir!(BinOp);
 
+/// Arithmetic works on `int`s, and so do comparisons, except that `Eq` and `Ne`
+/// also compare `string`s: there is no `==` on `bool`s. `Cons` puts `lhs` in front
+/// of the list `rhs`, `Concat` joins two strings, and `Assign` stores `rhs` in
+/// the reference cell `lhs` and evaluates to `()`.
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum BinOpKind {
     Add,
     Sub,
     Div,
+    Mod,
     Mul,
     Lt,
+    Le,
     Eq,
+    Ne,
     Gt,
+    Ge,
+    Cons,
+    Concat,
+    Assign,
+}
+
+impl fmt::Debug for BinOpKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            BinOpKind::Add => "+",
+            BinOpKind::Sub => "-",
+            BinOpKind::Div => "/",
+            BinOpKind::Mod => "%",
+            BinOpKind::Mul => "*",
+            BinOpKind::Lt => "<",
+            BinOpKind::Le => "<=",
+            BinOpKind::Eq => "==",
+            BinOpKind::Ne => "!=",
+            BinOpKind::Gt => ">",
+            BinOpKind::Ge => ">=",
+            BinOpKind::Cons => "::",
+            BinOpKind::Concat => "++",
+            BinOpKind::Assign => ":=",
+        })
+    }
+}
+
+#[derive(Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct UnOp {
+    pub arg: Ir,
+    pub kind: UnOpKind,
+}
+
+into_ir!(UnOp);
+
+/// Operations on lists. `Head` and `Tail` of the empty list fail at runtime.
+/// `Payload` is the argument of a constructor. `Ref` puts its argument in a
+/// new reference cell, and `Deref` reads a cell. `Print` writes a string or
+/// an integer and evaluates to `()`. `IntOfFloat` and `FloatOfInt` convert
+/// between the two kinds of numbers.
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum UnOpKind {
+    Head,
+    Tail,
+    IsEmpty,
+    Payload,
+    Ref,
+    Deref,
+    Print,
+    IntOfFloat,
+    FloatOfInt,
+}
+
+impl fmt::Debug for UnOpKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            UnOpKind::Head => "head",
+            UnOpKind::Tail => "tail",
+            UnOpKind::IsEmpty => "isEmpty",
+            UnOpKind::Payload => "payload",
+            UnOpKind::Ref => "ref",
+            UnOpKind::Deref => "!",
+            UnOpKind::Print => "print",
+            UnOpKind::IntOfFloat => "int_of_float",
+            UnOpKind::FloatOfInt => "float_of_int",
+        })
+    }
+}
+
+/// The `tag`th constructor of its type. The `name` is only for display.
+#[derive(Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Construct {
+    pub tag: usize,
+    pub name: String,
+    pub arg: Option<Ir>,
+}
+
+into_ir!(Construct);
+
+/// The fields are evaluated in the order they are written.
+#[derive(Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Record {
+    pub fields: Vec<(String, Ir)>,
+}
+
+into_ir!(Record);
+
+/// The value of the field `name` of `record`.
+#[derive(Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Field {
+    pub record: Ir,
+    pub name: String,
 }
 
+into_ir!(Field);
+
+/// Evaluates the arm with the tag of the constructed `scrutinee`.
+#[derive(Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Case {
+    pub scrutinee: Ir,
+    pub arms: Vec<Ir>,
+}
+
+into_ir!(Case);
+
+#[derive(Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct If {
     pub cond: Ir,
     pub tru: Ir,
@@ -54,6 +500,19 @@ pub struct If {
 
 into_ir!(If);
 
+/// Evaluates `first` for its effects, drops its value and evaluates `second`.
+#[derive(Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Seq {
+    pub first: Ir,
+    pub second: Ir,
+}
+
+into_ir!(Seq);
+
+/// A function that can call itself by `fun_name`.
+#[derive(Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Fun {
     pub fun_name: Name,
     pub arg_name: Name,
@@ -62,6 +521,18 @@ pub struct Fun {
 
 into_ir!(Fun);
 
+/// Functions which can call each other by their `fun_name`s, in `body` as well.
+#[derive(Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct LetRec {
+    pub funs: Vec<Fun>,
+    pub body: Ir,
+}
+
+into_ir!(LetRec);
+
+#[derive(Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Apply {
     pub fun: Ir,
     pub arg: Ir,
@@ -69,37 +540,179 @@ pub struct Apply {
 
 into_ir!(Apply);
 
-struct Renamer<'a> {
-    names: HashMap<&'a str, Name>,
+/// Numbers source names in the order they are first seen. The numbers
+/// depend only on the program, not on how symbols were interned or on the
+/// order of `names`, which is only ever used for lookups, so compiling a
+/// program always gives the same code.
+struct Renamer {
+    names: HashMap<Symbol, Name>,
+    tags: HashMap<Symbol, usize>,
+    /// Once it is cancelled, expressions desugar to `()`, as the IR is thrown away.
+    cancel: Option<CancelToken>,
 }
 
-impl<'a> Renamer<'a> {
-    fn empty() -> Renamer<'static> {
-        Renamer { names: HashMap::new() }
+impl Renamer {
+    fn empty() -> Renamer {
+        Renamer {
+            names: HashMap::new(),
+            tags: HashMap::new(),
+            cancel: None,
+        }
     }
 
-    fn lookup(&mut self, name: &'a str) -> Name {
-        if !self.names.contains_key(name) {
-            let new_id = self.names.len();
-            self.names.insert(name, new_id);
+    fn with_names(names: &HashMap<Symbol, Name>) -> Renamer {
+        Renamer {
+            names: names.clone(),
+            tags: HashMap::new(),
+            cancel: None,
         }
-        self.names[name] * 2
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancel.as_ref().map_or(false, |token| token.is_cancelled())
+    }
+
+    fn tag(&self, constructor: &ast::Ident) -> usize {
+        *self.tags
+             .get(&constructor.symbol())
+             .expect("unknown constructor, the program should be typechecked first")
+    }
+
+    fn lookup(&mut self, name: Symbol) -> Name {
+        let new_id = self.names.len() * 2;
+        *self.names.entry(name).or_insert(new_id)
     }
 }
 
 trait Sugar {
-    fn desugar<'e>(&'e self, &mut Renamer<'e>) -> Ir;
+    fn desugar(&self, &mut Renamer) -> Ir;
 }
 
 impl Sugar for Expr {
-    fn desugar<'e>(&'e self, renamer: &mut Renamer<'e>) -> Ir {
-        match *self {
-            Expr::Var(ref v) => Ir::Var(renamer.lookup(v.as_ref())),
-            Expr::Literal(ast::Literal::Number(n)) => Ir::IntLiteral(n),
-            Expr::Literal(ast::Literal::Bool(b)) => Ir::BoolLiteral(b),
-            Expr::ArithBinOp(ref op) => op.desugar(renamer),
-            Expr::CmpBinOp(ref op) => op.desugar(renamer),
-            Expr::If(ref if_) => {
+    fn desugar(&self, renamer: &mut Renamer) -> Ir {
+        if renamer.is_cancelled() {
+            return Ir::Unit;
+        }
+        match self.kind {
+            ExprKind::Var(ref v) => Ir::Var(renamer.lookup(v.symbol())),
+            ExprKind::Literal(ast::Literal::Number(n)) => Ir::IntLiteral(n),
+            ExprKind::Literal(ast::Literal::Float(x)) => Ir::FloatLiteral(x),
+            ExprKind::Literal(ast::Literal::Bool(b)) => Ir::BoolLiteral(b),
+            ExprKind::Literal(ast::Literal::Str(ref s)) => Ir::StrLiteral(s.clone()),
+            ExprKind::Literal(ast::Literal::Unit) => Ir::Unit,
+            ExprKind::ArithBinOp(ref op) => op.desugar(renamer),
+            ExprKind::CmpBinOp(ref op) => op.desugar(renamer),
+            // Branches, so that the right operand is only evaluated when needed.
+            ExprKind::LogicBinOp(ref op) => {
+                let (lhs, rhs) = (op.lhs.desugar(renamer), op.rhs.desugar(renamer));
+                let (tru, fls) = match op.kind {
+                    ast::LogicOp::And => (rhs, Ir::BoolLiteral(false)),
+                    ast::LogicOp::Or => (Ir::BoolLiteral(true), rhs),
+                };
+                If {
+                    cond: lhs,
+                    tru: tru,
+                    fls: fls,
+                }
+                .into()
+            }
+            ExprKind::Not(ref not) => {
+                If {
+                    cond: not.arg.desugar(renamer),
+                    tru: Ir::BoolLiteral(false),
+                    fls: Ir::BoolLiteral(true),
+                }
+                .into()
+            }
+            ExprKind::List(ref list) => {
+                let items = list.items.iter().map(|item| item.desugar(renamer)).collect::<Vec<_>>();
+                items.into_iter().rev().fold(Ir::Nil, |tail, head| cons(head, tail))
+            }
+            ExprKind::Cons(ref c) => cons(c.head.desugar(renamer), c.tail.desugar(renamer)),
+            ExprKind::Concat(ref c) => {
+                BinOp {
+                    lhs: c.lhs.desugar(renamer),
+                    rhs: c.rhs.desugar(renamer),
+                    kind: BinOpKind::Concat,
+                }
+                .into()
+            }
+            ExprKind::ListUnOp(ref op) => {
+                UnOp {
+                    arg: op.arg.desugar(renamer),
+                    kind: match op.kind {
+                        ast::ListOp::Head => UnOpKind::Head,
+                        ast::ListOp::Tail => UnOpKind::Tail,
+                        ast::ListOp::IsEmpty => UnOpKind::IsEmpty,
+                    },
+                }
+                .into()
+            }
+            ExprKind::RefUnOp(ref op) => {
+                UnOp {
+                    arg: op.arg.desugar(renamer),
+                    kind: match op.kind {
+                        ast::RefOp::Ref => UnOpKind::Ref,
+                        ast::RefOp::Deref => UnOpKind::Deref,
+                    },
+                }
+                .into()
+            }
+            ExprKind::PrintUnOp(ref op) => {
+                UnOp {
+                    arg: op.arg.desugar(renamer),
+                    kind: UnOpKind::Print,
+                }
+                .into()
+            }
+            ExprKind::ConvertUnOp(ref op) => {
+                UnOp {
+                    arg: op.arg.desugar(renamer),
+                    kind: match op.kind {
+                        ast::ConvertOp::IntOfFloat => UnOpKind::IntOfFloat,
+                        ast::ConvertOp::FloatOfInt => UnOpKind::FloatOfInt,
+                    },
+                }
+                .into()
+            }
+            ExprKind::Assign(ref assign) => {
+                BinOp {
+                    lhs: assign.cell.desugar(renamer),
+                    rhs: assign.value.desugar(renamer),
+                    kind: BinOpKind::Assign,
+                }
+                .into()
+            }
+            ExprKind::Seq(ref seq) => {
+                Seq {
+                    first: seq.first.desugar(renamer),
+                    second: seq.second.desugar(renamer),
+                }
+                .into()
+            }
+            ExprKind::Construct(ref c) => {
+                Construct {
+                    tag: renamer.tag(&c.name),
+                    name: c.name.as_ref().to_owned(),
+                    arg: c.arg.as_ref().map(|arg| arg.desugar(renamer)),
+                }
+                .into()
+            }
+            ExprKind::Record(ref record) => {
+                let fields = record.fields.iter().map(|&(name, ref value)| {
+                    (name.as_ref().to_owned(), value.desugar(renamer))
+                });
+                Record { fields: fields.collect() }.into()
+            }
+            ExprKind::Field(ref field) => {
+                Field {
+                    record: field.record.desugar(renamer),
+                    name: field.name.as_ref().to_owned(),
+                }
+                .into()
+            }
+            ExprKind::Match(ref match_) => match_.desugar(renamer),
+            ExprKind::If(ref if_) => {
                 If {
                     cond: if_.cond.desugar(renamer),
                     tru: if_.tru.desugar(renamer),
@@ -107,10 +720,13 @@ impl Sugar for Expr {
                 }
                 .into()
             }
-            Expr::Fun(ref fun) => fun.desugar(renamer),
-            Expr::LetFun(ref let_fun) => let_fun.desugar(renamer),
-            Expr::LetRec(ref let_rec) => let_rec.desugar(renamer),
-            Expr::Apply(ref apply) => {
+            ExprKind::Fun(ref fun) => fun.desugar(renamer),
+            ExprKind::Let(ref let_) => let_.desugar(renamer),
+            ExprKind::LetFun(ref let_fun) => let_fun.desugar(renamer),
+            ExprKind::LetRec(ref let_rec) => let_rec.desugar(renamer),
+            ExprKind::LetType(ref let_type) => let_type.desugar(renamer),
+            ExprKind::TypeAlias(ref alias) => alias.body.desugar(renamer),
+            ExprKind::Apply(ref apply) => {
                 Apply {
                     fun: apply.fun.desugar(renamer),
                     arg: apply.arg.desugar(renamer),
@@ -128,6 +744,7 @@ impl From<ast::ArithOp> for BinOpKind {
             ast::ArithOp::Sub => BinOpKind::Sub,
             ast::ArithOp::Mul => BinOpKind::Mul,
             ast::ArithOp::Div => BinOpKind::Div,
+            ast::ArithOp::Mod => BinOpKind::Mod,
         }
     }
 }
@@ -136,8 +753,11 @@ impl From<ast::CmpOp> for BinOpKind {
     fn from(op: ast::CmpOp) -> Self {
         match op {
             ast::CmpOp::Lt => BinOpKind::Lt,
+            ast::CmpOp::Le => BinOpKind::Le,
             ast::CmpOp::Eq => BinOpKind::Eq,
+            ast::CmpOp::Ne => BinOpKind::Ne,
             ast::CmpOp::Gt => BinOpKind::Gt,
+            ast::CmpOp::Ge => BinOpKind::Ge,
         }
     }
 }
@@ -146,7 +766,7 @@ impl<OP> Sugar for ast::BinOp<OP>
     where BinOpKind: From<OP>,
           OP: Copy
 {
-    fn desugar<'e>(&'e self, renamer: &mut Renamer<'e>) -> Ir {
+    fn desugar(&self, renamer: &mut Renamer) -> Ir {
         BinOp {
             lhs: self.lhs.desugar(renamer),
             rhs: self.rhs.desugar(renamer),
@@ -157,27 +777,52 @@ impl<OP> Sugar for ast::BinOp<OP>
 }
 
 impl Sugar for ast::Fun {
-    fn desugar<'e>(&'e self, renamer: &mut Renamer<'e>) -> Ir {
+    fn desugar(&self, renamer: &mut Renamer) -> Ir {
         desugar_fun(self, renamer).into()
     }
 }
 
-fn desugar_fun<'e>(fun: &'e ast::Fun, renamer: &mut Renamer<'e>) -> Fun {
+/// A function of several parameters becomes nested functions of one, the
+/// inner ones anonymous.
+fn desugar_fun(fun: &ast::Fun, renamer: &mut Renamer) -> Fun {
+    let fun_name = renamer.lookup(fun.fun_name.symbol());
+    let arg_names: Vec<Name> = fun.args
+                                  .iter()
+                                  .map(|&(ref name, _)| renamer.lookup(name.symbol()))
+                                  .collect();
+    let mut body = fun.body.desugar(renamer);
+    for &arg_name in arg_names[1..].iter().rev() {
+        body = Fun {
+                   fun_name: ANONYMOUS,
+                   arg_name: arg_name,
+                   body: body,
+               }
+               .into();
+    }
     Fun {
-        fun_name: renamer.lookup(fun.fun_name.as_ref()),
-        arg_name: renamer.lookup(fun.arg_name.as_ref()),
-        body: fun.body.desugar(renamer),
+        fun_name: fun_name,
+        arg_name: arg_names[0],
+        body: body,
+    }
+}
+
+impl Sugar for ast::Let {
+    fn desugar(&self, renamer: &mut Renamer) -> Ir {
+        let value = self.value.desugar(renamer);
+        let name = renamer.lookup(self.name.symbol());
+        let body = self.body.desugar(renamer);
+        bind(name, value, body)
     }
 }
 
 impl Sugar for ast::LetFun {
-    fn desugar<'e>(&'e self, renamer: &mut Renamer<'e>) -> Ir {
+    fn desugar(&self, renamer: &mut Renamer) -> Ir {
         let fun = self.fun.desugar(renamer);
         let expr = self.body.desugar(renamer);
         Apply {
             fun: Fun {
-                     fun_name: 1,
-                     arg_name: renamer.lookup(self.fun.fun_name.as_ref()),
+                     fun_name: ANONYMOUS,
+                     arg_name: renamer.lookup(self.fun.fun_name.symbol()),
                      body: expr,
                  }
                  .into(),
@@ -188,134 +833,94 @@ impl Sugar for ast::LetFun {
 }
 
 impl Sugar for ast::LetRec {
-    // See tests `mutual_recursion3` for an example of transform.
-    // On a high level, we convert a set of mutually recursive functions into a single function of
-    // two arguments, the first of which is a tag
-    fn desugar<'e>(&'e self, renamer: &mut Renamer<'e>) -> Ir {
-        let funs = self.funs.iter().map(|fun| desugar_fun(fun, renamer)).collect::<Vec<_>>();
-        let fun_names = funs.iter().map(|fun| fun.fun_name).collect::<Vec<_>>();
-
-        let dispatch_arg = 5;
-        let dispatch_if = {
-            let mut result = undefined();
-            for (i, fun) in funs.into_iter().enumerate() {
-                let my_tag = i as i64;
-                let dispatch_arg = Ir::Var(dispatch_arg);
-                result = if_eq(dispatch_arg,
-                               Ir::IntLiteral(my_tag),
-                               fun_wrapper(my_tag, fun, &fun_names),
-                               result)
-            }
-            result
-        };
-        let anon_name = 1;
-        let dispatch_name = 3;
-        let dispatch_fun: Ir = Fun {
-                                   fun_name: dispatch_name,
-                                   arg_name: dispatch_arg,
-                                   body: dispatch_if,
-                               }
-                               .into();
-
-        let mut result = self.body.desugar(renamer);
-        for (i, name) in fun_names.into_iter().enumerate() {
-            let f: Ir = Fun {
-                            fun_name: anon_name,
-                            arg_name: name,
-                            body: result,
-                        }
-                        .into();
-            result = f.apply(Ir::Var(dispatch_name).apply(Ir::IntLiteral(i as i64)))
-        }
-
-        let f: Ir = Fun {
-                        fun_name: anon_name,
-                        arg_name: dispatch_name,
-                        body: result,
-                    }
-                    .into();
-        f.apply(dispatch_fun)
+    fn desugar(&self, renamer: &mut Renamer) -> Ir {
+        let funs = self.funs.iter().map(|fun| desugar_fun(fun, renamer)).collect();
+        let body = self.body.desugar(renamer);
+        let_rec(funs, body)
     }
 }
 
-fn fun_wrapper(my_tag: i64, fun: Fun, fun_names: &[Name]) -> Ir {
-
-    let mut bindins = vec![];
-    let dispatch_name = 3;
-    for (i, &name) in fun_names.iter().enumerate() {
-        let fun_tag = i as i64;
-        if fun_tag == my_tag {
-            continue;
+impl Sugar for ast::LetType {
+    fn desugar(&self, renamer: &mut Renamer) -> Ir {
+        let outer_tags = renamer.tags.clone();
+        for (tag, variant) in self.variants.iter().enumerate() {
+            renamer.tags.insert(variant.name.symbol(), tag);
         }
-        let x = 1;
-        bindins.push(Fun {
-            fun_name: name,
-            arg_name: x,
-            body: Ir::Var(dispatch_name)
-                      .apply(Ir::IntLiteral(fun_tag))
-                      .apply(Ir::Var(x)),
-        })
+        let body = self.body.desugar(renamer);
+        renamer.tags = outer_tags;
+        body
     }
+}
 
-    Fun {
-        fun_name: fun.fun_name,
-        arg_name: fun.arg_name,
-        body: lets(bindins, fun.body),
+// The arms which use the argument of the constructor get it from the
+// scrutinee, which is bound to `SCRUTINEE` for that.
+impl Sugar for ast::Match {
+    fn desugar(&self, renamer: &mut Renamer) -> Ir {
+        let scrutinee = self.scrutinee.desugar(renamer);
+        let mut arms = self.arms
+                           .iter()
+                           .map(|arm| {
+                               let body = match arm.binding {
+                                   Some(ref binding) => {
+                                       let name = renamer.lookup(binding.symbol());
+                                       let payload = UnOp {
+                                           arg: Ir::Var(SCRUTINEE),
+                                           kind: UnOpKind::Payload,
+                                       };
+                                       bind(name, payload.into(), arm.body.desugar(renamer))
+                                   }
+                                   None => arm.body.desugar(renamer),
+                               };
+                               (renamer.tag(&arm.constructor), body)
+                           })
+                           .collect::<Vec<_>>();
+        arms.sort_by_key(|&(tag, _)| tag);
+        let arms = arms.into_iter().map(|(_, body)| body).collect();
+        if self.arms.iter().all(|arm| arm.binding.is_none()) {
+            return Case {
+                scrutinee: scrutinee,
+                arms: arms,
+            }
+            .into();
+        }
+        let case = Case {
+            scrutinee: Ir::Var(SCRUTINEE),
+            arms: arms,
+        };
+        bind(SCRUTINEE, scrutinee, case.into())
     }
-    .into()
 }
 
-fn if_eq(lhs: Ir, rhs: Ir, tru: Ir, fls: Ir) -> Ir {
-    If {
-        cond: BinOp {
-                  lhs: lhs,
-                  rhs: rhs,
-                  kind: BinOpKind::Eq,
-              }
-              .into(),
-        tru: tru,
-        fls: fls,
+fn let_rec(funs: Vec<Fun>, body: Ir) -> Ir {
+    LetRec {
+        funs: funs,
+        body: body,
     }
     .into()
 }
 
-fn lets(mut bindings: Vec<Fun>, body: Ir) -> Ir {
-    if let Some(head) = bindings.pop() {
-        lets(bindings, let_(head, body))
-    } else {
-        body
+fn let_(fun: Fun, body: Ir) -> Ir {
+    bind(fun.fun_name, fun.into(), body)
+}
+
+fn cons(head: Ir, tail: Ir) -> Ir {
+    BinOp {
+        lhs: head,
+        rhs: tail,
+        kind: BinOpKind::Cons,
     }
+    .into()
 }
 
-fn let_(fun: Fun, body: Ir) -> Ir {
+fn bind(name: Name, value: Ir, body: Ir) -> Ir {
     Apply {
         fun: Fun {
-                 fun_name: 1,
-                 arg_name: fun.fun_name,
+                 fun_name: ANONYMOUS,
+                 arg_name: name,
                  body: body,
              }
              .into(),
-        arg: fun.into(),
+        arg: value,
     }
     .into()
-
-}
-
-fn undefined() -> Ir {
-    BinOp {
-        lhs: Ir::IntLiteral(0),
-        rhs: Ir::IntLiteral(0),
-        kind: BinOpKind::Div,
-    }
-    .into()
-}
-
-impl Ir {
-    fn apply<I: Into<Ir>>(self, arg: I) -> Ir {
-        Apply {
-            fun: self,
-            arg: arg.into(),
-        }
-        .into()
-    }
 }
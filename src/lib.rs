@@ -1,16 +1,57 @@
 extern crate ast;
 extern crate syntax;
+extern crate syntax_ll;
+extern crate secd;
+#[cfg(feature = "wasm")]
+extern crate wasm_bindgen;
+#[cfg(feature = "tracing")]
+extern crate tracing;
+#[cfg(feature = "serde")]
+extern crate serde;
 
-pub use syntax::parse;
-pub use compile::compile;
-pub use typecheck::typecheck;
-pub use machine::Machine;
+pub use syntax::{parse, parse_program, parse_decl, strip_comments};
+pub use ir::{Ir, BinOpKind, UnOpKind, desugar, desugar_with_names, desugar_program, desugar_program_with_names,
+             desugar_in, desugar_program_in, desugar_decl, name_in, NameTable};
+pub use compile::{compile, compile_program, compile_program_optimized, compile_program_cancellable, compile_ir};
+pub use opt::{OptConfig, optimize};
+pub use typecheck::{Type, TypeError, TypeErrorKind, typecheck, typecheck_program, typecheck_program_cancellable,
+                    typecheck_all, typecheck_program_all, typecheck_in, typecheck_program_in, typecheck_decl};
+pub use secd::{Machine, Frame, Instruction, Name, Native, NativeFn, Trace, Tracer, MachineSnapshot, MachineState,
+               Profile, Value, Status, GcConfig, GcTrigger, GcStats, IntArithmetic, disassemble};
+pub use error::{Error, ParseError, ParseErrorKind};
+pub use parser::Parser;
+pub use secd::{RuntimeError, RuntimeErrorKind};
+pub use eval::{Stats, eval, eval_typed, eval_program, eval_program_to_string, eval_checked_to_string,
+               exec_to_string};
+pub use walk::eval_ast;
+pub use plugin::{Pass, Plugins};
+pub use interpreter::Interpreter;
+pub use value::OwnedValue;
+pub use session::{Input, Inspect, Output, Session, parse_input, parse_input_with};
+pub use secd::{CancelToken, Cancelled, ProgramId, Scheduler};
 
 mod typecheck;
-mod ir;
+pub mod ir;
+pub mod opt;
 mod context;
+mod instrument;
 mod compile;
-mod machine;
+mod error;
+mod parser;
+mod eval;
+mod walk;
+mod plugin;
+mod interpreter;
+mod value;
+mod session;
+pub mod ffi;
+pub mod bytecode;
+pub mod codegen;
+pub mod ide;
+#[cfg(feature = "wasm")]
+pub mod wasm;
 
 #[cfg(test)]
 mod tests;
+#[cfg(test)]
+mod golden;
@@ -0,0 +1,378 @@
+//! Loads programs split into files. A file can start with lines like
+//! `import "lists.ml";;`, naming files relative to it, whose declarations it
+//! can then use as if they were its own. Their trailing expressions, if any,
+//! are ignored.
+//!
+//! Each imported file is parsed and typechecked once, however many files
+//! import it, against the names its own imports export. The program run is
+//! all of the declarations in the order the files depend on each other, with
+//! the names bound at the top of an imported file qualified by its path, like
+//! `lists.map`, so that helpers of different files can't clash, and the file
+//! importing one only sees the names it exports.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use ast::{Decl, Expr, ExprKind, Fun, Ident, Program, Span};
+use miniml::{self, Type};
+
+use diagnostics::{Location, Renderer};
+use {Error, Options, read_file, type_error};
+
+/// Parses the program in `src`, read from `path`, with the files it imports
+/// linked in. Errors in those are printed, and the one returned names the file.
+pub fn load(path: &str, src: &str, entry: Option<&str>, options: &Options) -> Result<Program, Error> {
+    let dir = Path::new(path).parent().unwrap_or(Path::new("")).to_owned();
+    let mut loader = Loader {
+        options: options,
+        dir: normalize(&dir),
+        modules: Vec::new(),
+        loaded: HashMap::new(),
+        loading: Vec::new(),
+    };
+    let (imports, code) = try!(split_imports(src));
+    let exports = try!(loader.imports(&dir, &imports, src));
+    let mut program = try!(options.frontend.parse_program(&code));
+    if let Some(entry) = entry {
+        program = program.with_entry(entry);
+    }
+    let mut renamer = Renamer { scope: exports.iter().map(|export| (export.name, export.linked)).collect() };
+    for decl in &mut program.decls {
+        renamer.decl(decl, None);
+    }
+    renamer.expr(&mut program.body);
+    let mut decls = loader.modules.into_iter().flat_map(|module| module.decls).collect::<Vec<_>>();
+    decls.extend(program.decls);
+    Ok(Program {
+        decls: decls,
+        body: program.body,
+    })
+}
+
+/// A name an imported file binds at the top, and the one it is linked under.
+struct Export {
+    name: Ident,
+    linked: Ident,
+    type_: Type,
+}
+
+struct Module {
+    /// With the names it binds linked under theirs.
+    decls: Vec<Decl>,
+    exports: Vec<Export>,
+}
+
+struct Loader<'a> {
+    options: &'a Options,
+    /// Where the program run is, which the names of modules are relative to.
+    dir: PathBuf,
+    /// Each module after the ones it imports.
+    modules: Vec<Module>,
+    /// Modules by their canonical path.
+    loaded: HashMap<PathBuf, usize>,
+    /// The files being loaded, each imported by the one before.
+    loading: Vec<PathBuf>,
+}
+
+impl<'a> Loader<'a> {
+    /// Loads the files `imports` name, relative to `dir`, from `src`, and
+    /// returns the names they export, later ones shadowing earlier ones.
+    fn imports(&mut self, dir: &Path, imports: &[(String, Span)], src: &str) -> Result<Vec<Export>, Error> {
+        let mut exports = Vec::new();
+        for &(ref import, span) in imports {
+            let path = normalize(&dir.join(import));
+            let key = fs::canonicalize(&path).unwrap_or_else(|_| path.clone());
+            if self.loading.contains(&key) {
+                let message = format!("`{}` imports itself", path.display());
+                return Err(Error::Parse(message, Some(Location::new(span, src))));
+            }
+            let module = match self.loaded.get(&key) {
+                Some(&module) => module,
+                None => {
+                    self.loading.push(key.clone());
+                    let module = try!(self.module(&path));
+                    self.loading.pop();
+                    self.loaded.insert(key, module);
+                    module
+                }
+            };
+            exports.extend(self.modules[module].exports.iter().map(|export| {
+                Export {
+                    name: export.name,
+                    linked: export.linked,
+                    type_: export.type_.clone(),
+                }
+            }));
+        }
+        Ok(exports)
+    }
+
+    /// Loads the file at `path` after the ones it imports.
+    fn module(&mut self, path: &Path) -> Result<usize, Error> {
+        let file = path.display().to_string();
+        let src = try!(read_file(&file));
+        let (imports, code) = try!(split_imports(&src).map_err(|e| self.error_in(&file, e)));
+        let dir = path.parent().unwrap_or(Path::new("")).to_owned();
+        let imported = try!(self.imports(&dir, &imports, &src).map_err(|e| self.error_in(&file, e)));
+        let program = try!(self.options.frontend.parse_program(&code).map_err(|e| self.error_in(&file, e)));
+
+        let module = path.strip_prefix(&self.dir).unwrap_or(path).with_extension("").display().to_string();
+        let mut env = imported.iter().map(|export| (export.name, export.type_.clone())).collect::<Vec<_>>();
+        let mut renamer = Renamer { scope: imported.iter().map(|export| (export.name, export.linked)).collect() };
+        let mut exports = Vec::new();
+        let mut decls = Vec::new();
+        for mut decl in program.decls {
+            let bindings = try!(miniml::typecheck_decl(&env, &decl)
+                                    .map_err(|e| self.error_in(&file, type_error(e, &src))));
+            renamer.decl(&mut decl, Some(&module));
+            for (name, type_) in bindings {
+                env.push((name, type_.clone()));
+                exports.push(Export {
+                    name: name,
+                    linked: qualify(&module, name),
+                    type_: type_,
+                });
+            }
+            decls.push(decl);
+        }
+        self.modules.push(Module {
+            decls: decls,
+            exports: exports,
+        });
+        Ok(self.modules.len() - 1)
+    }
+
+    /// Prints `error`, in the imported `file`, and returns one saying where it is.
+    fn error_in(&self, file: &str, error: Error) -> Error {
+        match error {
+            Error::Parse(..) | Error::Type(..) => {
+                eprintln!("{}", Renderer::stderr(self.options).error_in(file, &error));
+            }
+            _ => return error,
+        }
+        let message = format!("can't import `{}`", file);
+        match error {
+            Error::Parse(..) => Error::Parse(message, None),
+            _ => Error::Type(message, None),
+        }
+    }
+}
+
+/// `path` without `.` and with `dir/..` left out, so that a module is named
+/// the same however it is imported.
+fn normalize(path: &Path) -> PathBuf {
+    let mut result = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir if result.file_name().is_some() => {
+                result.pop();
+            }
+            component => result.push(component.as_os_str()),
+        }
+    }
+    result
+}
+
+/// The name `name` bound at the top of `module` is linked under.
+fn qualify(module: &str, name: Ident) -> Ident {
+    Ident::from_str(&format!("{}.{}", module, name))
+}
+
+/// The paths of the `import "file";;` lines `src` starts with, with their
+/// spans, and `src` with those lines and the comments between them blanked
+/// out, so that offsets in it are the same.
+fn split_imports(src: &str) -> Result<(Vec<(String, Span)>, String), Error> {
+    let code = miniml::strip_comments(src);
+    let mut imports = Vec::new();
+    let mut end = 0;
+    loop {
+        let start = end + (code[end..].len() - code[end..].trim_start().len());
+        let rest = &code[start..];
+        let keyword = rest.starts_with("import") &&
+                      !rest["import".len()..].starts_with(|c: char| c.is_alphanumeric() || c == '_');
+        if !keyword {
+            break;
+        }
+        let after = rest["import".len()..].trim_start();
+        let path_start = code.len() - after.len();
+        let path = after.strip_prefix('"').and_then(|quoted| {
+            quoted.find(['"', '\n']).filter(|&i| quoted[i..].starts_with('"')).map(|i| &quoted[..i])
+        });
+        let path = match path {
+            Some(path) => path,
+            None => {
+                let message = "expected the file to import in quotes, as in `import \"lib.ml\";;`".to_owned();
+                let span = Span::new(start, path_start);
+                return Err(Error::Parse(message, Some(Location::new(span, src))));
+            }
+        };
+        let span = Span::new(path_start, path_start + path.len() + 2);
+        let after = code[span.end as usize..].trim_start();
+        if !after.starts_with(";;") {
+            let message = "expected `;;` after the file to import".to_owned();
+            return Err(Error::Parse(message, Some(Location::new(span, src))));
+        }
+        end = code.len() - after.len() + 2;
+        imports.push((path.to_owned(), span));
+    }
+    let blank = code[..end].bytes().map(|b| if b == b'\n' { '\n' } else { ' ' }).collect::<String>();
+    Ok((imports, blank + &src[end..]))
+}
+
+/// Renames the variables of a module to the names they are linked under.
+/// Local bindings shadow the names at the top, as in the typechecker.
+struct Renamer {
+    /// The names in scope, with the ones they are linked under.
+    scope: Vec<(Ident, Ident)>,
+}
+
+impl Renamer {
+    /// Renames a declaration at the top of a module, qualifying the names it
+    /// binds with `module`, and leaves them in scope.
+    fn decl(&mut self, decl: &mut Decl, module: Option<&str>) {
+        let qualified = |name: Ident| module.map_or(name, |module| qualify(module, name));
+        match *decl {
+            Decl::Fun(ref mut fun) => {
+                let linked = qualified(fun.fun_name);
+                self.fun(fun, linked);
+                self.scope.push((fun.fun_name, linked));
+                fun.fun_name = linked;
+            }
+            Decl::LetRec(ref mut funs) => {
+                let linked = funs.iter().map(|fun| qualified(fun.fun_name)).collect::<Vec<_>>();
+                self.group(funs, &linked);
+            }
+            Decl::Value(ref mut name, ref mut value) => {
+                self.expr(value);
+                let linked = qualified(*name);
+                self.scope.push((*name, linked));
+                *name = linked;
+            }
+        }
+    }
+
+    fn expr(&mut self, expr: &mut Expr) {
+        let scope = self.scope.len();
+        match expr.kind {
+            ExprKind::Var(ref mut name) => {
+                if let Some(&(_, linked)) = self.scope.iter().rev().find(|&&(from, _)| from == *name) {
+                    *name = linked;
+                }
+            }
+            ExprKind::Literal(_) => {}
+            ExprKind::ArithBinOp(ref mut op) => self.exprs(&mut [&mut op.lhs, &mut op.rhs]),
+            ExprKind::CmpBinOp(ref mut op) => self.exprs(&mut [&mut op.lhs, &mut op.rhs]),
+            ExprKind::LogicBinOp(ref mut op) => self.exprs(&mut [&mut op.lhs, &mut op.rhs]),
+            ExprKind::Not(ref mut not) => self.expr(&mut not.arg),
+            ExprKind::List(ref mut list) => {
+                for item in &mut list.items {
+                    self.expr(item);
+                }
+            }
+            ExprKind::Cons(ref mut cons) => self.exprs(&mut [&mut cons.head, &mut cons.tail]),
+            ExprKind::Concat(ref mut concat) => self.exprs(&mut [&mut concat.lhs, &mut concat.rhs]),
+            ExprKind::ListUnOp(ref mut op) => self.expr(&mut op.arg),
+            ExprKind::RefUnOp(ref mut op) => self.expr(&mut op.arg),
+            ExprKind::PrintUnOp(ref mut op) => self.expr(&mut op.arg),
+            ExprKind::ConvertUnOp(ref mut op) => self.expr(&mut op.arg),
+            ExprKind::Assign(ref mut assign) => self.exprs(&mut [&mut assign.cell, &mut assign.value]),
+            ExprKind::Seq(ref mut seq) => self.exprs(&mut [&mut seq.first, &mut seq.second]),
+            ExprKind::Record(ref mut record) => {
+                for &mut (_, ref mut value) in &mut record.fields {
+                    self.expr(value);
+                }
+            }
+            ExprKind::Field(ref mut field) => self.expr(&mut field.record),
+            ExprKind::Construct(ref mut construct) => {
+                if let Some(ref mut arg) = construct.arg {
+                    self.expr(arg);
+                }
+            }
+            ExprKind::Match(ref mut match_) => {
+                self.expr(&mut match_.scrutinee);
+                for arm in &mut match_.arms {
+                    self.scope.extend(arm.binding.map(|binding| (binding, binding)));
+                    self.expr(&mut arm.body);
+                    self.scope.truncate(scope);
+                }
+            }
+            ExprKind::If(ref mut if_) => self.exprs(&mut [&mut if_.cond, &mut if_.tru, &mut if_.fls]),
+            ExprKind::Fun(ref mut fun) => {
+                let name = fun.fun_name;
+                self.fun(fun, name);
+            }
+            ExprKind::Let(ref mut let_) => {
+                self.expr(&mut let_.value);
+                self.scope.push((let_.name, let_.name));
+                self.expr(&mut let_.body);
+            }
+            ExprKind::LetFun(ref mut let_fun) => {
+                let name = let_fun.fun.fun_name;
+                self.fun(&mut let_fun.fun, name);
+                self.scope.push((name, name));
+                self.expr(&mut let_fun.body);
+            }
+            ExprKind::LetRec(ref mut let_rec) => {
+                let names = let_rec.funs.iter().map(|fun| fun.fun_name).collect::<Vec<_>>();
+                self.group(&mut let_rec.funs, &names);
+                self.expr(&mut let_rec.body);
+            }
+            ExprKind::LetType(ref mut let_type) => self.expr(&mut let_type.body),
+            ExprKind::TypeAlias(ref mut alias) => self.expr(&mut alias.body),
+            ExprKind::Apply(ref mut apply) => self.exprs(&mut [&mut apply.fun, &mut apply.arg]),
+        }
+        self.scope.truncate(scope);
+    }
+
+    fn exprs(&mut self, exprs: &mut [&mut Expr]) {
+        for expr in exprs {
+            self.expr(expr);
+        }
+    }
+
+    /// Renames the body of `fun`, which is linked under `linked`. As in the
+    /// typechecker, the name of the function shadows the first parameter.
+    fn fun(&mut self, fun: &mut Fun, linked: Ident) {
+        let scope = self.scope.len();
+        self.scope.push((fun.args[0].0, fun.args[0].0));
+        self.scope.push((fun.fun_name, linked));
+        self.scope.extend(fun.args[1..].iter().map(|arg| (arg.0, arg.0)));
+        self.expr(&mut fun.body);
+        self.scope.truncate(scope);
+    }
+
+    /// Binds the functions of a `let rec` group under `linked`, and renames
+    /// their bodies. The functions are left in scope.
+    fn group(&mut self, funs: &mut [Fun], linked: &[Ident]) {
+        self.scope.extend(funs.iter().map(|fun| fun.fun_name).zip(linked.iter().cloned()));
+        for (fun, &linked) in funs.iter_mut().zip(linked) {
+            self.fun(fun, linked);
+            fun.fun_name = linked;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::split_imports;
+
+    #[test]
+    fn imports() {
+        let src = "-- Uses lists.\nimport \"lists.ml\";;\n  import \"../maps.ml\" ;;\nsum [1]";
+        let (imports, code) = split_imports(src).ok().unwrap();
+        let paths = imports.iter().map(|&(ref path, span)| {
+            (path.as_ref(), &src[span.start as usize..span.end as usize])
+        });
+        assert_eq!(paths.collect::<Vec<_>>(),
+                   vec![("lists.ml", "\"lists.ml\""), ("../maps.ml", "\"../maps.ml\"")]);
+        assert_eq!(code.len(), src.len());
+        assert_eq!(code.trim(), "sum [1]");
+        assert_eq!(code.lines().count(), src.lines().count());
+
+        assert!(split_imports("important 1").ok().unwrap().0.is_empty());
+        assert!(split_imports("import lists;;").is_err());
+        assert!(split_imports("import \"lists.ml\" 1").is_err());
+    }
+}
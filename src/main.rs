@@ -1,65 +1,735 @@
 extern crate miniml;
+extern crate ast;
+extern crate syntax_ll;
+extern crate rustyline;
 
 use std::io::prelude::*;
+use std::io::IsTerminal;
 use std::fs::File;
-use std::io;
+use std::path::{Path, PathBuf};
+use std::{fmt, io, process};
+use std::time::{Duration, Instant};
 
-fn readline(ps: &str, buffer: &mut String) {
-    write!(io::stdout(), "{} ", ps).unwrap();
-    io::stdout().flush().unwrap();
-    io::stdin().read_line(buffer).unwrap();
+mod debugger;
+mod diagnostics;
+mod init;
+mod loader;
+mod profiler;
+mod repl;
+mod script;
+mod test_runner;
+
+use diagnostics::{ColorChoice, Location, MessageFormat, Renderer};
+use miniml::{Input, Inspect, OptConfig, Output, Session, Stats};
+
+enum Error {
+    Usage(String),
+    Io(String),
+    Parse(String, Option<Location>),
+    Type(String, Option<Location>),
+    Runtime(String),
+    TestsFailed(usize),
 }
 
-fn repl<F: Fn(&str) -> String>(f: F) {
-    let mut buffer = String::new();
-    println!("Hello! Type :q to quit");
-    loop {
-        buffer.clear();
-        readline(">", &mut buffer);
-        if buffer.starts_with(":q") {
-            println!("Bye!");
-            return;
+impl Error {
+    fn exit_code(&self) -> i32 {
+        match *self {
+            Error::Usage(_) | Error::Io(_) => 1,
+            Error::Parse(..) => 2,
+            Error::Type(..) => 3,
+            Error::Runtime(_) => 4,
+            Error::TestsFailed(_) => 5,
+        }
+    }
+
+    /// Identifies the kind of error in `--message-format=json` output.
+    fn code(&self) -> &'static str {
+        match *self {
+            Error::Usage(_) => "usage",
+            Error::Io(_) => "io",
+            Error::Parse(..) => "parse",
+            Error::Type(..) => "type",
+            Error::Runtime(_) => "runtime",
+            Error::TestsFailed(_) => "tests-failed",
+        }
+    }
+
+    fn header(&self) -> &'static str {
+        match *self {
+            Error::Usage(_) | Error::Io(_) | Error::TestsFailed(_) => "Error",
+            Error::Parse(..) => "Parse error",
+            Error::Type(..) => "Type error",
+            Error::Runtime(_) => "Runtime error",
+        }
+    }
+
+    fn message(&self) -> String {
+        match *self {
+            Error::Usage(ref msg) | Error::Io(ref msg) | Error::Parse(ref msg, _) |
+            Error::Type(ref msg, _) | Error::Runtime(ref msg) => msg.clone(),
+            Error::TestsFailed(n) => format!("{} test(s) failed", n),
+        }
+    }
+
+    fn location(&self) -> Option<&Location> {
+        match *self {
+            Error::Parse(_, ref location) | Error::Type(_, ref location) => location.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// Like `from`, but finds where in `src` a parse or type error is.
+    fn in_source(error: miniml::Error, src: &str) -> Error {
+        match error {
+            miniml::Error::Parse(error) => parse_error(error, src),
+            miniml::Error::Type(error) => type_error(error, src),
+            error => Error::from(error),
+        }
+    }
+}
+
+fn parse_error(error: miniml::ParseError, src: &str) -> Error {
+    Error::Parse(error.kind.to_string(), Some(Location::new(error.span, src)))
+}
+
+fn type_error(error: miniml::TypeError, src: &str) -> Error {
+    Error::Type(error.to_string(), error.span.map(|span| Location::new(span, src)))
+}
+
+impl From<miniml::Error> for Error {
+    fn from(error: miniml::Error) -> Error {
+        match error {
+            miniml::Error::Parse(error) => Error::Parse(error.to_string(), None),
+            miniml::Error::Type(error) => Error::Type(error.to_string(), None),
+            miniml::Error::Runtime(error) => Error::Runtime(error.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.header(), self.message())
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Frontend {
+    Lalrpop,
+    Ll,
+    // Hidden: parse with both and fail if the ASTs differ.
+    Compare,
+}
+
+impl Frontend {
+    fn from_name(name: &str) -> Result<Frontend, Error> {
+        match name {
+            "lalrpop" => Ok(Frontend::Lalrpop),
+            "ll" => Ok(Frontend::Ll),
+            _ => Err(Error::Usage(format!("unknown parser `{}`, expected `lalrpop` or `ll`", name))),
+        }
+    }
+
+    fn parse_expr(self, src: &str) -> Result<ast::Expr, Error> {
+        self.parse(src, |parser, src| parser.parse(src))
+    }
+
+    fn parse_decl(self, src: &str) -> Result<ast::Decl, Error> {
+        self.parse(src, |parser, src| parser.parse_decl(src))
+    }
+
+    fn parse_program(self, src: &str) -> Result<ast::Program, Error> {
+        self.parse(src, |parser, src| parser.parse_program(src))
+    }
+
+    fn parse<T, P>(self, src: &str, parse: P) -> Result<T, Error>
+        where T: fmt::Debug,
+              P: Fn(miniml::Parser, &str) -> Result<T, miniml::ParseError>
+    {
+        match self {
+            Frontend::Lalrpop => parse(miniml::Parser::Lalrpop, src).map_err(|e| parse_error(e, src)),
+            Frontend::Ll => parse(miniml::Parser::Ll, src).map_err(|e| parse_error(e, src)),
+            Frontend::Compare => {
+                let expected = try!(parse(miniml::Parser::Lalrpop, src)
+                    .map_err(|e| parse_error(e, src)));
+                let actual = try!(parse(miniml::Parser::Ll, src).map_err(|e| {
+                    let message = format!("only the LALRPOP parser accepts the input: {}", e.kind);
+                    Error::Parse(message, Some(Location::new(e.span, src)))
+                }));
+                let (expected_ast, actual_ast) = (format!("{:?}", expected), format!("{:?}", actual));
+                if expected_ast != actual_ast {
+                    let message = format!("parsers disagree\nlalrpop: {}\nll:      {}", expected_ast, actual_ast);
+                    return Err(Error::Parse(message, None));
+                }
+                Ok(expected)
+            }
+        }
+    }
+}
+
+/// What `--emit` prints instead of running a program.
+#[derive(Clone, Copy)]
+enum Emit {
+    Tokens,
+    Ast,
+    Ir,
+    Bytecode,
+    Type,
+}
+
+impl Emit {
+    fn from_name(name: &str) -> Result<Emit, Error> {
+        match name {
+            "tokens" => Ok(Emit::Tokens),
+            "ast" => Ok(Emit::Ast),
+            "ir" => Ok(Emit::Ir),
+            "bytecode" => Ok(Emit::Bytecode),
+            "type" => Ok(Emit::Type),
+            _ => {
+                let expected = "`tokens`, `ast`, `ir`, `bytecode` or `type`";
+                Err(Error::Usage(format!("unknown output `{}`, expected {}", name, expected)))
+            }
+        }
+    }
+}
+
+/// What `build` compiles a program to.
+#[derive(Clone, Copy)]
+enum Target {
+    Rust,
+}
+
+impl Target {
+    fn from_name(name: &str) -> Result<Target, Error> {
+        match name {
+            "rust" => Ok(Target::Rust),
+            _ => Err(Error::Usage(format!("unknown target `{}`, expected `rust`", name))),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Options {
+    frontend: Frontend,
+    time: bool,
+    show_type: bool,
+    color: ColorChoice,
+    message_format: MessageFormat,
+    // Print nothing but results and errors.
+    quiet: bool,
+    // Optimizations for the `compile` and `build` subcommands, `--emit=ir` and `--emit=bytecode`.
+    opt: OptConfig,
+}
+
+struct Timings {
+    phases: Vec<(&'static str, Duration)>,
+    instructions: u64,
+}
+
+impl Timings {
+    fn new() -> Timings {
+        Timings {
+            phases: Vec::new(),
+            instructions: 0,
+        }
+    }
+
+    fn time<T, F: FnOnce() -> T>(&mut self, phase: &'static str, f: F) -> T {
+        let start = Instant::now();
+        let result = f();
+        self.phases.push((phase, start.elapsed()));
+        result
+    }
+
+    fn add(&mut self, stats: Stats) {
+        self.phases.extend(stats.phases);
+        self.instructions += stats.instructions;
+    }
+
+    fn report(&self) {
+        eprintln!("{}", self.render());
+    }
+
+    fn render(&self) -> String {
+        let mut lines = self.phases
+                            .iter()
+                            .map(|&(phase, duration)| {
+                                format!("{:>12}: {:.3}ms", phase, duration.as_secs_f64() * 1000.0)
+                            })
+                            .collect::<Vec<_>>();
+        lines.push(format!("{:>12}: {}", "instructions", self.instructions));
+        lines.join("\n")
+    }
+}
+
+/// Typechecks `program`, parsed from `src`, printing all its type errors
+/// but the last, which is returned.
+fn typecheck_program(program: &ast::Program, src: &str, options: &Options) -> Result<miniml::Type, Error> {
+    let (type_, mut errors) = miniml::typecheck_program_all(program);
+    if let Some(type_) = type_ {
+        return Ok(type_);
+    }
+    let last = errors.pop().expect("a program without a type has errors");
+    let renderer = Renderer::stderr(options);
+    for error in errors {
+        eprintln!("{}", renderer.error(&type_error(error, src)));
+    }
+    Err(type_error(last, src))
+}
+
+/// Runs `program`, parsed from `src`.
+fn run_program(program: &ast::Program,
+               src: &str,
+               options: &Options,
+               timings: &mut Timings)
+               -> Result<String, Error> {
+    let mut stats = Stats::default();
+    let type_ = try!(stats.time("typecheck", || typecheck_program(program, src, options)));
+    let result = miniml::eval_checked_to_string(program, &mut stats);
+    timings.add(stats);
+    let value = try!(result.map_err(|e| Error::in_source(e, src)));
+    Ok(show_value(value, &type_, options))
+}
+
+fn execute(input: &str, options: &Options, session: &mut Session) -> Result<String, Error> {
+    if let Some(output) = session.command(input) {
+        return Ok(show_output(output, options));
+    }
+    if let Some((inspect, src)) = Inspect::parse(input) {
+        let src = src.trim();
+        let expr = try!(options.frontend.parse_expr(src));
+        return session.inspect(inspect, &expr)
+                      .map(|output| show_output(output, options))
+                      .map_err(|e| Error::in_source(e, src));
+    }
+    let mut timings = Timings::new();
+    let mut stats = Stats::default();
+    let result = timings.time("parse", || parse_input(input, options)).and_then(|parsed| {
+        session.run(parsed, &mut stats)
+               .map(|output| show_output(output, options))
+               // The input is parsed without surrounding whitespace, see `parse_input_with`.
+               .map_err(|e| Error::in_source(e, input.trim()))
+    });
+    timings.add(stats);
+    let result = match session.take_trace() {
+        Some(trace) => result.map(|result| show_trace(result, &trace)),
+        None => result,
+    };
+    if !session.timing() || options.quiet {
+        return result;
+    }
+    match result {
+        Ok(result) => Ok(format!("{}\n{}", result, timings.render())),
+        Err(e) => {
+            timings.report();
+            Err(e)
+        }
+    }
+}
+
+/// Appends `trace` to `result`, indenting each instruction by its call depth.
+fn show_trace(result: String, trace: &miniml::Trace) -> String {
+    let mut lines = vec![result];
+    for &(depth, instruction) in &trace.instructions {
+        lines.push(format!("  {:2$}{}", "", instruction, depth * 2));
+    }
+    if trace.omitted > 0 {
+        lines.push(format!("  ... {} more instructions", trace.omitted));
+    }
+    lines.retain(|line| !line.is_empty());
+    lines.join("\n")
+}
+
+fn parse_input(input: &str, options: &Options) -> Result<Input, Error> {
+    miniml::parse_input_with(input,
+                             |src| options.frontend.parse_expr(src),
+                             |src| options.frontend.parse_decl(src))
+}
+
+/// Like `Output`'s `Display`, but with colored types and respecting `-t` and `--quiet`.
+fn show_output(output: Output, options: &Options) -> String {
+    let renderer = Renderer::stdout(options);
+    match output {
+        Output::Defined(_) if options.quiet => String::new(),
+        Output::Defined(bindings) => {
+            let lines = bindings.into_iter()
+                                .map(|(name, type_)| format!("{} : {}", name, renderer.type_(&type_)))
+                                .collect::<Vec<_>>();
+            lines.join("\n")
+        }
+        Output::Value(value, type_) => show_value(value, &type_, options),
+        Output::Type(type_) => renderer.type_(&type_),
+        Output::Bindings(ref bindings) if !bindings.is_empty() => {
+            let lines = bindings.iter()
+                                .map(|&(ref name, ref type_, ref value)| {
+                                    format!("{} : {} = {}", name, renderer.type_(type_), value)
+                                })
+                                .collect::<Vec<_>>();
+            lines.join("\n")
         }
-        println!("{}", f(&buffer));
+        output => output.to_string(),
+    }
+}
+
+fn show_value(value: String, type_: &miniml::Type, options: &Options) -> String {
+    if options.show_type && !options.quiet {
+        format!("{} : {}", value, Renderer::stdout(options).type_(type_))
+    } else {
+        value
+    }
+}
+
+fn start_repl(options: &Options, init: Option<&str>, no_init: bool) -> Result<(), Error> {
+    let mut options = *options;
+    let mut prompt = "> ".to_owned();
+    let mut session = Session::new();
+    session.set_timing(options.time);
+    if !no_init {
+        try!(init::load(init, &mut options, &mut prompt, &mut session));
     }
+    if options.quiet {
+        prompt.clear();
+    }
+    let renderer = Renderer::stderr(&options);
+    // A quiet repl is fed by a script, which shouldn't end up in the history.
+    let history = if options.quiet { None } else { repl::history_path() };
+    repl::run(&prompt,
+              !options.quiet,
+              history.as_ref().map(PathBuf::as_path),
+              |input| execute(input, &options, &mut session).map_err(|e| renderer.error(&e)));
+    Ok(())
 }
 
-fn execute(expr: &str) -> String {
-    let expr = match miniml::parse(expr) {
-        Err(e) => return format!("Parse error: {:?}", e),
-        Ok(e) => e,
+/// Reads a program from `path`, or from stdin if `path` is `-`.
+fn read_file(path: &str) -> Result<String, Error> {
+    let mut buffer = String::new();
+    let result = if path == "-" {
+        io::stdin().read_to_string(&mut buffer)
+    } else {
+        File::open(path).and_then(|mut file| file.read_to_string(&mut buffer))
     };
-    if let Err(e) = miniml::typecheck(&expr) {
-        return format!("Type error: {:?}", e);
+    try!(result.map_err(|e| Error::Io(format!("can't read `{}`: {}", path, e))));
+    Ok(buffer)
+}
+
+fn exec_file(path: &str, entry: Option<&str>, options: &Options) -> Result<(), Error> {
+    let src = try!(read_file(path));
+    exec_source(path, &src, entry, options)
+}
+
+/// Runs `src`, read from `path`, which the files it imports are relative to.
+fn exec_source(path: &str, src: &str, entry: Option<&str>, options: &Options) -> Result<(), Error> {
+    println!("{}", try!(eval_source(path, src, entry, options)));
+    Ok(())
+}
+
+fn eval_source(path: &str, src: &str, entry: Option<&str>, options: &Options) -> Result<String, Error> {
+    let mut timings = Timings::new();
+    let result = timings.time("parse", || loader::load(path, src, entry, options))
+                        .and_then(|program| run_program(&program, src, options, &mut timings));
+    if options.time && !options.quiet {
+        timings.report();
+    }
+    result
+}
+
+fn run_script(path: &str, options: &Options) -> Result<(), Error> {
+    let src = miniml::strip_comments(&try!(read_file(path)));
+    let mut session = Session::new();
+    session.set_timing(options.time);
+    let renderer = Renderer::stdout(options);
+    script::run(&src, options.quiet, |statement| {
+        execute(statement, options, &mut session).map_err(|e| renderer.error(&e))
+    });
+    Ok(())
+}
+
+fn run_tests(dir: &str, options: &Options) -> Result<(), Error> {
+    // Results are compared with the expected ones as plain text.
+    let options = &Options { color: ColorChoice::Never, ..*options };
+    let eval = |path: &Path, src: &str| eval_source(&path.display().to_string(), src, None, options);
+    let failed = try!(test_runner::run(dir, options.quiet, eval)
+                          .map_err(|e| Error::Io(format!("can't read `{}`: {}", dir, e))));
+    if failed > 0 {
+        return Err(Error::TestsFailed(failed));
+    }
+    Ok(())
+}
+
+fn debug_file(path: &str, entry: Option<&str>, options: &Options) -> Result<(), Error> {
+    let src = try!(read_file(path));
+    let program = try!(loader::load(path, &src, entry, options));
+    try!(typecheck_program(&program, &src, options));
+    let (ir, names) = miniml::desugar_program_with_names(&program);
+    debugger::run(&miniml::compile_ir(&ir), &names);
+    Ok(())
+}
+
+/// Runs the program in `path` counting the instructions it executes, and
+/// prints its value, then which functions and instructions took the most steps.
+fn profile_file(path: &str, entry: Option<&str>, options: &Options) -> Result<(), Error> {
+    let src = try!(read_file(path));
+    let program = try!(loader::load(path, &src, entry, options));
+    let type_ = try!(typecheck_program(&program, &src, options));
+    let (ir, names) = miniml::desugar_program_with_names(&program);
+    let frame = miniml::compile_ir(&ir);
+    let mut machine = miniml::Machine::new(&frame).with_profiling();
+    let value = try!(machine.exec().map_err(|e| Error::Runtime(names.name_error(e).to_string())));
+    println!("{}", show_value(machine.show(value), &type_, options));
+    eprintln!("{}", profiler::report(&frame, machine.profile().expect("the machine profiles"), &names));
+    Ok(())
+}
+
+/// Compiles the program in `path` to bytecode in `output`, by default next
+/// to the source with the `.mlbc` extension.
+fn compile_file(path: &str,
+                output: Option<&str>,
+                entry: Option<&str>,
+                options: &Options)
+                -> Result<(), Error> {
+    let src = try!(read_file(path));
+    let program = try!(loader::load(path, &src, entry, options));
+    try!(typecheck_program(&program, &src, options));
+    let bytes = miniml::bytecode::save(&miniml::compile_program_optimized(&program, &options.opt));
+    let output = match output {
+        Some(output) => PathBuf::from(output),
+        None => Path::new(path).with_extension("mlbc"),
     };
-    let program = miniml::compile(&expr);
-    let mut machine = miniml::Machine::new(&program);
-    let result = match machine.exec() {
-        Err(e) => return format!("{}", e.message),
-        Ok(x) => x,
+    File::create(&output)
+        .and_then(|mut file| file.write_all(&bytes))
+        .map_err(|e| Error::Io(format!("can't write `{}`: {}", output.display(), e)))
+}
+
+/// Compiles the program in `path` to the source of a Rust program in
+/// `output`, by default next to it with the `.rs` extension, which `rustc -O`
+/// builds into an executable.
+fn build_file(path: &str,
+              output: Option<&str>,
+              target: Target,
+              entry: Option<&str>,
+              options: &Options)
+              -> Result<(), Error> {
+    let src = try!(read_file(path));
+    let program = try!(loader::load(path, &src, entry, options));
+    try!(typecheck_program(&program, &src, options));
+    let (ir, names) = miniml::desugar_program_with_names(&program);
+    let code = match target {
+        Target::Rust => miniml::codegen::rust(&miniml::optimize(ir, &options.opt), &names),
     };
-    format!("{}", result)
+    let output = match output {
+        Some(output) => PathBuf::from(output),
+        None => Path::new(path).with_extension("rs"),
+    };
+    File::create(&output)
+        .and_then(|mut file| file.write_all(code.as_bytes()))
+        .map_err(|e| Error::Io(format!("can't write `{}`: {}", output.display(), e)))
+}
 
+/// Rewrites the program in `path` in the layout of `ast::pretty`, or prints
+/// it for `-`. The comments at the top of the file are kept, but there is no
+/// telling where the others belong, so a file with any is left alone.
+fn format_file(path: &str, options: &Options) -> Result<(), Error> {
+    let src = try!(read_file(path));
+    // The lines are taken with their terminators, `\r\n` or `\n`, so that the
+    // header ends at the start of a line, whatever the file uses.
+    let header_len = src.split_inclusive('\n')
+                        .take_while(|line| line.trim().is_empty() || line.trim_start().starts_with("--"))
+                        .map(str::len)
+                        .sum::<usize>();
+    let (header, code) = src.split_at(header_len);
+    if miniml::strip_comments(code) != code {
+        return Err(Error::Usage(format!("can't format `{}`: it has comments below the top ones", path)));
+    }
+    let program = try!(options.frontend.parse_program(&src));
+    let mut formatted = header.trim_end().to_owned();
+    if !formatted.is_empty() {
+        formatted.push_str("\n\n");
+    }
+    formatted.push_str(&ast::pretty::program(&program, &ast::pretty::Style::default()));
+    if path == "-" {
+        print!("{}", formatted);
+        return Ok(());
+    }
+    File::create(path)
+        .and_then(|mut file| file.write_all(formatted.as_bytes()))
+        .map_err(|e| Error::Io(format!("can't write `{}`: {}", path, e)))
 }
 
-fn start_repl() {
-    repl(execute);
+/// Runs a program compiled by `compile_file`.
+fn run_bytecode(path: &str, options: &Options) -> Result<(), Error> {
+    let mut bytes = Vec::new();
+    try!(File::open(path)
+             .and_then(|mut file| file.read_to_end(&mut bytes))
+             .map_err(|e| Error::Io(format!("can't read `{}`: {}", path, e))));
+    let frame = try!(miniml::bytecode::load(&bytes)
+                         .map_err(|e| Error::Io(format!("can't load `{}`: {}", path, e.message))));
+    let mut timings = Timings::new();
+    let mut stats = Stats::default();
+    let result = miniml::exec_to_string(&frame, &mut stats);
+    timings.add(stats);
+    if options.time && !options.quiet {
+        timings.report();
+    }
+    println!("{}", try!(result));
+    Ok(())
 }
 
-fn exec_file(path: &str) {
-    let mut buffer = String::new();
-    let mut file = File::open(path).unwrap();
-    file.read_to_string(&mut buffer).unwrap();
-    let result = execute(&buffer);
-    println!("{}", result);
+/// Prints what `src`, read from `path`, is turned into at the stage `emit` of
+/// the pipeline instead of running it.
+fn emit(path: &str, src: &str, emit: Emit, entry: Option<&str>, options: &Options) -> Result<(), Error> {
+    if let Emit::Tokens = emit {
+        let tokens = try!(syntax_ll::tokens(src)
+            .map_err(|e| parse_error(miniml::ParseError::from_ll(e), src)));
+        for (span, token) in tokens {
+            println!("{:?} {}", span, token);
+        }
+        return Ok(());
+    }
+    let program = try!(loader::load(path, src, entry, options));
+    if let Emit::Ast = emit {
+        println!("{:?}", program);
+        return Ok(());
+    }
+    let type_ = try!(typecheck_program(&program, src, options));
+    match emit {
+        Emit::Ir => {
+            let (ir, names) = miniml::desugar_program_with_names(&program);
+            let ir = miniml::optimize(ir, &options.opt);
+            println!("{:?}", ir.with_names(&names))
+        }
+        Emit::Type => println!("{}", Renderer::stdout(options).type_(&type_)),
+        Emit::Bytecode => {
+            println!("{}", miniml::disassemble(&miniml::compile_program_optimized(&program, &options.opt)))
+        }
+        Emit::Tokens | Emit::Ast => unreachable!(),
+    }
+    Ok(())
+}
+
+fn run(options: &mut Options) -> Result<(), Error> {
+    let mut file = None;
+    let mut expr = None;
+    let mut entry = None;
+    let mut init = None;
+    let mut no_init = false;
+    let mut output = None;
+    let mut emit_stage = None;
+    let mut target = Target::Rust;
+    let mut optimize = false;
+    let mut no_inline = false;
+    let mut subcommand = None;
+    let mut args = std::env::args().skip(1).peekable();
+    if let Some(name) = args.peek().cloned() {
+        let what = match name.as_ref() {
+            "test" => Some("a directory"),
+            "debug" | "script" | "compile" | "build" | "run" | "fmt" | "profile" => Some("a file"),
+            _ => None,
+        };
+        if let Some(what) = what {
+            args.next();
+            // The path is the first argument which isn't an option, so
+            // options can come before it, as in `build --target=rust foo.ml`.
+            subcommand = Some((name, what));
+        }
+    }
+    while let Some(arg) = args.next() {
+        if arg.starts_with("--parser=") {
+            options.frontend = try!(Frontend::from_name(&arg["--parser=".len()..]));
+            continue;
+        }
+        if arg.starts_with("--color=") {
+            options.color = try!(ColorChoice::from_name(&arg["--color=".len()..]));
+            continue;
+        }
+        if arg.starts_with("--message-format=") {
+            options.message_format =
+                try!(MessageFormat::from_name(&arg["--message-format=".len()..]));
+            continue;
+        }
+        if arg.starts_with("--emit=") {
+            emit_stage = Some(try!(Emit::from_name(&arg["--emit=".len()..])));
+            continue;
+        }
+        if arg.starts_with("--target=") {
+            target = try!(Target::from_name(&arg["--target=".len()..]));
+            continue;
+        }
+        match arg.as_ref() {
+            "--compare-parsers" => options.frontend = Frontend::Compare,
+            "--time" => options.time = true,
+            "-t" => options.show_type = true,
+            "-q" | "--quiet" | "--porcelain" => options.quiet = true,
+            "-e" => {
+                let src = try!(args.next()
+                                   .ok_or(Error::Usage("`-e` requires an expression".to_owned())));
+                expr = Some(src);
+            }
+            "--entry" => {
+                let name = try!(args.next()
+                                    .ok_or(Error::Usage("`--entry` requires a name".to_owned())));
+                entry = Some(name);
+            }
+            "--init" => {
+                let path = try!(args.next()
+                                    .ok_or(Error::Usage("`--init` requires a file".to_owned())));
+                init = Some(path);
+            }
+            "--no-init" => no_init = true,
+            "-O" => optimize = true,
+            "--no-inline" => no_inline = true,
+            "-o" => {
+                let path = try!(args.next().ok_or(Error::Usage("`-o` requires a file".to_owned())));
+                output = Some(path);
+            }
+            _ => file = Some(arg),
+        }
+    }
+    if optimize {
+        options.opt = OptConfig { inline: !no_inline, ..OptConfig::default() };
+    }
+    let entry = entry.as_ref().map(String::as_ref);
+    if let Some((name, what)) = subcommand {
+        let path = try!(file.ok_or(Error::Usage(format!("`{}` requires {}", name, what))));
+        return match name.as_ref() {
+            "test" => run_tests(&path, options),
+            "script" => run_script(&path, options),
+            "compile" => compile_file(&path, output.as_ref().map(String::as_ref), entry, options),
+            "build" => build_file(&path, output.as_ref().map(String::as_ref), target, entry, options),
+            "run" => run_bytecode(&path, options),
+            "fmt" => format_file(&path, options),
+            "profile" => profile_file(&path, entry, options),
+            _ => debug_file(&path, entry, options),
+        };
+    }
+    if let Some(stage) = emit_stage {
+        let path = file.as_ref().map_or("-", String::as_ref);
+        let src = match expr {
+            Some(expr) => expr,
+            None => try!(read_file(path)),
+        };
+        return emit(path, &src, stage, entry, options);
+    }
+    if let Some(expr) = expr {
+        return exec_source("-", &expr, entry, options);
+    }
+    match file {
+        Some(file) => exec_file(&file, entry, options),
+        None if !io::stdin().is_terminal() => exec_file("-", entry, options),
+        None => start_repl(options, init.as_ref().map(String::as_ref), no_init),
+    }
 }
 
 fn main() {
-    let mut args = std::env::args();
-    args.next().unwrap();
-    if let Some(file) = args.next() {
-        exec_file(&file)
-    } else {
-        start_repl()
+    let mut options = Options {
+        frontend: Frontend::Lalrpop,
+        time: false,
+        show_type: false,
+        color: ColorChoice::Auto,
+        message_format: MessageFormat::Human,
+        quiet: false,
+        opt: OptConfig::none(),
+    };
+    if let Err(e) = run(&mut options) {
+        eprintln!("{}", Renderer::stderr(&options).error(&e));
+        process::exit(e.exit_code());
     }
 }
@@ -0,0 +1,344 @@
+//! Optimizations of the IR, run between desugaring and compilation. Each one
+//! is a `Pass`, and `OptConfig` says which of them `optimize` runs.
+
+use ir::{Ir, BinOp, UnOp, Construct, Record, Field, Case, If, Seq, Fun, LetRec, Apply, Name};
+use plugin::Pass;
+
+/// Which passes `optimize` runs. The default runs all of them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OptConfig {
+    pub inline: bool,
+    /// The largest function body, in IR nodes, that `Inline` copies into its callers.
+    pub inline_threshold: usize,
+}
+
+impl OptConfig {
+    /// Runs no passes at all.
+    pub fn none() -> OptConfig {
+        OptConfig { inline: false, ..OptConfig::default() }
+    }
+}
+
+impl Default for OptConfig {
+    fn default() -> OptConfig {
+        OptConfig {
+            inline: true,
+            inline_threshold: 20,
+        }
+    }
+}
+
+pub fn optimize(ir: Ir, config: &OptConfig) -> Ir {
+    let mut passes: Vec<Box<dyn Pass>> = Vec::new();
+    if config.inline {
+        passes.push(Box::new(Inline { threshold: config.inline_threshold }));
+    }
+    passes.iter().fold(ir, |ir, pass| pass.run(ir))
+}
+
+/// Replaces an application of a non-recursive function to a variable, a
+/// literal or a small non-recursive function with the body of the function,
+/// so that `let x = 1 in ...` and trivial `let fun` helpers cost neither a
+/// closure nor a call. Other arguments may fail or never finish, so they are
+/// still evaluated before the call.
+pub struct Inline {
+    pub threshold: usize,
+}
+
+impl Pass for Inline {
+    fn name(&self) -> &str {
+        "inline"
+    }
+
+    fn run(&self, ir: Ir) -> Ir {
+        self.inline(ir)
+    }
+}
+
+impl Inline {
+    fn inline(&self, ir: Ir) -> Ir {
+        match map_children(ir, |child| self.inline(child)) {
+            Ir::Apply(apply) => {
+                let Apply { fun, arg } = *apply;
+                if let Ir::Fun(ref fun) = fun {
+                    if self.can_inline(fun, &arg) {
+                        // The body may now apply `arg` itself.
+                        return self.inline(substitute(fun.body.clone(), fun.arg_name, &arg));
+                    }
+                }
+                Apply {
+                    fun: fun,
+                    arg: arg,
+                }
+                .into()
+            }
+            ir => ir,
+        }
+    }
+
+    fn can_inline(&self, fun: &Fun, arg: &Ir) -> bool {
+        let trivial = match *arg {
+            Ir::Var(_) | Ir::IntLiteral(_) | Ir::FloatLiteral(_) | Ir::BoolLiteral(_) | Ir::Unit |
+            Ir::Nil => true,
+            Ir::Fun(ref arg) => !is_free(arg.fun_name, &arg.body) && size(&arg.body) <= self.threshold,
+            _ => false,
+        };
+        trivial && !is_free(fun.fun_name, &fun.body) && !captures(&fun.body, fun.arg_name, arg)
+    }
+}
+
+/// Number of nodes in `ir`.
+fn size(ir: &Ir) -> usize {
+    1 + children(ir).into_iter().map(size).sum::<usize>()
+}
+
+fn is_free(name: Name, ir: &Ir) -> bool {
+    match *ir {
+        Ir::Var(var) => var == name,
+        Ir::Fun(ref fun) => fun.fun_name != name && fun.arg_name != name && is_free(name, &fun.body),
+        Ir::LetRec(ref let_rec) => {
+            !binds(let_rec, name) &&
+            (let_rec.funs.iter().any(|fun| fun.arg_name != name && is_free(name, &fun.body)) ||
+             is_free(name, &let_rec.body))
+        }
+        _ => children(ir).into_iter().any(|child| is_free(name, child)),
+    }
+}
+
+/// Whether a function in `ir` binds a free variable of `value` around a free
+/// occurrence of `name`, so that `value` can't be put in place of `name`.
+fn captures(ir: &Ir, name: Name, value: &Ir) -> bool {
+    match *ir {
+        Ir::Fun(ref fun) if fun.fun_name == name || fun.arg_name == name => false,
+        Ir::Fun(ref fun) => {
+            let binds = is_free(fun.fun_name, value) || is_free(fun.arg_name, value);
+            binds && is_free(name, &fun.body) || captures(&fun.body, name, value)
+        }
+        Ir::LetRec(ref let_rec) if binds(let_rec, name) => false,
+        Ir::LetRec(ref let_rec) => {
+            let binds = let_rec.funs.iter().any(|fun| is_free(fun.fun_name, value));
+            let_rec.funs.iter().any(|fun| {
+                if fun.arg_name == name {
+                    return false;
+                }
+                let binds = binds || is_free(fun.arg_name, value);
+                binds && is_free(name, &fun.body) || captures(&fun.body, name, value)
+            }) || binds && is_free(name, &let_rec.body) || captures(&let_rec.body, name, value)
+        }
+        _ => children(ir).into_iter().any(|child| captures(child, name, value)),
+    }
+}
+
+/// Whether `name` is one of the functions of `let_rec`.
+fn binds(let_rec: &LetRec, name: Name) -> bool {
+    let_rec.funs.iter().any(|fun| fun.fun_name == name)
+}
+
+/// `ir` with the free occurrences of `name` replaced by `value`, which must
+/// not be captured, see `captures`.
+fn substitute(ir: Ir, name: Name, value: &Ir) -> Ir {
+    match ir {
+        Ir::Var(var) if var == name => value.clone(),
+        Ir::Fun(fun) => {
+            if fun.fun_name == name || fun.arg_name == name {
+                return Ir::Fun(fun);
+            }
+            let Fun { fun_name, arg_name, body } = *fun;
+            Fun {
+                fun_name: fun_name,
+                arg_name: arg_name,
+                body: substitute(body, name, value),
+            }
+            .into()
+        }
+        Ir::LetRec(ref let_rec) if binds(let_rec, name) => ir,
+        Ir::LetRec(let_rec) => {
+            let LetRec { funs, body } = *let_rec;
+            let funs = funs.into_iter().map(|fun| {
+                if fun.arg_name == name {
+                    return fun;
+                }
+                let Fun { fun_name, arg_name, body } = fun;
+                Fun {
+                    fun_name: fun_name,
+                    arg_name: arg_name,
+                    body: substitute(body, name, value),
+                }
+            });
+            LetRec {
+                funs: funs.collect(),
+                body: substitute(body, name, value),
+            }
+            .into()
+        }
+        ir => map_children(ir, |child| substitute(child, name, value)),
+    }
+}
+
+fn children(ir: &Ir) -> Vec<&Ir> {
+    match *ir {
+        Ir::Var(_) | Ir::IntLiteral(_) | Ir::FloatLiteral(_) | Ir::BoolLiteral(_) | Ir::StrLiteral(_) | Ir::Unit |
+        Ir::Nil => vec![],
+        Ir::BinOp(ref op) => vec![&op.lhs, &op.rhs],
+        Ir::UnOp(ref op) => vec![&op.arg],
+        Ir::Construct(ref c) => c.arg.iter().collect(),
+        Ir::Record(ref record) => record.fields.iter().map(|field| &field.1).collect(),
+        Ir::Field(ref field) => vec![&field.record],
+        Ir::Case(ref case) => Some(&case.scrutinee).into_iter().chain(&case.arms).collect(),
+        Ir::If(ref if_) => vec![&if_.cond, &if_.tru, &if_.fls],
+        Ir::Seq(ref seq) => vec![&seq.first, &seq.second],
+        Ir::Fun(ref fun) => vec![&fun.body],
+        Ir::LetRec(ref let_rec) => {
+            let_rec.funs.iter().map(|fun| &fun.body).chain(Some(&let_rec.body)).collect()
+        }
+        Ir::Apply(ref apply) => vec![&apply.fun, &apply.arg],
+    }
+}
+
+fn map_children<F: FnMut(Ir) -> Ir>(ir: Ir, mut f: F) -> Ir {
+    match ir {
+        Ir::BinOp(op) => {
+            let BinOp { lhs, rhs, kind } = *op;
+            BinOp {
+                lhs: f(lhs),
+                rhs: f(rhs),
+                kind: kind,
+            }
+            .into()
+        }
+        Ir::UnOp(op) => {
+            let UnOp { arg, kind } = *op;
+            UnOp {
+                arg: f(arg),
+                kind: kind,
+            }
+            .into()
+        }
+        Ir::Construct(c) => {
+            let Construct { tag, name, arg } = *c;
+            Construct {
+                tag: tag,
+                name: name,
+                arg: arg.map(&mut f),
+            }
+            .into()
+        }
+        Ir::Record(record) => {
+            let fields = record.fields.into_iter().map(|(name, value)| (name, f(value)));
+            Record { fields: fields.collect() }.into()
+        }
+        Ir::Field(field) => {
+            let Field { record, name } = *field;
+            Field {
+                record: f(record),
+                name: name,
+            }
+            .into()
+        }
+        Ir::Case(case) => {
+            let Case { scrutinee, arms } = *case;
+            Case {
+                scrutinee: f(scrutinee),
+                arms: arms.into_iter().map(&mut f).collect(),
+            }
+            .into()
+        }
+        Ir::If(if_) => {
+            let If { cond, tru, fls } = *if_;
+            If {
+                cond: f(cond),
+                tru: f(tru),
+                fls: f(fls),
+            }
+            .into()
+        }
+        Ir::Seq(seq) => {
+            let Seq { first, second } = *seq;
+            Seq {
+                first: f(first),
+                second: f(second),
+            }
+            .into()
+        }
+        Ir::Fun(fun) => {
+            let Fun { fun_name, arg_name, body } = *fun;
+            Fun {
+                fun_name: fun_name,
+                arg_name: arg_name,
+                body: f(body),
+            }
+            .into()
+        }
+        Ir::LetRec(let_rec) => {
+            let LetRec { funs, body } = *let_rec;
+            let funs = funs.into_iter().map(|fun| {
+                let Fun { fun_name, arg_name, body } = fun;
+                Fun {
+                    fun_name: fun_name,
+                    arg_name: arg_name,
+                    body: f(body),
+                }
+            });
+            LetRec {
+                funs: funs.collect(),
+                body: f(body),
+            }
+            .into()
+        }
+        Ir::Apply(apply) => {
+            let Apply { fun, arg } = *apply;
+            Apply {
+                fun: f(fun),
+                arg: f(arg),
+            }
+            .into()
+        }
+        ir => ir,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use compile::compile_ir;
+    use ir::desugar;
+    use secd::{Machine, Value};
+    use syntax::parse;
+    use super::{OptConfig, optimize};
+
+    fn optimized(src: &str) -> String {
+        format!("{:?}", optimize(desugar(&parse(src).unwrap()), &OptConfig::default()))
+    }
+
+    #[test]
+    fn test_inline() {
+        assert_eq!(optimized("let x = 1 in x + x"), "(+ 1 1)");
+        assert_eq!(optimized("let fun inc(x: int): int is x + 1 in inc 1 + inc 2"), "(+ (+ 1 1) (+ 2 1))");
+        // The argument might fail, so it is still evaluated first.
+        assert_eq!(optimized("let x = 1 / 0 in 92"), "((λ v1 (v0) 92) (/ 1 0))");
+        assert_eq!(optimized("let fun f(x: int): int is f x in f 1"), "((λ v1 (v0) (v0 1)) (λ v0 (v2) (v0 v2)))");
+        // `f` can't be copied under the inner `y`, which would capture its outer `y`.
+        assert_eq!(optimized("let y = 1 / 1 in let fun f(z: int): int is y + z in let y = 2 / 1 in f y"),
+                   "((λ v1 (v0) ((λ v1 (v2) ((λ v1 (v0) (v2 v0)) (/ 2 1))) (λ v2 (v4) (+ v0 v4)))) (/ 1 1))");
+        assert_eq!(optimized("let x = 1 in let rec fun f(n: int): int is if n == 0 then x else f (n - 1) in f 2"),
+                   "(letrec [(λ v2 (v4) (if (== v4 0) 1 (v2 (- v4 1))))] (v2 2))");
+    }
+
+    #[test]
+    fn test_inline_preserves_results() {
+        let programs = [
+            ("let y = 1 in let fun f(z: int): int is y + z in let y = 2 in f y", 3),
+            ("let y = 1 / 1 in let fun f(z: int): int is y + z in let y = 2 / 1 in f y", 3),
+            ("let fun twice(x: int): int is 2 * x in let fun add(x: int): int is x + twice x in add 30", 90),
+            ("let rec fun even(n: int): bool is if n == 0 then true else odd (n - 1) \
+              and fun odd(n: int): bool is if n == 0 then false else even (n - 1) \
+              in if even 10 then 92 else 0", 92),
+        ];
+        for &(src, expected) in programs.iter() {
+            let expr = parse(src).unwrap();
+            for config in &[OptConfig::none(), OptConfig::default()] {
+                let frame = compile_ir(&optimize(desugar(&expr), config));
+                assert_eq!(Machine::new(&frame).exec().unwrap(), Value::Int(expected), "{}", src);
+            }
+        }
+    }
+}
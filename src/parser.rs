@@ -0,0 +1,59 @@
+//! The two parsers of miniml, which accept the same language and build the
+//! same AST: the one generated by LALRPOP, which `parse` and the rest of the
+//! library use, and the hand-written one in `syntax_ll`.
+
+use ast::{Decl, Expr, Program};
+use error::ParseError;
+use syntax;
+use syntax_ll;
+
+/// Which parser reads source text, with both reporting errors as `ParseError`:
+///
+/// ```
+/// use miniml::Parser;
+///
+/// let src = "let fun inc(x: int): int is x + 1;; inc 1 -- two";
+/// let lalrpop = Parser::Lalrpop.parse_program(src).unwrap();
+/// let ll = Parser::Ll.parse_program(src).unwrap();
+/// assert_eq!(format!("{:?}", lalrpop), format!("{:?}", ll));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Parser {
+    Lalrpop,
+    Ll,
+}
+
+impl Parser {
+    pub fn parse(self, src: &str) -> Result<Expr, ParseError> {
+        match self {
+            Parser::Lalrpop => {
+                syntax::parse(&syntax::strip_comments(src)).map_err(|e| ParseError::from_lalrpop(e, src))
+            }
+            Parser::Ll => syntax_ll::parse(src).map_err(ParseError::from_ll),
+        }
+    }
+
+    pub fn parse_program(self, src: &str) -> Result<Program, ParseError> {
+        match self {
+            Parser::Lalrpop => {
+                syntax::parse_program(&syntax::strip_comments(src)).map_err(|e| ParseError::from_lalrpop(e, src))
+            }
+            Parser::Ll => syntax_ll::parse_program(src).map_err(ParseError::from_ll),
+        }
+    }
+
+    pub fn parse_decl(self, src: &str) -> Result<Decl, ParseError> {
+        match self {
+            Parser::Lalrpop => {
+                syntax::parse_decl(&syntax::strip_comments(src)).map_err(|e| ParseError::from_lalrpop(e, src))
+            }
+            Parser::Ll => syntax_ll::parse_decl(src).map_err(ParseError::from_ll),
+        }
+    }
+}
+
+impl Default for Parser {
+    fn default() -> Parser {
+        Parser::Lalrpop
+    }
+}
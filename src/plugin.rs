@@ -0,0 +1,245 @@
+//! Extension points for downstream crates: extra IR passes, run between
+//! desugaring and compilation, and builtins implemented in Rust.
+
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use ast::{self, Expr, Ident, Program, Symbol};
+use compile::compile_ir;
+use ir::{Ir, Name, desugar_in, desugar_program_in, name_in};
+use secd::{self, Frame, Machine, NativeFn, RuntimeErrorKind, Value};
+use typecheck::{self, Type, typecheck_in, typecheck_program_in};
+
+/// A transformation of the IR. It must preserve the meaning of the program.
+pub trait Pass {
+    fn name(&self) -> &str;
+    fn run(&self, ir: Ir) -> Ir;
+}
+
+struct Builtin {
+    name: &'static str,
+    type_: Type,
+    arity: usize,
+    fun: Rc<NativeBody>,
+}
+
+type NativeBody = dyn for<'p> Fn(&[Value<'p>]) -> secd::Result<Value<'p>>;
+
+/// Passes and builtins to compile and run expressions with:
+///
+/// ```
+/// extern crate ast;
+/// extern crate miniml;
+///
+/// use ast::Type;
+/// use miniml::{Plugins, Value};
+///
+///
+/// fn double<'p>(x: Value<'p>) -> Result<Value<'p>, String> {
+///     match x {
+///         Value::Int(x) => Ok(Value::Int(x * 2)),
+///         _ => Err("expected an int".to_owned()),
+///     }
+/// }
+///
+/// # fn main() {
+/// let plugins = Plugins::new().builtin("double", Type::int().arrow(Type::int()), double);
+/// let expr = miniml::parse("double 46").unwrap();
+/// plugins.typecheck(&expr).unwrap();
+/// let frame = plugins.compile(&expr);
+/// assert_eq!(plugins.machine(&frame).exec().unwrap(), Value::Int(92));
+/// # }
+/// ```
+pub struct Plugins {
+    passes: Vec<Box<dyn Pass>>,
+    builtins: Vec<Builtin>,
+}
+
+impl Plugins {
+    pub fn new() -> Plugins {
+        Plugins {
+            passes: Vec::new(),
+            builtins: Vec::new(),
+        }
+    }
+
+    /// Adds a pass to run after the ones added before.
+    pub fn pass<P: Pass + 'static>(mut self, pass: P) -> Plugins {
+        self.passes.push(Box::new(pass));
+        self
+    }
+
+    /// Makes `name` a function of type `type_` implemented by `fun`.
+    pub fn builtin(self, name: &'static str, type_: ast::Type, fun: NativeFn) -> Plugins {
+        self.native(name, type_, 1, move |args| fun(args[0]).map_err(|e| RuntimeErrorKind::Native(e).into()))
+    }
+
+    /// Makes `name` a function of type `type_` which takes `arity`
+    /// arguments and passes all of them to `fun`, see `Machine::register_native`.
+    /// `fun` may keep state, like a random number generator.
+    pub fn native<F>(mut self, name: &'static str, type_: ast::Type, arity: usize, fun: F) -> Plugins
+        where F: for<'p> Fn(&[Value<'p>]) -> secd::Result<Value<'p>> + 'static
+    {
+        self.builtins.push(Builtin {
+            name: name,
+            type_: Type::from(&type_),
+            arity: arity,
+            fun: Rc::new(fun),
+        });
+        self
+    }
+
+    /// Typechecks `expr` with the builtins in scope.
+    pub fn typecheck(&self, expr: &Expr) -> typecheck::Result {
+        typecheck_in(&self.env(), expr)
+    }
+
+    /// Typechecks `program` with the builtins in scope.
+    pub fn typecheck_program(&self, program: &Program) -> typecheck::Result {
+        typecheck_program_in(&self.env(), program)
+    }
+
+    /// Desugars `expr`, runs the passes over it and compiles the result.
+    /// Run it on a machine from `machine`, which knows the builtins.
+    pub fn compile(&self, expr: &Expr) -> Frame {
+        let mut names = HashMap::new();
+        self.builtin_names(&mut names);
+        let ir = desugar_in(&mut names, expr);
+        self.finish(ir)
+    }
+
+    /// Like `compile`, for a whole program.
+    pub fn compile_program(&self, program: &Program) -> Frame {
+        let mut names = HashMap::new();
+        self.builtin_names(&mut names);
+        let ir = desugar_program_in(&mut names, program);
+        self.finish(ir)
+    }
+
+    /// A machine to run `program` with the builtins defined.
+    pub fn machine<'p>(&self, program: &'p Frame) -> Machine<'p> {
+        let mut machine = Machine::new(program);
+        for (name, builtin) in self.builtin_names(&mut HashMap::new()).into_iter().zip(&self.builtins) {
+            let fun = builtin.fun.clone();
+            machine.register_native(name, builtin.name, builtin.arity, move |args| fun(args));
+        }
+        machine
+    }
+
+    fn env(&self) -> Vec<(Ident, Type)> {
+        self.builtins
+            .iter()
+            .map(|builtin| (Ident::from_str(builtin.name), builtin.type_.clone()))
+            .collect()
+    }
+
+    fn finish(&self, ir: Ir) -> Frame {
+        let ir = self.passes.iter().fold(ir, |ir, pass| pass.run(ir));
+        compile_ir(&ir)
+    }
+
+    fn builtin_names(&self, names: &mut HashMap<Symbol, Name>) -> Vec<Name> {
+        self.builtins.iter().map(|builtin| name_in(names, Symbol::intern(builtin.name))).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    use ast::Type;
+    use ir::{Ir, BinOp, BinOpKind, If, Apply};
+    use secd::Value;
+    use syntax::{parse, parse_program};
+    use super::{Pass, Plugins};
+
+    /// Folds additions of literals outside of functions.
+    struct FoldAdd;
+
+    impl Pass for FoldAdd {
+        fn name(&self) -> &str {
+            "fold-add"
+        }
+
+        fn run(&self, ir: Ir) -> Ir {
+            match ir {
+                Ir::BinOp(op) => {
+                    let BinOp { lhs, rhs, kind } = *op;
+                    match (self.run(lhs), self.run(rhs), kind) {
+                        (Ir::IntLiteral(l), Ir::IntLiteral(r), BinOpKind::Add) => Ir::IntLiteral(l + r),
+                        (lhs, rhs, kind) => {
+                            BinOp {
+                                lhs: lhs,
+                                rhs: rhs,
+                                kind: kind,
+                            }
+                            .into()
+                        }
+                    }
+                }
+                Ir::If(if_) => {
+                    let If { cond, tru, fls } = *if_;
+                    If {
+                        cond: self.run(cond),
+                        tru: self.run(tru),
+                        fls: self.run(fls),
+                    }
+                    .into()
+                }
+                Ir::Apply(apply) => {
+                    let Apply { fun, arg } = *apply;
+                    Apply {
+                        fun: self.run(fun),
+                        arg: self.run(arg),
+                    }
+                    .into()
+                }
+                ir => ir,
+            }
+        }
+    }
+
+    fn is_even<'p>(n: Value<'p>) -> Result<Value<'p>, String> {
+        n.into_int().map(|n| Value::Bool(n % 2 == 0)).map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn test_plugins() {
+        let plugins = Plugins::new()
+                          .pass(FoldAdd)
+                          .builtin("is_even", Type::int().arrow(Type::bool()), is_even);
+        let expr = parse("if is_even (40 + 2) then 1 + 2 + 3 else 0").unwrap();
+        assert_eq!(format!("{:?}", plugins.typecheck(&expr).unwrap()), "int");
+        let frame = plugins.compile(&expr);
+        assert!(frame.iter().any(|inst| inst.to_string() == "push 42"));
+        assert_eq!(plugins.machine(&frame).exec().unwrap(), Value::Int(6));
+
+        assert!(plugins.typecheck(&parse("is_even true").unwrap()).is_err());
+        assert!(Plugins::new().typecheck(&expr).is_err());
+    }
+
+    #[test]
+    fn test_natives() {
+        let rolls = Rc::new(Cell::new(0));
+        let counter = rolls.clone();
+        let plugins = Plugins::new()
+                          .native("max", Type::int().arrow(Type::int().arrow(Type::int())), 2, |args| {
+                              Ok(Value::Int(::std::cmp::max(try!(args[0].into_int()), try!(args[1].into_int()))))
+                          })
+                          .native("roll", Type::unit().arrow(Type::int()), 1, move |_| {
+                              counter.set(counter.get() + 1);
+                              Ok(Value::Int(counter.get() % 6 + 1))
+                          });
+        let program = parse_program("let at_least_three = max 3;;
+                                     let fun roll_twice(u: unit): int is roll () + roll ();;
+                                     at_least_three (roll_twice ())")
+                          .unwrap();
+        assert_eq!(format!("{:?}", plugins.typecheck_program(&program).unwrap()), "int");
+        let frame = plugins.compile_program(&program);
+        assert_eq!(plugins.machine(&frame).exec().unwrap(), Value::Int(5));
+        assert_eq!(rolls.get(), 2);
+
+        assert!(plugins.typecheck(&parse("max 1 true").unwrap()).is_err());
+    }
+}
@@ -0,0 +1,64 @@
+use std::collections::HashMap;
+
+use miniml::{Frame, NameTable, Profile};
+
+/// Where the steps of a run of `program` went, the largest share first:
+/// how many there were, then the share of each function, like
+/// "73.0% of steps inside `fib`", then the share of each opcode.
+pub fn report(program: &Frame, profile: &Profile, names: &NameTable) -> String {
+    let total = profile.steps();
+    let share = |steps: u64| 100.0 * steps as f64 / total.max(1) as f64;
+    let mut lines = vec![format!("{} steps", total)];
+
+    // Functions with the same source name, like local helpers called `go`,
+    // are counted together.
+    let mut functions = HashMap::new();
+    for (name, steps) in profile.functions(0, program) {
+        let place = match name.map(|name| names.symbol(name)) {
+            None => "at the top level".to_owned(),
+            Some(Some(symbol)) => format!("inside `{}`", symbol),
+            Some(None) => "inside anonymous functions".to_owned(),
+        };
+        *functions.entry(place).or_insert(0) += steps;
+    }
+    for (steps, place) in largest_first(functions) {
+        lines.push(format!("{:>5.1}% of steps {}", share(steps), place));
+    }
+
+    lines.push("by instruction:".to_owned());
+    let opcodes = profile.opcodes.iter().map(|(&opcode, &steps)| (opcode.to_owned(), steps)).collect();
+    for (steps, opcode) in largest_first(opcodes) {
+        lines.push(format!("{:>5.1}% {}", share(steps), opcode));
+    }
+    lines.join("\n")
+}
+
+/// Sorted by the number of steps, then by name, so that reports don't
+/// depend on the order of a `HashMap`.
+fn largest_first(steps: HashMap<String, u64>) -> Vec<(u64, String)> {
+    let mut steps = steps.into_iter().map(|(name, steps)| (steps, name)).collect::<Vec<_>>();
+    steps.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+    steps
+}
+
+#[cfg(test)]
+mod tests {
+    use miniml::{Machine, compile_ir, desugar_program_with_names, parse_program};
+
+    use super::report;
+
+    #[test]
+    fn test_report() {
+        let src = "let rec fun fib(n: int): int is if n < 2 then n else fib (n - 1) + fib (n - 2);; fib 10";
+        let (ir, names) = desugar_program_with_names(&parse_program(src).unwrap());
+        let frame = compile_ir(&ir);
+        let mut machine = Machine::new(&frame).with_profiling();
+        machine.exec().unwrap();
+        let report = report(&frame, machine.profile().unwrap(), &names);
+        let lines = report.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], format!("{} steps", machine.steps()));
+        assert!(lines[1].ends_with("% of steps inside `fib`"), "{}", report);
+        assert!(lines.contains(&"by instruction:"), "{}", report);
+        assert!(lines.iter().any(|line| line.ends_with("% call")), "{}", report);
+    }
+}
@@ -0,0 +1,208 @@
+use std::{env, fmt, mem};
+use std::path::{Path, PathBuf};
+
+use miniml;
+use rustyline::{self, Context, Editor, Helper};
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+
+const KEYWORDS: &'static [&'static str] = &["if", "then", "else", "fun", "is", "let", "rec",
+                                            "and", "in", "true", "false", "int", "bool", "string",
+                                            "unit", "not"];
+
+/// Keywords an input can't end with.
+const CONTINUED: &'static [&'static str] = &["if", "then", "else", "fun", "is", "let", "rec", "and",
+                                             "in", "match", "with", "type", "of", "not"];
+
+/// With `banner` off, only results and errors are printed.
+///
+/// An incomplete input, see `is_incomplete`, is continued on the next line
+/// after a `... ` prompt. An empty line submits it as it is, Ctrl-C drops it
+/// and Ctrl-D quits. Inputs are saved to `history` on the way out.
+pub fn run<F, E>(prompt: &str, banner: bool, history: Option<&Path>, mut f: F)
+    where F: FnMut(&str) -> Result<String, E>,
+          E: fmt::Display
+{
+    let mut editor: Editor<ReplHelper, DefaultHistory> = match Editor::new() {
+        Ok(editor) => editor,
+        Err(e) => return eprintln!("Error: can't start the repl: {}", e),
+    };
+    editor.set_helper(Some(ReplHelper));
+    if let Some(history) = history {
+        // There is no history before the first session.
+        let _ = editor.load_history(history);
+    }
+    if banner {
+        println!("Hello! Type :q to quit");
+    }
+    let continuation = if prompt.is_empty() { "" } else { "... " };
+    let mut input = String::new();
+    loop {
+        let line = match editor.readline(if input.is_empty() { prompt } else { continuation }) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => {
+                input.clear();
+                continue;
+            }
+            Err(_) => break,
+        };
+        if input.is_empty() && line.starts_with(":q") {
+            break;
+        }
+        input.push_str(&line);
+        input.push('\n');
+        if !line.trim().is_empty() && is_incomplete(&input) {
+            continue;
+        }
+        if !input.trim().is_empty() {
+            let _ = editor.add_history_entry(input.trim_end());
+        }
+        match f(&mem::replace(&mut input, String::new())) {
+            Ok(ref result) if result.is_empty() => {}
+            Ok(result) => println!("{}", result),
+            Err(e) => eprintln!("{}", e),
+        }
+    }
+    if let Some(history) = history {
+        if let Err(e) = editor.save_history(history) {
+            eprintln!("Error: can't save the history to `{}`: {}", history.display(), e);
+        }
+    }
+    if banner {
+        println!("Bye!");
+    }
+}
+
+/// `~/.miniml_history`, if there is a home directory.
+pub fn history_path() -> Option<PathBuf> {
+    env::var_os("HOME").map(|home| PathBuf::from(home).join(".miniml_history"))
+}
+
+struct ReplHelper;
+
+impl Completer for ReplHelper {
+    type Candidate = String;
+
+    fn complete(&self,
+                line: &str,
+                pos: usize,
+                _: &Context)
+                -> rustyline::Result<(usize, Vec<String>)> {
+        let prefix_len = line[..pos]
+                             .chars()
+                             .rev()
+                             .take_while(|&c| is_ident_char(c))
+                             .map(char::len_utf8)
+                             .sum::<usize>();
+        let start = pos - prefix_len;
+        Ok((start, completions(&line[start..pos], &[&line[..start], &line[pos..]])))
+    }
+}
+
+impl Hinter for ReplHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ReplHelper {}
+
+impl Validator for ReplHelper {}
+
+impl Helper for ReplHelper {}
+
+/// Keywords and identifiers mentioned elsewhere in the input that start with `prefix`.
+fn completions(prefix: &str, context: &[&str]) -> Vec<String> {
+    if prefix.is_empty() {
+        return Vec::new();
+    }
+    let idents = context.iter().flat_map(|text| {
+        text.split(|c: char| !is_ident_char(c))
+            .filter(|word| word.starts_with(|c: char| c.is_alphabetic() || c == '_'))
+    });
+    let mut result = KEYWORDS.iter()
+                             .cloned()
+                             .chain(idents)
+                             .filter(|word| word.starts_with(prefix) && *word != prefix)
+                             .map(String::from)
+                             .collect::<Vec<_>>();
+    result.sort();
+    result.dedup();
+    result
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Whether `input` needs more lines: it has unclosed parentheses or
+/// brackets, or ends with a keyword or an operator that must be followed by
+/// something. Meta-commands are always complete.
+fn is_incomplete(input: &str) -> bool {
+    if input.trim_start().starts_with(':') {
+        return false;
+    }
+    let input = miniml::strip_comments(input);
+    let mut depth = 0i32;
+    let mut in_string = false;
+    let mut escaped = false;
+    let mut code = String::with_capacity(input.len());
+    for c in input.chars() {
+        if in_string {
+            in_string = escaped || c != '"';
+            escaped = !escaped && c == '\\';
+            code.push(' ');
+            continue;
+        }
+        match c {
+            '"' => in_string = true,
+            '(' | '[' => depth += 1,
+            ')' | ']' => depth -= 1,
+            _ => {}
+        }
+        code.push(c);
+    }
+    if in_string || depth > 0 {
+        return true;
+    }
+    match code.split_whitespace().last() {
+        // `;;` ends a declaration, but a single `;` needs the next expression.
+        Some(last) if last.ends_with(';') => !last.ends_with(";;"),
+        Some(last) => CONTINUED.contains(&last) || last.ends_with(|c| "=|&+-*/:<>".contains(c)),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{completions, is_incomplete};
+
+    #[test]
+    fn test_completions() {
+        assert_eq!(completions("th", &[]), vec!["then"]);
+        assert_eq!(completions("i", &[]), vec!["if", "in", "int", "is"]);
+        assert_eq!(completions("fi", &["let fun fib(n: int): int is n in ", " 10"]),
+                   vec!["fib"]);
+        assert_eq!(completions("x", &["let fun f(x: int): int is x1 + x_2 + 1x"]),
+                   vec!["x1", "x_2"]);
+        assert!(completions("", &["fib"]).is_empty());
+    }
+
+    #[test]
+    fn test_is_incomplete() {
+        assert!(is_incomplete("let rec fun f(x: int): int is\n"));
+        assert!(is_incomplete("if x then 1\nelse\n"));
+        assert!(is_incomplete("let x = 1 in\n"));
+        assert!(is_incomplete("f (1 +\n 2\n"));
+        assert!(is_incomplete("[1, 2 -- ]\n"));
+        assert!(is_incomplete("\"(\" ++\n"));
+        assert!(!is_incomplete("\"(\" ++ \"then\"\n"));
+        assert!(!is_incomplete("let x = 92;;\n"));
+        assert!(is_incomplete("r := 1;\n"));
+        assert!(!is_incomplete("1 + 1)\n"));
+        assert!(!is_incomplete(":type 1 +\n"));
+        assert!(!is_incomplete("x -- then\n"));
+    }
+}
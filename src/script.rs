@@ -0,0 +1,69 @@
+use std::fmt;
+
+/// Evaluates the statements of `src` one by one, printing a transcript:
+///
+/// ```text
+/// > let x = 90 + 2;;
+/// = x : int
+/// > x / 0;;
+/// ! Runtime error: Division by zero
+/// ```
+///
+/// In `quiet` mode only the results are printed, and errors go to stderr.
+pub fn run<F, E>(src: &str, quiet: bool, mut f: F)
+    where F: FnMut(&str) -> Result<String, E>,
+          E: fmt::Display
+{
+    for statement in statements(src) {
+        if quiet {
+            match f(&statement) {
+                Ok(ref result) if result.is_empty() => {}
+                Ok(result) => println!("{}", result),
+                Err(e) => eprintln!("{}", e),
+            }
+            continue;
+        }
+        let lines = statement.lines().filter(|line| !line.trim().is_empty());
+        for (i, line) in lines.enumerate() {
+            println!("{} {}", if i == 0 { ">" } else { " " }, line.trim_end());
+        }
+        match f(&statement) {
+            Ok(result) => {
+                for line in result.lines() {
+                    println!("= {}", line);
+                }
+            }
+            Err(e) => println!("! {}", e),
+        }
+    }
+}
+
+/// Splits `src` after each `;;`. The last statement may lack the terminator.
+pub fn statements(src: &str) -> Vec<String> {
+    let mut result = Vec::new();
+    let mut rest = src;
+    loop {
+        let end = rest.find(";;").map_or(rest.len(), |end| end + 2);
+        let statement = rest[..end].trim();
+        if !statement.is_empty() && statement != ";;" {
+            result.push(statement.to_owned());
+        }
+        if end == rest.len() {
+            return result;
+        }
+        rest = &rest[end..];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::statements;
+
+    #[test]
+    fn test_statements() {
+        assert_eq!(statements("let x = 1;;\n\nlet fun f(y: int): int is\n  y;; ;;\nf x\n"),
+                   vec!["let x = 1;;", "let fun f(y: int): int is\n  y;;", "f x"]);
+        assert_eq!(statements("1;;"), vec!["1;;"]);
+        assert!(statements(" \n").is_empty());
+    }
+}
@@ -0,0 +1,368 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+use ast::{self, Decl, Expr, ExprKind, Fun, Ident, Symbol};
+use compile::compile_ir;
+use error::Error;
+use eval::Stats;
+use ir::{Ir, NameTable, desugar_decl, desugar_in, name_in};
+use parser::Parser;
+use secd::{Frame, Machine, Name, Trace, Value, disassemble};
+use typecheck::{Type, typecheck_decl, typecheck_in};
+
+/// How many instructions of each input `:trace` shows.
+const TRACE_LIMIT: usize = 200;
+
+/// A line of REPL input: a declaration or an expression.
+pub enum Input {
+    Decl(Decl),
+    Expr(Expr),
+}
+
+/// Parses a line of REPL input. As in program files, declarations are
+/// terminated with `;;`. So may be expressions, to allow writing `1 + 1;;`
+/// like in a script.
+pub fn parse_input(input: &str) -> Result<Input, Error> {
+    let parser = Parser::default();
+    parse_input_with(input, |src| parser.parse(src), |src| parser.parse_decl(src)).map_err(Error::Parse)
+}
+
+/// `parse_input` with other parsers for expressions and declarations.
+pub fn parse_input_with<E, PE, PD>(input: &str, parse_expr: PE, parse_decl: PD) -> Result<Input, E>
+    where PE: Fn(&str) -> Result<Expr, E>,
+          PD: Fn(&str) -> Result<Decl, E>
+{
+    let input = input.trim();
+    if !input.ends_with(";;") {
+        return parse_expr(input).map(Input::Expr);
+    }
+    let expr = &input[..input.len() - 2];
+    if !input.starts_with("let") {
+        return parse_expr(expr).map(Input::Expr);
+    }
+    match parse_decl(input) {
+        Ok(decl) => Ok(Input::Decl(decl)),
+        // Could be a `let ... in ...;;` expression.
+        Err(e) => parse_expr(expr).map(Input::Expr).map_err(|_| e),
+    }
+}
+
+/// A command showing what an expression turns into, without running it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Inspect {
+    /// `:type expr`, its type.
+    Type,
+    /// `:ast expr`, the parsed expression.
+    Ast,
+    /// `:ir expr`, the desugared `Ir`.
+    Ir,
+    /// `:dis expr`, the compiled instructions.
+    Dis,
+}
+
+impl Inspect {
+    /// Splits `input` like `:type x + 1` into the command and the expression.
+    pub fn parse(input: &str) -> Option<(Inspect, &str)> {
+        let input = input.trim_start();
+        let command = input.split_whitespace().next().unwrap_or("");
+        let inspect = match command {
+            ":type" => Inspect::Type,
+            ":ast" => Inspect::Ast,
+            ":ir" => Inspect::Ir,
+            ":dis" => Inspect::Dis,
+            _ => return None,
+        };
+        Some((inspect, &input[command.len()..]))
+    }
+}
+
+/// What a session did with an input.
+pub enum Output {
+    /// A declaration, with the types of the names it defined.
+    Defined(Vec<(Ident, Type)>),
+    /// An expression, with its value and type.
+    Value(String, Type),
+    /// `:env`, with every binding, its type and its value.
+    Bindings(Vec<(Ident, Type, String)>),
+    /// `:trace`, with the new state.
+    Tracing(bool),
+    /// `:time`, with the new state.
+    Timing(bool),
+    /// `:type`, with the type of the expression.
+    Type(Type),
+    /// `:ast`, `:ir` or `:dis`, with the dump.
+    Dump(String),
+}
+
+/// The output as the REPL shows it, without colors.
+impl fmt::Display for Output {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Output::Defined(ref bindings) => {
+                let lines = bindings.iter()
+                                    .map(|&(ref name, ref type_)| format!("{} : {:?}", name, type_))
+                                    .collect::<Vec<_>>();
+                f.write_str(&lines.join("\n"))
+            }
+            Output::Value(ref value, _) => f.write_str(value),
+            Output::Bindings(ref bindings) if bindings.is_empty() => f.write_str("No bindings"),
+            Output::Bindings(ref bindings) => {
+                let lines = bindings.iter()
+                                    .map(|&(ref name, ref type_, ref value)| {
+                                        format!("{} : {:?} = {}", name, type_, value)
+                                    })
+                                    .collect::<Vec<_>>();
+                f.write_str(&lines.join("\n"))
+            }
+            Output::Tracing(on) => write!(f, "Tracing is {}", if on { "on" } else { "off" }),
+            Output::Timing(on) => write!(f, "Timing is {}", if on { "on" } else { "off" }),
+            Output::Type(ref type_) => write!(f, "{:?}", type_),
+            Output::Dump(ref dump) => f.write_str(dump),
+        }
+    }
+}
+
+/// State of a REPL session: the types of the definitions made so far, the
+/// names the IR knows them by, and a machine holding their values.
+///
+/// ```
+/// use miniml::{Session, Stats};
+///
+/// let mut session = Session::new();
+/// session.execute("let x = 90;;", &mut Stats::default()).unwrap();
+/// let output = session.execute("x + 2", &mut Stats::default()).unwrap();
+/// assert_eq!(output.to_string(), "92");
+/// assert_eq!(session.execute(":env", &mut Stats::default()).unwrap().to_string(),
+///            "x : int = 90\nit : int = 92\n_1 : int = 92");
+/// ```
+pub struct Session {
+    env: Vec<(Ident, Type)>,
+    // Functions are shown by their signature rather than as `<closure>`.
+    signatures: HashMap<String, String>,
+    names: HashMap<Symbol, Name>,
+    machine: Machine<'static>,
+    results: usize,
+    tracing: bool,
+    timing: bool,
+    /// The code of each input, which the closures it defines point into.
+    /// It is declared after `machine`, so that it is dropped after it.
+    frames: Vec<Rc<Frame>>,
+}
+
+impl Session {
+    pub fn new() -> Session {
+        Session {
+            env: Vec::new(),
+            signatures: HashMap::new(),
+            names: HashMap::new(),
+            machine: Machine::empty(),
+            results: 0,
+            tracing: false,
+            timing: false,
+            frames: Vec::new(),
+        }
+    }
+
+    /// Handles a line of input: a meta-command, a declaration or an
+    /// expression. The time of each phase is recorded in `stats`; if tracing
+    /// is on, the executed instructions are available from `take_trace`.
+    pub fn execute(&mut self, input: &str, stats: &mut Stats) -> Result<Output, Error> {
+        if let Some(output) = self.command(input) {
+            return Ok(output);
+        }
+        if let Some((inspect, expr)) = Inspect::parse(input) {
+            let src = expr.trim();
+            let expr = try!(stats.time("parse", || Parser::default().parse(src)).map_err(Error::Parse));
+            return self.inspect(inspect, &expr);
+        }
+        let input = try!(stats.time("parse", || parse_input(input)));
+        self.run(input, stats)
+    }
+
+    /// Handles `input` if it is a meta-command: `:env`, `:trace` or `:time`.
+    pub fn command(&mut self, input: &str) -> Option<Output> {
+        match input.trim() {
+            ":env" => {
+                let bindings = self.bindings()
+                                   .into_iter()
+                                   .map(|(name, type_, value)| (name.clone(), type_.clone(), value))
+                                   .collect();
+                Some(Output::Bindings(bindings))
+            }
+            ":trace" => {
+                self.tracing = !self.tracing;
+                Some(Output::Tracing(self.tracing))
+            }
+            ":time" => {
+                self.timing = !self.timing;
+                Some(Output::Timing(self.timing))
+            }
+            _ => None,
+        }
+    }
+
+    /// Shows what `expr` turns into, typechecking it in the scope of the
+    /// session unless only its AST is asked for.
+    pub fn inspect(&self, inspect: Inspect, expr: &Expr) -> Result<Output, Error> {
+        if inspect == Inspect::Ast {
+            return Ok(Output::Dump(format!("{:?}", expr)));
+        }
+        let type_ = try!(typecheck_in(&self.env, expr).map_err(Error::Type));
+        // Desugaring binds the names of new variables, which mustn't leak
+        // into the session.
+        let ir = desugar_in(&mut self.names.clone(), expr);
+        Ok(match inspect {
+            Inspect::Type => Output::Type(type_),
+            Inspect::Ir => Output::Dump(format!("{:?}", ir)),
+            _ => Output::Dump(disassemble(&compile_ir(&ir))),
+        })
+    }
+
+    /// Evaluates an already parsed input. A function on its own, like
+    /// `fun inc(x: int): int is x + 1`, is defined under its name as if it
+    /// were a `let fun`.
+    pub fn run(&mut self, input: Input, stats: &mut Stats) -> Result<Output, Error> {
+        if self.tracing {
+            self.machine.start_trace(TRACE_LIMIT);
+        }
+        match input {
+            Input::Decl(decl) => self.define(&decl, stats).map(Output::Defined),
+            Input::Expr(Expr { kind: ExprKind::Fun(fun), .. }) => {
+                self.define(&Decl::Fun(*fun), stats).map(Output::Defined)
+            }
+            Input::Expr(expr) => self.eval(expr, stats).map(|(value, type_)| Output::Value(value, type_)),
+        }
+    }
+
+    /// Evaluates `decl` and adds its bindings to the session, returning their types.
+    pub fn define(&mut self, decl: &Decl, stats: &mut Stats) -> Result<Vec<(Ident, Type)>, Error> {
+        let bindings = try!(stats.time("typecheck", || typecheck_decl(&self.env, decl))
+                                 .map_err(Error::Type));
+        let names = &mut self.names;
+        let values = stats.time("desugar", || desugar_decl(names, decl));
+        for (name, ir) in values {
+            let value = try!(self.exec(&ir, stats));
+            self.machine.define(name, value);
+        }
+
+        let funs = match *decl {
+            Decl::Fun(ref fun) => vec![fun],
+            Decl::LetRec(ref funs) => funs.iter().collect(),
+            Decl::Value(..) => vec![],
+        };
+        for &(ref name, _) in &bindings {
+            self.signatures.remove(name.as_ref());
+        }
+        // The bindings of functions are in the order of the functions.
+        for (fun, &(_, ref type_)) in funs.into_iter().zip(&bindings) {
+            self.signatures.insert(fun.fun_name.to_string(), signature(fun, type_));
+        }
+
+        for &(ref name, ref type_) in &bindings {
+            self.bind(name.clone(), type_.clone());
+        }
+        Ok(bindings)
+    }
+
+    /// Evaluates `expr` and binds the result to `it` and to `_1`, `_2`, ...
+    /// for the first, second, ... result of the session.
+    pub fn eval(&mut self, expr: Expr, stats: &mut Stats) -> Result<(String, Type), Error> {
+        let it = Ident::from_str("it");
+        let (_, type_) = try!(self.define(&Decl::Value(it.clone(), expr), stats)).remove(0);
+        self.results += 1;
+        let numbered = Ident::from_str(&format!("_{}", self.results));
+        let value = self.machine.env()[&self.names[&it.symbol()]];
+        let name = name_in(&mut self.names, numbered.symbol());
+        self.machine.define(name, value);
+        self.signatures.remove(numbered.as_ref());
+        self.bind(numbered, type_.clone());
+        Ok((self.show(&it), type_))
+    }
+
+    /// Whether `:trace` is on.
+    pub fn tracing(&self) -> bool {
+        self.tracing
+    }
+
+    /// Whether `:time` is on. The session only keeps the setting; showing
+    /// the times recorded in `Stats` is up to the frontend.
+    pub fn timing(&self) -> bool {
+        self.timing
+    }
+
+    pub fn set_timing(&mut self, timing: bool) {
+        self.timing = timing;
+    }
+
+    /// The instructions executed for the last input, if tracing is on.
+    pub fn take_trace(&mut self) -> Option<Trace> {
+        self.machine.take_trace()
+    }
+
+    /// Every binding with its type and value, in the order of definition.
+    pub fn bindings(&self) -> Vec<(&Ident, &Type, String)> {
+        self.env
+            .iter()
+            .map(|&(ref name, ref type_)| (name, type_, self.show(name)))
+            .collect()
+    }
+
+    fn bind(&mut self, name: Ident, type_: Type) {
+        self.env.retain(|&(ref bound, _)| bound != &name);
+        self.env.push((name, type_));
+    }
+
+    fn show(&self, name: &Ident) -> String {
+        match self.signatures.get(name.as_ref()) {
+            Some(signature) => signature.clone(),
+            None => self.machine.show(self.machine.env()[&self.names[&name.symbol()]]),
+        }
+    }
+
+    fn exec(&mut self, ir: &Ir, stats: &mut Stats) -> Result<Value<'static>, Error> {
+        // Closures defined by an input point into its code, so the code has to
+        // live as long as the machine. It does: `frames` is dropped after it,
+        // and unlike a `Box`, an `Rc` may be moved while what it holds is
+        // borrowed. Nothing borrowed from the machine outlives the session, as
+        // `take_trace` borrows the session.
+        let code = Rc::new(stats.time("compile", || compile_ir(ir)));
+        let frame: &'static Frame = unsafe { &*Rc::as_ptr(&code) };
+        self.frames.push(code);
+        let gc_time = self.machine.gc_time();
+        let steps = self.machine.steps();
+        let machine = &mut self.machine;
+        let result = stats.time("execute", || machine.run(frame));
+        stats.phases.push(("gc", self.machine.gc_time() - gc_time));
+        stats.instructions += self.machine.steps() - steps;
+        result.map_err(|e| Error::Runtime(NameTable::new(&self.names).name_error(e)))
+    }
+}
+
+/// How a function defined in a session is shown, like `<fun add(x: int, y: int): int>`. The
+/// types left out in its header are shown as inferred, from `type_`, the type of the function.
+fn signature(fun: &Fun, type_: &Type) -> String {
+    let shown = |annotation: &ast::Type, inferred: &Type| {
+        match *annotation {
+            ast::Type::Infer => format!("{:?}", inferred),
+            ref annotation => format!("{:?}", annotation),
+        }
+    };
+    let mut type_ = type_;
+    let mut args = Vec::new();
+    for &(ref name, ref annotation) in &fun.args {
+        let (arg_type, ret_type) = match *type_ {
+            Type::Arrow(ref arg_type, ref ret_type) => (&**arg_type, &**ret_type),
+            _ => unreachable!("a function has an arrow type for each parameter"),
+        };
+        args.push(format!("{}: {}", name, shown(annotation, arg_type)));
+        type_ = ret_type;
+    }
+    format!("<fun {}({}): {}>", fun.fun_name, args.join(", "), shown(&fun.fun_type, type_))
+}
+
+impl Default for Session {
+    fn default() -> Session {
+        Session::new()
+    }
+}
@@ -0,0 +1,102 @@
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+/// What a test file declares about its own result via a comment directive:
+///
+/// ```text
+/// -- expect: 92
+/// -- expect error: Type error
+/// ```
+///
+/// `expect` compares the printed value, `expect error` looks for a fragment of
+/// the error message. Files without a directive just have to run successfully.
+#[derive(Debug, PartialEq, Eq)]
+enum Expectation {
+    Value(String),
+    Error(String),
+    Success,
+}
+
+fn expectation(src: &str) -> Expectation {
+    for line in src.lines() {
+        let line = line.trim();
+        if line.starts_with("-- expect error:") {
+            return Expectation::Error(line["-- expect error:".len()..].trim().to_owned());
+        }
+        if line.starts_with("-- expect:") {
+            return Expectation::Value(line["-- expect:".len()..].trim().to_owned());
+        }
+    }
+    Expectation::Success
+}
+
+/// Runs every `.ml` file in `dir` with `eval` of its path and text, printing a
+/// report. Returns the number of failures. In `quiet` mode only the failures are reported.
+pub fn run<F, E>(dir: &str, quiet: bool, eval: F) -> io::Result<usize>
+    where F: Fn(&Path, &str) -> Result<String, E>,
+          E: fmt::Display
+{
+    let mut files = try!(fs::read_dir(dir))
+                        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                        .filter(|path| path.extension().map_or(false, |ext| ext == "ml"))
+                        .collect::<Vec<PathBuf>>();
+    files.sort();
+
+    if !quiet {
+        println!("running {} tests", files.len());
+    }
+    let mut failed = 0;
+    for path in &files {
+        let mut src = String::new();
+        try!(File::open(path).and_then(|mut file| file.read_to_string(&mut src)));
+        let result = eval(path, &src).map_err(|e| e.to_string());
+        let failure = match (expectation(&src), result) {
+            (Expectation::Value(ref expected), Ok(ref actual)) if expected == actual => None,
+            (Expectation::Error(ref expected), Err(ref actual)) if actual.contains(expected) => None,
+            (Expectation::Success, Ok(_)) => None,
+            (Expectation::Value(expected), actual) |
+            (Expectation::Error(expected), actual) => {
+                Some(format!("expected: {}\n    got:      {}", expected, show(actual)))
+            }
+            (Expectation::Success, actual) => Some(format!("got: {}", show(actual))),
+        };
+        match failure {
+            None if quiet => {}
+            None => println!("test {} ... ok", path.display()),
+            Some(message) => {
+                failed += 1;
+                println!("test {} ... FAILED\n    {}", path.display(), message);
+            }
+        }
+    }
+    if !quiet {
+        println!("\ntest result: {}. {} passed; {} failed",
+                 if failed == 0 { "ok" } else { "FAILED" },
+                 files.len() - failed,
+                 failed);
+    }
+    Ok(failed)
+}
+
+fn show(result: Result<String, String>) -> String {
+    match result {
+        Ok(value) => value,
+        Err(e) => e,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{expectation, Expectation};
+
+    #[test]
+    fn test_expectation() {
+        assert_eq!(expectation("-- expect: 92\n90 + 2"),
+                   Expectation::Value("92".to_owned()));
+        assert_eq!(expectation("1 + true\n  -- expect error: Type error  "),
+                   Expectation::Error("Type error".to_owned()));
+        assert_eq!(expectation("-- just a comment\n92"), Expectation::Success);
+    }
+}
@@ -1,7 +1,21 @@
+use std::cell::RefCell;
+use std::io;
+use std::rc::Rc;
+use std::thread;
+
 use syntax;
-use machine::{Machine, Value};
-use typecheck::typecheck;
-use compile::compile;
+use secd::{Instruction, Machine, RuntimeErrorKind, Status, Value};
+use typecheck::{Type, TypeErrorKind, typecheck, typecheck_program, typecheck_program_cancellable};
+use compile::{compile, compile_program, compile_program_cancellable};
+use secd::{CancelToken, Cancelled};
+use error::{Error, ParseError, ParseErrorKind};
+use eval::{Stats, eval, eval_typed, eval_program_to_string, eval_checked_to_string, exec_to_string};
+use walk::eval_ast;
+use ast::{Span, Symbol};
+use bytecode;
+use session::Session;
+use interpreter::Interpreter;
+use value::OwnedValue;
 
 fn assert_execs<V: Into<Value<'static>>>(expected: V, program: &str) {
     let expected = expected.into();
@@ -49,20 +63,19 @@ fn fib() {
 
 #[test]
 fn fix_factorial() {
-    // Can't typecheck fixpoint combinator ;(
+    // In `x x`, `x` takes its own type: `self` is that recursive type, `Self`
+    // wraps a function into it and `match` unwraps it again.
     let fix_factorial = "
-((fun fix(F: (int -> int) -> (int -> int)): (int -> int) is
-    (fun a(x: int): int is (F fun b(n: int): int is (x x) n))
-     fun a(x: int): int is (F fun b(n: int): int is (x x) n))
+type self = Self of self -> int -> int in
+((fun fix(f: (int -> int) -> (int -> int)): (int -> int) is
+    (fun a(x: self): int -> int is (f fun b(n: int): int is (match x with Self g -> g x) n))
+     (Self fun a(x: self): int -> int is (f fun b(n: int): int is (match x with Self g -> g x) n)))
 
-fun Fact(F: (int -> int)): (int -> int) is fun i(n: int): int is
-    if n == 0 then 1 else n * F (n - 1))
+fun fact(f: (int -> int)): (int -> int) is fun i(n: int): int is
+    if n == 0 then 1 else n * f (n - 1))
 5
 ";
-    let program = syntax::parse(&fix_factorial).unwrap();
-    let program = compile(&program);
-    let mut machine = Machine::new(&program);
-    assert_eq!(machine.exec().unwrap(), Value::Int(120));
+    assert_execs(120, fix_factorial);
 }
 
 #[test]
@@ -77,19 +90,58 @@ fn fib_let() {
 
 #[test]
 fn fix_factorial_let() {
-    // Can't typecheck fixpoint combinator ;(
     let fix_factorial = "
-let fun fix(F: (int -> int) -> (int -> int)): (int -> int) is
-    (fun a(x: int): int is (F fun b(n: int): int is (x x) n))
-     fun a(x: int): int is (F fun b(n: int): int is (x x) n)
-in let fun Fact(F: (int -> int)): (int -> int) is fun i(n: int): int is
-    if n == 0 then 1 else n * F (n - 1)
-in (fix Fact) 5
+type self = Self of self -> int -> int in
+let fun fix(f: (int -> int) -> (int -> int)): (int -> int) is
+    let fun unfold(x: self): self -> int -> int is match x with Self g -> g
+    in (fun a(x: self): int -> int is (f fun b(n: int): int is unfold x x n))
+        (Self fun a(x: self): int -> int is (f fun b(n: int): int is unfold x x n))
+in let fun fact(f: (int -> int)): (int -> int) is fun i(n: int): int is
+    if n == 0 then 1 else n * f (n - 1)
+in (fix fact) 5
 ";
-    let program = syntax::parse(&fix_factorial).unwrap();
-    let program = compile(&program);
-    let mut machine = Machine::new(&program);
-    assert_eq!(machine.exec().unwrap(), Value::Int(120));
+    assert_execs(120, fix_factorial);
+}
+
+#[test]
+fn fix_infinite_type() {
+    // Without `self`, the type of `x` would have to contain itself.
+    let fix_factorials = ["
+((fun fix(f: (int -> int) -> (int -> int)): (int -> int) is
+    (fun a(x) is (f fun b(n: int): int is (x x) n))
+     fun a(x) is (f fun b(n: int): int is (x x) n))
+
+fun fact(f: (int -> int)): (int -> int) is fun i(n: int): int is
+    if n == 0 then 1 else n * f (n - 1))
+5
+",
+                          "
+let fun fix(f) is
+    (fun a(x) is f (fun b(n) is (x x) n))
+     fun a(x) is f (fun b(n) is (x x) n)
+in let fun fact(f: (int -> int)): (int -> int) is fun i(n: int): int is
+    if n == 0 then 1 else n * f (n - 1)
+in (fix fact) 5
+"];
+    for fix_factorial in &fix_factorials {
+        let e = typecheck(&syntax::parse(fix_factorial).unwrap()).unwrap_err();
+        match e.kind {
+            TypeErrorKind::InfiniteType { .. } => {}
+            _ => panic!("{}", e),
+        }
+        assert_eq!(e.to_string(), "Cannot construct infinite type: 'a = 'a -> 'b");
+        let span = e.span.unwrap();
+        assert_eq!(&fix_factorial[span.start as usize..span.end as usize], "(x x)");
+    }
+}
+
+#[test]
+fn let_value() {
+    assert_execs(16, "let x = 2 + 2 in x * x");
+    assert_execs(92,
+                 "let x = 90 in
+                  let fun add(y: int): int is x + y
+                  in let x = 0 in add 2");
 }
 
 #[test]
@@ -103,12 +155,12 @@ fn let_shadowing() {
 #[test]
 fn mutual_recusion() {
     let odd_even = "
-let fun F(odd: int -> bool): int -> (int -> bool) is
-  let fun odd(x: int): bool is if x == 0 then false else (F odd 1) (x - 1)
+let fun dispatch(odd: int -> bool): int -> (int -> bool) is
+  let fun odd(x: int): bool is if x == 0 then false else (dispatch odd 1) (x - 1)
   in let fun even(x: int): bool is if x == 0 then true else odd (x - 1)
   in fun p(c: int): int -> bool is if c == 0 then odd else even
 in let fun bottom(x: int): bool is bottom x
-in let fun f(x: int): bool is F bottom {is_even} x
+in let fun f(x: int): bool is dispatch bottom {is_even} x
 in f {n}";
 
     assert_execs(true,
@@ -124,18 +176,18 @@ in f {n}";
 #[test]
 fn mutual_recusion2() {
     let odd_even = "
-let fun F(d: int -> (int -> bool)): int -> (int -> bool) is
+let fun dispatch(d: int -> (int -> bool)): int -> (int -> bool) is
   let    fun odd (x: int): bool is
       let fun dd(x:int): int -> bool is if x == 0 then odd else d x in
-      let fun even(x: int): bool is (F dd 1 x) in
+      let fun even(x: int): bool is (dispatch dd 1 x) in
       if x == 0 then false else even (x - 1)
   in let fun even(x: int): bool is
       let fun dd(x:int): int -> bool is if x == 1 then even else d x in
-      let fun odd (x: int): bool is (F dd 0 x) in
+      let fun odd (x: int): bool is (dispatch dd 0 x) in
       if x == 0 then true else odd (x - 1)
   in fun d(c: int): int -> bool is if c == 0 then odd else even
 in let fun bottom(x: int): int -> bool is bottom x
-in let fun f(x: int): bool is F bottom {is_even} x
+in let fun f(x: int): bool is dispatch bottom {is_even} x
 in f {n}";
 
     assert_execs(true,
@@ -177,6 +229,25 @@ in {fun} {n}";
                  &odd_even.replace("{fun}", "even").replace("{n}", "92"));
 }
 
+#[test]
+fn type_aliases() {
+    let odd_even = "
+type predicate = int -> bool in
+let fun f(tag: int): predicate is
+    if tag == 0
+    then let fun even(n: int): bool is f 1 n in fun odd(n: int): bool is if n == 0 then false else even (n - 1)
+    else let fun odd(n: int): bool is f 0 n in fun even(n: int): bool is if n == 0 then true else odd (n - 1)
+in f {tag} {n}";
+    assert_execs(true, &odd_even.replace("{tag}", "0").replace("{n}", "143"));
+    assert_execs(true, &odd_even.replace("{tag}", "1").replace("{n}", "92"));
+    assert_eq!(eval("type predicate = int -> bool in
+                     let fun test(p: predicate, x: int): bool is p x
+                     in test (fun not_(b: bool): bool is not b) 1")
+                   .unwrap_err()
+                   .to_string(),
+               "Type error: Expected predicate, got bool -> bool in (λ not_ (b: bool): bool (not b))");
+}
+
 #[test]
 fn let_rec() {
     let odd_even = "
@@ -204,3 +275,478 @@ in mod_3 {n}
         assert_execs(n % 3, &code.replace("{n}", &n.to_string()))
     }
 }
+
+#[test]
+fn let_rec_remainder() {
+    let code = "
+let rec fun is_zero(n: int): bool is n == 0
+and fun mod_3(n: int): int is if is_zero (n % 3) then 0 else n % 3
+in mod_3 {n}
+";
+    for n in 0..10 {
+        assert_execs(n % 3, &code.replace("{n}", &n.to_string()))
+    }
+}
+
+#[test]
+fn tail_calls() {
+    let odd_even = "
+let rec fun even(n: int): bool is if n == 0 then true else odd (n - 1)
+and fun odd(n: int): bool is if n == 0 then false else even (n - 1)
+in even 100000";
+    let program = compile(&syntax::parse(odd_even).unwrap());
+    let mut machine = Machine::new(&program);
+    let mut max_depth = 0;
+    let result = loop {
+        max_depth = max_depth.max(machine.depth());
+        match machine.step() {
+            Status::Running => {}
+            Status::Finished(result) => break result,
+            Status::Error(e) => panic!("{}", e),
+        }
+    };
+    assert_eq!(result, Value::Bool(true));
+    assert!(max_depth < 10, "tail calls grew the stack to {}", max_depth);
+}
+
+#[test]
+fn closure_captures() {
+    let program = compile(&syntax::parse("fun f(x: int): int -> int is fun g(y: int): int is x + y").unwrap());
+    match (&program[0], &program[1]) {
+        (&Instruction::Closure { arg, captures: Some(ref outer), .. },
+         &Instruction::Closure { captures: Some(ref inner), .. }) => {
+            assert_eq!(*outer, vec![]);
+            assert_eq!(*inner, vec![arg]);
+        }
+        _ => panic!("not a closure in a closure: {:?}", program),
+    }
+    assert_execs(92, "let x = 90 in let fun f(y: int): int is x + y in let x = 0 in f 2");
+}
+
+#[test]
+fn program() {
+    let program = "
+let fun double(x: int): int is x * 2;;
+let rec fun odd(n: int): bool is if n == 0 then false else even (n - 1)
+and fun even(n: int): bool is if n == 0 then true else odd (n - 1);;
+let main = if even 10 then double 46 else 0;; -- 92
+";
+    let program = syntax::parse_program(&syntax::strip_comments(program)).unwrap();
+    typecheck_program(&program).unwrap();
+    let program = compile_program(&program);
+    let mut machine = Machine::new(&program);
+    assert_eq!(machine.exec().unwrap(), Value::Int(92));
+}
+
+fn assert_shows(expected: &str, program: &str) {
+    let program = syntax::parse_program(program).unwrap();
+    let (value, _) = eval_program_to_string(&program, &mut Stats::default()).unwrap();
+    assert_eq!(value, expected);
+    // Without typechecking it again.
+    let mut stats = Stats::default();
+    assert_eq!(eval_checked_to_string(&program, &mut stats).unwrap(), expected);
+    assert!(stats.phases.iter().all(|&(phase, _)| phase != "typecheck"));
+}
+
+#[test]
+fn lists() {
+    assert_shows("[1, 2, 3]", "[1, 2, 3]");
+    assert_shows("[]", "[]: bool list");
+    assert_shows("[0, 1, 2]", "0 :: 1 :: [2]");
+    assert_shows("[[1], []]", "[[1], []: int list]");
+    assert_execs(2, "head tail [1, 2, 3]");
+    assert_execs(true, "isEmpty tail [1]");
+    assert_shows("[<closure>]", "[fun id(x: int): int is x]");
+
+    let src = "
+let rec fun range(n: int): int list is if n == 0 then []: int list else n :: range (n - 1);;
+let rec fun sum(xs: int list): int is if isEmpty xs then 0 else head xs + sum (tail xs);;
+let rec fun map(f: int -> int): int list -> int list is
+  fun map_f(xs: int list): int list is
+    if isEmpty xs then xs else f (head xs) :: map f (tail xs);;
+";
+    assert_shows("[6, 4, 2]",
+                 &format!("{} map (fun double(x: int): int is x * 2) (range 3)", src));
+    // Plenty of garbage collections happen while the lists are built.
+    assert_shows("5050", &format!("{} sum (range 100)", src));
+    assert_eq!(eval("head ([]: int list)").unwrap_err().to_string(),
+               "Runtime error: Head of an empty list");
+}
+
+#[test]
+fn variants() {
+    let shape = "type shape = Circle of int | Rect of int list | Point in
+                 let fun area(s: shape): int is
+                   match s with Circle r -> 3 * r * r
+                              | Rect sides -> head sides * head (tail sides)
+                              | Point -> 0
+                 in ";
+    assert_execs(12, &format!("{} area (Circle 2)", shape));
+    assert_execs(6, &format!("{} area (Rect [2, 3])", shape));
+    assert_execs(0, &format!("{} area Point", shape));
+    assert_execs(true, "type t = A | B in match B with A -> false | B -> true");
+
+    let tree = "
+type tree = Leaf | Node of tree list in
+let rec fun size(t: tree): int is match t with Leaf -> 1 | Node children -> 1 + sum children
+and fun sum(ts: tree list): int is if isEmpty ts then 0 else size (head ts) + sum (tail ts)
+in let rec fun full(depth: int): tree is
+     if depth == 0 then Leaf else Node [full (depth - 1), full (depth - 1)]
+in size (full 6)";
+    // Plenty of garbage collections happen while the tree is built.
+    assert_execs(127, tree);
+}
+
+#[test]
+fn variants_escaping_scope() {
+    // `x` and the cell of `r` are from before the declaration of `t`, so they
+    // can't hold a `t`, which would be matched as another `t` afterwards.
+    let escaping = ["
+let fun g(x) is (type t = A in let y = if true then x else A in ()); match x with A -> 1 in 0",
+                    "
+let r = ref [] in
+(type t = A in r := [A]);
+type t = B of int in match head !r with B n -> n"];
+    for program in &escaping {
+        assert_eq!(eval(program).unwrap_err().to_string(), "Type error: The type t escapes its scope");
+    }
+    // Types made inside the declaration can mention it, and the types of the
+    // rest of the program can't be generalized through it.
+    assert_execs(1, "type t = A in let fun f(x) is x in match f A with A -> 1");
+    let generalized = "
+let r = (type t = A in ref []) in
+let fun f(x) is (r := [x]; x) in
+let n = f 1 in f true";
+    assert_eq!(eval(generalized).unwrap_err().to_string(), "Type error: Expected int, got bool in true");
+}
+
+#[test]
+fn logic() {
+    assert_execs(true, "true && not false");
+    assert_execs(false, "false || 1 > 2");
+    // The right operand isn't evaluated once the left one decides the result.
+    assert_execs(false, "false && 1 / 0 == 0");
+    assert_execs(true, "true || head ([]: bool list)");
+    assert_execs(true, "let fun even(n: int): bool is n / 2 * 2 == n in even 4 && not (even 5)");
+}
+
+#[test]
+fn comparisons() {
+    assert_execs(true, "1 <= 1 && 2 >= 1 && 1 != 2");
+    assert_execs(false, "2 <= 1 || 1 >= 2 || 1 != 1");
+    assert_execs(true, r#""a" != "b""#);
+    assert_execs(false, r#""a" != "a""#);
+}
+
+#[test]
+fn remainder_and_negation() {
+    assert_execs(2, "92 % 10");
+    assert_execs(-2, "-92 % 10");
+    assert_execs(-3, "let x = 2 in -(x + 1)");
+    assert_execs(4, "1 - -3");
+    assert_eq!(eval("1 % 0").unwrap_err().to_string(), "Runtime error: Division by zero");
+    assert_eq!(eval("9223372036854775807 + 1").unwrap_err().to_string(), "Runtime error: Integer overflow");
+    assert_eq!(eval("-9223372036854775807 - 2").unwrap_err().to_string(), "Runtime error: Integer overflow");
+}
+
+#[test]
+fn strings() {
+    assert_shows(r#""hello, world""#, r#""hello, " ++ "world""#);
+    assert_shows(r#""say \"hi\"\n""#, r#""say " ++ "\"hi\"" ++ "\n""#);
+    assert_shows(r#"["a", "b"]"#, r#"["a", "b"]"#);
+    assert_execs(true, r#""ab" == "a" ++ "b""#);
+    assert_execs(false, r#""ab" == "ba""#);
+
+    let src = r#"
+let rec fun repeat(n: int): string is if n == 0 then "" else "ab" ++ repeat (n - 1);;
+let fun greet(name: string): string is "hello, " ++ name;;
+"#;
+    assert_shows(r#""hello, miniml""#, &format!("{} greet \"miniml\"", src));
+    // Plenty of garbage collections happen while the string is built.
+    assert_execs(true, &format!("{} repeat 100 == repeat 50 ++ repeat 50", src));
+}
+
+#[test]
+fn refs() {
+    assert_execs(92, "let r = ref 90 in !r + 2");
+    assert_execs(3, "let r = ref 1 in r := !r + 2; !r");
+    assert_shows("ref [1, 2]", "ref [1, 2]");
+    assert_shows("[ref 1, ref 1]", "let r = ref 1 in [r, r]");
+
+    let counter = "
+let total = ref 0;;
+let fun add(x: int): unit is total := !total + x;;
+let rec fun loop(n: int): int is if n == 0 then !total else add n; loop (n - 1);;
+";
+    // Plenty of garbage collections happen while the cell is updated.
+    assert_execs(5050, &format!("{} loop 100", counter));
+    assert_eq!(eval("!(1)").unwrap_err().to_string().split(':').next(), Some("Type error"));
+}
+
+#[test]
+fn records() {
+    assert_execs(3, "let p = {x = 1, y = 2} in p.x + p.y");
+    assert_execs(true, "let fun norm(p: {x: int, y: int}): int is p.x * p.x + p.y * p.y
+                        in norm {y = 4, x = 3} == 25");
+    assert_shows("{a = [1], b = true}", "{b = true, a = [1]}");
+    assert_execs(92, "let r = {count = ref 90} in r.count := !r.count + 2; !r.count");
+    assert_eq!(eval("{x = 1}.y").unwrap_err().to_string().split(':').next(), Some("Type error"));
+    assert_eq!(eval("{x = 1, x = 2}").unwrap_err().to_string().split(':').next(), Some("Type error"));
+}
+
+#[test]
+fn floats() {
+    assert_execs(2.5, "1.0 + 3.0 / 2.0");
+    assert_execs(3, "int_of_float (float_of_int 7 / 2.0)");
+    assert_execs(true, "0.1 + 0.2 > 0.3");
+    assert_shows("[1.0, -0.5]", "[1.0, -0.5]");
+    let sqrt = "
+let fun sqrt(x: float): float is
+  let rec fun improve(guess: float): float is
+    if guess * guess - x < 0.000001 then guess else improve ((guess + x / guess) / 2.0)
+  in improve x
+in int_of_float (sqrt 2.0 * 1000.0)";
+    assert_execs(1414, sqrt);
+    assert_eq!(eval("1 + 1.0").unwrap_err().to_string().split(':').next(), Some("Type error"));
+}
+
+#[test]
+fn curried_functions() {
+    assert_execs(3, "let fun add(x: int, y: int): int is x + y in add 1 2");
+    assert_execs(92, "let fun add(x: int, y: int): int is x + y in let inc = add 1 in inc 91");
+    assert_execs(10, "let rec fun sum(acc: int, n: int): int is if n == 0 then acc else sum (acc + n) (n - 1)
+                      in sum 0 4");
+    assert_execs(true, "let rec fun even(n: int, yes: bool): bool is if n == 0 then yes else odd (n - 1) yes
+                        and fun odd(n: int, yes: bool): bool is if n == 0 then not yes else even (n - 1) yes
+                        in even 10 true");
+    assert_execs(5, "let fun pick(b: bool, x: int, y: int): int is if b then x else y in
+                     let n = 4 in pick false 3 (n + 1)");
+}
+
+#[test]
+fn sequences() {
+    assert_shows("()", "()");
+    assert_shows("()", "let r = ref 1 in r := 2");
+    assert_execs(92, "(); (); 92");
+    assert_execs(true, "let fun skip(u: unit): unit is u in skip (); true");
+    assert_execs(0, "let r = ref 1000 in
+                     let rec fun count(u: unit): int is if !r == 0 then 0 else r := !r - 1; count ()
+                     in count ()");
+    assert_eq!(eval("1; 2").unwrap_err().to_string().split(':').next(), Some("Type error"));
+}
+
+/// Runs `program` and checks what it printed.
+fn assert_prints(expected: &str, program: &str) {
+    let program = syntax::parse(&program).unwrap();
+    typecheck(&program).unwrap();
+    let program = compile(&program);
+    let mut output = Vec::new();
+    Machine::new(&program).with_output(&mut output).exec().unwrap();
+    assert_eq!(String::from_utf8(output).unwrap(), expected);
+}
+
+#[test]
+fn print() {
+    assert_prints("hello, world\n", r#"print ("hello, " ++ "world")"#);
+    assert_prints("3\n2\n1\n", "let rec fun countdown(n: int): unit is
+                                    if n == 0 then () else print_int n; countdown (n - 1)
+                                  in countdown 3");
+    assert_prints("1\n2\n", "let r = ref 1 in print_int !r; r := 2; print_int !r");
+}
+
+#[test]
+fn session_definitions() {
+    let mut session = Session::new();
+    let mut execute = |input: &str| session.execute(input, &mut Stats::default()).unwrap().to_string();
+    assert_eq!(execute("fun inc(x: int): int is x + 1"), "inc : int -> int");
+    assert_eq!(execute("let two = inc 1;;"), "two : int");
+    assert_eq!(execute("inc two"), "3");
+    assert_eq!(execute("let fun twice(f, x) is f (f x);;"), "twice : ('a -> 'a) -> 'a -> 'a");
+    assert_eq!(execute("twice inc two"), "4");
+    let shown = |name: &str| session.bindings().into_iter().find(|binding| binding.0.as_ref() == name).unwrap().2;
+    assert_eq!(shown("inc"), "<fun inc(x: int): int>");
+    assert_eq!(shown("twice"), "<fun twice(f: 'a -> 'a, x: 'a): 'a>");
+}
+
+#[test]
+fn session_inspection() {
+    let mut session = Session::new();
+    let mut execute = |input: &str| session.execute(input, &mut Stats::default()).map(|o| o.to_string());
+    execute("let x = 90;;").unwrap();
+    assert_eq!(execute(":type x < 92").unwrap(), "bool");
+    assert_eq!(execute(":ast 1 + 2 * 3").unwrap(), "(+ 1 (* 2 3))");
+    assert_eq!(execute(":ir 1 + 2").unwrap(), "(+ 1 2)");
+    assert_eq!(execute(":dis 1 + 2").unwrap(), "   0 push 1\n   1 push 2\n   2 add");
+    assert!(execute(":type y").is_err());
+    assert_eq!(execute(":env").unwrap(), "x : int = 90");
+}
+
+#[test]
+fn eval_api() {
+    assert_eq!(eval("let x = 90;; x + 2").unwrap(), OwnedValue::Int(92));
+    let value = eval("[ref 1, ref 2]").unwrap();
+    assert_eq!(thread::spawn(move || value.to_string()).join().unwrap(), "[ref 1, ref 2]");
+    let (value, type_) = eval_typed("fun id(x: int): int is x").unwrap();
+    assert_eq!((value.to_string(), format!("{:?}", type_)),
+               ("<closure>".to_owned(), "int -> int".to_owned()));
+    assert_eq!(eval("1 +").unwrap_err().to_string().split(':').next(), Some("Parse error"));
+    assert_eq!(eval("1 + true").unwrap_err().to_string().split(':').next(), Some("Type error"));
+    assert_eq!(eval("1 / 0").unwrap_err().to_string(), "Runtime error: Division by zero");
+}
+
+#[test]
+fn tree_walker() {
+    let programs = ["let rec fun fib(n: int): int is if n < 2 then n else fib (n - 1) + fib (n - 2) in fib 9",
+                    "let fun add(x: int, y: int): int is x + y in let inc = add 1 in [inc 1, inc 2, add 3 4]",
+                    "let rec fun even(n: int): bool is if n == 0 then true else odd (n - 1) \
+                     and fun odd(n: int): bool is if n == 0 then false else even (n - 1) \
+                     in {even = even 10, odd = odd 10}",
+                    "let r = ref 1 in r := !r + 1; {total = !r, cell = r}",
+                    "type shape = Circle of float | Square of float in \
+                     match Square 2.0 with Circle r -> 3.14 * r * r | Square a -> a * a",
+                    r#"let s = "mini" ++ "ml" in s == "miniml" && 1.5 < 2.5"#,
+                    "tail (1 :: 2 :: [3])",
+                    "fun id(x: int): int is x"];
+    for src in programs.iter() {
+        let expr = syntax::parse(src).unwrap();
+        let expected = eval(src).unwrap();
+        assert_eq!(eval_ast(&expr).unwrap(), expected, "{}", src);
+    }
+    for &(src, ref kind) in &[("1 / 0", RuntimeErrorKind::DivisionByZero),
+                              ("head ([]: int list)", RuntimeErrorKind::HeadOfEmptyList),
+                              ("9223372036854775807 + 1", RuntimeErrorKind::Overflow)] {
+        match eval_ast(&syntax::parse(src).unwrap()) {
+            Err(Error::Runtime(e)) => assert_eq!(e.kind, *kind),
+            result => panic!("{}: {:?}", src, result),
+        }
+    }
+    assert!(eval_ast(&syntax::parse("1 + true").unwrap()).is_err());
+}
+
+/// Output which the test can read while an `Interpreter` owns the writer.
+#[derive(Clone, Default)]
+struct SharedOutput(Rc<RefCell<Vec<u8>>>);
+
+impl io::Write for SharedOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn interpreter() {
+    let output = SharedOutput::default();
+    let mut interpreter = Interpreter::new().with_limits(100_000).with_output(output.clone());
+    assert_eq!(interpreter.eval("let fun inc(x: int): int is x + 1;;").unwrap(), OwnedValue::Unit);
+    assert_eq!(interpreter.eval("let total = ref (inc 1);; inc !total").unwrap(), OwnedValue::Int(3));
+    assert_eq!(interpreter.eval("total := 90; print_int (inc !total); !total").unwrap(),
+               OwnedValue::Int(90));
+    assert_eq!(String::from_utf8(output.0.borrow().clone()).unwrap(), "91\n");
+
+    assert_eq!(interpreter.eval(r#"[["a"], []: string list]"#).unwrap(),
+               OwnedValue::List(vec![OwnedValue::List(vec![OwnedValue::Str("a".to_owned())]),
+                                     OwnedValue::List(vec![])]));
+    let shown = |interpreter: &mut Interpreter, src: &str| interpreter.eval(src).unwrap().to_string();
+    assert_eq!(shown(&mut interpreter, "let r = ref [1] in [r, r]"), "[ref [1], ref [1]]");
+    assert_eq!(shown(&mut interpreter, "ref (ref total)"), "ref (ref (ref 90))");
+    assert_eq!(shown(&mut interpreter, "inc"), "<closure>");
+
+    let fuel = interpreter.eval("let rec fun loop(n: int): int is loop n;; loop 0");
+    match fuel {
+        Err(Error::Runtime(ref e)) => assert_eq!(e.kind, RuntimeErrorKind::OutOfFuel),
+        _ => panic!("{:?}", fuel),
+    }
+    assert!(interpreter.eval("missing").is_err());
+    // Each program gets its own fuel, and failed ones keep what they defined.
+    assert_eq!(interpreter.eval("inc !total").unwrap(), OwnedValue::Int(91));
+    assert!(interpreter.eval("loop").is_ok());
+
+    // The code of earlier programs is freed, but not while a function or a
+    // record made by it is still defined.
+    interpreter.eval("let point = {x = 1, y = 2};;").unwrap();
+    for n in 0..100 {
+        interpreter.eval(&format!("let fun add(x: int): int is x + {};; add 0", n)).unwrap();
+    }
+    assert_eq!(interpreter.eval("add point.x + point.y").unwrap(), OwnedValue::Int(102));
+    assert_eq!(interpreter.eval("point").unwrap().to_string(), "{x = 1, y = 2}");
+}
+
+#[test]
+fn error_kinds() {
+    match eval("1 +").unwrap_err() {
+        Error::Parse(ParseError { kind: ParseErrorKind::UnexpectedEnd { .. }, span }) => {
+            assert_eq!(span, Span::new(3, 3))
+        }
+        e => panic!("{}", e),
+    }
+    match eval("if 1 then 2 else 3").unwrap_err() {
+        Error::Type(e) => {
+            assert_eq!(e.kind,
+                       TypeErrorKind::Mismatch {
+                           expected: Type::Bool,
+                           found: Type::Int,
+                           expr: "1".to_owned(),
+                       })
+        }
+        e => panic!("{}", e),
+    }
+    match eval("head ([]: int list)").unwrap_err() {
+        Error::Runtime(e) => assert_eq!(e.kind, RuntimeErrorKind::HeadOfEmptyList),
+        e => panic!("{}", e),
+    }
+}
+
+#[test]
+fn cancellation() {
+    let program = syntax::parse_program("let fun loop(x: int): int is loop x in loop 0").unwrap();
+    let token = CancelToken::new();
+    typecheck_program_cancellable(&program, &token).unwrap();
+    let frame = compile_program_cancellable(&program, &token).unwrap();
+    token.cancel();
+    assert_eq!(typecheck_program_cancellable(&program, &token).unwrap_err().kind,
+               TypeErrorKind::Cancelled);
+    assert_eq!(compile_program_cancellable(&program, &token).unwrap_err(), Cancelled);
+    let mut machine = Machine::new(&frame);
+    machine.set_cancel_token(token.clone());
+    assert_eq!(machine.exec().unwrap_err().kind, RuntimeErrorKind::Cancelled);
+}
+
+#[test]
+fn deterministic_compilation() {
+    let template = "
+let rec fun {odd}({n}: int): bool is if {n} == 0 then false else {even} ({n} - 1)
+and fun {even}({n}: int): bool is if {n} == 0 then true else {odd} ({n} - 1);;
+let fun {twice}({f}: int -> int): int -> int is fun {twice_f}({x}: int): int is {f} ({f} {x});;
+let fun {inc}({x}: int): int is {x} + 1;;
+if {odd} 7 then {twice} {inc} 90 else 0";
+    let names = ["odd", "n", "even", "twice", "f", "twice_f", "x", "inc"];
+    let instantiate = |prefix: &str| {
+        names.iter().fold(template.to_owned(), |src, name| {
+            src.replace(&format!("{{{}}}", name), &format!("{}{}", prefix, name))
+        })
+    };
+    let compile = |src: &str| bytecode::save(&compile_program(&syntax::parse_program(src).unwrap()));
+
+    let src = instantiate("");
+    let bytes = compile(&src);
+    assert!(bytes == compile(&src));
+    assert_eq!(bytecode::load(&bytes).unwrap(), compile_program(&syntax::parse_program(&src).unwrap()));
+
+    // Symbols are numbered in the order they are interned, which must not
+    // leak into the code: intern the names of a renamed copy backwards.
+    for name in names.iter().rev() {
+        Symbol::intern(&format!("deterministic_{}", name));
+    }
+    assert!(bytes == compile(&instantiate("deterministic_")));
+}
+
+#[test]
+fn run_saved_bytecode() {
+    let program = syntax::parse_program("let fun inc(x: int): int is x + 1;; [inc 1, inc 2]").unwrap();
+    let frame = bytecode::load(&bytecode::save(&compile_program(&program))).unwrap();
+    assert_eq!(exec_to_string(&frame, &mut Stats::default()).unwrap(), "[2, 3]");
+}
@@ -1,30 +1,419 @@
 use std::rc::Rc;
+use std::slice;
 use std::collections::HashSet;
-use std::fmt;
+use std::{error, fmt};
 
-use ast::{self, Ident, Expr, Literal, ArithBinOp, CmpBinOp, If, Fun, LetFun, LetRec, Apply};
-use context::TypeContext;
+use ast::{self, Ident, Span, Expr, Literal, ArithBinOp, CmpBinOp, CmpOp, LogicBinOp, Not, List, Cons, Concat, ListOp,
+          ListUnOp, RefOp, RefUnOp, PrintOp, PrintUnOp, ConvertOp, ConvertUnOp, Assign, Seq, Record, Field, Construct, Match,
+          If, Fun, Let, LetFun, LetRec, LetType, TypeAlias, Apply, Program, Decl};
+use secd::CancelToken;
+use context::{TypeContext, TypeVar};
+
+macro_rules! bail {
+    ($kind:expr) => {
+        return Err(TypeError::from($kind))
+    };
+}
 
 pub type Result = ::std::result::Result<Type, TypeError>;
 
 #[derive(Debug)]
 pub struct TypeError {
-    pub message: String,
+    pub kind: TypeErrorKind,
+    /// The expression the error is about, if it was parsed from source.
+    pub span: Option<Span>,
+}
+
+/// Expressions are kept as they are printed in the message, like `(+ x 1)`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TypeErrorKind {
+    Mismatch {
+        expected: Type,
+        found: Type,
+        expr: String,
+    },
+    UnboundVariable(Ident),
+    UnknownType(Ident),
+    /// A type parameter like `'a` outside the signatures of functions.
+    UnboundTypeParam(Ident),
+    /// The annotation of an empty list isn't a list type.
+    NotAListType {
+        found: Type,
+        expr: String,
+    },
+    NotAList {
+        found: Type,
+        expr: String,
+    },
+    /// `!` or `:=` on something other than a reference cell.
+    NotARef {
+        found: Type,
+        expr: String,
+    },
+    /// A record literal or a record type has two fields with the name.
+    DuplicateField(Ident),
+    /// A field projected from something other than a record.
+    NotARecord {
+        found: Type,
+        expr: String,
+    },
+    NoSuchField {
+        name: Ident,
+        found: Type,
+        expr: String,
+    },
+    UnknownConstructor(Ident),
+    MissingArgument(Ident),
+    UnexpectedArgument(Ident),
+    /// The scrutinee of a `match` doesn't have a type declared with `type`.
+    NotAVariant {
+        found: Type,
+        expr: String,
+    },
+    ForeignConstructor {
+        constructor: Ident,
+        type_name: Ident,
+    },
+    DuplicateArm {
+        constructor: Ident,
+        expr: String,
+    },
+    MissingArm {
+        constructor: Ident,
+        expr: String,
+    },
+    MatchArmsDiffer {
+        first: Type,
+        other: Type,
+    },
+    IfArmsDiffer {
+        tru: Type,
+        fls: Type,
+    },
+    TypeRedefined(Ident),
+    DuplicateConstructors(Ident),
+    LowercaseConstructor(Ident),
+    EscapingType {
+        name: Ident,
+        expr: String,
+    },
+    /// A variable from before the declaration of a type would stand for a
+    /// type mentioning it, like that of `x` in `fun g(x) is type t = A in x == A`.
+    EscapingScope(Ident),
+    /// Two functions of a `let rec` group have the name.
+    DuplicateDefinitions(Ident),
+    /// The signature or the body of the function of a `let rec` group doesn't typecheck.
+    InRecursiveFunction {
+        fun: Ident,
+        error: Box<TypeErrorKind>,
+    },
+    NotAFunction {
+        expr: String,
+    },
+    /// A type would have to contain itself, like that of `x` in `x x`: the
+    /// variable `var` would stand for `type_`, which mentions it.
+    InfiniteType {
+        var: Type,
+        type_: Type,
+    },
+    /// A declaration checked on its own binds a name whose type isn't fully
+    /// inferred, like `let r = ref []`, so its uses can't tell it later.
+    AmbiguousType {
+        name: Ident,
+        found: Type,
+    },
+    /// A function isn't generic in a type parameter of its signature, like
+    /// `'a` in `fun inc(x: 'a): 'a is x + 1`, which can only be `int`.
+    NotGeneric {
+        param: Ident,
+        found: Type,
+    },
+    /// The `CancelToken` of `typecheck_program_cancellable` was cancelled.
+    Cancelled,
+}
+
+impl fmt::Display for TypeErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Mismatch { ref expected, ref found, ref expr } => {
+                write!(f, "Expected {:?}, got {:?} in {}", expected, found, expr)
+            }
+            UnboundVariable(name) => write!(f, "Unbound variable: {}", name),
+            UnknownType(name) => write!(f, "Unknown type: {}", name),
+            UnboundTypeParam(name) => write!(f, "Unbound type parameter: '{}", name),
+            NotAListType { ref found, ref expr } => {
+                write!(f, "Expected a list type, got {:?} in {}", found, expr)
+            }
+            NotAList { ref found, ref expr } => write!(f, "Expected a list, got {:?} in {}", found, expr),
+            NotARef { ref found, ref expr } => {
+                write!(f, "Expected a reference, got {:?} in {}", found, expr)
+            }
+            DuplicateField(name) => write!(f, "Duplicate field: {}", name),
+            NotARecord { ref found, ref expr } => write!(f, "Expected a record, got {:?} in {}", found, expr),
+            NoSuchField { name, ref found, ref expr } => {
+                write!(f, "No field {} in {:?}, the type of {}", name, found, expr)
+            }
+            UnknownConstructor(name) => write!(f, "Unknown constructor: {}", name),
+            MissingArgument(name) => write!(f, "Constructor {} expects an argument", name),
+            UnexpectedArgument(name) => write!(f, "Constructor {} takes no argument", name),
+            NotAVariant { ref found, ref expr } => {
+                write!(f,
+                       "Can only match on types declared with `type`, got {:?} in {}",
+                       found,
+                       expr)
+            }
+            ForeignConstructor { constructor, type_name } => {
+                write!(f, "{} is not a constructor of {}", constructor, type_name)
+            }
+            DuplicateArm { constructor, ref expr } => {
+                write!(f, "Duplicate arms for {} in {}", constructor, expr)
+            }
+            MissingArm { constructor, ref expr } => write!(f, "No arm for {} in {}", constructor, expr),
+            MatchArmsDiffer { ref first, ref other } => {
+                write!(f, "Arms of a match have different types: {:?} {:?}", first, other)
+            }
+            IfArmsDiffer { ref tru, ref fls } => {
+                write!(f, "Arms of an if have different types: {:?} {:?}", tru, fls)
+            }
+            TypeRedefined(name) => write!(f, "Type {} is already defined", name),
+            DuplicateConstructors(name) => write!(f, "Duplicate constructors in type {}", name),
+            LowercaseConstructor(name) => {
+                write!(f, "Constructors must start with a capital letter: {}", name)
+            }
+            EscapingType { name, ref expr } => {
+                write!(f, "The type {} escapes its declaration in {}", name, expr)
+            }
+            EscapingScope(name) => write!(f, "The type {} escapes its scope", name),
+            DuplicateDefinitions(name) => write!(f, "Duplicate definitions of {} in letrec", name),
+            InRecursiveFunction { fun, ref error } => write!(f, "In {}: {}", fun, error),
+            NotAFunction { ref expr } => write!(f, "Not a function {}", expr),
+            InfiniteType { ref var, ref type_ } => {
+                write!(f, "Cannot construct infinite type: {:?} = {:?}", var, type_)
+            }
+            AmbiguousType { name, ref found } => {
+                write!(f, "The type of {} is not fully known: {:?}", name, found)
+            }
+            NotGeneric { param, ref found } => {
+                write!(f, "The type parameter '{} can't be any type, it is {:?}", param, found)
+            }
+            Cancelled => f.write_str("Cancelled"),
+        }
+    }
+}
+
+impl TypeErrorKind {
+    /// Whether the error is about a type with `Error` in it, and so caused
+    /// by another error.
+    fn is_caused_by_error(&self) -> bool {
+        match *self {
+            Mismatch { ref expected, ref found, .. } => expected.has_error() || found.has_error(),
+            NotAListType { ref found, .. } | NotAList { ref found, .. } | NotARef { ref found, .. } |
+            NotARecord { ref found, .. } | NoSuchField { ref found, .. } | NotAVariant { ref found, .. } |
+            AmbiguousType { ref found, .. } | NotGeneric { ref found, .. } => {
+                found.has_error()
+            }
+            MatchArmsDiffer { ref first, ref other } => first.has_error() || other.has_error(),
+            IfArmsDiffer { ref tru, ref fls } => tru.has_error() || fls.has_error(),
+            InRecursiveFunction { ref error, .. } => error.is_caused_by_error(),
+            _ => false,
+        }
+    }
+
+    /// Shows the parts of the types in the message that are `type_` by the
+    /// name of its alias.
+    fn with_alias(self, name: Ident, type_: &Type) -> TypeErrorKind {
+        self.map_types(&mut |t: Type| t.with_alias(name, type_))
+    }
+
+    /// Replaces the types in the message by `rename` of them.
+    fn map_types<F: FnMut(Type) -> Type>(self, rename: &mut F) -> TypeErrorKind {
+        match self {
+            Mismatch { expected, found, expr } => {
+                Mismatch {
+                    expected: rename(expected),
+                    found: rename(found),
+                    expr: expr,
+                }
+            }
+            NotAListType { found, expr } => NotAListType { found: rename(found), expr: expr },
+            NotAList { found, expr } => NotAList { found: rename(found), expr: expr },
+            NotARef { found, expr } => NotARef { found: rename(found), expr: expr },
+            NotAVariant { found, expr } => NotAVariant { found: rename(found), expr: expr },
+            NotARecord { found, expr } => NotARecord { found: rename(found), expr: expr },
+            NoSuchField { name: field, found, expr } => {
+                NoSuchField {
+                    name: field,
+                    found: rename(found),
+                    expr: expr,
+                }
+            }
+            MatchArmsDiffer { first, other } => {
+                MatchArmsDiffer {
+                    first: rename(first),
+                    other: rename(other),
+                }
+            }
+            IfArmsDiffer { tru, fls } => IfArmsDiffer { tru: rename(tru), fls: rename(fls) },
+            InRecursiveFunction { fun, error } => {
+                InRecursiveFunction {
+                    fun: fun,
+                    error: Box::new(error.map_types(rename)),
+                }
+            }
+            InfiniteType { var, type_ } => InfiniteType { var: rename(var), type_: rename(type_) },
+            AmbiguousType { name, found } => AmbiguousType { name: name, found: rename(found) },
+            NotGeneric { param, found } => NotGeneric { param: param, found: rename(found) },
+            kind => kind,
+        }
+    }
+}
+
+impl fmt::Display for TypeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.kind.fmt(f)
+    }
+}
+
+impl error::Error for TypeError {}
+
+impl From<TypeErrorKind> for TypeError {
+    fn from(kind: TypeErrorKind) -> TypeError {
+        TypeError {
+            kind: kind,
+            span: None,
+        }
+    }
+}
+
+impl TypeError {
+    /// Points the error at `span`, unless it already points at a part of it.
+    fn at(mut self, span: Span) -> TypeError {
+        if self.span.is_none() && !span.is_empty() {
+            self.span = Some(span);
+        }
+        self
+    }
+
+    /// Says that the error is in the function `fun` of a `let rec` group.
+    fn in_function(self, fun: Ident) -> TypeError {
+        if self.kind == Cancelled {
+            return self;
+        }
+        TypeError {
+            kind: InRecursiveFunction {
+                fun: fun,
+                error: Box::new(self.kind),
+            },
+            span: self.span,
+        }
+    }
 }
 
 #[derive(PartialEq, Eq, Clone)]
 pub enum Type {
     Int,
+    Float,
     Bool,
+    Str,
+    Unit,
     Arrow(Rc<Type>, Rc<Type>),
+    List(Rc<Type>),
+    Ref(Rc<Type>),
+    /// The fields are sorted by name, so that records with the same fields
+    /// have the same type whatever order they are written in.
+    Record(Rc<Vec<(Ident, Type)>>),
+    /// Types declared with `type` are equal if their names are. A `type`
+    /// can't shadow another one, and its name can't escape its scope.
+    /// Aliases are replaced by the types they stand for, and are only
+    /// `Named` in error messages.
+    Named(Ident),
+    /// The type of an expression which doesn't typecheck, given to it by
+    /// `typecheck_all` to go on. It fits where any type is expected.
+    Error,
+    /// A type not inferred yet, like that of `x` in `fun id(x) is x` at first.
+    /// It only appears while typechecking, and stands for whatever type it is
+    /// unified with.
+    Var(u32),
+    /// A type parameter of a polymorphic type, like `'a` in `'a -> 'a`, the
+    /// type of `id`. Each use of a name bound to such a type gets another
+    /// `Var` for each parameter. Type variables left once typechecking is
+    /// over are shown as parameters too.
+    Param(u32),
 }
 
 use self::Type::*;
+use self::TypeErrorKind::*;
 
 impl Type {
     fn maps_to(self, other: Type) -> Type {
         Arrow(Rc::new(self), Rc::new(other))
     }
+
+    fn with_alias(self, name: Ident, type_: &Type) -> Type {
+        if self == *type_ {
+            return Named(name);
+        }
+        match self {
+            Arrow(l, r) => {
+                let l = l.as_ref().clone().with_alias(name, type_);
+                l.maps_to(r.as_ref().clone().with_alias(name, type_))
+            }
+            Type::List(t) => Type::List(Rc::new(t.as_ref().clone().with_alias(name, type_))),
+            Type::Ref(t) => Type::Ref(Rc::new(t.as_ref().clone().with_alias(name, type_))),
+            Type::Record(fields) => {
+                let fields = fields.iter().map(|&(field, ref t)| (field, t.clone().with_alias(name, type_)));
+                Type::Record(Rc::new(fields.collect()))
+            }
+            t => t,
+        }
+    }
+
+    fn mentions(&self, name: &Ident) -> bool {
+        match *self {
+            Int | Float | Bool | Str | Unit => false,
+            Arrow(ref l, ref r) => l.mentions(name) || r.mentions(name),
+            Type::List(ref t) | Type::Ref(ref t) => t.mentions(name),
+            Type::Record(ref fields) => fields.iter().any(|field| field.1.mentions(name)),
+            Named(ref n) => n == name,
+            Error | Type::Var(_) | Param(_) => false,
+        }
+    }
+
+    fn has_error(&self) -> bool {
+        match *self {
+            Error => true,
+            Arrow(ref l, ref r) => l.has_error() || r.has_error(),
+            Type::List(ref t) | Type::Ref(ref t) => t.has_error(),
+            Type::Record(ref fields) => fields.iter().any(|field| field.1.has_error()),
+            Int | Float | Bool | Str | Unit | Named(_) | Type::Var(_) | Param(_) => false,
+        }
+    }
+
+    fn has_vars(&self) -> bool {
+        match *self {
+            Type::Var(_) => true,
+            Arrow(ref l, ref r) => l.has_vars() || r.has_vars(),
+            Type::List(ref t) | Type::Ref(ref t) => t.has_vars(),
+            Type::Record(ref fields) => fields.iter().any(|field| field.1.has_vars()),
+            Int | Float | Bool | Str | Unit | Named(_) | Error | Param(_) => false,
+        }
+    }
+
+    /// The type with the parts for which `f` returns a type replaced by it.
+    fn substitute<F: FnMut(&Type) -> Option<Type>>(&self, f: &mut F) -> Type {
+        if let Some(t) = f(self) {
+            return t;
+        }
+        match *self {
+            Arrow(ref l, ref r) => l.substitute(f).maps_to(r.substitute(f)),
+            Type::List(ref t) => Type::List(Rc::new(t.substitute(f))),
+            Type::Ref(ref t) => Type::Ref(Rc::new(t.substitute(f))),
+            Type::Record(ref fields) => {
+                Type::Record(Rc::new(fields.iter().map(|&(name, ref t)| (name, t.substitute(f))).collect()))
+            }
+            ref t => t.clone(),
+        }
+    }
 }
 
 trait IntoType {
@@ -35,48 +424,526 @@ impl IntoType for ast::Type {
     fn as_type(&self) -> Type {
         match *self {
             ast::Type::Int => Int,
+            ast::Type::Float => Float,
             ast::Type::Bool => Bool,
+            ast::Type::Str => Str,
+            ast::Type::Unit => Unit,
             ast::Type::Arrow(ref l, ref r) => Arrow(Rc::new(l.as_type()), Rc::new(r.as_type())),
+            ast::Type::List(ref t) => Type::List(Rc::new(t.as_type())),
+            ast::Type::Ref(ref t) => Type::Ref(Rc::new(t.as_type())),
+            ast::Type::Record(ref fields) => {
+                let mut fields = fields.iter().map(|&(name, ref t)| (name, t.as_type())).collect::<Vec<_>>();
+                fields.sort_by(|l, r| l.0.as_ref().cmp(r.0.as_ref()));
+                Type::Record(Rc::new(fields))
+            }
+            ast::Type::Named(name) => Named(name),
+            // Only annotations checked by `check_type` are inferred or generic.
+            ast::Type::Param(_) | ast::Type::Infer => Error,
         }
     }
 }
 
+impl<'a> From<&'a ast::Type> for Type {
+    fn from(type_: &'a ast::Type) -> Type {
+        type_.as_type()
+    }
+}
+
 impl fmt::Debug for Type {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             Int => f.write_str("int"),
+            Float => f.write_str("float"),
             Bool => f.write_str("bool"),
+            Str => f.write_str("string"),
+            Unit => f.write_str("unit"),
             Arrow(ref l, ref r) => {
                 match **l {
                     Arrow(..) => write!(f, "({:?}) -> {:?}", l, r),
                     _ => write!(f, "{:?} -> {:?}", l, r),
                 }
             }
+            Type::List(ref t) => {
+                match **t {
+                    Arrow(..) => write!(f, "({:?}) list", t),
+                    _ => write!(f, "{:?} list", t),
+                }
+            }
+            Type::Ref(ref t) => {
+                match **t {
+                    Arrow(..) => write!(f, "({:?}) ref", t),
+                    _ => write!(f, "{:?} ref", t),
+                }
+            }
+            Type::Record(ref fields) => {
+                try!(f.write_str("{"));
+                for (i, field) in fields.iter().enumerate() {
+                    if i > 0 {
+                        try!(f.write_str(", "));
+                    }
+                    try!(write!(f, "{}: {:?}", field.0, field.1));
+                }
+                f.write_str("}")
+            }
+            Named(ref name) => write!(f, "{}", name),
+            Error => f.write_str("<error>"),
+            Type::Var(var) => write!(f, "'_{}", var),
+            // 'a to 'z, then 'a1 to 'z1 and so on.
+            Param(param) => {
+                let letter = (b'a' + (param % 26) as u8) as char;
+                match param / 26 {
+                    0 => write!(f, "'{}", letter),
+                    n => write!(f, "'{}{}", letter, n),
+                }
+            }
         }
     }
 }
 
+/// The type of `expr`. Parts of it which nothing tells are type parameters,
+/// so `fun id(x) is x` has type `'a -> 'a`.
 pub fn typecheck(expr: &Expr) -> Result {
     let mut ctx = TypeContext::empty();
-    expr.check(&mut ctx)
+    let result = expr.check(&mut ctx);
+    finish(result, &ctx)
 }
 
-macro_rules! bail {
-    ($msg:expr) => { bail!($e, $msg,) };
+pub fn typecheck_program(program: &Program) -> Result {
+    let mut ctx = TypeContext::empty();
+    let result = check_decls(&program.decls, &program.body, &mut ctx);
+    finish(result, &ctx)
+}
+
+/// Like `typecheck_program`, but gives up with a "Cancelled" error once `token` is cancelled.
+pub fn typecheck_program_cancellable(program: &Program, token: &CancelToken) -> Result {
+    let mut ctx = TypeContext::with_cancel_token(token);
+    let result = check_decls(&program.decls, &program.body, &mut ctx);
+    finish(result, &ctx)
+}
 
-    ($msg:expr, $($farg:expr),*) => {
-        return Err(TypeError {
-            message: format!($msg $(, $farg)*),
+/// The type of a whole expression as it is shown, see `show`.
+fn finish(result: Result, ctx: &TypeContext) -> Result {
+    result.map(|t| show(&t, &mut Vec::new(), ctx))
+}
+
+/// Typechecks `expr` without stopping at the first error. Each expression
+/// which doesn't typecheck gets a type fitting anywhere instead, so that the
+/// rest can be checked, and the errors only caused by it aren't reported.
+/// Returns the type of `expr` if there were no errors, and all the errors.
+pub fn typecheck_all(expr: &Expr) -> (Option<Type>, Vec<TypeError>) {
+    let mut ctx = TypeContext::collecting_errors();
+    let result = expr.check(&mut ctx);
+    all_errors(result, ctx)
+}
+
+/// Like `typecheck_all`, for a program. A declaration with an error still
+/// binds its names for the ones after it.
+pub fn typecheck_program_all(program: &Program) -> (Option<Type>, Vec<TypeError>) {
+    let mut ctx = TypeContext::collecting_errors();
+    let result = check_decls(&program.decls, &program.body, &mut ctx);
+    all_errors(result, ctx)
+}
+
+fn all_errors(result: Result, ctx: TypeContext) -> (Option<Type>, Vec<TypeError>) {
+    let result = finish(result, &ctx);
+    let mut errors = ctx.into_errors();
+    match result {
+        Ok(t) if errors.is_empty() => (Some(t), errors),
+        Ok(_) => (None, errors),
+        Err(e) => {
+            errors.push(e);
+            (None, errors)
+        }
+    }
+}
+
+/// Typechecks `expr` in a scope where the names in `env` are bound.
+pub fn typecheck_in(env: &[(Ident, Type)], expr: &Expr) -> Result {
+    let mut ctx = TypeContext::empty();
+    let result = ctx.with_bindings(env.iter().map(|&(ref name, ref type_)| (name, type_.clone())),
+                                   |ctx| expr.check(ctx));
+    finish(result, &ctx)
+}
+
+/// Typechecks `program` in a scope where the names in `env` are bound, like
+/// the functions an embedder provides.
+pub fn typecheck_program_in(env: &[(Ident, Type)], program: &Program) -> Result {
+    let mut ctx = TypeContext::empty();
+    let result = ctx.with_bindings(env.iter().map(|&(ref name, ref type_)| (name, type_.clone())),
+                                   |ctx| check_decls(&program.decls, &program.body, ctx));
+    finish(result, &ctx)
+}
+
+/// Typechecks a declaration in a scope where the names in `env` are bound,
+/// returning the names it binds with their types, which must be fully known.
+pub fn typecheck_decl(env: &[(Ident, Type)],
+                      decl: &Decl)
+                      -> ::std::result::Result<Vec<(Ident, Type)>, TypeError> {
+    let mut ctx = TypeContext::empty();
+    ctx.with_bindings(env.iter().map(|&(ref name, ref type_)| (name, type_.clone())), |ctx| {
+        let bindings = try!(check_decl(decl, ctx).and_then(|bindings| known_bindings(bindings, ctx)));
+        Ok(bindings.into_iter().map(|(name, type_)| (name.clone(), type_)).collect())
+    })
+}
+
+/// What an editor shows of a part of a program: all its errors, found as by
+/// `typecheck_all`, and the type of each expression in it parsed from source,
+/// inner ones first.
+pub struct Annotations {
+    pub errors: Vec<TypeError>,
+    pub types: Vec<(Span, Type)>,
+}
+
+/// Annotates `expr` in a scope where the names in `env` are bound.
+pub fn annotate_in(env: &[(Ident, Type)], expr: &Expr) -> Annotations {
+    let mut ctx = TypeContext::annotating();
+    let result = ctx.with_bindings(env.iter().map(|&(ref name, ref type_)| (name, type_.clone())),
+                                   |ctx| expr.check(ctx));
+    let types = shown_annotations(&mut ctx);
+    let mut errors = ctx.into_errors();
+    if let Err(e) = result {
+        errors.push(e);
+    }
+    Annotations {
+        errors: errors,
+        types: types,
+    }
+}
+
+/// Annotates a declaration in a scope where the names in `env` are bound,
+/// returning the names it binds with their types as well. As in
+/// `typecheck_program_all`, a declaration with an error still binds its names.
+pub fn annotate_decl(env: &[(Ident, Type)], decl: &Decl) -> (Vec<(Ident, Type)>, Annotations) {
+    let mut ctx = TypeContext::annotating();
+    let result = ctx.with_bindings(env.iter().map(|&(ref name, ref type_)| (name, type_.clone())), |ctx| {
+        check_decl(decl, ctx).and_then(|bindings| known_bindings(bindings, ctx)).or_else(|e| {
+            try!(recover(e, ctx));
+            Ok(decl.names().into_iter().map(|name| (name, Error)).collect())
         })
+    });
+    let types = shown_annotations(&mut ctx);
+    let mut errors = ctx.into_errors();
+    let bindings = match result {
+        Ok(bindings) => bindings,
+        Err(e) => {
+            errors.push(e);
+            decl.names().into_iter().map(|name| (name, Error)).collect()
+        }
+    };
+    let annotations = Annotations {
+        errors: errors,
+        types: types,
+    };
+    (bindings.into_iter().map(|(name, type_)| (name.clone(), type_)).collect(), annotations)
+}
+
+/// The annotations kept by `ctx`, with their types as they are shown.
+fn shown_annotations(ctx: &mut TypeContext) -> Vec<(Span, Type)> {
+    let types = ctx.take_annotations();
+    types.into_iter().map(|(span, t)| (span, show(&t, &mut Vec::new(), ctx))).collect()
+}
+
+/// Checks that the types of the `bindings` of a declaration checked on its own
+/// are fully known: in a program, the uses of `r` after `let r = ref [];;` tell
+/// what it holds, but they aren't checked along with it here.
+fn known_bindings<'c>(bindings: Vec<(&'c Ident, Type)>,
+                      ctx: &TypeContext)
+                      -> ::std::result::Result<Vec<(&'c Ident, Type)>, TypeError> {
+    let mut result = Vec::new();
+    for (name, type_) in bindings {
+        let type_ = resolve(&type_, ctx);
+        if type_.has_vars() {
+            bail!(AmbiguousType {
+                name: *name,
+                found: show(&type_, &mut Vec::new(), ctx),
+            });
+        }
+        result.push((name, type_));
+    }
+    Ok(result)
+}
+
+fn check_decls<'c>(decls: &'c [Decl], body: &'c Expr, ctx: &mut TypeContext<'c>) -> Result {
+    let (decl, rest) = match decls.split_first() {
+        Some(split) => split,
+        None => return body.check(ctx),
+    };
+    let bindings = match check_decl(decl, ctx) {
+        Ok(bindings) => bindings,
+        Err(e) => {
+            // Going on, the names of the declaration are still bound.
+            try!(recover(e, ctx));
+            decl.names().into_iter().map(|name| (name, Error)).collect()
+        }
+    };
+    ctx.with_bindings(bindings, |ctx| check_decls(rest, body, ctx))
+}
+
+fn check_decl<'c>(decl: &'c Decl,
+                  ctx: &mut TypeContext<'c>)
+                  -> ::std::result::Result<Vec<(&'c Ident, Type)>, TypeError> {
+    match *decl {
+        Decl::Fun(ref fun) => Ok(vec![(&fun.fun_name, try!(check_let_fun(fun, ctx)))]),
+        Decl::LetRec(ref funs) => check_group(funs, ctx),
+        Decl::Value(ref name, ref value) => Ok(vec![(name, try!(check_let_value(value, ctx)))]),
+    }
+}
+
+/// Converts a type annotation, checking that the named types in it are declared
+/// and replacing aliases by the types they stand for. A type left out is a new
+/// type variable, and a type parameter the variable given to it by `with_params`.
+fn check_type(type_: &ast::Type, ctx: &mut TypeContext) -> Result {
+    let result = match *type_ {
+        ast::Type::Arrow(ref l, ref r) => try!(check_type(l, ctx)).maps_to(try!(check_type(r, ctx))),
+        ast::Type::List(ref t) => Type::List(Rc::new(try!(check_type(t, ctx)))),
+        ast::Type::Ref(ref t) => Type::Ref(Rc::new(try!(check_type(t, ctx)))),
+        ast::Type::Record(ref fields) => {
+            let mut field_types = Vec::new();
+            for &(name, ref t) in fields {
+                field_types.push((name, try!(check_type(t, ctx))));
+            }
+            try!(record_type(field_types))
+        }
+        ast::Type::Named(ref name) => {
+            match ctx.lookup_alias(name) {
+                Some(alias) => alias.clone(),
+                None if ctx.lookup_type(name).is_none() => bail!(UnknownType(*name)),
+                None => Named(*name),
+            }
+        }
+        ast::Type::Param(ref name) => {
+            match ctx.lookup_type_param(name) {
+                Some(var) => var.clone(),
+                None => bail!(UnboundTypeParam(*name)),
+            }
+        }
+        ast::Type::Infer => ctx.fresh(),
+        ast::Type::Int | ast::Type::Float | ast::Type::Bool | ast::Type::Str | ast::Type::Unit => type_.as_type(),
+    };
+    Ok(result)
+}
+
+/// The type of records with the `fields`, which must have different names.
+fn record_type(mut fields: Vec<(Ident, Type)>) -> Result {
+    fields.sort_by(|l, r| l.0.as_ref().cmp(r.0.as_ref()));
+    if let Some(pair) = fields.windows(2).find(|pair| pair[0].0 == pair[1].0) {
+        bail!(DuplicateField(pair[0].0));
+    }
+    Ok(Type::Record(Rc::new(fields)))
+}
+
+/// `type_` with the type variables bound so far replaced by their types.
+fn resolve(type_: &Type, ctx: &TypeContext) -> Type {
+    type_.substitute(&mut |t| match *t {
+        Type::Var(var) => {
+            match *ctx.type_var(var) {
+                TypeVar::Bound(ref t) => Some(resolve(t, ctx)),
+                TypeVar::Unbound(_) => None,
+            }
+        }
+        _ => None,
+    })
+}
+
+/// `type_` as it is shown to users: resolved, and with the type variables
+/// still unknown as type parameters, named in the order `names` first sees
+/// them. The types of a message share `names`, so the same variable has the
+/// same name in all of them.
+fn show(type_: &Type, names: &mut Vec<u32>, ctx: &TypeContext) -> Type {
+    resolve(type_, ctx).substitute(&mut |t| match *t {
+        Type::Var(var) => Some(param(var, names)),
+        _ => None,
+    })
+}
+
+/// The parameter standing for the variable `var`, numbered by its place in `vars`.
+fn param(var: u32, vars: &mut Vec<u32>) -> Type {
+    let i = match vars.iter().position(|&v| v == var) {
+        Some(i) => i,
+        None => {
+            vars.push(var);
+            vars.len() - 1
+        }
+    };
+    Param(i as u32)
+}
+
+/// The type of a name bound by a `let` to a value of type `type_`: the
+/// variables only this value mentions become parameters, so that each use of
+/// the name can give them other types.
+fn generalize(type_: &Type, ctx: &TypeContext) -> Type {
+    let mut vars = Vec::new();
+    resolve(type_, ctx).substitute(&mut |t| match *t {
+        Type::Var(var) if is_local(var, ctx) => Some(param(var, &mut vars)),
+        _ => None,
+    })
+}
+
+/// The type of a use of a name of type `type_`, with new variables for its parameters.
+fn instantiate(type_: &Type, ctx: &mut TypeContext) -> Type {
+    let mut vars: Vec<(u32, Type)> = Vec::new();
+    type_.substitute(&mut |t| match *t {
+        Param(param) => {
+            if let Some(&(_, ref var)) = vars.iter().find(|&&(p, _)| p == param) {
+                return Some(var.clone());
+            }
+            let var = ctx.fresh();
+            vars.push((param, var.clone()));
+            Some(var)
+        }
+        _ => None,
+    })
+}
+
+/// `type_`, or the type its variable is bound to if it is one, so that its
+/// outermost constructor is known if anything is.
+fn shallow(type_: &Type, ctx: &TypeContext) -> Type {
+    if let Type::Var(var) = *type_ {
+        if let TypeVar::Bound(ref t) = *ctx.type_var(var) {
+            return shallow(t, ctx);
+        }
+    }
+    type_.clone()
+}
+
+/// Why two types can't be unified.
+enum UnifyError {
+    /// Their parts differ, like `int` and `bool`.
+    Differ,
+    /// A variable would have to stand for a type mentioning it, which would be
+    /// infinite: `'a` and `'a -> 'b` in `x x`.
+    Infinite(u32, Type),
+    /// A variable would have to stand for a type mentioning a type declared
+    /// after it, outside of the scope of that type.
+    Escapes(Ident),
+}
+
+impl UnifyError {
+    /// The error to report, `differ` if the types just differ.
+    fn or(self, differ: TypeErrorKind) -> TypeErrorKind {
+        match self {
+            UnifyError::Differ => differ,
+            UnifyError::Infinite(var, type_) => InfiniteType { var: Type::Var(var), type_: type_ },
+            UnifyError::Escapes(name) => EscapingScope(name),
+        }
+    }
+}
+
+/// Makes `l` and `r` the same type by binding type variables in them. `Error`
+/// is the same as any type.
+fn unify(l: &Type, r: &Type, ctx: &mut TypeContext) -> ::std::result::Result<(), UnifyError> {
+    match (shallow(l, ctx), shallow(r, ctx)) {
+        (Error, _) | (_, Error) => Ok(()),
+        (Type::Var(l), Type::Var(r)) if l == r => Ok(()),
+        (Type::Var(var), t) | (t, Type::Var(var)) => bind(var, t, ctx),
+        (Arrow(l_arg, l_ret), Arrow(r_arg, r_ret)) => {
+            try!(unify(&l_arg, &r_arg, ctx));
+            unify(&l_ret, &r_ret, ctx)
+        }
+        (Type::List(l), Type::List(r)) | (Type::Ref(l), Type::Ref(r)) => unify(&l, &r, ctx),
+        (Type::Record(ref l), Type::Record(ref r)) if l.len() == r.len() => {
+            for (l, r) in l.iter().zip(r.iter()) {
+                if l.0 != r.0 {
+                    return Err(UnifyError::Differ);
+                }
+                try!(unify(&l.1, &r.1, ctx));
+            }
+            Ok(())
+        }
+        (ref l, ref r) if l == r => Ok(()),
+        _ => Err(UnifyError::Differ),
+    }
+}
+
+/// Binds the unbound variable `var` to `type_`, unless `type_` mentions it or
+/// a type declared after it.
+fn bind(var: u32, type_: Type, ctx: &mut TypeContext) -> ::std::result::Result<(), UnifyError> {
+    let level = match *ctx.type_var(var) {
+        TypeVar::Unbound(level) => level,
+        TypeVar::Bound(_) => unreachable!("`unify` binds unbound variables"),
     };
+    let later_types = ctx.types_above(level);
+    if !later_types.is_empty() {
+        let resolved = resolve(&type_, ctx);
+        if let Some(&name) = later_types.iter().find(|name| resolved.mentions(name)) {
+            return Err(UnifyError::Escapes(name));
+        }
+    }
+    if !lower_levels(var, level, &type_, ctx) {
+        return Err(UnifyError::Infinite(var, type_));
+    }
+    ctx.set_type_var(var, TypeVar::Bound(type_));
+    Ok(())
+}
+
+/// Checks that `var` doesn't occur in `type_`, which would have to be infinite,
+/// and lowers the variables in `type_` to `level`, as whatever mentions `var`
+/// will mention them.
+fn lower_levels(var: u32, level: u32, type_: &Type, ctx: &mut TypeContext) -> bool {
+    match shallow(type_, ctx) {
+        Type::Var(v) if v == var => false,
+        Type::Var(v) => {
+            if let TypeVar::Unbound(l) = *ctx.type_var(v) {
+                if l > level {
+                    ctx.set_type_var(v, TypeVar::Unbound(level));
+                }
+            }
+            true
+        }
+        Arrow(l, r) => lower_levels(var, level, &l, ctx) && lower_levels(var, level, &r, ctx),
+        Type::List(t) | Type::Ref(t) => lower_levels(var, level, &t, ctx),
+        Type::Record(fields) => fields.iter().all(|field| lower_levels(var, level, &field.1, ctx)),
+        Int | Float | Bool | Str | Unit | Named(_) | Error | Param(_) => true,
+    }
 }
 
 fn expect<'c>(expr: &'c Expr, type_: Type, ctx: &mut TypeContext<'c>) -> Result {
     let t = try!(expr.check(ctx));
-    if t != type_ {
-        bail!("Expected {:?}, got {:?} in {:?}", type_, t, expr);
+    fits(expr, t, type_, ctx)
+}
+
+/// Unifies `found`, the type of `expr`, with `expected`.
+fn fits(expr: &Expr, found: Type, expected: Type, ctx: &mut TypeContext) -> Result {
+    match unify(&found, &expected, ctx) {
+        Ok(()) => {}
+        // Using `expr` there, as in the application `x x`, is wrong, rather
+        // than `expr` itself: `Expr::check` of the one around gives the error
+        // its span.
+        Err(UnifyError::Infinite(var, type_)) => {
+            try!(recover(TypeError::from(InfiniteType { var: Type::Var(var), type_: type_ }), ctx));
+            // Nothing else is wrong with the types mentioning `var`.
+            ctx.set_type_var(var, TypeVar::Bound(Error));
+        }
+        Err(UnifyError::Escapes(name)) => {
+            try!(recover(TypeError::from(EscapingScope(name)), ctx));
+        }
+        Err(UnifyError::Differ) => {
+            let kind = Mismatch {
+                expected: expected.clone(),
+                found: found,
+                expr: format!("{:?}", expr),
+            };
+            // The expression is wrong, not the one around it, which can go on
+            // with the type it expects.
+            try!(recover(TypeError::from(kind).at(expr.span), ctx));
+        }
+    }
+    Ok(expected)
+}
+
+/// Whether evaluating `expr` can't have effects, so that the type of a name
+/// bound to it can be generalized: `r` in `let r = ref []` must hold lists of
+/// one type, or it could be given ints and read as bools.
+fn is_value(expr: &Expr) -> bool {
+    use ast::ExprKind::*;
+    match expr.kind {
+        Literal(_) | Var(_) | Fun(_) => true,
+        List(ref list) => list.items.iter().all(is_value),
+        Cons(ref cons) => is_value(&cons.head) && is_value(&cons.tail),
+        Record(ref record) => record.fields.iter().all(|field| is_value(&field.1)),
+        Construct(ref construct) => construct.arg.as_ref().map_or(true, is_value),
+        _ => false,
     }
-    Ok(type_)
 }
 
 trait Typecheck {
@@ -84,31 +951,82 @@ trait Typecheck {
 }
 
 impl Typecheck for Expr {
+    /// Errors point at the innermost expression they come from.
     fn check<'c>(&'c self, ctx: &mut TypeContext<'c>) -> Result {
-        use ast::Expr::*;
-        match *self {
+        use ast::ExprKind::*;
+        if ctx.is_cancelled() {
+            bail!(TypeErrorKind::Cancelled);
+        }
+        let reported = ctx.error_count();
+        let result = match self.kind {
             Var(ref ident) => {
-                ctx.lookup(ident)
-                   .cloned()
-                   .ok_or(TypeError { message: format!("Unbound variable: {}", ident) })
+                match ctx.lookup(ident).cloned() {
+                    Some(t) => Ok(instantiate(&t, ctx)),
+                    None => Err(TypeError::from(UnboundVariable(*ident))),
+                }
             }
             Literal(ref l) => l.check(ctx),
             ArithBinOp(ref op) => op.check(ctx),
             CmpBinOp(ref op) => op.check(ctx),
+            LogicBinOp(ref op) => op.check(ctx),
+            Not(ref not) => not.check(ctx),
+            List(ref list) => list.check(ctx),
+            Cons(ref cons) => cons.check(ctx),
+            Concat(ref concat) => concat.check(ctx),
+            ListUnOp(ref op) => op.check(ctx),
+            RefUnOp(ref op) => op.check(ctx),
+            PrintUnOp(ref op) => op.check(ctx),
+            ConvertUnOp(ref op) => op.check(ctx),
+            Assign(ref assign) => assign.check(ctx),
+            Seq(ref seq) => seq.check(ctx),
+            Record(ref record) => record.check(ctx),
+            Field(ref field) => field.check(ctx),
+            Construct(ref construct) => construct.check(ctx),
+            Match(ref match_) => match_.check(ctx),
             If(ref if_) => if_.check(ctx),
             Fun(ref fun) => fun.check(ctx),
+            Let(ref let_) => let_.check(ctx),
             LetFun(ref let_fun) => let_fun.check(ctx),
             LetRec(ref let_rec) => let_rec.check(ctx),
+            LetType(ref let_type) => let_type.check(ctx),
+            TypeAlias(ref alias) => alias.check(ctx),
             Apply(ref apply) => apply.check(ctx),
+        };
+        let result = result.or_else(|e| recover(e.at(self.span), ctx));
+        ctx.map_errors_from(reported, |e| e.at(self.span));
+        if let Ok(ref t) = result {
+            ctx.annotate(self.span, t);
         }
+        result
     }
 }
 
+/// Reports `error` to a context which goes on after errors, and gives the
+/// expression it is about the type `Error`. Errors caused by another one
+/// aren't reported again.
+fn recover(error: TypeError, ctx: &mut TypeContext) -> Result {
+    if error.kind == Cancelled {
+        return Err(error);
+    }
+    let mut names = Vec::new();
+    let error = TypeError {
+        kind: error.kind.map_types(&mut |t| show(&t, &mut names, ctx)),
+        span: error.span,
+    };
+    if !error.kind.is_caused_by_error() {
+        try!(ctx.report(error));
+    }
+    Ok(Error)
+}
+
 impl Typecheck for Literal {
     fn check<'c>(&'c self, _: &mut TypeContext<'c>) -> Result {
         let t = match *self {
             Literal::Number(_) => Int,
+            Literal::Float(_) => Float,
             Literal::Bool(_) => Bool,
+            Literal::Str(_) => Str,
+            Literal::Unit => Unit,
         };
         Ok(t)
     }
@@ -116,27 +1034,299 @@ impl Typecheck for Literal {
 
 impl Typecheck for ArithBinOp {
     fn check<'c>(&'c self, ctx: &mut TypeContext<'c>) -> Result {
-        try!(expect(&self.lhs, Int, ctx));
-        try!(expect(&self.rhs, Int, ctx));
-        Ok(Int)
+        // Both operands are ints or both are floats, there are no implicit conversions.
+        // An operand of an unknown type has that of the other, or is an int.
+        let lhs = try!(self.lhs.check(ctx));
+        let rhs = try!(self.rhs.check(ctx));
+        let operand_type = match (resolve(&lhs, ctx), resolve(&rhs, ctx)) {
+            (Float, _) | (Type::Var(_), Float) => Float,
+            (Int, _) | (Type::Var(_), _) => Int,
+            (t, _) => {
+                bail!(Mismatch {
+                    expected: Int,
+                    found: t,
+                    expr: format!("{:?}", self.lhs),
+                })
+            }
+        };
+        try!(fits(&self.lhs, lhs, operand_type.clone(), ctx));
+        fits(&self.rhs, rhs, operand_type, ctx)
     }
 }
 
 impl Typecheck for CmpBinOp {
     fn check<'c>(&'c self, ctx: &mut TypeContext<'c>) -> Result {
-        try!(expect(&self.lhs, Int, ctx));
-        try!(expect(&self.rhs, Int, ctx));
+        // Strings can be compared for equality, but not ordered. As in arithmetics,
+        // an operand of an unknown type has that of the other, or is an int.
+        let lhs = try!(self.lhs.check(ctx));
+        let rhs = try!(self.rhs.check(ctx));
+        let operand_type = match (self.kind, resolve(&lhs, ctx), resolve(&rhs, ctx)) {
+            (CmpOp::Eq, Str, _) | (CmpOp::Ne, Str, _) => Str,
+            (CmpOp::Eq, Type::Var(_), Str) | (CmpOp::Ne, Type::Var(_), Str) => Str,
+            (_, Int, _) => Int,
+            (_, Float, _) | (_, Type::Var(_), Float) => Float,
+            (_, Type::Var(_), _) => Int,
+            (_, t, _) => {
+                bail!(Mismatch {
+                    expected: Int,
+                    found: t,
+                    expr: format!("{:?}", self.lhs),
+                })
+            }
+        };
+        try!(fits(&self.lhs, lhs, operand_type.clone(), ctx));
+        try!(fits(&self.rhs, rhs, operand_type, ctx));
         Ok(Bool)
     }
 }
 
+impl Typecheck for LogicBinOp {
+    fn check<'c>(&'c self, ctx: &mut TypeContext<'c>) -> Result {
+        try!(expect(&self.lhs, Bool, ctx));
+        expect(&self.rhs, Bool, ctx)
+    }
+}
+
+impl Typecheck for Not {
+    fn check<'c>(&'c self, ctx: &mut TypeContext<'c>) -> Result {
+        expect(&self.arg, Bool, ctx)
+    }
+}
+
+impl Typecheck for Concat {
+    fn check<'c>(&'c self, ctx: &mut TypeContext<'c>) -> Result {
+        try!(expect(&self.lhs, Str, ctx));
+        expect(&self.rhs, Str, ctx)
+    }
+}
+
+impl Typecheck for List {
+    fn check<'c>(&'c self, ctx: &mut TypeContext<'c>) -> Result {
+        // Without an annotation, the first item gives the type of the others,
+        // and an empty list can have any type.
+        let (element_type, checked) = match self.type_ {
+            Some(ref type_) => {
+                match try!(check_type(type_, ctx)) {
+                    Type::List(t) => (t.as_ref().clone(), 0),
+                    t => bail!(NotAListType { found: t, expr: format!("{:?}", self) }),
+                }
+            }
+            None => {
+                match self.items.first() {
+                    Some(item) => (try!(item.check(ctx)), 1),
+                    None => (ctx.fresh(), 0),
+                }
+            }
+        };
+        for item in &self.items[checked..] {
+            try!(expect(item, element_type.clone(), ctx));
+        }
+        Ok(Type::List(Rc::new(element_type)))
+    }
+}
+
+impl Typecheck for Cons {
+    fn check<'c>(&'c self, ctx: &mut TypeContext<'c>) -> Result {
+        let head_type = try!(self.head.check(ctx));
+        expect(&self.tail, Type::List(Rc::new(head_type)), ctx)
+    }
+}
+
+impl Typecheck for ListUnOp {
+    fn check<'c>(&'c self, ctx: &mut TypeContext<'c>) -> Result {
+        let t = try!(self.arg.check(ctx));
+        let element_type = ctx.fresh();
+        if unify(&t, &Type::List(Rc::new(element_type.clone())), ctx).is_err() {
+            bail!(NotAList { found: t, expr: format!("{:?}", self.arg) });
+        }
+        Ok(match self.kind {
+            ListOp::Head => element_type,
+            ListOp::Tail => Type::List(Rc::new(element_type)),
+            ListOp::IsEmpty => Bool,
+        })
+    }
+}
+
+impl Typecheck for RefUnOp {
+    fn check<'c>(&'c self, ctx: &mut TypeContext<'c>) -> Result {
+        let t = try!(self.arg.check(ctx));
+        match self.kind {
+            RefOp::Ref => Ok(Type::Ref(Rc::new(t))),
+            RefOp::Deref => {
+                let value_type = ctx.fresh();
+                if unify(&t, &Type::Ref(Rc::new(value_type.clone())), ctx).is_err() {
+                    bail!(NotARef { found: t, expr: format!("{:?}", self.arg) });
+                }
+                Ok(value_type)
+            }
+        }
+    }
+}
+
+impl Typecheck for PrintUnOp {
+    fn check<'c>(&'c self, ctx: &mut TypeContext<'c>) -> Result {
+        let arg_type = match self.kind {
+            PrintOp::Print => Str,
+            PrintOp::PrintInt => Int,
+        };
+        try!(expect(&self.arg, arg_type, ctx));
+        Ok(Unit)
+    }
+}
+
+impl Typecheck for ConvertUnOp {
+    fn check<'c>(&'c self, ctx: &mut TypeContext<'c>) -> Result {
+        let (from, to) = match self.kind {
+            ConvertOp::IntOfFloat => (Float, Int),
+            ConvertOp::FloatOfInt => (Int, Float),
+        };
+        try!(expect(&self.arg, from, ctx));
+        Ok(to)
+    }
+}
+
+impl Typecheck for Assign {
+    fn check<'c>(&'c self, ctx: &mut TypeContext<'c>) -> Result {
+        let t = try!(self.cell.check(ctx));
+        let value_type = ctx.fresh();
+        if unify(&t, &Type::Ref(Rc::new(value_type.clone())), ctx).is_err() {
+            bail!(NotARef { found: t, expr: format!("{:?}", self.cell) });
+        }
+        try!(expect(&self.value, value_type, ctx));
+        Ok(Unit)
+    }
+}
+
+impl Typecheck for Seq {
+    fn check<'c>(&'c self, ctx: &mut TypeContext<'c>) -> Result {
+        try!(expect(&self.first, Unit, ctx));
+        self.second.check(ctx)
+    }
+}
+
+impl Typecheck for Record {
+    fn check<'c>(&'c self, ctx: &mut TypeContext<'c>) -> Result {
+        let mut fields = Vec::new();
+        for &(name, ref value) in &self.fields {
+            fields.push((name, try!(value.check(ctx))));
+        }
+        record_type(fields)
+    }
+}
+
+impl Typecheck for Field {
+    fn check<'c>(&'c self, ctx: &mut TypeContext<'c>) -> Result {
+        // Records aren't inferred from their fields, their type must be known.
+        let t = resolve(&try!(self.record.check(ctx)), ctx);
+        let found = match t {
+            Type::Record(ref fields) => {
+                fields.iter().find(|field| field.0 == self.name).map(|field| field.1.clone())
+            }
+            _ => bail!(NotARecord { found: t, expr: format!("{:?}", self.record) }),
+        };
+        match found {
+            Some(field_type) => Ok(field_type),
+            None => {
+                bail!(NoSuchField {
+                    name: self.name,
+                    found: t,
+                    expr: format!("{:?}", self.record),
+                })
+            }
+        }
+    }
+}
+
+impl Typecheck for Construct {
+    fn check<'c>(&'c self, ctx: &mut TypeContext<'c>) -> Result {
+        let (type_name, variant) = match ctx.lookup_constructor(&self.name) {
+            Some((type_name, _, variant)) => (*type_name, variant),
+            None => bail!(UnknownConstructor(self.name)),
+        };
+        match (&variant.arg, &self.arg) {
+            (&Some(ref type_), &Some(ref arg)) => {
+                let arg_type = try!(check_type(type_, ctx));
+                try!(expect(arg, arg_type, ctx));
+            }
+            (&None, &None) => {}
+            (&Some(_), &None) => bail!(MissingArgument(self.name)),
+            (&None, &Some(_)) => bail!(UnexpectedArgument(self.name)),
+        }
+        Ok(Named(type_name))
+    }
+}
+
+impl Typecheck for Match {
+    fn check<'c>(&'c self, ctx: &mut TypeContext<'c>) -> Result {
+        let scrutinee_type = try!(self.scrutinee.check(ctx));
+        // A scrutinee of an unknown type has the type of the first constructor.
+        let first = ctx.lookup_constructor(&self.arms[0].constructor);
+        if let (Type::Var(_), Some((type_name, _, _))) = (resolve(&scrutinee_type, ctx), first) {
+            let _ = unify(&scrutinee_type, &Named(*type_name), ctx);
+        }
+        let type_name = match resolve(&scrutinee_type, ctx) {
+            Named(name) => name,
+            t => bail!(NotAVariant { found: t, expr: format!("{:?}", self.scrutinee) }),
+        };
+        let variants = match ctx.lookup_type(&type_name) {
+            Some(variants) => variants,
+            None => bail!(EscapingScope(type_name)),
+        };
+        let mut seen = HashSet::new();
+        let mut result: Option<Type> = None;
+        for arm in &self.arms {
+            let variant = match variants.iter().find(|variant| variant.name == arm.constructor) {
+                Some(variant) => variant,
+                None => {
+                    bail!(ForeignConstructor {
+                        constructor: arm.constructor,
+                        type_name: type_name,
+                    })
+                }
+            };
+            if !seen.insert(arm.constructor) {
+                bail!(DuplicateArm {
+                    constructor: arm.constructor,
+                    expr: format!("{:?}", self),
+                });
+            }
+            let t = match (&variant.arg, &arm.binding) {
+                (&Some(ref type_), &Some(ref binding)) => {
+                    let arg_type = try!(check_type(type_, ctx));
+                    try!(ctx.with_bindings(vec![(binding, arg_type)], |ctx| arm.body.check(ctx)))
+                }
+                (_, &None) => try!(arm.body.check(ctx)),
+                (&None, &Some(_)) => bail!(UnexpectedArgument(arm.constructor)),
+            };
+            match result {
+                Some(ref first) => {
+                    if let Err(e) = unify(first, &t, ctx) {
+                        bail!(e.or(MatchArmsDiffer {
+                            first: first.clone(),
+                            other: t,
+                        }))
+                    }
+                }
+                None => result = Some(t),
+            }
+        }
+        if let Some(missing) = variants.iter().find(|variant| !seen.contains(&variant.name)) {
+            bail!(MissingArm {
+                constructor: missing.name,
+                expr: format!("{:?}", self),
+            });
+        }
+        Ok(result.expect("a match has at least one arm"))
+    }
+}
+
 impl Typecheck for If {
     fn check<'c>(&'c self, ctx: &mut TypeContext<'c>) -> Result {
         try!(expect(&self.cond, Bool, ctx));
         let t1 = try!(self.tru.check(ctx));
         let t2 = try!(self.fls.check(ctx));
-        if t1 != t2 {
-            bail!("Arms of an if have different types: {:?} {:?}", t1, t2);
+        if let Err(e) = unify(&t1, &t2, ctx) {
+            bail!(e.or(IfArmsDiffer { tru: t1, fls: t2 }));
         }
         Ok(t1)
     }
@@ -144,23 +1334,150 @@ impl Typecheck for If {
 
 impl Typecheck for Fun {
     fn check<'c>(&'c self, ctx: &mut TypeContext<'c>) -> Result {
-        let result = fun_type(self);
-        try!(ctx.with_bindings(vec![(&self.arg_name, self.arg_type.as_type()),
-                                    (&self.fun_name, result.clone())],
-                               |ctx| expect(&self.body, self.fun_type.as_type(), ctx)));
-        Ok(result)
+        with_params(slice::from_ref(self), ctx, |ctx| {
+            let (arg_types, ret_type) = try!(signature(self, ctx));
+            check_fun(self, arg_types, ret_type, ctx)
+        })
+    }
+}
+
+/// Checks a group of functions with `f`, with the type parameters their
+/// signatures introduce bound to new variables, then checks that these are
+/// still different variables which nothing around the functions mentions:
+/// the functions must work whatever types their parameters are.
+fn with_params<'c, R, F>(funs: &'c [Fun],
+                         ctx: &mut TypeContext<'c>,
+                         f: F)
+                         -> ::std::result::Result<R, TypeError>
+    where F: FnOnce(&mut TypeContext<'c>) -> ::std::result::Result<R, TypeError>
+{
+    let (result, params) = ctx.nested(|ctx| {
+        let params = new_params(funs, ctx);
+        (ctx.with_type_params(params.clone(), f), params)
+    });
+    let result = try!(result);
+    let mut vars = Vec::new();
+    for (param, var) in params {
+        let found = resolve(&var, ctx);
+        match found {
+            Type::Var(var) if !vars.contains(&var) && is_local(var, ctx) => vars.push(var),
+            _ => {
+                bail!(NotGeneric {
+                    param: param,
+                    found: show(&found, &mut Vec::new(), ctx),
+                })
+            }
+        }
+    }
+    Ok(result)
+}
+
+/// New variables for the type parameters the signatures of `funs` mention,
+/// except those of the signatures around them, which they aren't generic in:
+/// in `fun f(x: 'a) is fun g(y: 'a) is y`, `g` takes values of the type `f`
+/// is given.
+fn new_params(funs: &[Fun], ctx: &mut TypeContext) -> Vec<(Ident, Type)> {
+    let mut names = Vec::new();
+    for f in funs {
+        for type_ in f.args.iter().map(|arg| &arg.1).chain(Some(&f.fun_type)) {
+            param_names(type_, &mut names);
+        }
+    }
+    let mut params = Vec::new();
+    for name in names {
+        if ctx.lookup_type_param(&name).is_none() {
+            params.push((name, ctx.fresh()));
+        }
     }
+    params
 }
 
-fn fun_type(f: &Fun) -> Type {
-    let arg_type = f.arg_type.as_type();
-    let ret_type = f.fun_type.as_type();
-    arg_type.clone().maps_to(ret_type.clone())
+/// Adds the names of the type parameters in `type_` to `names`, once each.
+fn param_names(type_: &ast::Type, names: &mut Vec<Ident>) {
+    match *type_ {
+        ast::Type::Param(name) => {
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+        ast::Type::Arrow(ref l, ref r) => {
+            param_names(l, names);
+            param_names(r, names);
+        }
+        ast::Type::List(ref t) | ast::Type::Ref(ref t) => param_names(t, names),
+        ast::Type::Record(ref fields) => {
+            for field in fields {
+                param_names(&field.1, names);
+            }
+        }
+        ast::Type::Int | ast::Type::Float | ast::Type::Bool | ast::Type::Str | ast::Type::Unit |
+        ast::Type::Named(_) | ast::Type::Infer => {}
+    }
+}
+
+/// Whether `var` is unbound, and only mentioned by values nested deeper than
+/// the current level, see `TypeContext::nested`.
+fn is_local(var: u32, ctx: &TypeContext) -> bool {
+    match *ctx.type_var(var) {
+        TypeVar::Unbound(level) => level > ctx.level(),
+        TypeVar::Bound(_) => false,
+    }
+}
+
+/// Checks the body of `f` against its signature, and returns the type of `f`.
+fn check_fun<'c>(f: &'c Fun, arg_types: Vec<Type>, ret_type: Type, ctx: &mut TypeContext<'c>) -> Result {
+    let result = curry(&arg_types, ret_type.clone());
+    // The scopes are those of the nested functions the parameters desugar
+    // to: the name of the function shadows the first parameter, and the
+    // other parameters shadow it.
+    let mut bindings = vec![(&f.args[0].0, arg_types[0].clone()), (&f.fun_name, result.clone())];
+    bindings.extend(f.args[1..].iter().map(|arg| &arg.0).zip(arg_types[1..].iter().cloned()));
+    try!(ctx.with_bindings(bindings, |ctx| expect(&f.body, ret_type, ctx)));
+    Ok(result)
+}
+
+/// The types of the parameters of `f` and the type it returns, variables for
+/// those left out.
+fn signature(f: &Fun, ctx: &mut TypeContext) -> ::std::result::Result<(Vec<Type>, Type), TypeError> {
+    let mut arg_types = Vec::new();
+    for &(_, ref arg_type) in &f.args {
+        arg_types.push(try!(check_type(arg_type, ctx)));
+    }
+    Ok((arg_types, try!(check_type(&f.fun_type, ctx))))
+}
+
+fn curry(arg_types: &[Type], ret_type: Type) -> Type {
+    arg_types.iter().rev().fold(ret_type, |ret_type, arg_type| arg_type.clone().maps_to(ret_type))
+}
+
+/// The type of a name bound to `value` by a `let`, generalized if `value` is
+/// a value, see `is_value`.
+fn check_let_value<'c>(value: &'c Expr, ctx: &mut TypeContext<'c>) -> Result {
+    if !is_value(value) {
+        return value.check(ctx);
+    }
+    let t = try!(ctx.nested(|ctx| value.check(ctx)));
+    Ok(generalize(&t, ctx))
+}
+
+/// The generalized type of a function bound by a `let`. Its name has the type
+/// it is being inferred in its body, so a function can't call itself with
+/// arguments of other types.
+fn check_let_fun<'c>(fun: &'c Fun, ctx: &mut TypeContext<'c>) -> Result {
+    let t = try!(ctx.nested(|ctx| fun.check(ctx)));
+    Ok(generalize(&t, ctx))
+}
+
+impl Typecheck for Let {
+    fn check<'c>(&'c self, ctx: &mut TypeContext<'c>) -> Result {
+        let value_type = try!(check_let_value(&self.value, ctx));
+        ctx.with_bindings(vec![(&self.name, value_type)], |ctx| self.body.check(ctx))
+    }
 }
 
 impl Typecheck for LetFun {
     fn check<'c>(&'c self, ctx: &mut TypeContext<'c>) -> Result {
-        let fun_type = try!(self.fun.check(ctx));
+        let fun_type = try!(check_let_fun(&self.fun, ctx));
         ctx.with_bindings(vec![(&self.fun.fun_name, fun_type)],
                           |ctx| self.body.check(ctx))
     }
@@ -168,33 +1485,121 @@ impl Typecheck for LetFun {
 
 impl Typecheck for LetRec {
     fn check<'c>(&'c self, ctx: &mut TypeContext<'c>) -> Result {
-        let bindings = try!(collect_bindings(&self.funs));
-        ctx.with_bindings(bindings, |ctx| {
-            for fun in &self.funs {
-                try!(fun.check(ctx));
+        let bindings = try!(check_group(&self.funs, ctx));
+        ctx.with_bindings(bindings, |ctx| self.body.check(ctx))
+    }
+}
+
+/// Binds the signatures of all the functions of a `let rec` group first, so
+/// that they can call each other, then checks each body against its
+/// signature. Returns the bindings of the group, generalized once all the
+/// bodies are checked.
+fn check_group<'c>(funs: &'c [Fun],
+                   ctx: &mut TypeContext<'c>)
+                   -> ::std::result::Result<Vec<(&'c Ident, Type)>, TypeError> {
+    let bindings = try!(with_params(funs, ctx, |ctx| -> ::std::result::Result<Vec<(&'c Ident, Type)>, _> {
+        let signatures = try!(collect_signatures(funs, ctx));
+        let bindings = funs.iter()
+                           .zip(&signatures)
+                           .map(|(fun, &(ref args, ref ret))| (&fun.fun_name, curry(args, ret.clone())))
+                           .collect::<Vec<_>>();
+        try!(ctx.with_bindings(bindings.clone(), |ctx| -> ::std::result::Result<(), TypeError> {
+            for (fun, (arg_types, ret_type)) in funs.iter().zip(signatures) {
+                let start = ctx.error_count();
+                let result = check_fun(fun, arg_types, ret_type, ctx);
+                ctx.map_errors_from(start, |e| e.in_function(fun.fun_name));
+                try!(result.map_err(|e| e.in_function(fun.fun_name)));
             }
-            self.body.check(ctx)
-        })
+            Ok(())
+        }));
+        Ok(bindings)
+    }));
+    Ok(bindings.into_iter().map(|(name, t)| (name, generalize(&t, ctx))).collect())
+}
+
+impl Typecheck for LetType {
+    fn check<'c>(&'c self, ctx: &mut TypeContext<'c>) -> Result {
+        if ctx.lookup_type(&self.name).is_some() || ctx.lookup_alias(&self.name).is_some() {
+            bail!(TypeRedefined(self.name));
+        }
+        let names = self.variants.iter().map(|variant| &variant.name).collect::<HashSet<_>>();
+        if names.len() != self.variants.len() {
+            bail!(DuplicateConstructors(self.name));
+        }
+        let t = try!(ctx.with_type(&self.name, &self.variants, |ctx| {
+            for variant in &self.variants {
+                if !variant.name.as_ref().starts_with(|c: char| c.is_uppercase()) {
+                    bail!(LowercaseConstructor(variant.name));
+                }
+                if let Some(ref arg) = variant.arg {
+                    try!(check_type(arg, ctx));
+                }
+            }
+            let t = try!(self.body.check(ctx));
+            if resolve(&t, ctx).mentions(&self.name) {
+                bail!(EscapingType {
+                    name: self.name,
+                    expr: format!("{:?}", self),
+                });
+            }
+            Ok(t)
+        }));
+        // The variables of `t` are mentioned outside the declaration now, so
+        // they go down to the level there, as when a variable of it is bound
+        // to `t`.
+        let outer = ctx.fresh();
+        let _ = unify(&outer, &t, ctx);
+        Ok(t)
+    }
+}
+
+impl Typecheck for TypeAlias {
+    fn check<'c>(&'c self, ctx: &mut TypeContext<'c>) -> Result {
+        if ctx.lookup_type(&self.name).is_some() || ctx.lookup_alias(&self.name).is_some() {
+            bail!(TypeRedefined(self.name));
+        }
+        let type_ = try!(check_type(&self.type_, ctx));
+        let name = self.name;
+        let rename = |e: TypeError| {
+            TypeError {
+                kind: e.kind.with_alias(name, &type_),
+                span: e.span,
+            }
+        };
+        let start = ctx.error_count();
+        let result = ctx.with_alias(&self.name, type_.clone(), |ctx| self.body.check(ctx));
+        ctx.map_errors_from(start, &rename);
+        result.map_err(rename)
     }
 }
 
-fn collect_bindings(funs: &[Fun]) -> ::std::result::Result<Vec<(&Ident, Type)>, TypeError> {
-    let names = funs.iter().map(|fun| &fun.fun_name).collect::<HashSet<_>>();
-    if names.len() != funs.len() {
-        return bail!("Duplicate definitions in letrec: {:?}", funs);
+fn collect_signatures(funs: &[Fun],
+                      ctx: &mut TypeContext)
+                      -> ::std::result::Result<Vec<(Vec<Type>, Type)>, TypeError> {
+    let mut names = HashSet::new();
+    let mut result = Vec::new();
+    for f in funs {
+        if !names.insert(&f.fun_name) {
+            bail!(DuplicateDefinitions(f.fun_name));
+        }
+        result.push(try!(signature(f, ctx).map_err(|e| e.in_function(f.fun_name))));
     }
-    Ok(funs.iter().map(|f| (&f.fun_name, fun_type(f))).collect())
+    Ok(result)
 }
 
 impl Typecheck for Apply {
     fn check<'c>(&'c self, ctx: &mut TypeContext<'c>) -> Result {
-        match try!(self.fun.check(ctx)) {
-            Type::Arrow(arg, ret) => {
-                try!(expect(&self.arg, arg.as_ref().clone(), ctx));
-                Ok(ret.as_ref().clone())
-            }
-            _ => return bail!("Not a function {:?}", self.fun),
+        let fun_type = try!(self.fun.check(ctx));
+        if resolve(&fun_type, ctx) == Error {
+            try!(self.arg.check(ctx));
+            return Ok(Error);
         }
+        let (arg_type, ret_type) = (ctx.fresh(), ctx.fresh());
+        if unify(&fun_type, &arg_type.clone().maps_to(ret_type.clone()), ctx).is_err() {
+            bail!(NotAFunction { expr: format!("{:?}", self.fun) });
+        }
+        try!(expect(&self.arg, arg_type, ctx));
+        Ok(ret_type)
     }
 }
 
@@ -237,6 +1642,9 @@ mod tests {
 
         assert_valid("1 + 1", Int);
         assert_fails("1 * true");
+        assert_valid("-(7 % 2)", Int);
+        assert_fails("true % 1");
+        assert_fails("-true");
     }
 
     #[test]
@@ -244,6 +1652,17 @@ mod tests {
         assert_valid("1 < 1", Bool);
         assert_fails("true == true");
         assert_fails("false > 92");
+        assert_valid("1 <= 2", Bool);
+        assert_valid(r#""a" != "b""#, Bool);
+        assert_fails(r#""a" >= "b""#);
+    }
+
+    #[test]
+    fn test_logic() {
+        assert_valid("true && not false || 1 < 2", Bool);
+        assert_fails("1 && true");
+        assert_fails("true || 1");
+        assert_fails("not 1");
     }
 
     #[test]
@@ -265,6 +1684,212 @@ mod tests {
         assert_fails("(fun id (x: int): int is x) true");
     }
 
+    #[test]
+    fn test_lists() {
+        let ints = Type::List(Rc::new(Int));
+        assert_valid("[1, 2, 3]", ints.clone());
+        assert_valid("[]: int list", ints.clone());
+        assert_valid("1 :: 2 :: []: int list", ints.clone());
+        assert_valid("head [1]", Int);
+        assert_valid("tail [1]", ints.clone());
+        assert_valid("isEmpty ([]: bool list)", Bool);
+        assert_valid("[[1], []: int list]", Type::List(Rc::new(ints.clone())));
+        assert_valid("[fun id(x: int): int is x]", Type::List(Rc::new(Int.maps_to(Int))));
+
+        assert_fails("[1, true]");
+        assert_fails("[]: int");
+        assert_fails("true :: [1]");
+        assert_fails("1 :: 2");
+        assert_fails("head 1");
+        assert_fails("[1] + 1");
+        assert_fails("[1] == [1]");
+    }
+
+    #[test]
+    fn test_variants() {
+        let shape = "type shape = Circle of int | Square of int | Point in ";
+        let valid = |expr: &str, type_: Type| assert_valid(&format!("{}{}", shape, expr), type_);
+        valid("1", Int);
+        valid("match Circle 1 with Circle r -> r | Square a -> a * a | Point -> 0", Int);
+        valid("match Point with Point -> true | Square _a -> false | Circle _r -> false", Bool);
+        valid("match Point with Point -> 1 | Square a -> a | Circle -> 0", Int);
+        valid("(fun area(s: shape): int is match s with Circle r -> 3 * r * r | Square a -> a * a
+                                                   | Point -> 0) (Square 2)",
+              Int);
+        assert_valid("type nat = Z | S of nat in
+                      let rec fun to_int(n: nat): int is match n with Z -> 0 | S m -> 1 + to_int m
+                      in to_int (S (S Z))",
+                     Int);
+
+        let fails = |expr: &str| assert_fails(&format!("{}{}", shape, expr));
+        fails("Circle");
+        fails("Circle true");
+        fails("Point 1");
+        fails("Triangle 1");
+        fails("Circle 1");
+        fails("fun f(s: shape): shape is s");
+        fails("match 1 with Circle r -> r | Square a -> a | Point -> 0");
+        fails("match Point with Circle r -> r | Square a -> a");
+        fails("match Point with Circle r -> r | Square a -> a | Point -> 0 | Point -> 1");
+        fails("match Point with Circle r -> r | Square a -> true | Point -> 0");
+        fails("match Point with Circle r -> r | Square a -> a | Point p -> 0");
+        fails("type shape = Empty in 1");
+        assert_fails("type t = A | A in 1");
+        assert_fails("type t = a in 1");
+        assert_fails("type t = A of u in 1");
+        assert_fails("fun f(x: t): int is 1");
+        assert_fails("[]: t list");
+    }
+
+    #[test]
+    fn test_type_aliases() {
+        let predicate = Int.maps_to(Bool);
+        assert_valid("type predicate = int -> bool in fun even(n: int): bool is n % 2 == 0",
+                     predicate.clone());
+        assert_valid("type predicate = int -> bool in
+                      let fun both(p: predicate, q: predicate): predicate is fun r(n: int): bool is p n && q n
+                      in both",
+                     predicate.clone().maps_to(predicate.clone().maps_to(predicate.clone())));
+        assert_valid("type ints = int list in type table = ints -> int in fun first(xs: ints): int is head xs",
+                     Type::List(Rc::new(Int)).maps_to(Int));
+        assert_valid("type r = int ref in type shape = Circle of r | Point in
+                      match Circle (ref 1) with Circle r -> !r | Point -> 0",
+                     Int);
+
+        assert_fails("type predicate = int -> bool in type predicate = int -> int in 1");
+        assert_fails("type shape = Point in type shape = int list in 1");
+        assert_fails("let x = (type n = int in 1) in fun f(y: n): int is y");
+        assert_fails("type shape = Circle of r | Point in type r = int in 1");
+
+        let expr = parse("type predicate = int -> bool in
+                          let fun apply(p: predicate, x: int): bool is p x in apply 1 2");
+        let message = typecheck(&expr).unwrap_err().to_string();
+        assert_eq!(message, "Expected predicate, got int in 1");
+    }
+
+    #[test]
+    fn test_records() {
+        let point = Type::Record(Rc::new(vec![(Ident::from_str("x"), Int), (Ident::from_str("y"), Bool)]));
+        assert_valid("{x = 1, y = true}", point.clone());
+        assert_valid("{y = true, x = 1}", point.clone());
+        assert_valid("{x = 1, y = true}.x + 1", Int);
+        assert_valid("fun f(p: {y: bool, x: int}): bool is p.y", point.clone().maps_to(Bool));
+        assert_valid("(fun f(p: {x: int, y: bool}): int is p.x) {y = false, x = 92}", Int);
+        assert_valid("{inner = {x = 1, y = true}}.inner", point.clone());
+        assert_valid("[{x = 1, y = true}, {y = false, x = 2}]", Type::List(Rc::new(point.clone())));
+        assert_valid("type point = {x: int, y: bool} in fun origin(u: unit): point is {x = 0, y = false}",
+                     Unit.maps_to(point.clone()));
+
+        assert_fails("{x = 1, x = 2}");
+        assert_fails("fun f(p: {x: int, x: int}): int is 1");
+        assert_fails("fun f(p: {x: t}): int is 1");
+        assert_fails("{x = 1}.y");
+        assert_fails("1.x");
+        assert_fails("{x = 1} == {x = 1}");
+        assert_fails("[{x = 1}, {x = true}]");
+        assert_fails("[{x = 1}, {x = 1, y = true}]");
+
+        let message = |src: &str| typecheck(&parse(src)).unwrap_err().to_string();
+        assert_eq!(message("{x = 1, y = true}.z"),
+                   "No field z in {x: int, y: bool}, the type of {x = 1, y = true}");
+        assert_eq!(message("let r = 1 in r.x"), "Expected a record, got int in r");
+        assert_eq!(message("{x = 1, y = 2, x = 3}"), "Duplicate field: x");
+        assert_eq!(message("type point = {x: int} in (fun f(p: point): int is p.x) 1"),
+                   "Expected point, got int in 1");
+    }
+
+    #[test]
+    fn test_strings() {
+        assert_valid(r#""hello""#, Str);
+        assert_valid(r#""hello, " ++ "world""#, Str);
+        assert_valid(r#""a" ++ "b" ++ "c""#, Str);
+        assert_valid(r#""a" == "b""#, Bool);
+        assert_valid("fun greet(name: string): string is \"hi \" ++ name", Str.maps_to(Str));
+        assert_valid(r#"["a", "b"]"#, Type::List(Rc::new(Str)));
+
+        assert_fails(r#""a" ++ 1"#);
+        assert_fails(r#"1 ++ "a""#);
+        assert_fails(r#""a" + "b""#);
+        assert_fails(r#""a" < "b""#);
+        assert_fails(r#""a" == 1"#);
+        assert_fails(r#"1 == "a""#);
+    }
+
+    #[test]
+    fn test_refs() {
+        let int_ref = Type::Ref(Rc::new(Int));
+        assert_valid("ref 1", int_ref.clone());
+        assert_valid("!(ref 1) + 1", Int);
+        assert_valid("let r = ref 1 in r := !r + 1", Unit);
+        assert_valid("[ref 1, ref 2]", Type::List(Rc::new(int_ref.clone())));
+        assert_valid("fun get(r: int ref): int is !r", int_ref.clone().maps_to(Int));
+        assert_valid("ref (ref true)", Type::Ref(Rc::new(Type::Ref(Rc::new(Bool)))));
+
+        assert_fails("!1");
+        assert_fails("1 := 2");
+        assert_fails("(ref 1) := true");
+        assert_fails("ref 1 == ref 1");
+        assert_fails("fun f(r: t ref): int is 1");
+    }
+
+    #[test]
+    fn test_sequences() {
+        assert_valid("()", Unit);
+        assert_valid("let r = ref 1 in r := 2; r := !r + 1; !r", Int);
+        assert_valid("fun skip(u: unit): unit is ()", Unit.maps_to(Unit));
+        assert_valid("(); true", Bool);
+
+        assert_fails("1; 2");
+        assert_fails("() + 1");
+        assert_fails("() == ()");
+    }
+
+    #[test]
+    fn test_print() {
+        assert_valid(r#"print "hello""#, Unit);
+        assert_valid(r#"print_int 1; print ("a" ++ "b"); 92"#, Int);
+        assert_valid("fun show(x: int): unit is print_int x", Int.maps_to(Unit));
+
+        assert_fails("print 1");
+        assert_fails(r#"print_int "1""#);
+        assert_fails("print_int 1 + 1");
+    }
+
+    #[test]
+    fn test_float() {
+        assert_valid("1.5 * 2.0 - 0.5", Float);
+        assert_valid("0.5 < 1.0 && 1.5 == 1.5", Bool);
+        assert_valid("int_of_float (float_of_int 3 / 2.0)", Int);
+        assert_valid("fun half(x: float): float is x / 2.0", Float.maps_to(Float));
+
+        assert_fails("1 + 1.0");
+        assert_fails("1.0 < 2");
+        assert_fails("int_of_float 1");
+        assert_fails("float_of_int 1.0");
+        assert_fails("print_int 1.0");
+    }
+
+    #[test]
+    fn test_curried_fun() {
+        assert_valid("fun add(x: int, y: int): int is x + y", Int.maps_to(Int.maps_to(Int)));
+        assert_valid("let fun add(x: int, y: int): int is x + y in add 1 2", Int);
+        assert_valid("let fun add(x: int, y: int): int is x + y in add 1", Int.maps_to(Int));
+        assert_valid("fun pick(b: bool, x: int, y: int): int is if b then x else y",
+                     Bool.maps_to(Int.maps_to(Int.maps_to(Int))));
+
+        assert_fails("let fun add(x: int, y: int): int is x + y in add 1 true");
+        assert_fails("fun add(x: int, y: int): int is add x");
+    }
+
+    #[test]
+    fn test_let() {
+        assert_valid("let x = 2 + 2 in x * x", Int);
+        assert_valid("let f = fun id (x: int): int is x in f 92", Int);
+
+        assert_fails("let x = true in x + 1");
+        assert_fails("let x = x in x");
+    }
+
     #[test]
     fn test_let_fun() {
         assert_valid("let fun inc (x: int): int is x + 1 in inc 92", Int);
@@ -272,12 +1897,212 @@ mod tests {
         assert_fails("let fun inc (x: int): int is x + 1 in inc inc");
     }
 
+    #[test]
+    fn test_inference() {
+        let ints = Type::List(Rc::new(Int));
+        assert_valid("fun inc(x) is x + 1", Int.maps_to(Int));
+        assert_valid("fun half(x) is x / 2.0", Float.maps_to(Float));
+        assert_valid("fun greet(name) is \"hi \" ++ name", Str.maps_to(Str));
+        assert_valid("fun call(f, x: int): bool is f x", Int.maps_to(Bool).maps_to(Int.maps_to(Bool)));
+        assert_valid("fun sum(xs) is if isEmpty xs then 0 else head xs + sum (tail xs)", ints.maps_to(Int));
+        assert_valid("fun const(x, y) is x", Param(0).maps_to(Param(1).maps_to(Param(0))));
+        assert_valid("[]", Type::List(Rc::new(Param(0))));
+        assert_valid("let r = ref [] in r := [1]; head !r", Int);
+        assert_valid("type shape = Circle of int | Point in
+                      (fun area(s) is match s with Circle r -> r * r | Point -> 0) Point",
+                     Int);
+
+        assert_fails("fun f(x) is x + true");
+        assert_fails("fun f(x) is x.y");
+        assert_fails("fun f(x) is if x then x else 1");
+        assert_fails("let r = ref [] in r := [1]; r := [true]; 1");
+
+        let message = |src: &str| typecheck(&parse(src)).unwrap_err().to_string();
+        assert_eq!(message("let fun id(x) is x in 1 + id"), "Expected int, got 'a -> 'a in id");
+    }
+
+    #[test]
+    fn test_polymorphism() {
+        let a = || Param(0);
+        let record = |a: Type, b: Type| {
+            Type::Record(Rc::new(vec![(Ident::from_str("a"), a), (Ident::from_str("b"), b)]))
+        };
+        assert_valid("fun id(x) is x", a().maps_to(a()));
+        assert_valid("let fun id(x) is x in {a = id 1, b = id true}", record(Int, Bool));
+        assert_valid("let fun id(x) is x in id id", a().maps_to(a()));
+        assert_valid("let fun twice(f, x) is f (f x) in twice", a().maps_to(a()).maps_to(a().maps_to(a())));
+        assert_valid("let fun twice(f, x) is f (f x) in
+                      let fun inc(n: int): int is n + 1 in
+                      {a = twice inc 0, b = twice (fun neg(b: bool): bool is not b) true}",
+                     record(Int, Bool));
+        assert_valid("let fun compose(f, g) is fun h(x) is f (g x) in compose",
+                     Param(0).maps_to(Param(1))
+                             .maps_to(Param(2).maps_to(Param(0)).maps_to(Param(2).maps_to(Param(1)))));
+        assert_valid("let fun compose(f, g) is fun h(x) is f (g x) in
+                      let fun even(n: int): bool is n % 2 == 0 in
+                      let fun id(x) is x in
+                      {a = compose id even 2, b = (compose id id) \"c\"}",
+                     record(Bool, Str));
+        assert_valid("let rec fun len(xs) is if isEmpty xs then 0 else 1 + len (tail xs)
+                      in {a = len [1], b = len [true]}",
+                     record(Int, Int));
+        assert_fails("let fun f(x) is let y = f 1 in f true in f");
+
+        // Only names bound to values are generalized.
+        assert_valid("let f = fun id(x) is x in {a = f 1, b = f true}", record(Int, Bool));
+        assert_valid("let xs = [] in {a = 1 :: xs, b = true :: xs}",
+                     record(Type::List(Rc::new(Int)), Type::List(Rc::new(Bool))));
+        assert_fails("let f = (fun id(x) is x) (fun id(x) is x) in {a = f 1, b = f true}");
+        assert_fails("let r = ref [] in {a = 1 :: !r, b = true :: !r}");
+
+        let decl = |src: &str| ::syntax::parse_decl(src).unwrap();
+        let bindings = typecheck_decl(&[], &decl("let fun id(x) is x;;")).unwrap();
+        assert!(bindings == vec![(Ident::from_str("id"), a().maps_to(a()))]);
+        assert!(typecheck_in(&bindings, &parse("{a = id 1, b = id true}")).unwrap() == record(Int, Bool));
+        let message = typecheck_decl(&[], &decl("let r = ref [];;")).unwrap_err().to_string();
+        assert_eq!(message, "The type of r is not fully known: 'a list ref");
+        // In a program, the uses of `r` tell its type.
+        let program = ::syntax::parse_program("let r = ref [];; r := [1]; head !r").unwrap();
+        assert!(typecheck_program(&program).unwrap() == Int);
+    }
+
+    #[test]
+    fn test_type_params() {
+        let (a, b) = (|| Param(0), || Param(1));
+        assert_valid("fun apply(f: 'a -> 'b, x: 'a): 'b is f x", a().maps_to(b()).maps_to(a().maps_to(b())));
+        assert_valid("fun id(x: 'b): 'b is x", a().maps_to(a()));
+        assert_valid("let fun id(x: 'a): 'a is x in if id true then id 1 else 2", Int);
+        assert_valid("let rec fun len(xs: 'a list): int is if isEmpty xs then 0 else 1 + len (tail xs)
+                      in len [1] + len [true]",
+                     Int);
+        // `'a` in `g` is that of `f`.
+        assert_valid("fun f(x: 'a): 'a is let fun g(y: 'a): 'a is y in g x", a().maps_to(a()));
+        assert_valid("(fun id(x: 'a): 'a is x) 1", Int);
+
+        let message = |src: &str| typecheck(&parse(src)).unwrap_err().to_string();
+        assert_eq!(message("fun inc(x: 'a): 'a is x + 1"),
+                   "The type parameter 'a can't be any type, it is int");
+        assert_eq!(message("fun first(x: 'a, y: 'b): 'a is if true then x else y"),
+                   "The type parameter 'b can't be any type, it is 'a");
+        assert_eq!(message("fun f(x) is let fun g(y: 'a): 'a is x in g"),
+                   "The type parameter 'a can't be any type, it is 'a");
+        assert_eq!(message("type t = A of 'a in 1"), "Unbound type parameter: 'a");
+        assert_fails("let rec fun f(x: 'a): 'a is g x and fun g(y: 'b): int is 1 in f");
+    }
+
+    #[test]
+    fn test_infinite_types() {
+        let error = |src: &str| typecheck(&parse(src)).unwrap_err();
+        let e = error("fun f(x) is x x");
+        assert_eq!(e.to_string(), "Cannot construct infinite type: 'a = 'a -> 'b");
+        assert_eq!(e.span, Some(Span::new(12, 15)));
+        assert_eq!(error("fun f(x) is if true then x else [x]").to_string(),
+                   "Cannot construct infinite type: 'a = 'a list");
+        assert_eq!(error("fun f(x) is x :: x").to_string(), "Cannot construct infinite type: 'a = 'a list");
+        assert_eq!(error("let rec fun f(x) is f in f").to_string(),
+                   "In f: Cannot construct infinite type: 'a = 'b -> 'a");
+        assert_fails("let r = ref [] in r := [r]; 1");
+    }
+
+    #[test]
+    fn test_program() {
+        let program = |src: &str| {
+            ::syntax::parse_program(src).expect(&format!("Failed to parse {}", src))
+        };
+        let main = program("let fun inc(x: int): int is x + 1;;
+                            let main = inc 91;;");
+        assert!(typecheck_program(&main).unwrap() == Int);
+        let with_body = program("let rec fun a(x: int): bool is b x
+                                 and fun b(x: int): bool is a x;;
+                                 a");
+        assert!(typecheck_program(&with_body).unwrap() == Int.maps_to(Bool));
+
+        assert!(typecheck_program(&program("let x = 92;;")).is_err());
+        assert!(typecheck_program(&program("let x = 92;; x + true")).is_err());
+    }
+
+    #[test]
+    fn test_env() {
+        use ast::Ident;
+        let env = vec![(Ident::from_str("x"), Int)];
+        assert!(typecheck_in(&env, &parse("x + 1")).unwrap() == Int);
+        assert!(typecheck_in(&[], &parse("x + 1")).is_err());
+
+        let decl = ::syntax::parse_decl("let rec fun f(b: bool): int is if b then x else g 1
+                                         and fun g(y: int): int is f true;;")
+                       .unwrap();
+        let bindings = typecheck_decl(&env, &decl).unwrap();
+        assert!(bindings == vec![(Ident::from_str("f"), Bool.maps_to(Int)),
+                                 (Ident::from_str("g"), Int.maps_to(Int))]);
+        assert!(typecheck_decl(&[], &decl).is_err());
+
+        let program = ::syntax::parse_program("let fun inc(y: int): int is x + y;; inc x").unwrap();
+        assert!(typecheck_program_in(&env, &program).unwrap() == Int);
+        assert!(typecheck_program_in(&[], &program).is_err());
+    }
+
+    #[test]
+    fn test_error_spans() {
+        let span = |src: &str| typecheck(&parse(src)).unwrap_err().span;
+        assert_eq!(span("1 + y"), Some(Span::new(4, 5)));
+        assert_eq!(span("if 1 then 2 else 3"), Some(Span::new(3, 4)));
+        assert_eq!(span("(fun f(x: int): int is x) true"), Some(Span::new(26, 30)));
+        assert_eq!(span("1 2"), Some(Span::new(0, 3)));
+        assert_eq!(typecheck(&ast::expr::add(ast::expr::int(1), ast::expr::bool(true))).unwrap_err().span,
+                   None);
+    }
+
     #[test]
     fn test_let_rec() {
         assert_valid("let rec fun a(x: int): int is b (a (b 1))
                       and fun b(x: int): int is (a (b (a 1)))
                       in (a (a (b (b 1))))",
                      Int);
+        assert_valid("let rec fun even(n: int): bool is if n == 0 then true else odd (n - 1)
+                      and fun odd(n: int): bool is if n == 0 then false else even (n - 1)
+                      in odd",
+                     Int.maps_to(Bool));
+
+        let message = |src: &str| typecheck(&parse(src)).unwrap_err().to_string();
+        assert_eq!(message("let rec fun even(n: int): bool is if n == 0 then true else odd (n - 1)
+                            and fun odd(n: int): bool is if n == 0 then 1 else even (n - 1)
+                            in even 2"),
+                   "In odd: Arms of an if have different types: int bool");
+        assert_eq!(message("let rec fun f(n: int): int is g n and fun g(n: bool): int is 1 in f 1"),
+                   "In f: Expected bool, got int in n");
+        assert_eq!(message("let rec fun f(n: int): int is n and fun g(n: int): foo is n in f 1"),
+                   "In g: Unknown type: foo");
+        assert_eq!(message("let rec fun f(n: int): int is n and fun f(n: int): int is n in f 1"),
+                   "Duplicate definitions of f in letrec");
+    }
+
+    #[test]
+    fn test_all_errors() {
+        let messages = |src: &str| {
+            let (type_, errors) = typecheck_all(&parse(src));
+            assert!(type_.is_none());
+            errors.iter().map(|e| e.to_string()).collect::<Vec<_>>()
+        };
+        assert_eq!(messages("(1 + true) :: [false]"),
+                   vec!["Expected int, got bool in true", "Expected int list, got bool list in [false]"]);
+        assert_eq!(messages("(1 + true) :: [2 + false]"),
+                   vec!["Expected int, got bool in true", "Expected int, got bool in false"]);
+        assert_eq!(messages("if 1 then y else 2 + y"),
+                   vec!["Expected bool, got int in 1", "Unbound variable: y", "Unbound variable: y"]);
+        assert_eq!(messages("(fun f(x: int): bool is x) (1 + true)"),
+                   vec!["Expected bool, got int in x", "Expected int, got bool in true"]);
+        assert_eq!(messages("(fun f(x) is x x) (1 + true)"),
+                   vec!["Cannot construct infinite type: 'a = 'a -> 'b", "Expected int, got bool in true"]);
+        assert_eq!(typecheck_all(&parse("(fun f(x) is x x) (1 + true)")).1[0].span, Some(Span::new(13, 16)));
+        let (type_, errors) = typecheck_all(&parse("1 + 2"));
+        assert!(type_ == Some(Int) && errors.is_empty());
 
+        let program = ::syntax::parse_program("let fun f(x: int): int is x + true;; \
+                                               let y = z;; f true + y")
+                          .unwrap();
+        let (type_, errors) = typecheck_program_all(&program);
+        assert!(type_.is_none());
+        assert_eq!(errors.iter().map(|e| e.to_string()).collect::<Vec<_>>(),
+                   vec!["Expected int, got bool in true", "Unbound variable: z", "Expected int, got bool in true"]);
     }
 }
@@ -0,0 +1,108 @@
+//! Values that don't borrow the program which computed them.
+
+use std::fmt;
+
+use ast;
+use secd::{Machine, Value};
+
+/// A value copied out of the machine that computed it, so that, unlike
+/// `Value`, it can outlive the machine and the compiled program, and be sent
+/// to another thread.
+#[derive(Debug, Clone, PartialEq)]
+pub enum OwnedValue {
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Unit,
+    Str(String),
+    List(Vec<OwnedValue>),
+    /// A constructor, applied to its argument if it has one.
+    Variant(String, Option<Box<OwnedValue>>),
+    /// A record, with its fields sorted by name.
+    Record(Vec<(String, OwnedValue)>),
+    /// A reference cell, with what it held when the value was copied.
+    Ref(Box<OwnedValue>),
+    /// A reference cell inside of itself, which stands for the outer one.
+    Cycle,
+    /// A function, which can only be shown, like `<closure>`.
+    Function(String),
+}
+
+impl OwnedValue {
+    /// Copies `value`, with the lists, strings, records and cells it refers to, out of `machine`.
+    pub fn new<'p>(machine: &Machine<'p>, value: Value<'p>) -> OwnedValue {
+        copy(machine, value, &mut Vec::new())
+    }
+}
+
+/// Shows the value like the REPL does.
+impl fmt::Display for OwnedValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            OwnedValue::Int(i) => i.fmt(f),
+            OwnedValue::Float(x) => write!(f, "{:?}", x),
+            OwnedValue::Bool(b) => b.fmt(f),
+            OwnedValue::Unit => f.write_str("()"),
+            OwnedValue::Str(ref s) => write!(f, "\"{}\"", ast::escape(s)),
+            OwnedValue::List(ref items) => {
+                let items = items.iter().map(|item| item.to_string()).collect::<Vec<_>>();
+                write!(f, "[{}]", items.join(", "))
+            }
+            OwnedValue::Variant(ref name, None) => f.write_str(name),
+            OwnedValue::Variant(ref name, Some(ref arg)) => write!(f, "{} {}", name, Arg(arg)),
+            OwnedValue::Record(ref fields) => {
+                let fields = fields.iter().map(|field| format!("{} = {}", field.0, field.1)).collect::<Vec<_>>();
+                write!(f, "{{{}}}", fields.join(", "))
+            }
+            OwnedValue::Ref(ref value) => write!(f, "ref {}", Arg(value)),
+            OwnedValue::Cycle => f.write_str("<cycle>"),
+            OwnedValue::Function(ref display) => f.write_str(display),
+        }
+    }
+}
+
+/// A value as an argument of a constructor or `ref`: parenthesized if it is
+/// itself an application.
+struct Arg<'a>(&'a OwnedValue);
+
+impl<'a> fmt::Display for Arg<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self.0 {
+            OwnedValue::Variant(_, Some(_)) | OwnedValue::Ref(_) => write!(f, "({})", self.0),
+            ref value => value.fmt(f),
+        }
+    }
+}
+
+/// Copies `value` out of `machine`; `cells` are the reference cells it is inside of.
+fn copy<'p>(machine: &Machine<'p>, value: Value<'p>, cells: &mut Vec<Value<'p>>) -> OwnedValue {
+    match value {
+        Value::Int(i) => OwnedValue::Int(i),
+        Value::Float(x) => OwnedValue::Float(x),
+        Value::Bool(b) => OwnedValue::Bool(b),
+        Value::Unit => OwnedValue::Unit,
+        Value::Str(_) => OwnedValue::Str(machine.string(value).unwrap().to_owned()),
+        Value::Nil | Value::Cons(_) => {
+            let items = machine.list(value).unwrap();
+            OwnedValue::List(items.into_iter().map(|item| copy(machine, item, cells)).collect())
+        }
+        Value::Variant(_) => {
+            let (_, name, arg) = machine.variant(value).unwrap();
+            OwnedValue::Variant(name.to_owned(), arg.map(|arg| Box::new(copy(machine, arg, cells))))
+        }
+        Value::Record(_) => {
+            let fields = machine.record(value).unwrap().iter().map(|&(name, value)| {
+                (name.to_owned(), copy(machine, value, cells))
+            });
+            OwnedValue::Record(fields.collect())
+        }
+        Value::Ref(_) if cells.contains(&value) => OwnedValue::Cycle,
+        Value::Ref(_) => {
+            cells.push(value);
+            let contents = copy(machine, machine.contents(value).unwrap(), cells);
+            cells.pop();
+            OwnedValue::Ref(Box::new(contents))
+        }
+        Value::Closure(_) | Value::Native(_) => OwnedValue::Function(value.to_string()),
+    }
+}
@@ -0,0 +1,401 @@
+//! A tree-walking interpreter, which evaluates the AST directly instead of
+//! compiling it for the machine. It needs no bytecode and starts at once, and
+//! as it shares nothing with the compiler and the machine but the AST, it is
+//! an oracle to check them against.
+//!
+//! Environments are linked lists shared between closures. Calls recurse on
+//! the Rust stack, without tail calls, so deeply recursive programs which
+//! the machine runs in constant space can overflow it.
+
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+use ast::{ArithOp, CmpOp, ConvertOp, Expr, ExprKind, Fun, ListOp, Literal, LogicOp, PrintOp, RefOp};
+use error::Error;
+use secd::{RuntimeError, RuntimeErrorKind};
+use typecheck::typecheck;
+use value::OwnedValue;
+
+/// Typechecks and evaluates `expr` without the machine. What it prints goes
+/// to the standard output.
+///
+/// ```
+/// use miniml::{OwnedValue, eval_ast, parse};
+///
+/// let expr = parse("let rec fun fact(n: int): int is if n == 0 then 1 else n * fact (n - 1) in fact 5");
+/// assert_eq!(eval_ast(&expr.unwrap()).unwrap(), OwnedValue::Int(120));
+/// ```
+pub fn eval_ast(expr: &Expr) -> Result<OwnedValue, Error> {
+    try!(typecheck(expr));
+    let value = try!(eval(expr, &Env(None)));
+    Ok(own(&value, &mut Vec::new()))
+}
+
+#[derive(Clone)]
+enum Value<'e> {
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Unit,
+    Str(Rc<str>),
+    Closure(Rc<Closure<'e>>),
+    List(Rc<Vec<Value<'e>>>),
+    /// A constructor, applied to its argument if it has one.
+    Variant(&'e str, Option<Rc<Value<'e>>>),
+    /// The fields, sorted by name.
+    Record(Rc<Vec<(&'e str, Value<'e>)>>),
+    Ref(Rc<RefCell<Value<'e>>>),
+}
+
+/// Function `index` of a group of mutually recursive functions, applied to
+/// its first `applied` parameters, which are bound in `env`.
+struct Closure<'e> {
+    group: &'e [Fun],
+    index: usize,
+    applied: usize,
+    env: Env<'e>,
+}
+
+#[derive(Clone)]
+struct Env<'e>(Option<Rc<Binding<'e>>>);
+
+struct Binding<'e> {
+    name: &'e str,
+    value: Value<'e>,
+    outer: Env<'e>,
+}
+
+impl<'e> Env<'e> {
+    fn bind(&self, name: &'e str, value: Value<'e>) -> Env<'e> {
+        Env(Some(Rc::new(Binding {
+            name: name,
+            value: value,
+            outer: self.clone(),
+        })))
+    }
+
+    fn lookup(&self, name: &str) -> Option<&Value<'e>> {
+        let mut env = self;
+        while let Some(ref binding) = env.0 {
+            if binding.name == name {
+                return Some(&binding.value);
+            }
+            env = &binding.outer;
+        }
+        None
+    }
+
+    /// Binds the functions of `group`, which can call each other.
+    fn bind_group(&self, group: &'e [Fun]) -> Env<'e> {
+        let mut env = self.clone();
+        for (i, fun) in group.iter().enumerate() {
+            let closure = Closure {
+                group: group,
+                index: i,
+                applied: 0,
+                env: self.clone(),
+            };
+            env = env.bind(fun.fun_name.as_ref(), Value::Closure(Rc::new(closure)));
+        }
+        env
+    }
+}
+
+fn eval<'e>(expr: &'e Expr, env: &Env<'e>) -> Result<Value<'e>, RuntimeError> {
+    match expr.kind {
+        ExprKind::Var(ref name) => env.lookup(name.as_ref()).cloned().ok_or(ill_typed()),
+        ExprKind::Literal(Literal::Number(n)) => Ok(Value::Int(n)),
+        ExprKind::Literal(Literal::Float(x)) => Ok(Value::Float(x)),
+        ExprKind::Literal(Literal::Bool(b)) => Ok(Value::Bool(b)),
+        ExprKind::Literal(Literal::Str(ref s)) => Ok(Value::Str(s.as_str().into())),
+        ExprKind::Literal(Literal::Unit) => Ok(Value::Unit),
+        ExprKind::ArithBinOp(ref op) => {
+            let (lhs, rhs) = match try!(eval(&op.lhs, env)) {
+                Value::Int(lhs) => (lhs, try!(int(&op.rhs, env))),
+                Value::Float(lhs) => {
+                    let rhs = try!(float(&op.rhs, env));
+                    return Ok(Value::Float(match op.kind {
+                        ArithOp::Add => lhs + rhs,
+                        ArithOp::Sub => lhs - rhs,
+                        ArithOp::Mul => lhs * rhs,
+                        ArithOp::Div => lhs / rhs,
+                        ArithOp::Mod => lhs % rhs,
+                    }));
+                }
+                _ => return Err(ill_typed()),
+            };
+            let result = match op.kind {
+                ArithOp::Div | ArithOp::Mod if rhs == 0 => {
+                    return Err(runtime_error(RuntimeErrorKind::DivisionByZero))
+                }
+                ArithOp::Add => lhs.checked_add(rhs),
+                ArithOp::Sub => lhs.checked_sub(rhs),
+                ArithOp::Mul => lhs.checked_mul(rhs),
+                ArithOp::Div => lhs.checked_div(rhs),
+                ArithOp::Mod => lhs.checked_rem(rhs),
+            };
+            result.map(Value::Int).ok_or(runtime_error(RuntimeErrorKind::Overflow))
+        }
+        ExprKind::CmpBinOp(ref op) => {
+            let lhs = try!(eval(&op.lhs, env));
+            let rhs = try!(eval(&op.rhs, env));
+            Ok(Value::Bool(match (op.kind, lhs, rhs) {
+                (CmpOp::Eq, Value::Str(lhs), Value::Str(rhs)) => lhs == rhs,
+                (CmpOp::Ne, Value::Str(lhs), Value::Str(rhs)) => lhs != rhs,
+                (kind, Value::Int(lhs), Value::Int(rhs)) => compare(kind, lhs, rhs),
+                (kind, Value::Float(lhs), Value::Float(rhs)) => compare(kind, lhs, rhs),
+                _ => return Err(ill_typed()),
+            }))
+        }
+        ExprKind::LogicBinOp(ref op) => {
+            let lhs = try!(bool(&op.lhs, env));
+            match op.kind {
+                LogicOp::And if !lhs => Ok(Value::Bool(false)),
+                LogicOp::Or if lhs => Ok(Value::Bool(true)),
+                _ => bool(&op.rhs, env).map(Value::Bool),
+            }
+        }
+        ExprKind::Not(ref not) => bool(&not.arg, env).map(|b| Value::Bool(!b)),
+        ExprKind::List(ref list) => {
+            let mut items = Vec::with_capacity(list.items.len());
+            for item in &list.items {
+                items.push(try!(eval(item, env)));
+            }
+            Ok(Value::List(Rc::new(items)))
+        }
+        ExprKind::Cons(ref cons) => {
+            let head = try!(eval(&cons.head, env));
+            let tail = try!(list(&cons.tail, env));
+            let mut items = Vec::with_capacity(tail.len() + 1);
+            items.push(head);
+            items.extend(tail.iter().cloned());
+            Ok(Value::List(Rc::new(items)))
+        }
+        ExprKind::Concat(ref concat) => {
+            let lhs = try!(string(&concat.lhs, env));
+            let rhs = try!(string(&concat.rhs, env));
+            Ok(Value::Str((lhs.to_string() + &rhs).into()))
+        }
+        ExprKind::ListUnOp(ref op) => {
+            let items = try!(list(&op.arg, env));
+            match op.kind {
+                ListOp::IsEmpty => Ok(Value::Bool(items.is_empty())),
+                ListOp::Head if items.is_empty() => Err(runtime_error(RuntimeErrorKind::HeadOfEmptyList)),
+                ListOp::Tail if items.is_empty() => Err(runtime_error(RuntimeErrorKind::TailOfEmptyList)),
+                ListOp::Head => Ok(items[0].clone()),
+                ListOp::Tail => Ok(Value::List(Rc::new(items[1..].to_vec()))),
+            }
+        }
+        ExprKind::RefUnOp(ref op) => {
+            match (op.kind, try!(eval(&op.arg, env))) {
+                (RefOp::Ref, value) => Ok(Value::Ref(Rc::new(RefCell::new(value)))),
+                (RefOp::Deref, Value::Ref(cell)) => Ok(cell.borrow().clone()),
+                _ => Err(ill_typed()),
+            }
+        }
+        ExprKind::PrintUnOp(ref op) => {
+            let line = match op.kind {
+                PrintOp::Print => try!(string(&op.arg, env)).to_string(),
+                PrintOp::PrintInt => try!(int(&op.arg, env)).to_string(),
+            };
+            try!(writeln!(io::stdout(), "{}", line)
+                     .map_err(|e| runtime_error(RuntimeErrorKind::Output(e.to_string()))));
+            Ok(Value::Unit)
+        }
+        ExprKind::ConvertUnOp(ref op) => {
+            match op.kind {
+                ConvertOp::IntOfFloat => float(&op.arg, env).map(|x| Value::Int(x as i64)),
+                ConvertOp::FloatOfInt => int(&op.arg, env).map(|i| Value::Float(i as f64)),
+            }
+        }
+        ExprKind::Assign(ref assign) => {
+            let cell = match try!(eval(&assign.cell, env)) {
+                Value::Ref(cell) => cell,
+                _ => return Err(ill_typed()),
+            };
+            *cell.borrow_mut() = try!(eval(&assign.value, env));
+            Ok(Value::Unit)
+        }
+        ExprKind::Seq(ref seq) => {
+            try!(eval(&seq.first, env));
+            eval(&seq.second, env)
+        }
+        ExprKind::Record(ref record) => {
+            let mut fields = Vec::with_capacity(record.fields.len());
+            for &(ref name, ref value) in &record.fields {
+                fields.push((name.as_ref(), try!(eval(value, env))));
+            }
+            fields.sort_by_key(|&(name, _)| name);
+            Ok(Value::Record(Rc::new(fields)))
+        }
+        ExprKind::Field(ref field) => {
+            match try!(eval(&field.record, env)) {
+                Value::Record(fields) => {
+                    fields.iter()
+                          .find(|&&(name, _)| name == field.name.as_ref())
+                          .map(|&(_, ref value)| value.clone())
+                          .ok_or(ill_typed())
+                }
+                _ => Err(ill_typed()),
+            }
+        }
+        ExprKind::Construct(ref construct) => {
+            let arg = match construct.arg {
+                Some(ref arg) => Some(Rc::new(try!(eval(arg, env)))),
+                None => None,
+            };
+            Ok(Value::Variant(construct.name.as_ref(), arg))
+        }
+        ExprKind::Match(ref match_) => {
+            let (name, arg) = match try!(eval(&match_.scrutinee, env)) {
+                Value::Variant(name, arg) => (name, arg),
+                _ => return Err(ill_typed()),
+            };
+            let arm = match match_.arms.iter().find(|arm| arm.constructor.as_ref() == name) {
+                Some(arm) => arm,
+                None => return Err(ill_typed()),
+            };
+            match (&arm.binding, arg) {
+                (&Some(ref binding), Some(arg)) => {
+                    eval(&arm.body, &env.bind(binding.as_ref(), (*arg).clone()))
+                }
+                _ => eval(&arm.body, env),
+            }
+        }
+        ExprKind::If(ref if_) => {
+            if try!(bool(&if_.cond, env)) {
+                eval(&if_.tru, env)
+            } else {
+                eval(&if_.fls, env)
+            }
+        }
+        ExprKind::Fun(ref fun) => {
+            Ok(Value::Closure(Rc::new(Closure {
+                group: ::std::slice::from_ref(&**fun),
+                index: 0,
+                applied: 0,
+                env: env.clone(),
+            })))
+        }
+        ExprKind::Let(ref let_) => {
+            let value = try!(eval(&let_.value, env));
+            eval(&let_.body, &env.bind(let_.name.as_ref(), value))
+        }
+        ExprKind::LetFun(ref let_fun) => {
+            eval(&let_fun.body, &env.bind_group(::std::slice::from_ref(&let_fun.fun)))
+        }
+        ExprKind::LetRec(ref let_rec) => eval(&let_rec.body, &env.bind_group(&let_rec.funs)),
+        ExprKind::LetType(ref let_type) => eval(&let_type.body, env),
+        ExprKind::TypeAlias(ref alias) => eval(&alias.body, env),
+        ExprKind::Apply(ref apply) => {
+            let closure = match try!(eval(&apply.fun, env)) {
+                Value::Closure(closure) => closure,
+                _ => return Err(ill_typed()),
+            };
+            let arg = try!(eval(&apply.arg, env));
+            let fun = &closure.group[closure.index];
+            let env = if closure.applied == 0 {
+                closure.env.bind_group(closure.group)
+            } else {
+                closure.env.clone()
+            };
+            let env = env.bind(fun.args[closure.applied].0.as_ref(), arg);
+            if closure.applied + 1 < fun.args.len() {
+                return Ok(Value::Closure(Rc::new(Closure {
+                    group: closure.group,
+                    index: closure.index,
+                    applied: closure.applied + 1,
+                    env: env,
+                })));
+            }
+            eval(&fun.body, &env)
+        }
+    }
+}
+
+fn compare<T: PartialOrd>(kind: CmpOp, lhs: T, rhs: T) -> bool {
+    match kind {
+        CmpOp::Eq => lhs == rhs,
+        CmpOp::Ne => lhs != rhs,
+        CmpOp::Lt => lhs < rhs,
+        CmpOp::Le => lhs <= rhs,
+        CmpOp::Gt => lhs > rhs,
+        CmpOp::Ge => lhs >= rhs,
+    }
+}
+
+fn list<'e>(expr: &'e Expr, env: &Env<'e>) -> Result<Rc<Vec<Value<'e>>>, RuntimeError> {
+    match try!(eval(expr, env)) {
+        Value::List(items) => Ok(items),
+        _ => Err(ill_typed()),
+    }
+}
+
+fn bool<'e>(expr: &'e Expr, env: &Env<'e>) -> Result<bool, RuntimeError> {
+    match try!(eval(expr, env)) {
+        Value::Bool(b) => Ok(b),
+        _ => Err(ill_typed()),
+    }
+}
+
+fn string<'e>(expr: &'e Expr, env: &Env<'e>) -> Result<Rc<str>, RuntimeError> {
+    match try!(eval(expr, env)) {
+        Value::Str(s) => Ok(s),
+        _ => Err(ill_typed()),
+    }
+}
+
+fn int<'e>(expr: &'e Expr, env: &Env<'e>) -> Result<i64, RuntimeError> {
+    match try!(eval(expr, env)) {
+        Value::Int(i) => Ok(i),
+        _ => Err(ill_typed()),
+    }
+}
+
+fn float<'e>(expr: &'e Expr, env: &Env<'e>) -> Result<f64, RuntimeError> {
+    match try!(eval(expr, env)) {
+        Value::Float(x) => Ok(x),
+        _ => Err(ill_typed()),
+    }
+}
+
+fn runtime_error(kind: RuntimeErrorKind) -> RuntimeError {
+    RuntimeError::from(kind)
+}
+
+/// A value of the wrong type or an unbound variable, which the typechecker
+/// rules out.
+fn ill_typed() -> RuntimeError {
+    runtime_error(RuntimeErrorKind::Fatal("ill typed expression"))
+}
+
+/// Copies `value` into an `OwnedValue`; `cells` are the reference cells it is inside of.
+fn own<'e>(value: &Value<'e>, cells: &mut Vec<*const RefCell<Value<'e>>>) -> OwnedValue {
+    match *value {
+        Value::Int(i) => OwnedValue::Int(i),
+        Value::Float(x) => OwnedValue::Float(x),
+        Value::Bool(b) => OwnedValue::Bool(b),
+        Value::Unit => OwnedValue::Unit,
+        Value::Str(ref s) => OwnedValue::Str(s.to_string()),
+        Value::Closure(_) => OwnedValue::Function("<closure>".to_owned()),
+        Value::List(ref items) => OwnedValue::List(items.iter().map(|item| own(item, cells)).collect()),
+        Value::Variant(name, ref arg) => {
+            OwnedValue::Variant(name.to_owned(), arg.as_ref().map(|arg| Box::new(own(arg, cells))))
+        }
+        Value::Record(ref fields) => {
+            let fields = fields.iter().map(|&(name, ref value)| (name.to_owned(), own(value, cells)));
+            OwnedValue::Record(fields.collect())
+        }
+        Value::Ref(ref cell) => {
+            let ptr = &**cell as *const RefCell<Value<'e>>;
+            if cells.contains(&ptr) {
+                return OwnedValue::Cycle;
+            }
+            cells.push(ptr);
+            let contents = own(&cell.borrow(), cells);
+            cells.pop();
+            OwnedValue::Ref(Box::new(contents))
+        }
+    }
+}
@@ -0,0 +1,90 @@
+//! Bindings for a browser playground, built with `--features wasm`.
+//!
+//! Errors are thrown as JS exceptions carrying the message. Programs run on a
+//! budget of `FUEL` instructions and `MAX_HEAP` objects on the heap, so that a
+//! runaway program can't hang or exhaust the page. What they `print` is
+//! kept in memory, as a page has no standard output; with `--features
+//! playground`, `eval_to_string` returns it.
+
+use wasm_bindgen::prelude::*;
+
+use ast::Program;
+use compile::compile_ir;
+use error::Error;
+use ir::desugar_program;
+use parser::Parser;
+use secd::{Machine, RuntimeErrorKind, Status};
+use typecheck::typecheck_program;
+
+const FUEL: u64 = 10_000_000;
+const MAX_HEAP: usize = 1_000_000;
+
+/// Runs the program `src` and returns its value, formatted like in the REPL.
+#[wasm_bindgen]
+pub fn execute(src: &str) -> Result<JsValue, JsValue> {
+    to_js(run(src, &mut Vec::new()))
+}
+
+/// Runs the program `src` and returns what it printed followed by its value,
+/// or by the error which stopped it, for a page to show as it is.
+#[cfg(feature = "playground")]
+#[wasm_bindgen]
+pub fn eval_to_string(src: &str) -> String {
+    let mut output = Vec::new();
+    let result = run(src, &mut output);
+    let mut text = String::from_utf8_lossy(&output).into_owned();
+    match result {
+        Ok(value) => text.push_str(&value),
+        Err(e) => text.push_str(&e.to_string()),
+    }
+    text
+}
+
+/// Returns the type of the program `src`.
+#[wasm_bindgen]
+pub fn typecheck(src: &str) -> Result<JsValue, JsValue> {
+    to_js(parse(src).and_then(|program| {
+        typecheck_program(&program)
+            .map(|type_| format!("{:?}", type_))
+            .map_err(Error::Type)
+    }))
+}
+
+/// Returns the AST of the program `src` as an s-expression.
+#[wasm_bindgen]
+pub fn dump_ast(src: &str) -> Result<JsValue, JsValue> {
+    to_js(parse(src).map(|program| format!("{:?}", program)))
+}
+
+/// Returns the machine instructions the program `src` compiles to.
+#[wasm_bindgen]
+pub fn dump_bytecode(src: &str) -> Result<JsValue, JsValue> {
+    to_js(parse(src).map(|program| format!("{:#?}", compile_ir(&desugar_program(&program)))))
+}
+
+fn run(src: &str, output: &mut Vec<u8>) -> Result<String, Error> {
+    let program = try!(parse(src));
+    try!(typecheck_program(&program).map_err(Error::Type));
+    let frame = compile_ir(&desugar_program(&program));
+    let mut machine = Machine::new(&frame).with_output(output);
+    for _ in 0..FUEL {
+        match machine.step() {
+            Status::Finished(value) => return Ok(machine.show(value)),
+            Status::Running if machine.heap_size() > MAX_HEAP => {
+                return Err(Error::Runtime(RuntimeErrorKind::OutOfMemory.into()))
+            }
+            Status::Running => {}
+            Status::Error(e) => return Err(Error::Runtime(e)),
+        }
+    }
+    Err(Error::Runtime(RuntimeErrorKind::OutOfFuel.into()))
+}
+
+fn parse(src: &str) -> Result<Program, Error> {
+    Parser::default().parse_program(src).map_err(Error::Parse)
+}
+
+fn to_js(result: Result<String, Error>) -> Result<JsValue, JsValue> {
+    result.map(|value| JsValue::from_str(&value))
+          .map_err(|e| JsValue::from_str(&e.to_string()))
+}
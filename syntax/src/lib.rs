@@ -1,8 +1,42 @@
 extern crate ast;
+extern crate lalrpop_util;
 
 mod parser;
 mod parser_util;
 
 pub use self::parser::parse_Expr as parse;
 pub use self::parser::parse_Type as parse_type;
+pub use self::parser::parse_Program as parse_program;
+pub use self::parser::parse_Decl as parse_decl;
+pub use lalrpop_util::ParseError;
 
+/// Blanks out `--` line comments, which the LALRPOP lexer can't skip.
+/// Comments are replaced with spaces, so offsets in parse errors stay valid.
+/// A `--` inside a string literal is part of the string.
+pub fn strip_comments(input: &str) -> String {
+    let mut result = String::with_capacity(input.len());
+    let mut in_comment = false;
+    let mut in_string = false;
+    let mut escaped = false;
+    let mut chars = input.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\n' {
+            in_comment = false;
+            in_string = false;
+        } else if in_string {
+            in_string = escaped || c != '"';
+            escaped = !escaped && c == '\\';
+        } else if c == '"' && !in_comment {
+            in_string = true;
+            escaped = false;
+        } else if c == '-' && chars.peek() == Some(&'-') {
+            in_comment = true;
+        }
+        if in_comment {
+            result.extend(::std::iter::repeat(' ').take(c.len_utf8()));
+        } else {
+            result.push(c);
+        }
+    }
+    result
+}
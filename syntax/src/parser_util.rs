@@ -1,4 +1,12 @@
-use ast::{Ident, Type, Expr, ArithBinOp, ArithOp, CmpBinOp, CmpOp, If, Apply, Fun, LetFun, LetRec};
+use ast::{Ident, Span, Type, Expr, ExprKind, ArithBinOp, ArithOp, CmpBinOp, CmpOp, LogicBinOp, LogicOp, Not, List,
+          Cons, Concat, ListOp, ListUnOp, RefOp, RefUnOp, PrintOp, PrintUnOp, ConvertOp, ConvertUnOp, Assign, Seq,
+          Record, Field, Construct, Match, Arm, If, Apply, Fun, Let, LetFun, LetRec, LetType, TypeAlias, Variant,
+          Program, Decl};
+
+/// `expr` with the span from `start` to `end`.
+pub fn spanned<E: Into<Expr>>(start: usize, expr: E, end: usize) -> Expr {
+    expr.into().at(Span::new(start, end))
+}
 
 pub fn arith_op(l: Expr, op: ArithOp, r: Expr) -> Expr {
     ArithBinOp {
@@ -18,6 +26,186 @@ pub fn cmp_op(l: Expr, op: CmpOp, r: Expr) -> Expr {
     .into()
 }
 
+pub use ast::expr::neg;
+
+pub fn logic_op(l: Expr, op: LogicOp, r: Expr) -> Expr {
+    LogicBinOp {
+        kind: op,
+        lhs: l,
+        rhs: r,
+    }
+    .into()
+}
+
+pub fn not(arg: Expr) -> Expr {
+    Not { arg: arg }.into()
+}
+
+pub fn cons(head: Expr, tail: Expr) -> Expr {
+    Cons {
+        head: head,
+        tail: tail,
+    }
+    .into()
+}
+
+pub fn concat(lhs: Expr, rhs: Expr) -> Expr {
+    Concat {
+        lhs: lhs,
+        rhs: rhs,
+    }
+    .into()
+}
+
+/// The string a literal token stands for.
+pub fn string_literal(token: &str) -> String {
+    ast::unescape(&token[1..token.len() - 1]).expect("the lexer only accepts valid escapes")
+}
+
+pub fn list(items: Vec<Expr>, last: Expr) -> Expr {
+    let mut items = items;
+    items.push(last);
+    List {
+        items: items,
+        type_: None,
+    }
+    .into()
+}
+
+pub fn nil(type_: Option<Type>) -> Expr {
+    List {
+        items: vec![],
+        type_: type_,
+    }
+    .into()
+}
+
+pub fn list_op(kind: ListOp, arg: Expr) -> Expr {
+    ListUnOp {
+        kind: kind,
+        arg: arg,
+    }
+    .into()
+}
+
+pub fn ref_op(kind: RefOp, arg: Expr) -> Expr {
+    RefUnOp {
+        kind: kind,
+        arg: arg,
+    }
+    .into()
+}
+
+pub fn print_op(kind: PrintOp, arg: Expr) -> Expr {
+    PrintUnOp {
+        kind: kind,
+        arg: arg,
+    }
+    .into()
+}
+
+pub fn convert_op(kind: ConvertOp, arg: Expr) -> Expr {
+    ConvertUnOp {
+        kind: kind,
+        arg: arg,
+    }
+    .into()
+}
+
+pub fn assign(cell: Expr, value: Expr) -> Expr {
+    Assign {
+        cell: cell,
+        value: value,
+    }
+    .into()
+}
+
+pub fn seq(first: Expr, second: Expr) -> Expr {
+    Seq {
+        first: first,
+        second: second,
+    }
+    .into()
+}
+
+pub fn record(fields: Vec<(Ident, Expr)>, last: (Ident, Expr)) -> Expr {
+    let mut fields = fields;
+    fields.push(last);
+    Record { fields: fields }.into()
+}
+
+pub fn field(record: Expr, name: Ident) -> Expr {
+    Field {
+        record: record,
+        name: name,
+    }
+    .into()
+}
+
+pub fn record_type(fields: Vec<(Ident, Type)>, last: (Ident, Type)) -> Type {
+    let mut fields = fields;
+    fields.push(last);
+    Type::Record(fields)
+}
+
+/// Capitalized identifiers are constructors, the rest are variables.
+pub fn ident(name: Ident) -> Expr {
+    if name.as_ref().starts_with(|c: char| c.is_uppercase()) {
+        Construct {
+            name: name,
+            arg: None,
+        }
+        .into()
+    } else {
+        ExprKind::Var(name).into()
+    }
+}
+
+pub fn match_expr(scrutinee: Expr, arms: Vec<Arm>, last_arm: Arm) -> Expr {
+    let mut arms = arms;
+    arms.push(last_arm);
+    Match {
+        scrutinee: scrutinee,
+        arms: arms,
+    }
+    .into()
+}
+
+pub fn arm(constructor: Ident, binding: Option<Ident>, body: Expr) -> Arm {
+    Arm {
+        constructor: constructor,
+        binding: binding,
+        body: body,
+    }
+}
+
+pub fn let_type(name: Ident, variants: Vec<Variant>, last_variant: Variant, body: Expr) -> Expr {
+    let mut variants = variants;
+    variants.push(last_variant);
+    LetType {
+        name: name,
+        variants: variants,
+        body: body,
+    }
+    .into()
+}
+
+pub fn type_alias(name: Ident, type_: Type, body: Expr) -> Expr {
+    TypeAlias {
+        name: name,
+        type_: type_,
+        body: body,
+    }
+    .into()
+}
+
+pub fn variant(name: Ident, arg: Option<Type>) -> Variant {
+    Variant {
+        name: name,
+        arg: arg,
+    }
+}
+
 pub fn if_expr(cond: Expr, tru: Expr, fls: Expr) -> Expr {
     If {
         cond: cond,
@@ -27,16 +215,30 @@ pub fn if_expr(cond: Expr, tru: Expr, fls: Expr) -> Expr {
     .into()
 }
 
-pub fn fun(name: Ident, arg_name: Ident, arg_type: Type, fun_type: Type, body: Expr) -> Fun {
+pub fn fun(name: Ident,
+           args: Vec<(Ident, Type)>,
+           last: (Ident, Type),
+           fun_type: Option<Type>,
+           body: Expr)
+           -> Fun {
+    let mut args = args;
+    args.push(last);
     Fun {
         fun_name: name,
-        arg_name: arg_name,
-        arg_type: arg_type,
-        fun_type: fun_type,
+        args: args,
+        fun_type: fun_type.unwrap_or(Type::Infer),
         body: body,
     }
 }
 
+pub fn let_expr(name: Ident, value: Expr, body: Expr) -> Expr {
+    Let {
+        name: name,
+        value: value,
+        body: body,
+    }.into()
+}
+
 pub fn let_fun_expr(fun: Fun, body: Expr) -> Expr {
     LetFun {
         fun: fun,
@@ -57,7 +259,30 @@ pub fn let_rec_expr(funs: Vec<Fun>, last_fun: Fun, body: Expr) -> Expr {
     }.into()
 }
 
+pub fn let_rec_decl(funs: Vec<Fun>, last_fun: Fun) -> Decl {
+    let mut funs = funs;
+    funs.push(last_fun);
+    Decl::LetRec(funs)
+}
+
+pub fn program(decls: Vec<Decl>, body: Option<Expr>) -> Program {
+    Program {
+        decls: decls,
+        body: body.unwrap_or_else(|| ExprKind::Var(Ident::from_str("main")).into()),
+    }
+}
+
+/// Applying a constructor without an argument gives it one.
 pub fn application(fun: Expr, arg: Expr) -> Expr {
+    if let ExprKind::Construct(ref construct) = fun.kind {
+        if construct.arg.is_none() {
+            return Construct {
+                name: construct.name,
+                arg: Some(arg),
+            }
+            .into();
+        }
+    }
     Apply {
         fun: fun,
         arg: arg,
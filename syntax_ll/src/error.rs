@@ -1,15 +1,68 @@
+use std::fmt;
+
+use ast::Span;
 
 #[derive(Debug)]
 pub struct ParseError {
-    location: usize,
+    span: Span,
     message: String,
+    found: Option<String>,
+    expected: Vec<String>,
 }
 
 impl ParseError {
-    pub fn new(location: usize, message: String) -> ParseError {
+    /// An error at the token in `span`, whose text is `found`, or at the end
+    /// of the input if there is none.
+    pub fn new(span: Span, message: String, found: Option<String>) -> ParseError {
         ParseError {
-            location: location,
+            span: span,
             message: message,
+            found: found,
+            expected: Vec::new(),
+        }
+    }
+
+    pub fn with_expected(self, expected: Vec<String>) -> ParseError {
+        ParseError { expected: expected, ..self }
+    }
+
+    /// The byte offset in the input the parser stopped at.
+    pub fn location(&self) -> usize {
+        self.span.start as usize
+    }
+
+    /// The offending token, or an empty span at the end of the input.
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    /// The line and column of the offending token, both starting at 1.
+    pub fn line_col(&self, input: &str) -> (usize, usize) {
+        self.span.line_col(input)
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn found(&self) -> Option<&str> {
+        self.found.as_deref()
+    }
+
+    /// The tokens which would have been accepted instead, like "`then`",
+    /// when the parser knows them.
+    pub fn expected(&self) -> &[String] {
+        &self.expected
+    }
+}
+
+/// `Expected `then` after if condition, found `else``.
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(f.write_str(&self.message));
+        match self.found {
+            Some(ref found) => write!(f, ", found `{}`", found),
+            None => f.write_str(", found end of input"),
         }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,100 @@
+/// A word the parser treats specially, whatever it is spelled as.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Keyword {
+    If,
+    Then,
+    Else,
+    Fun,
+    Is,
+    Let,
+    Rec,
+    And,
+    In,
+    Head,
+    Tail,
+    IsEmpty,
+    Match,
+    With,
+    Type,
+    Of,
+    Not,
+    Ref,
+    Print,
+    PrintInt,
+    IntOfFloat,
+    FloatOfInt,
+}
+
+const DEFAULT: [(&'static str, Keyword); 22] = [
+    ("if", Keyword::If),
+    ("then", Keyword::Then),
+    ("else", Keyword::Else),
+    ("fun", Keyword::Fun),
+    ("is", Keyword::Is),
+    ("let", Keyword::Let),
+    ("rec", Keyword::Rec),
+    ("and", Keyword::And),
+    ("in", Keyword::In),
+    ("head", Keyword::Head),
+    ("tail", Keyword::Tail),
+    ("isEmpty", Keyword::IsEmpty),
+    ("match", Keyword::Match),
+    ("with", Keyword::With),
+    ("type", Keyword::Type),
+    ("of", Keyword::Of),
+    ("not", Keyword::Not),
+    ("ref", Keyword::Ref),
+    ("print", Keyword::Print),
+    ("print_int", Keyword::PrintInt),
+    ("int_of_float", Keyword::IntOfFloat),
+    ("float_of_int", Keyword::FloatOfInt),
+];
+
+/// How each keyword is spelled, so that a dialect can rename some of them:
+///
+/// ```
+/// use syntax_ll::{Keyword, Keywords, parse_with_keywords};
+///
+/// let keywords = Keywords::default().with_spelling(Keyword::Fun, "fn");
+/// assert_eq!(keywords.spelling(Keyword::Fun), "fn");
+/// assert!(parse_with_keywords("fn id(x: int): int is x", &keywords).is_ok());
+/// // `fun` is an ordinary identifier now.
+/// assert!(parse_with_keywords("fun", &keywords).is_ok());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Keywords {
+    table: Vec<(String, Keyword)>,
+}
+
+impl Keywords {
+    /// Spells `keyword` as `spelling` instead. A word is only read as a
+    /// keyword if it doesn't go on with letters, digits or `_`.
+    pub fn with_spelling(mut self, keyword: Keyword, spelling: &str) -> Keywords {
+        for entry in &mut self.table {
+            if entry.1 == keyword {
+                entry.0 = spelling.to_owned();
+            }
+        }
+        self
+    }
+
+    /// Every keyword with its spelling.
+    pub fn table(&self) -> &[(String, Keyword)] {
+        &self.table
+    }
+
+    pub fn spelling(&self, keyword: Keyword) -> &str {
+        self.table.iter().find(|&&(_, k)| k == keyword).map(|&(ref text, _)| text.as_str()).unwrap()
+    }
+
+    /// The keyword spelled as `word`, if it is one.
+    pub fn keyword(&self, word: &str) -> Option<Keyword> {
+        self.table.iter().find(|&&(ref text, _)| text == word).map(|&(_, k)| k)
+    }
+}
+
+impl Default for Keywords {
+    fn default() -> Keywords {
+        Keywords { table: DEFAULT.iter().map(|&(text, k)| (text.to_owned(), k)).collect() }
+    }
+}
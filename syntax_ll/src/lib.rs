@@ -2,6 +2,11 @@ extern crate ast;
 
 mod error;
 
+mod keywords;
+
 mod parser;
 
-pub use parser::parse;
+pub use parser::{parse, parse_program, parse_decl, parse_with_keywords, parse_program_with_keywords,
+                 parse_decl_with_keywords, tokens};
+pub use keywords::{Keyword, Keywords};
+pub use error::ParseError;
@@ -1,15 +1,69 @@
 use std::str::FromStr;
 
 use error::ParseError;
+use keywords::{Keyword, Keywords};
 
-use ast::{Ident, Type, Expr, CmpOp, CmpBinOp, ArithOp, ArithBinOp, If, Fun, LetFun, LetRec, Apply, Literal};
+use ast::{Ident, Type, Expr, ExprKind, Span, CmpOp, CmpBinOp, ArithOp, ArithBinOp, LogicOp, LogicBinOp, Not, List,
+          Cons, Concat, ListOp, ListUnOp, RefOp, RefUnOp, PrintOp, PrintUnOp, ConvertOp, ConvertUnOp, Assign, Seq,
+          Record, Field, Construct, Match, Arm, If, Fun, Let, LetFun, LetRec, LetType, TypeAlias, Variant, Apply, Literal, Program, Decl,
+          unescape};
+use ast::expr::neg;
 
 pub fn parse(input: &str) -> Result<Expr, ParseError> {
-    let tokenizer = Tokenizer::new(input);
+    parse_with_keywords(input, &Keywords::default())
+}
+
+pub fn parse_program(input: &str) -> Result<Program, ParseError> {
+    parse_program_with_keywords(input, &Keywords::default())
+}
+
+pub fn parse_decl(input: &str) -> Result<Decl, ParseError> {
+    parse_decl_with_keywords(input, &Keywords::default())
+}
+
+/// Like `parse`, with the keywords spelled as `keywords` says.
+pub fn parse_with_keywords(input: &str, keywords: &Keywords) -> Result<Expr, ParseError> {
+    let tokenizer = Tokenizer::new(input, keywords);
     let mut parser = Parser::new(tokenizer);
     parser.parse()
 }
 
+pub fn parse_program_with_keywords(input: &str, keywords: &Keywords) -> Result<Program, ParseError> {
+    let tokenizer = Tokenizer::new(input, keywords);
+    let mut parser = Parser::new(tokenizer);
+    parser.parse_program()
+}
+
+pub fn parse_decl_with_keywords(input: &str, keywords: &Keywords) -> Result<Decl, ParseError> {
+    let tokenizer = Tokenizer::new(input, keywords);
+    let mut parser = Parser::new(tokenizer);
+    let decl = try!(parser.parse_decl());
+    try!(parser.expect(Token::Sym(Sym::SemiSemi), "Expected `;;` after declaration"));
+    try!(parser.expect(Token::Eof, "Expected end of input"));
+    Ok(decl)
+}
+
+/// The tokens of `input` with their spans, shown as `Keyword(Let)` or
+/// `Ident("x")`, to see how the input is split up.
+pub fn tokens(input: &str) -> Result<Vec<(Span, String)>, ParseError> {
+    let keywords = Keywords::default();
+    let mut tokenizer = Tokenizer::new(input, &keywords);
+    let mut tokens = Vec::new();
+    loop {
+        let start = tokenizer.position;
+        match tokenizer.eat_token() {
+            Token::Eof => return Ok(tokens),
+            Token::Unknown => {
+                let len = tokenizer.input.chars().next().map_or(0, char::len_utf8);
+                let found = input[start..start + len].to_owned();
+                let span = Span::new(start, start + len);
+                return Err(ParseError::new(span, "Unknown token".to_owned(), Some(found)));
+            }
+            token => tokens.push((Span::new(start, tokenizer.end), format!("{:?}", token))),
+        }
+    }
+}
+
 struct Parser<'p> {
     tokenizer: Tokenizer<'p>
 }
@@ -21,9 +75,14 @@ impl<'p> Parser<'p> {
 
     fn precedence(sym: Sym) -> u8 {
         match sym {
-            Sym::Eq | Sym::Lt | Sym::Gt => 3,
+            Sym::Semi => 8,
+            Sym::ColonEq => 7,
+            Sym::Or => 6,
+            Sym::And => 5,
+            Sym::Eq | Sym::Ne | Sym::Lt | Sym::Le | Sym::Gt | Sym::Ge => 4,
+            Sym::Cons | Sym::Concat => 3,
             Sym::Add | Sym::Sub => 2,
-            Sym::Mul | Sym::Div => 1,
+            Sym::Mul | Sym::Div | Sym::Mod => 1,
             _ => 255,
         }
     }
@@ -34,19 +93,104 @@ impl<'p> Parser<'p> {
         self.parse_expr(Self::max_precedence())
     }
 
+    fn parse_program(&mut self) -> Result<Program, ParseError> {
+        let mut decls = vec![];
+        loop {
+            let start = self.tokenizer.position;
+            let decl = match self.tokenizer.lookahead() {
+                Token::Keyword(Keyword::Let) => try!(self.parse_decl()),
+                Token::Keyword(Keyword::Fun) => {
+                    self.tokenizer.eat_token();
+                    let fun = try!(self.parse_fun());
+                    // A function without `;;` after it is the body of the program
+                    if self.tokenizer.lookahead() != Token::Sym(Sym::SemiSemi) {
+                        let body: Expr = fun.into();
+                        let body = body.at(self.span_from(start));
+                        try!(self.expect(Token::Eof, "Expected end of input"));
+                        return Ok(Program { decls: decls, body: body });
+                    }
+                    Decl::Fun(fun)
+                }
+                _ => break,
+            };
+            // A `let ... in ...` expression rather than a declaration is the body of the program
+            if self.tokenizer.lookahead() == Token::Keyword(Keyword::In) {
+                self.tokenizer.eat_token();
+                let body = try!(self.parse());
+                let body: Expr = match decl {
+                    Decl::Fun(fun) => LetFun { fun: fun, body: body }.into(),
+                    Decl::LetRec(funs) => LetRec { funs: funs, body: body }.into(),
+                    Decl::Value(name, value) => {
+                        Let {
+                            name: name,
+                            value: value,
+                            body: body,
+                        }
+                        .into()
+                    }
+                };
+                let body = body.at(self.span_from(start));
+                try!(self.expect(Token::Eof, "Expected end of input"));
+                return Ok(Program { decls: decls, body: body });
+            }
+            try!(self.expect(Token::Sym(Sym::SemiSemi), "Expected `;;` after declaration"));
+            decls.push(decl);
+        }
+
+        let body = if self.tokenizer.lookahead() == Token::Eof {
+            ExprKind::Var(Ident::from_str("main")).into()
+        } else {
+            try!(self.parse())
+        };
+        try!(self.expect(Token::Eof, "Expected end of input"));
+        Ok(Program { decls: decls, body: body })
+    }
+
+    /// A `let` declaration, or a function on its own, which declares it as
+    /// `let fun` does.
+    fn parse_decl(&mut self) -> Result<Decl, ParseError> {
+        if self.tokenizer.lookahead() == Token::Keyword(Keyword::Fun) {
+            self.tokenizer.eat_token();
+            return Ok(Decl::Fun(try!(self.parse_fun())));
+        }
+        try!(self.expect(Token::Keyword(Keyword::Let), "Expected `let`"));
+        let decl = match self.tokenizer.eat_token() {
+            Token::Keyword(Keyword::Fun) => Decl::Fun(try!(self.parse_fun())),
+            Token::Keyword(Keyword::Rec) => Decl::LetRec(try!(self.parse_rec_funs())),
+            Token::Ident(name) => {
+                try!(self.expect(Token::Sym(Sym::Assign), "Expected `=`"));
+                Decl::Value(Ident::from_str(name), try!(self.parse()))
+            }
+            _ => return Err(self.err_eaten("Expected declaration")),
+        };
+        Ok(decl)
+    }
+
     fn parse_expr(&mut self, precedence: u8) -> Result<Expr, ParseError> {
-        let mut lhs = try!(self.parse_application());
+        let start = self.tokenizer.position;
+        let mut lhs = try!(self.parse_operand());
 
         let mut has_comarison = false;
 
         while let Some(sym) = self.eat_op_with_precendence(precedence) {
-            let rhs = try!(self.parse_expr(Self::precedence(sym)));
+            // `::`, `++`, `&&`, `||`, `:=` and `;` are right associative, so
+            // their right operand may contain them as well
+            let rhs_precedence = match sym {
+                Sym::Cons | Sym::Concat | Sym::And | Sym::Or | Sym::ColonEq | Sym::Semi => {
+                    Self::precedence(sym) + 1
+                }
+                _ => Self::precedence(sym),
+            };
+            let rhs = try!(self.parse_expr(rhs_precedence));
             match sym {
-                Sym::Eq | Sym::Lt | Sym::Gt => {
+                Sym::Eq | Sym::Ne | Sym::Lt | Sym::Le | Sym::Gt | Sym::Ge => {
                     let kind = match sym {
                         Sym::Eq => CmpOp::Eq,
+                        Sym::Ne => CmpOp::Ne,
                         Sym::Lt => CmpOp::Lt,
+                        Sym::Le => CmpOp::Le,
                         Sym::Gt => CmpOp::Gt,
+                        Sym::Ge => CmpOp::Ge,
                         _ => unreachable!()
                     };
                     if has_comarison {
@@ -57,112 +201,347 @@ impl<'p> Parser<'p> {
                     lhs = CmpBinOp { kind: kind, lhs: lhs, rhs: rhs }.into();
                 }
 
-                Sym::Add | Sym::Sub | Sym::Mul | Sym::Div => {
+                Sym::Add | Sym::Sub | Sym::Mul | Sym::Div | Sym::Mod => {
                     let kind = match sym {
                         Sym::Add => ArithOp::Add,
                         Sym::Sub => ArithOp::Sub,
                         Sym::Mul => ArithOp::Mul,
                         Sym::Div => ArithOp::Div,
+                        Sym::Mod => ArithOp::Mod,
                         _ => unreachable!()
                     };
 
                     lhs = ArithBinOp { kind: kind, lhs: lhs, rhs: rhs }.into();
                 }
 
+                Sym::Cons => lhs = Cons { head: lhs, tail: rhs }.into(),
+
+                Sym::Concat => lhs = Concat { lhs: lhs, rhs: rhs }.into(),
+
+                Sym::And => lhs = LogicBinOp { kind: LogicOp::And, lhs: lhs, rhs: rhs }.into(),
+
+                Sym::Or => lhs = LogicBinOp { kind: LogicOp::Or, lhs: lhs, rhs: rhs }.into(),
+
+                Sym::ColonEq => lhs = Assign { cell: lhs, value: rhs }.into(),
+
+                Sym::Semi => lhs = Seq { first: lhs, second: rhs }.into(),
+
                 _ => unreachable!()
             }
+            lhs = lhs.at(self.span_from(start));
         }
 
         Ok(lhs)
     }
 
+    /// An application, possibly negated: unary minus binds looser than
+    /// application and tighter than `*`.
+    fn parse_operand(&mut self) -> Result<Expr, ParseError> {
+        if self.tokenizer.lookahead() != Token::Sym(Sym::Sub) {
+            return self.parse_application();
+        }
+        let start = self.tokenizer.position;
+        self.tokenizer.eat_token();
+        let arg = try!(self.parse_operand());
+        Ok(neg(arg).at(self.span_from(start)))
+    }
+
     fn parse_application(&mut self) -> Result<Expr, ParseError> {
+        let start = self.tokenizer.position;
         let mut fun = match try!(self.parse_atom()) {
             Some(fun) => fun,
             None => return Err(self.err("Expected expression"))
         };
 
         while let Some(arg) = try!(self.parse_atom()) {
-            fun = Apply { fun: fun, arg: arg }.into();
+            let app: Expr = match fun.kind {
+                ExprKind::Construct(ref construct) if construct.arg.is_none() => {
+                    Construct { name: construct.name, arg: Some(arg) }.into()
+                }
+                _ => Apply { fun: fun, arg: arg }.into(),
+            };
+            fun = app.at(self.span_from(start));
         }
 
         Ok(fun)
     }
 
+    /// An atom and the fields projected from it, `r.x.y`.
     fn parse_atom(&mut self) -> Result<Option<Expr>, ParseError> {
+        let start = self.tokenizer.position;
+        let mut atom = match try!(self.parse_unspanned_atom()) {
+            Some(atom) => atom.at(self.span_from(start)),
+            None => return Ok(None),
+        };
+        while self.tokenizer.lookahead() == Token::Sym(Sym::Dot) {
+            self.tokenizer.eat_token();
+            let name = try!(self.parse_ident());
+            let field: Expr = Field { record: atom, name: Ident::from_str(name) }.into();
+            atom = field.at(self.span_from(start));
+        }
+        Ok(Some(atom))
+    }
+
+    fn parse_unspanned_atom(&mut self) -> Result<Option<Expr>, ParseError> {
         match self.tokenizer.lookahead() {
-            Token::Eof | Token::Paren(Paren::Close) | Token::Sym(_) => Ok(None),
+            Token::Keyword(Keyword::Ref) | Token::Sym(Sym::Bang) => {
+                let kind = match self.tokenizer.eat_token() {
+                    Token::Keyword(Keyword::Ref) => RefOp::Ref,
+                    _ => RefOp::Deref,
+                };
+                match try!(self.parse_atom()) {
+                    Some(arg) => Ok(Some(RefUnOp { kind: kind, arg: arg }.into())),
+                    None => Err(self.err("Expected an expression")),
+                }
+            }
+            Token::Eof | Token::Paren(Paren::Close) | Token::Paren(Paren::CloseBracket) |
+            Token::Paren(Paren::CloseBrace) | Token::Sym(_) => Ok(None),
             Token::Number(n) => {
                 self.tokenizer.eat_token();
-                Ok(Some(Expr::Literal(Literal::Number(n))))
+                Ok(Some(Literal::Number(n).into()))
+            }
+            Token::Float(x) => {
+                self.tokenizer.eat_token();
+                Ok(Some(Literal::Float(f64::from_str(x).unwrap()).into()))
             }
             Token::Bool(b) => {
                 self.tokenizer.eat_token();
-                Ok(Some(Expr::Literal(Literal::Bool(b))))
+                Ok(Some(Literal::Bool(b).into()))
+            }
+            Token::Str(s) => {
+                self.tokenizer.eat_token();
+                match unescape(s) {
+                    Some(s) => Ok(Some(Literal::Str(s).into())),
+                    None => Err(self.err_eaten("Invalid escape in string literal")),
+                }
             }
             Token::Ident(i) => {
                 self.tokenizer.eat_token();
-                Ok(Some(Expr::Var(Ident::from_str(i))))
+                let name = Ident::from_str(i);
+                // Capitalized identifiers are constructors
+                if i.starts_with(|c: char| c.is_uppercase()) {
+                    Ok(Some(Construct { name: name, arg: None }.into()))
+                } else {
+                    Ok(Some(ExprKind::Var(name).into()))
+                }
             }
             Token::Paren(Paren::Open) => {
                 self.tokenizer.eat_token();
+                if self.tokenizer.lookahead() == Token::Paren(Paren::Close) {
+                    self.tokenizer.eat_token();
+                    return Ok(Some(Literal::Unit.into()));
+                }
                 let expr = try!(self.parse());
                 try!(self.expect(Token::Paren(Paren::Close), "Expected `)`"));
                 Ok(Some(expr))
             }
+            Token::Paren(Paren::OpenBracket) => {
+                self.tokenizer.eat_token();
+                Ok(Some(try!(self.parse_list()).into()))
+            }
+            Token::Paren(Paren::OpenBrace) => {
+                self.tokenizer.eat_token();
+                Ok(Some(try!(self.parse_record()).into()))
+            }
+            Token::Keyword(keyword @ Keyword::Head) |
+            Token::Keyword(keyword @ Keyword::Tail) |
+            Token::Keyword(keyword @ Keyword::IsEmpty) => {
+                self.tokenizer.eat_token();
+                let kind = match keyword {
+                    Keyword::Head => ListOp::Head,
+                    Keyword::Tail => ListOp::Tail,
+                    _ => ListOp::IsEmpty,
+                };
+                match try!(self.parse_atom()) {
+                    Some(arg) => Ok(Some(ListUnOp { kind: kind, arg: arg }.into())),
+                    None => Err(self.err("Expected a list")),
+                }
+            }
+            Token::Keyword(Keyword::Not) => {
+                self.tokenizer.eat_token();
+                match try!(self.parse_atom()) {
+                    Some(arg) => Ok(Some(Not { arg: arg }.into())),
+                    None => Err(self.err("Expected a bool")),
+                }
+            }
+            Token::Keyword(keyword @ Keyword::Print) |
+            Token::Keyword(keyword @ Keyword::PrintInt) => {
+                self.tokenizer.eat_token();
+                let kind = match keyword {
+                    Keyword::Print => PrintOp::Print,
+                    _ => PrintOp::PrintInt,
+                };
+                match try!(self.parse_atom()) {
+                    Some(arg) => Ok(Some(PrintUnOp { kind: kind, arg: arg }.into())),
+                    None => Err(self.err("Expected an expression")),
+                }
+            }
+            Token::Keyword(keyword @ Keyword::IntOfFloat) |
+            Token::Keyword(keyword @ Keyword::FloatOfInt) => {
+                self.tokenizer.eat_token();
+                let kind = match keyword {
+                    Keyword::IntOfFloat => ConvertOp::IntOfFloat,
+                    _ => ConvertOp::FloatOfInt,
+                };
+                match try!(self.parse_atom()) {
+                    Some(arg) => Ok(Some(ConvertUnOp { kind: kind, arg: arg }.into())),
+                    None => Err(self.err("Expected an expression")),
+                }
+            }
             Token::Keyword(Keyword::If) => {
                 self.tokenizer.eat_token();
                 Ok(Some(try!(self.parse_if()).into()))
             }
+            Token::Keyword(Keyword::Match) => {
+                self.tokenizer.eat_token();
+                Ok(Some(try!(self.parse_match()).into()))
+            }
             Token::Keyword(Keyword::Fun) => {
                 self.tokenizer.eat_token();
                 Ok(Some(try!(self.parse_fun()).into()))
             }
+            Token::Keyword(Keyword::Type) => {
+                self.tokenizer.eat_token();
+                Ok(Some(try!(self.parse_let_type())))
+            }
             Token::Keyword(Keyword::Let) => {
                 self.tokenizer.eat_token();
                 match self.tokenizer.eat_token() {
                     Token::Keyword(Keyword::Fun) => Ok(Some(try!(self.parse_let()).into())),
                     Token::Keyword(Keyword::Rec) => Ok(Some(try!(self.parse_letrec()).into())),
-                    _ => Err(self.err("Expected let expression")),
+                    Token::Ident(name) => Ok(Some(try!(self.parse_let_value(name)).into())),
+                    _ => Err(self.err_eaten("Expected let expression")),
                 }
             }
             Token::Keyword(_) => Ok(None),
+            Token::TypeParam(_) => Err(self.err("Type parameters can only appear in types")),
             Token::Unknown => Err(self.unknown()),
         }
     }
 
+    fn parse_list(&mut self) -> Result<List, ParseError> {
+        if self.tokenizer.lookahead() == Token::Paren(Paren::CloseBracket) {
+            self.tokenizer.eat_token();
+            // Without an annotation, the type of the list is inferred.
+            let type_ = match self.tokenizer.lookahead() {
+                Token::Sym(Sym::Colon) => {
+                    self.tokenizer.eat_token();
+                    Some(try!(self.parse_type()))
+                }
+                _ => None,
+            };
+            return Ok(List { items: vec![], type_: type_ });
+        }
+        let mut items = vec![try!(self.parse())];
+        while self.tokenizer.lookahead() == Token::Sym(Sym::Comma) {
+            self.tokenizer.eat_token();
+            items.push(try!(self.parse()));
+        }
+        try!(self.expect(Token::Paren(Paren::CloseBracket), "Expected `]`"));
+        Ok(List { items: items, type_: None })
+    }
+
+    fn parse_record(&mut self) -> Result<Record, ParseError> {
+        let mut fields = vec![try!(self.parse_field())];
+        while self.tokenizer.lookahead() == Token::Sym(Sym::Comma) {
+            self.tokenizer.eat_token();
+            fields.push(try!(self.parse_field()));
+        }
+        try!(self.expect(Token::Paren(Paren::CloseBrace), "Expected `}`"));
+        Ok(Record { fields: fields })
+    }
+
+    fn parse_field(&mut self) -> Result<(Ident, Expr), ParseError> {
+        let name = try!(self.parse_ident());
+        try!(self.expect(Token::Sym(Sym::Assign), "Expected `=`"));
+        let value = try!(self.parse());
+        Ok((Ident::from_str(name), value))
+    }
+
     fn parse_if(&mut self) -> Result<If, ParseError> {
         let cond = try!(self.parse());
-        try!(self.expect(Token::Keyword(Keyword::Then), "Expected `then`"));
+        try!(self.expect(Token::Keyword(Keyword::Then), "Expected `then` after if condition"));
         let tru = try!(self.parse());
-        try!(self.expect(Token::Keyword(Keyword::Else), "Expected `else`"));
+        try!(self.expect(Token::Keyword(Keyword::Else), "Expected `else` after then branch"));
         let fls = try!(self.parse());
         Ok(If { cond: cond, tru: tru, fls: fls })
     }
 
+    fn parse_match(&mut self) -> Result<Match, ParseError> {
+        let scrutinee = try!(self.parse());
+        try!(self.expect(Token::Keyword(Keyword::With), "Expected `with` after match scrutinee"));
+        let mut arms = vec![try!(self.parse_arm())];
+        while self.tokenizer.lookahead() == Token::Sym(Sym::Bar) {
+            self.tokenizer.eat_token();
+            arms.push(try!(self.parse_arm()));
+        }
+        Ok(Match { scrutinee: scrutinee, arms: arms })
+    }
+
+    fn parse_arm(&mut self) -> Result<Arm, ParseError> {
+        let constructor = try!(self.parse_ident());
+        let binding = match self.tokenizer.lookahead() {
+            Token::Ident(name) => {
+                self.tokenizer.eat_token();
+                Some(Ident::from_str(name))
+            }
+            _ => None,
+        };
+        try!(self.expect(Token::Sym(Sym::Arrow), "Expected `->`"));
+        let body = try!(self.parse());
+        Ok(Arm {
+            constructor: Ident::from_str(constructor),
+            binding: binding,
+            body: body,
+        })
+    }
+
     fn parse_fun(&mut self) -> Result<Fun, ParseError> {
         let fun_name = try!(self.parse_ident());
 
         try!(self.expect(Token::Paren(Paren::Open), "Expected `(`"));
-        let arg_name = try!(self.parse_ident());
-        try!(self.expect(Token::Sym(Sym::Colon), "Expected `:`"));
-        let arg_type = try!(self.parse_type());
+        let mut args = vec![try!(self.parse_param())];
+        while self.tokenizer.lookahead() == Token::Sym(Sym::Comma) {
+            self.tokenizer.eat_token();
+            args.push(try!(self.parse_param()));
+        }
         try!(self.expect(Token::Paren(Paren::Close), "Expected `)`"));
 
-        try!(self.expect(Token::Sym(Sym::Colon), "Expected `:`"));
-        let fun_type = try!(self.parse_type());
+        let fun_type = try!(self.parse_annotation());
 
         try!(self.expect(Token::Keyword(Keyword::Is), "Expected `is` before function body"));
         let body = try!(self.parse());
         Ok(Fun {
             fun_name: Ident::from_str(fun_name),
-            arg_name: Ident::from_str(arg_name),
+            args: args,
             fun_type: fun_type,
-            arg_type: arg_type,
             body: body,
         })
     }
 
+    /// A parameter of a function, whose type can be left to inference.
+    fn parse_param(&mut self) -> Result<(Ident, Type), ParseError> {
+        let name = try!(self.parse_ident());
+        let type_ = try!(self.parse_annotation());
+        Ok((Ident::from_str(name), type_))
+    }
+
+    /// `: type`, or `Type::Infer` if there is no colon.
+    fn parse_annotation(&mut self) -> Result<Type, ParseError> {
+        if self.tokenizer.lookahead() != Token::Sym(Sym::Colon) {
+            return Ok(Type::Infer);
+        }
+        self.tokenizer.eat_token();
+        self.parse_type()
+    }
+
+    fn parse_arg(&mut self) -> Result<(Ident, Type), ParseError> {
+        let arg_name = try!(self.parse_ident());
+        try!(self.expect(Token::Sym(Sym::Colon), "Expected `:`"));
+        let arg_type = try!(self.parse_type());
+        Ok((Ident::from_str(arg_name), arg_type))
+    }
+
     fn parse_let(&mut self) -> Result<LetFun, ParseError> {
         let fun = try!(self.parse_fun());
         try!(self.expect(Token::Keyword(Keyword::In), "Expected `in` after let"));
@@ -170,7 +549,71 @@ impl<'p> Parser<'p> {
         Ok(LetFun { fun: fun, body: body })
     }
 
+    fn parse_let_value(&mut self, name: &str) -> Result<Let, ParseError> {
+        try!(self.expect(Token::Sym(Sym::Assign), "Expected `=`"));
+        let value = try!(self.parse());
+        try!(self.expect(Token::Keyword(Keyword::In), "Expected `in` after let"));
+        let body = try!(self.parse());
+        Ok(Let {
+            name: Ident::from_str(name),
+            value: value,
+            body: body,
+        })
+    }
+
+    /// A variant type, or an alias if the right side isn't a bare name,
+    /// which is taken for a constructor.
+    fn parse_let_type(&mut self) -> Result<Expr, ParseError> {
+        let name = try!(self.parse_ident());
+        try!(self.expect(Token::Sym(Sym::Assign), "Expected `=`"));
+        let type_ = try!(self.parse_type());
+        let first = match type_ {
+            Type::Named(constructor) => constructor,
+            type_ => {
+                try!(self.expect(Token::Keyword(Keyword::In), "Expected `in` after type"));
+                let body = try!(self.parse());
+                let alias = TypeAlias {
+                    name: Ident::from_str(name),
+                    type_: type_,
+                    body: body,
+                };
+                return Ok(alias.into());
+            }
+        };
+        let mut variants = vec![try!(self.parse_variant(first))];
+        while self.tokenizer.lookahead() == Token::Sym(Sym::Bar) {
+            self.tokenizer.eat_token();
+            let constructor = try!(self.parse_ident());
+            variants.push(try!(self.parse_variant(Ident::from_str(constructor))));
+        }
+        try!(self.expect(Token::Keyword(Keyword::In), "Expected `in` after type"));
+        let body = try!(self.parse());
+        let let_type = LetType {
+            name: Ident::from_str(name),
+            variants: variants,
+            body: body,
+        };
+        Ok(let_type.into())
+    }
+
+    fn parse_variant(&mut self, name: Ident) -> Result<Variant, ParseError> {
+        let arg = if self.tokenizer.lookahead() == Token::Keyword(Keyword::Of) {
+            self.tokenizer.eat_token();
+            Some(try!(self.parse_type()))
+        } else {
+            None
+        };
+        Ok(Variant { name: name, arg: arg })
+    }
+
     fn parse_letrec(&mut self) -> Result<LetRec, ParseError> {
+        let funs = try!(self.parse_rec_funs());
+        try!(self.expect(Token::Keyword(Keyword::In), "Expected `in` after let rec"));
+        let body = try!(self.parse());
+        Ok(LetRec { funs: funs, body: body })
+    }
+
+    fn parse_rec_funs(&mut self) -> Result<Vec<Fun>, ParseError> {
         let eat_fun = |p: &mut Parser| p.expect(Token::Keyword(Keyword::Fun), "Only funs allowed in letrec");
         try!(eat_fun(self));
         let fun = try!(self.parse_fun());
@@ -180,9 +623,7 @@ impl<'p> Parser<'p> {
             try!(eat_fun(self));
             funs.push(try!(self.parse_fun()));
         }
-        try!(self.expect(Token::Keyword(Keyword::In), "Expected `in` after let rec"));
-        let body = try!(self.parse());
-        Ok(LetRec { funs: funs, body: body })
+        Ok(funs)
     }
 
     fn parse_type(&mut self) -> Result<Type, ParseError> {
@@ -202,30 +643,54 @@ impl<'p> Parser<'p> {
     }
 
     fn parse_atom_type(&mut self) -> Result<Type, ParseError> {
-        match self.tokenizer.eat_token() {
-            Token::Ident(name) if name == "int" => Ok(Type::Int),
-            Token::Ident(name) if name == "bool" => Ok(Type::Bool),
+        let mut result = match self.tokenizer.eat_token() {
+            Token::Ident(name) if name == "int" => Type::Int,
+            Token::Ident(name) if name == "float" => Type::Float,
+            Token::Ident(name) if name == "bool" => Type::Bool,
+            Token::Ident(name) if name == "string" => Type::Str,
+            Token::Ident(name) if name == "unit" => Type::Unit,
+            Token::Ident(name) if name != "list" => Type::Named(Ident::from_str(name)),
+            Token::TypeParam(name) => Type::Param(Ident::from_str(name)),
             Token::Paren(Paren::Open) => {
                 let inner = try!(self.parse_type());
                 try!(self.expect(Token::Paren(Paren::Close), "Expected `)`"));
-                Ok(inner)
+                inner
+            }
+            Token::Paren(Paren::OpenBrace) => {
+                let mut fields = vec![try!(self.parse_arg())];
+                while self.tokenizer.lookahead() == Token::Sym(Sym::Comma) {
+                    self.tokenizer.eat_token();
+                    fields.push(try!(self.parse_arg()));
+                }
+                try!(self.expect(Token::Paren(Paren::CloseBrace), "Expected `}`"));
+                Type::Record(fields)
             }
-            _ => Err(self.err("Expected type"))
+            _ => return Err(self.err_eaten("Expected type"))
+        };
+        loop {
+            result = match self.tokenizer.lookahead() {
+                Token::Ident("list") => result.list(),
+                Token::Keyword(Keyword::Ref) => result.ref_(),
+                _ => break,
+            };
+            self.tokenizer.eat_token();
         }
+        Ok(result)
     }
 
     fn parse_ident(&mut self) -> Result<&'p str, ParseError> {
         match self.tokenizer.eat_token() {
             Token::Ident(name) => Ok(name),
-            _ => Err(self.err("Expected identifier")),
+            _ => Err(self.err_eaten("Expected identifier")),
         }
     }
 
     fn expect(&mut self, t: Token<'p>, msg: &'static str) -> Result<(), ParseError> {
-        if self.tokenizer.eat_token() == t {
+        if self.tokenizer.lookahead() == t {
+            self.tokenizer.eat_token();
             Ok(())
         } else {
-            Err(self.err(msg))
+            Err(self.err(msg).with_expected(vec![self.describe(t)]))
         }
     }
 
@@ -239,24 +704,82 @@ impl<'p> Parser<'p> {
         }
     }
 
+    /// From `start` to the end of the last token.
+    fn span_from(&self, start: usize) -> Span {
+        Span::new(start, self.tokenizer.end)
+    }
+
     fn unknown(&self) -> ParseError {
         self.err("Unknown token")
     }
 
+    /// How `token` is written, to say which token a parse error expected.
+    fn describe(&self, token: Token) -> String {
+        let text = match token {
+            Token::Eof => return "end of input".to_owned(),
+            Token::Paren(paren) => PARENS.iter().find(|&&(_, p)| p == paren).map(|&(text, _)| text),
+            Token::Sym(sym) => SYMS.iter().find(|&&(_, s)| s == sym).map(|&(text, _)| text),
+            Token::Keyword(keyword) => Some(self.tokenizer.keywords.spelling(keyword)),
+            _ => None,
+        };
+        match text {
+            Some(text) => format!("`{}`", text),
+            None => format!("{:?}", token),
+        }
+    }
+
+    /// An error at the next token, which the parser didn't expect.
     fn err(&self, msg: &'static str) -> ParseError {
-        ParseError::new(self.tokenizer.position, msg.to_owned())
+        let start = self.tokenizer.position;
+        let len = match self.tokenizer.next() {
+            // Nothing is eaten of an unknown token, blame its first character.
+            (Token::Unknown, _) => self.tokenizer.input.chars().next().map_or(0, char::len_utf8),
+            (_, len) => len,
+        };
+        self.err_at(Span::new(start, start + len), msg)
+    }
+
+    /// An error at the token just eaten.
+    fn err_eaten(&self, msg: &'static str) -> ParseError {
+        self.err_at(Span::new(self.tokenizer.start, self.tokenizer.end), msg)
+    }
+
+    fn err_at(&self, span: Span, msg: &'static str) -> ParseError {
+        let found = if span.is_empty() {
+            None
+        } else {
+            Some(self.tokenizer.src[span.start as usize..span.end as usize].to_owned())
+        };
+        ParseError::new(span, msg.to_owned(), found)
     }
 }
 
 
 struct Tokenizer<'p> {
     position: usize,
+    /// The position of the last token.
+    start: usize,
+    /// The position after the last token, before the whitespace following it.
+    end: usize,
+    /// What is left of the input.
     input: &'p str,
+    /// The whole input, to quote tokens in errors.
+    src: &'p str,
+    keywords: &'p Keywords,
 }
 
 impl<'p> Tokenizer<'p> {
-    fn new(input: &'p str) -> Self {
-        Tokenizer { position: 0, input: input }
+    fn new(input: &'p str, keywords: &'p Keywords) -> Self {
+        let mut tokenizer = Tokenizer {
+            position: 0,
+            start: 0,
+            end: 0,
+            input: input,
+            src: input,
+            keywords: keywords,
+        };
+        tokenizer.skip_whitespace();
+        tokenizer
     }
 
     fn lookahead(&self) -> Token<'p> {
@@ -266,7 +789,9 @@ impl<'p> Tokenizer<'p> {
 
     fn eat_token(&mut self) -> Token<'p> {
         let (tok, len) = self.next();
+        self.start = self.position;
         self.advance(len);
+        self.end = self.position;
         self.skip_whitespace();
         tok
     }
@@ -285,10 +810,13 @@ impl<'p> Tokenizer<'p> {
         };
 
         magic!(
+            (eat_float, Float),
             (eat_number, Number),
             (eat_bool, Bool),
+            (eat_str, Str),
             (eat_keyword, Keyword),
             (eat_ident, Ident),
+            (eat_type_param, TypeParam),
             (eat_paren, Paren),
             (eat_sym, Sym)
         );
@@ -297,7 +825,6 @@ impl<'p> Tokenizer<'p> {
     }
 
     fn eat_number(&self) -> Option<(i64, usize)> {
-        //TODO: negative numbers?
         let non_digit = self.input.find(|c: char| !c.is_digit(10)).unwrap_or(self.input.len());
         if non_digit == 0 {
             None
@@ -307,56 +834,88 @@ impl<'p> Tokenizer<'p> {
         }
     }
 
+    /// Digits, a point and more digits.
+    fn eat_float(&self) -> Option<(&'p str, usize)> {
+        let digits = |s: &str| s.find(|c: char| !c.is_digit(10)).unwrap_or(s.len());
+        let whole = digits(self.input);
+        if whole == 0 || !self.input[whole..].starts_with('.') {
+            return None;
+        }
+        let fraction = digits(&self.input[whole + 1..]);
+        if fraction == 0 {
+            return None;
+        }
+        let len = whole + 1 + fraction;
+        Some((&self.input[..len], len))
+    }
+
     fn eat_bool(&self) -> Option<(bool, usize)> {
         self.dispatch(&[("true", true), ("false", false)])
     }
 
+    /// A string literal, as its raw contents between the quotes.
+    fn eat_str(&self) -> Option<(&'p str, usize)> {
+        if !self.input.starts_with('"') {
+            return None;
+        }
+        let mut chars = self.input.char_indices().skip(1);
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => return Some((&self.input[1..i], i + 1)),
+                '\n' => return None,
+                '\\' => {
+                    chars.next();
+                }
+                _ => {}
+            }
+        }
+        None
+    }
+
     fn eat_paren(&self) -> Option<(Paren, usize)> {
-        self.dispatch(&[("(", Paren::Open), (")", Paren::Close)])
+        self.dispatch(&PARENS)
     }
 
     fn eat_ident(&self) -> Option<(&'p str, usize)> {
-        let non_letter = self.input.find(|c: char| !c.is_alphabetic()).unwrap_or(self.input.len());
-        if non_letter == 0 {
-            None
-        } else {
-            Some((&self.input[..non_letter], non_letter))
+        if !self.input.starts_with(|c: char| c.is_alphabetic() || c == '_') {
+            return None;
         }
+        let end = self.input.find(|c: char| !is_ident_char(c)).unwrap_or(self.input.len());
+        Some((&self.input[..end], end))
+    }
+
+    /// `'a`, as the name after the quote.
+    fn eat_type_param(&self) -> Option<(&'p str, usize)> {
+        let starts_ident = |s: &str| s.starts_with(|c: char| c.is_alphabetic() || c == '_');
+        if !self.input.starts_with('\'') || !starts_ident(&self.input[1..]) {
+            return None;
+        }
+        let end = self.input[1..].find(|c: char| !is_ident_char(c)).map_or(self.input.len(), |i| i + 1);
+        Some((&self.input[1..end], end))
     }
 
     fn eat_sym(&self) -> Option<(Sym, usize)> {
-        let table = [
-        ("->", Sym::Arrow),
-        ("==", Sym::Eq),
-        ("<", Sym::Lt),
-        (">", Sym::Gt),
-        ("+", Sym::Add),
-        ("-", Sym::Sub),
-        ("*", Sym::Mul),
-        ("/", Sym::Div),
-        (":", Sym::Colon),
-        ];
-        self.dispatch(&table)
+        self.dispatch(&SYMS)
     }
 
     fn eat_keyword(&self) -> Option<(Keyword, usize)> {
-        let table = [
-        ("if", Keyword::If),
-        ("then", Keyword::Then),
-        ("else", Keyword::Else),
-        ("fun", Keyword::Fun),
-        ("is", Keyword::Is),
-        ("let", Keyword::Let),
-        ("rec", Keyword::Rec),
-        ("and", Keyword::And),
-        ("in", Keyword::In),
-        ];
-        self.dispatch(&table)
+        // `print` is a prefix of `print_int`, but isn't followed by a word
+        // boundary in it, so the order doesn't matter.
+        self.keywords.table().iter()
+            .find(|&&(ref text, _)| self.at_word(text))
+            .map(|&(ref text, keyword)| (keyword, text.len()))
     }
 
     fn skip_whitespace(&mut self) {
-        let non_ws = self.input.find(|c: char| !c.is_whitespace()).unwrap_or(self.input.len());
-        self.advance(non_ws);
+        loop {
+            let non_ws = self.input.find(|c: char| !c.is_whitespace()).unwrap_or(self.input.len());
+            self.advance(non_ws);
+            if !self.input.starts_with("--") {
+                return;
+            }
+            let eol = self.input.find('\n').unwrap_or(self.input.len());
+            self.advance(eol);
+        }
     }
 
     fn advance(&mut self, n: usize) {
@@ -366,25 +925,78 @@ impl<'p> Tokenizer<'p> {
 
     fn dispatch<T: Copy>(&self, table: &[(&'static str, T)]) -> Option<(T, usize)> {
         for & (pat, val) in table {
-            if self.input.starts_with(pat) {
-                let is_preffix = pat.ends_with(|c: char| c.is_alphabetic())
-                && self.input[pat.len()..].starts_with(|c: char| c.is_alphabetic());
-                if !is_preffix {
-                    return Some((val, pat.len()));
-                }
+            if self.at_word(pat) {
+                return Some((val, pat.len()));
             }
         }
         None
     }
+
+    /// Whether the input starts with `pat`, which, if it is a word, has to
+    /// be all of the next word: `if` is not at the start of `iffy`.
+    fn at_word(&self, pat: &str) -> bool {
+        if !self.input.starts_with(pat) {
+            return false;
+        }
+        let is_preffix = pat.ends_with(is_ident_char) && self.input[pat.len()..].starts_with(is_ident_char);
+        !is_preffix
+    }
 }
 
+fn is_ident_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+const PARENS: [(&'static str, Paren); 6] = [
+    ("(", Paren::Open),
+    (")", Paren::Close),
+    ("[", Paren::OpenBracket),
+    ("]", Paren::CloseBracket),
+    ("{", Paren::OpenBrace),
+    ("}", Paren::CloseBrace),
+];
+
+/// Longer symbols come first, so that `==` isn't read as two `=`.
+const SYMS: [(&'static str, Sym); 25] = [
+    ("->", Sym::Arrow),
+    ("==", Sym::Eq),
+    ("!=", Sym::Ne),
+    ("!", Sym::Bang),
+    ("<=", Sym::Le),
+    (">=", Sym::Ge),
+    ("=", Sym::Assign),
+    (";;", Sym::SemiSemi),
+    (";", Sym::Semi),
+    ("&&", Sym::And),
+    ("||", Sym::Or),
+    ("<", Sym::Lt),
+    (">", Sym::Gt),
+    ("++", Sym::Concat),
+    ("+", Sym::Add),
+    ("-", Sym::Sub),
+    ("*", Sym::Mul),
+    ("/", Sym::Div),
+    ("%", Sym::Mod),
+    ("::", Sym::Cons),
+    (":=", Sym::ColonEq),
+    (":", Sym::Colon),
+    (",", Sym::Comma),
+    ("|", Sym::Bar),
+    (".", Sym::Dot),
+];
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 enum Token<'p> {
     Eof,
     Unknown,
     Number(i64),
+    /// A number with a fractional part, as written.
+    Float(&'p str),
     Bool(bool),
+    Str(&'p str),
     Ident(&'p str),
+    /// A type parameter, `'a`, without its quote.
+    TypeParam(&'p str),
     Paren(Paren),
     Sym(Sym),
     Keyword(Keyword),
@@ -392,31 +1004,38 @@ enum Token<'p> {
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 enum Paren {
-    Open, Close,
+    Open, Close, OpenBracket, CloseBracket, OpenBrace, CloseBrace,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 enum Sym {
     Eq,
+    Ne,
     Lt,
+    Le,
     Gt,
+    Ge,
     Add,
     Sub,
     Mul,
     Div,
+    Mod,
     Colon,
-    Arrow,
-}
-
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
-enum Keyword {
-    If,
-    Then,
-    Else,
-    Fun,
-    Is,
-    Let,
-    Rec,
+    Cons,
+    Concat,
+    Comma,
+    Bar,
     And,
-    In,
+    Or,
+    Arrow,
+    Assign,
+    SemiSemi,
+    /// `;`, between an expression evaluated for its effects and the next one.
+    Semi,
+    /// `!`, which reads a reference cell.
+    Bang,
+    /// `:=`, which stores into one.
+    ColonEq,
+    /// `.`, which projects a field of a record.
+    Dot,
 }
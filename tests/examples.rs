@@ -0,0 +1,102 @@
+//! Runs every program in `examples/` and checks the value stated in its
+//! `-- expect:` comment, on the machine and compiled to Rust, and checks that
+//! both parsers read the same programs as each other.
+
+extern crate miniml;
+extern crate ast;
+
+use std::env;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use ast::pretty::{self, Style};
+use miniml::{Machine, Parser, compile_program, desugar_program_with_names, parse_program, strip_comments,
+             typecheck_program};
+
+fn run(src: &str) -> Result<String, String> {
+    let program = try!(parse_program(&strip_comments(src)).map_err(|e| format!("{:?}", e)));
+    try!(typecheck_program(&program).map_err(|e| e.to_string()));
+    let frame = compile_program(&program);
+    let mut machine = Machine::new(&frame);
+    machine.exec().map(|value| machine.show(value)).map_err(|e| e.to_string())
+}
+
+/// Builds `src` with `rustc` from the code `codegen` makes of it, in a file
+/// named after `name`, and runs it.
+fn run_rust(name: &str, src: &str) -> Result<String, String> {
+    let program = try!(parse_program(&strip_comments(src)).map_err(|e| format!("{:?}", e)));
+    try!(typecheck_program(&program).map_err(|e| e.to_string()));
+    let (ir, names) = desugar_program_with_names(&program);
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR"));
+    let source = dir.join(name).with_extension("rs");
+    let executable = dir.join(name);
+    File::create(&source).unwrap().write_all(miniml::codegen::rust(&ir, &names).as_bytes()).unwrap();
+    let rustc = env::var("RUSTC").unwrap_or("rustc".to_owned());
+    let status = Command::new(rustc).arg("-O").arg("-o").arg(&executable).arg(&source).status().unwrap();
+    assert!(status.success(), "rustc can't build {}", source.display());
+    let output = Command::new(&executable).output().unwrap();
+    if output.status.success() {
+        Ok(String::from_utf8(output.stdout).unwrap().trim_end().to_owned())
+    } else {
+        Err(String::from_utf8(output.stderr).unwrap())
+    }
+}
+
+/// `src` written back by the formatter, which mustn't change what it means.
+fn formatted(src: &str) -> String {
+    pretty::program(&parse_program(&strip_comments(src)).unwrap(), &Style::default())
+}
+
+fn expected(src: &str) -> Option<&str> {
+    src.lines()
+       .map(|line| line.trim())
+       .find(|line| line.starts_with("-- expect:"))
+       .map(|line| line["-- expect:".len()..].trim())
+}
+
+/// The `.ml` files in `dir` of the repository, with their text.
+fn sources(dir: &str) -> Vec<(PathBuf, String)> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join(dir);
+    let mut paths = fs::read_dir(dir).unwrap()
+                                     .map(|entry| entry.unwrap().path())
+                                     .filter(|path| path.extension().map_or(false, |e| e == "ml"))
+                                     .collect::<Vec<_>>();
+    paths.sort();
+    assert!(!paths.is_empty());
+    paths.into_iter()
+         .map(|path| {
+             let mut src = String::new();
+             File::open(&path).unwrap().read_to_string(&mut src).unwrap();
+             (path, src)
+         })
+         .collect()
+}
+
+#[test]
+fn examples() {
+    for (path, src) in sources("examples") {
+        let expected = expected(&src).unwrap_or_else(|| panic!("{} has no expect comment", path.display()));
+        assert_eq!(run(&src), Ok(expected.to_owned()), "in {}", path.display());
+        assert_eq!(run(&formatted(&src)), Ok(expected.to_owned()), "in {} formatted", path.display());
+    }
+}
+
+#[test]
+fn examples_compiled_to_rust() {
+    for (path, src) in sources("examples") {
+        let expected = expected(&src).unwrap_or_else(|| panic!("{} has no expect comment", path.display()));
+        let name = path.file_stem().unwrap().to_str().unwrap();
+        assert_eq!(run_rust(name, &src), Ok(expected.to_owned()), "in {}", path.display());
+    }
+}
+
+#[test]
+fn parsers_agree() {
+    for (path, src) in sources("examples").into_iter().chain(sources("tests/golden")) {
+        let lalrpop = Parser::Lalrpop.parse_program(&src).unwrap();
+        let ll = Parser::Ll.parse_program(&src).unwrap_or_else(|e| panic!("in {}: {}", path.display(), e));
+        assert_eq!(format!("{:?}", ll), format!("{:?}", lalrpop), "in {}", path.display());
+    }
+}
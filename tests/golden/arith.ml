@@ -0,0 +1,2 @@
+-- Operator precedence and comparisons.
+if 1 + 2 * 3 < 10 then 92 / 2 else 0 - 1
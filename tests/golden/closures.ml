@@ -0,0 +1,3 @@
+-- A curried function capturing its first argument.
+let fun add(x: int): int -> int is fun add_x(y: int): int is x + y;;
+add 90 2
@@ -0,0 +1,4 @@
+-- Mutual recursion.
+let rec fun even(n: int): bool is if n == 0 then true else odd (n - 1)
+and fun odd(n: int): bool is if n == 0 then false else even (n - 1)
+in even 10
@@ -0,0 +1,3 @@
+-- A constructor with an argument, one without, and a match on both.
+type shape = Circle of int | Point in
+match Circle 2 with Circle r -> r * r | Point -> 0
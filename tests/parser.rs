@@ -1,7 +1,8 @@
 extern crate syntax_ll;
 extern crate ast;
 
-use ast::Expr;
+use ast::{Expr, ExprKind, Span};
+use ast::pretty::{self, Style};
 use syntax_ll::parse;
 
 
@@ -25,17 +26,21 @@ fn test_good_expressions() {
     assert_parses("true", "true");
     assert_parses("false", "false");
     assert_parses("spam", "spam");
+    assert_parses("_1 + x_2 * if0", "(+ _1 (* x_2 if0))");
     assert_parses("1 == 1", "(== 1 1)");
     assert_parses("1 < 1 + 1", "(< 1 (+ 1 1))");
     assert_parses("1 * 2 > 1", "(> (* 1 2) 1)");
     assert_parses("(1 == 2) == 3", "(== (== 1 2) 3)");
     assert_parses("1 < (2 > 3)", "(< 1 (> 2 3))");
+    assert_parses("x <= 1 && y >= 2 || z != 3", "(|| (&& (<= x 1) (>= y 2)) (!= z 3))");
+    assert_parses("x<=y", "(<= x y)");
     assert_parses("1 + 2 * 3", "(+ 1 (* 2 3))");
     assert_parses("if 1 then 2 else if 3 then 4 else 5", "(if 1 2 (if 3 4 5))");
     assert_parses("if 1 then if 2 then 3 else 4 else 5", "(if 1 (if 2 3 4) 5)");
     assert_parses("f 92 + x y z", "(+ (f 92) ((x y) z))");
     assert_parses("1 * f 92", "(* 1 (f 92))");
-    assert_parses("0 * if 1 then 2 else 3", "(* 0 (if 1 2 3))")
+    assert_parses("0 * if 1 then 2 else 3", "(* 0 (if 1 2 3))");
+    assert_parses("-- the answer\n92 -- is here\n-- end", "92");
 }
 
 #[test]
@@ -53,12 +58,211 @@ fn test_good_fns() {
 
     assert_parses("1 + fun f(n:bool):bool is n + 1",
                   "(+ 1 (λ f (n: bool): bool (+ n 1)))");
+
+    assert_parses("fun add(x: int, y: int): int is x + y",
+                  "(λ add (x: int, y: int): int (+ x y))");
+
+    assert_parses("fun twice(f: int -> int, x: int): int is f (f x)",
+                  "(λ twice (f: int -> int, x: int): int (f (f x)))");
+
+    assert_parses("fun id(x) is x", "(λ id (x: _): _ x)");
+    assert_parses("fun const(x: int, y): int is x", "(λ const (x: int, y: _): int x)");
+    assert_parses("fun apply(f: 'a -> 'b, x: 'a): 'b is f x", "(λ apply (f: 'a -> 'b, x: 'a): 'b (f x))");
+    assert_parses("fun first(xs: 'elem list ref): 'elem is head !xs",
+                  "(λ first (xs: 'elem list ref): 'elem (head (! xs)))");
+
+    you_shall_not_parse("fun f(): int is 1");
+    you_shall_not_parse("fun f(x:) is x");
+    you_shall_not_parse("fun f(x: ') is x");
+    you_shall_not_parse("fun f(x) is 'a");
+    you_shall_not_parse("fun f(x: int,): int is x");
+}
+
+#[test]
+fn test_lists() {
+    assert_parses("[1, x, 1 + 2]", "[1, x, (+ 1 2)]");
+    assert_parses("[]: int list", "[]: int list");
+    assert_parses("[]: (int -> bool) list list", "[]: (int -> bool) list list");
+    assert_parses("[] :: [x]", "(:: [] [x])");
+    assert_parses("1 + 2 :: 3 :: xs", "(:: (+ 1 2) (:: 3 xs))");
+    assert_parses("x :: xs == ys", "(== (:: x xs) ys)");
+    assert_parses("head xs + f tail xs", "(+ (head xs) (f (tail xs)))");
+    assert_parses("isEmpty [[]: bool list]", "(isEmpty [[]: bool list])");
+    assert_parses("fun f(xs: int list): int list -> bool is xs",
+                  "(λ f (xs: int list): int list -> bool xs)");
+    you_shall_not_parse("[1,]");
+    you_shall_not_parse("head");
+    you_shall_not_parse("1 :: ");
+}
+
+#[test]
+fn test_strings() {
+    assert_parses(r#""hello""#, r#""hello""#);
+    assert_parses(r#""""#, r#""""#);
+    assert_parses(r#""tab\t, \"quote\" and \\""#, r#""tab\t, \"quote\" and \\""#);
+    assert_parses(r#""-- not a comment""#, r#""-- not a comment""#);
+    assert_parses(r#""a" ++ "b" ++ c"#, r#"(++ "a" (++ "b" c))"#);
+    assert_parses(r#"x ++ "!" == y"#, r#"(== (++ x "!") y)"#);
+    assert_parses(r#"f "a" ++ "b""#, r#"(++ (f "a") "b")"#);
+    assert_parses("fun greet(name: string): string list is [name]",
+                  "(λ greet (name: string): string list [name])");
+    you_shall_not_parse(r#""unterminated"#);
+    you_shall_not_parse(r#""bad \q escape""#);
+    you_shall_not_parse("\"new\nline\"");
+    you_shall_not_parse(r#""a" ++"#);
+}
+
+#[test]
+fn test_negation() {
+    assert_parses("-5", "-5");
+    assert_parses("-(x + 1)", "(- 0 (+ x 1))");
+    assert_parses("1 - -2", "(- 1 -2)");
+    assert_parses("-2 * 3", "(* -2 3)");
+    assert_parses("2 * -x", "(* 2 (- 0 x))");
+    assert_parses("-f x", "(- 0 (f x))");
+    assert_parses("f -1", "(- f 1)");
+    assert_parses("7 % 3 + 1", "(+ (% 7 3) 1)");
+    assert_parses("x * y % z", "(% (* x y) z)");
+    you_shall_not_parse("-");
+    you_shall_not_parse("1 %");
+}
+
+#[test]
+fn test_logic() {
+    assert_parses("a && b || c", "(|| (&& a b) c)");
+    assert_parses("a || b && c", "(|| a (&& b c))");
+    assert_parses("a && b && c", "(&& a (&& b c))");
+    assert_parses("x < 1 && y == 2", "(&& (< x 1) (== y 2))");
+    assert_parses("not a && not (b || c)", "(&& (not a) (not (|| b c)))");
+    assert_parses("not f x", "((not f) x)");
+    assert_parses("match x with A -> a || b | B -> c", "(match x [A (|| a b)] [B c])");
+    you_shall_not_parse("a &&");
+    you_shall_not_parse("not");
+}
+
+#[test]
+fn test_refs() {
+    assert_parses("ref 1", "(ref 1)");
+    assert_parses("!r + 1", "(+ (! r) 1)");
+    assert_parses("f !r", "(f (! r))");
+    assert_parses("!(!r)", "(! (! r))");
+    assert_parses("r := !r + 1", "(:= r (+ (! r) 1))");
+    assert_parses("a := b := c || d", "(:= a (:= b (|| c d)))");
+    assert_parses("let r = ref 0 in r := 1", "(let r (ref 0) in (:= r 1))");
+    assert_parses("match x with A -> r := 1 | B -> 2", "(match x [A (:= r 1)] [B 2])");
+    assert_parses("fun get(rs: int ref list): int is !(head rs)",
+                  "(λ get (rs: int ref list): int (! (head rs)))");
+    assert_parses("[]: (int -> int) ref list", "[]: (int -> int) ref list");
+    you_shall_not_parse("ref");
+    you_shall_not_parse("!");
+    you_shall_not_parse("r :=");
+    you_shall_not_parse("r := := 1");
+}
+
+#[test]
+fn test_sequences() {
+    assert_parses("()", "()");
+    assert_parses("f ()", "(f ())");
+    assert_parses("a; b; c", "(; a (; b c))");
+    assert_parses("r := 1; !r", "(; (:= r 1) (! r))");
+    assert_parses("(a; b) + 1", "(+ (; a b) 1)");
+    assert_parses("let x = 1 in a; x", "(let x 1 in (; a x))");
+    assert_parses("if c then a else b; d", "(if c a (; b d))");
+    assert_parses("fun f(u: unit): unit is ()", "(λ f (u: unit): unit ())");
+    you_shall_not_parse("a;");
+    you_shall_not_parse("; a");
+    you_shall_not_parse("(;)");
+}
+
+#[test]
+fn test_print() {
+    assert_parses("print \"hi\"", "(print \"hi\")");
+    assert_parses("print_int 1 + 1", "(+ (print_int 1) 1)");
+    assert_parses("print_int (1 + 1); print_int !r", "(; (print_int (+ 1 1)) (print_int (! r)))");
+    assert_parses("f print_int x", "(f (print_int x))");
+    assert_parses("printer", "printer");
+    you_shall_not_parse("print");
+    you_shall_not_parse("let print_int = 1 in 2");
+}
+
+#[test]
+fn test_float() {
+    assert_parses("1.5 + 2.0", "(+ 1.5 2.0)");
+    assert_parses("-0.25 * x", "(* -0.25 x)");
+    assert_parses("float_of_int n / 2.0", "(\\ (float_of_int n) 2.0)");
+    assert_parses("int_of_float (x * 10.0)", "(int_of_float (* x 10.0))");
+    assert_parses("fun half(x: float): float is x / 2.0", "(λ half (x: float): float (\\ x 2.0))");
+    you_shall_not_parse("1.");
+    you_shall_not_parse("float_of_int");
+}
+
+#[test]
+fn test_variants() {
+    assert_parses("type shape = Circle of int | Square of int | Point in Circle 1",
+                  "(type shape [Circle of int | Square of int | Point] in (Circle 1))");
+    assert_parses("type t = Leaf | Node of t list in Node [Leaf]",
+                  "(type t [Leaf | Node of t list] in (Node [Leaf]))");
+    assert_parses("f Point (Circle 1 + 1)", "((f Point) (+ (Circle 1) 1))");
+    assert_parses("Circle 1 2", "((Circle 1) 2)");
+    assert_parses("match s with Circle r -> r * r | Point -> 0",
+                  "(match s [(Circle r) (* r r)] [Point 0])");
+    assert_parses("match s with A -> match t with B -> 1 | C -> 2",
+                  "(match s [A (match t [B 1] [C 2])])");
+    assert_parses("fun area(s: shape): int is 0", "(λ area (s: shape): int 0)");
+    you_shall_not_parse("type t = in 1");
+    you_shall_not_parse("type t = A | in 1");
+    you_shall_not_parse("match s with");
+    you_shall_not_parse("match s with A 1");
+}
+
+#[test]
+fn test_type_aliases() {
+    assert_parses("type predicate = int -> bool in fun p(x: int): bool is true",
+                  "(type predicate = int -> bool in (λ p (x: int): bool true))");
+    assert_parses("type shapes = shape list in []: shapes",
+                  "(type shapes = shape list in []: shapes)");
+    assert_parses("type table = (int -> bool) -> int in 1", "(type table = (int -> bool) -> int in 1)");
+    assert_parses("type n = int in 1", "(type n = int in 1)");
+    assert_parses("type t = shape in 1", "(type t [shape] in 1)");
+    you_shall_not_parse("type predicate = int -> in 1");
+    you_shall_not_parse("type n = int | A in 1");
+}
+
+#[test]
+fn test_records() {
+    assert_parses("{x = 1, y = true}", "{x = 1, y = true}");
+    assert_parses("{p = {x = 1 + 2}, f = fun id(x: int): int is x}",
+                  "{p = {x = (+ 1 2)}, f = (λ id (x: int): int x)}");
+    assert_parses("r.p.x + f r.y", "(+ r.p.x (f r.y))");
+    assert_parses("{x = 1}.x", "{x = 1}.x");
+    assert_parses("!r.x", "(! r.x)");
+    assert_parses("fun f(p: {x: int, y: bool}): {x: int} list is []: {x: int} list",
+                  "(λ f (p: {x: int, y: bool}): {x: int} list []: {x: int} list)");
+    assert_parses("type point = {x: int, y: int} in 1", "(type point = {x: int, y: int} in 1)");
+    you_shall_not_parse("{}");
+    you_shall_not_parse("{x = 1,}");
+    you_shall_not_parse("{x: 1}");
+    you_shall_not_parse("r.");
+    you_shall_not_parse("fun f(p: {x}): int is 1");
+}
+
+#[test]
+fn test_let() {
+    assert_parses("let x = 2 + 2 in x * x", "(let x (+ 2 2) in (* x x))");
+    assert_parses("let x = let y = 1 in y in 1 + let z = x in z",
+                  "(let x (let y 1 in y) in (+ 1 (let z x in z)))");
+    you_shall_not_parse("let x = 1");
+    you_shall_not_parse("let x 1 in x");
+
+    assert_parses("let λ = 1 in let café = λ + 1 in café", "(let λ 1 in (let café (+ λ 1) in café))");
 }
 
 #[test]
 fn test_let_fn() {
     assert_parses("let fun f(x: int): int is 92 in f 1",
-                  "(let f λ(x: int): int 92 in (f 1))")
+                  "(let f λ(x: int): int 92 in (f 1))");
+    assert_parses("let fun add(x: int, y: int): int is x + y in add 1 2",
+                  "(let add λ(x: int, y: int): int (+ x y) in ((add 1) 2))")
 }
 
 #[test]
@@ -68,11 +272,206 @@ fn test_let_rec() {
                    in a b 92",
                   "(letrec [(λ a (x: int): int (b x))(λ b (x: int): int (a x))] in ((a b) 92))")
 }
+#[test]
+fn test_program() {
+    let program = syntax_ll::parse_program("
+        let fun inc(x: int): int is x + 1;;
+        let rec fun a(x: int): int is b x and fun b(x: int): int is a x;;
+        let main = inc 91;;
+    ").unwrap();
+    assert_eq!(format!("{:?}", program),
+               "(let (λ inc (x: int): int (+ x 1)))\n\
+                (letrec [(λ a (x: int): int (b x))(λ b (x: int): int (a x))])\n\
+                (let main (inc 91))\n\
+                main");
+
+    let program = syntax_ll::parse_program("let x = 1;; let fun f(y: int): int is y in f x").unwrap();
+    assert_eq!(format!("{:?}", program),
+               "(let x 1)\n(let f λ(y: int): int y in (f x))");
+
+    assert!(syntax_ll::parse_program("let x = 1").is_err());
+    let program = syntax_ll::parse_program("let x = 1;; let y = x + 1 in y").unwrap();
+    assert_eq!(format!("{:?}", program), "(let x 1)\n(let y (+ x 1) in y)");
+
+    assert!(syntax_ll::parse_program("1 2 )").is_err());
+
+    let decl = syntax_ll::parse_decl("let x = 92;;").unwrap();
+    assert_eq!(format!("{:?}", decl), "(let x 92)");
+    assert!(syntax_ll::parse_decl("let x = 92").is_err());
+    assert!(syntax_ll::parse_decl("let x = 92;; x").is_err());
+    let program = syntax_ll::parse_program("let x = a; b;; x; x").unwrap();
+    assert_eq!(format!("{:?}", program), "(let x (; a b))\n(; x x)");
+
+    let program = syntax_ll::parse_program("fun inc(x: int): int is x + 1;; inc 91").unwrap();
+    assert_eq!(format!("{:?}", program), "(let (λ inc (x: int): int (+ x 1)))\n(inc 91)");
+    let program = syntax_ll::parse_program("let y = 1;; fun inc(x: int): int is x + y").unwrap();
+    assert_eq!(format!("{:?}", program), "(let y 1)\n(λ inc (x: int): int (+ x y))");
+    assert_eq!(program.body.span, Span::new(12, 41));
+    let decl = syntax_ll::parse_decl("fun inc(x: int): int is x + 1;;").unwrap();
+    assert_eq!(format!("{:?}", decl), "(let (λ inc (x: int): int (+ x 1)))");
+    assert!(syntax_ll::parse_program("fun inc(x: int): int is x;; fun").is_err());
+}
+
+/// Checks that `expr` is written back as `formatted`, which parses to the same tree.
+fn assert_formats(expr: &str, style: &Style, formatted: &str) {
+    let expr = parse(expr).unwrap();
+    assert_eq!(pretty::expr(&expr, style), formatted);
+    let reparsed = parse(formatted).unwrap_or_else(|e| {
+        panic!("\n`{}` failed to parse:\n {:?}\n", formatted, e)
+    });
+    assert_eq!(format!("{:?}", reparsed), format!("{:?}", expr));
+}
+
+#[test]
+fn test_pretty() {
+    let style = Style::default();
+    assert_formats("1+2*(3-4)", &style, "1 + 2 * (3 - 4)");
+    assert_formats("(f x) (g (-1))", &style, "f x (g (-1))");
+    assert_formats("x <= 1 && (y || not z)", &style, "x <= 1 && (y || not z)");
+    assert_formats("(1 :: [2]) :: [[]: int list]", &style, "(1 :: [2]) :: [[]: int list]");
+    assert_formats("!r.x + (!r).y", &style, "!r.x + (!r).y");
+    assert_formats("r := (if c then 1 else 2); print \"a\\n\"",
+                   &style,
+                   "r := (if c then 1 else 2); print \"a\\n\"");
+    assert_formats("1 + fun f(x: int, y: int): int is x", &style, "1 + (fun f(x: int, y: int): int is x)");
+    assert_formats("fun twice(f, x): int is f (f x)", &style, "fun twice(f, x): int is f (f x)");
+    assert_formats("fun apply(f: ('a->'b), x: 'a): 'b is f x",
+                   &style,
+                   "fun apply(f: 'a -> 'b, x: 'a): 'b is f x");
+    assert_formats("match s with A x -> (if x then 1 else 2) | B -> 3",
+                   &style,
+                   "match s with A x -> (if x then 1 else 2) | B -> 3");
+    assert_formats("type t = A of int | B in type p = {x: t} list in B",
+                   &style,
+                   "type t = A of int | B in type p = {x: t} list in B");
+
+    let style = Style {
+        width: 30,
+        indent: 2,
+    };
+    assert_formats("let rec fun even(n: int): bool is if n == 0 then true else odd (n - 1)
+                    and fun odd(n: int): bool is if n == 0 then false else even (n - 1)
+                    in even 92",
+                   &style,
+                   "let rec fun even(n: int): bool is\n  \
+                      if n == 0 then\n    \
+                        true\n  \
+                      else\n    \
+                        odd (n - 1)\n\
+                    and fun odd(n: int): bool is\n  \
+                      if n == 0 then\n    \
+                        false\n  \
+                      else\n    \
+                        even (n - 1)\n\
+                    in\n\
+                    even 92");
+    assert_formats("match shape with Circle r -> print_int r; r * r | Square a -> a",
+                   &style,
+                   "match shape with\n  \
+                      Circle r ->\n    \
+                        print_int r; r * r\n  \
+                      | Square a -> a");
+    assert_formats("let p = {x = 1, y = \"a longer string\"} in p.x",
+                   &style,
+                   "let p =\n  \
+                      {\n    \
+                        x = 1,\n    \
+                        y = \"a longer string\"\n  \
+                      }\n\
+                    in\n\
+                    p.x");
+
+    let program = syntax_ll::parse_program("let x = 1;; let fun f(y: int): int is y + x;; f 2").unwrap();
+    assert_eq!(pretty::program(&program, &Style::default()),
+               "let x = 1;;\n\nlet fun f(y: int): int is y + x;;\n\nf 2\n");
+    let program = syntax_ll::parse_program("let main = 92;;").unwrap();
+    assert_eq!(pretty::program(&program, &Style::default()), "let main = 92;;\n");
+}
+
+#[test]
+fn test_spans() {
+    let expr = parse("f (1 + 2)  * x -- comment").unwrap();
+    assert_eq!(expr.span, Span::new(0, 14));
+    match expr.kind {
+        ExprKind::ArithBinOp(ref op) => {
+            assert_eq!(op.lhs.span, Span::new(0, 9));
+            assert_eq!(op.rhs.span, Span::new(13, 14));
+        }
+        _ => panic!("expected a multiplication: {:?}", expr),
+    }
+    let program = syntax_ll::parse_program("let x = 1;;\nx + true").unwrap();
+    assert_eq!(program.body.span, Span::new(12, 20));
+}
+
+#[test]
+fn test_tokens() {
+    let tokens = syntax_ll::tokens("let x = 1.5;; -- comment\nf \"a\\\"\" [x]").unwrap();
+    let tokens = tokens.iter().map(|&(span, ref token)| format!("{:?} {}", span, token)).collect::<Vec<_>>();
+    assert_eq!(tokens,
+               vec!["0..3 Keyword(Let)",
+                    "4..5 Ident(\"x\")",
+                    "6..7 Sym(Assign)",
+                    "8..11 Float(\"1.5\")",
+                    "11..13 Sym(SemiSemi)",
+                    "25..26 Ident(\"f\")",
+                    "27..32 Str(\"a\\\\\\\"\")",
+                    "33..34 Paren(OpenBracket)",
+                    "34..35 Ident(\"x\")",
+                    "35..36 Paren(CloseBracket)"]);
+    assert!(syntax_ll::tokens("1 # 2").is_err());
+
+    let tokens = syntax_ll::tokens("iffy trueish x1 is_even print_int printer").unwrap();
+    let tokens = tokens.iter().map(|&(_, ref token)| token.as_str()).collect::<Vec<_>>();
+    assert_eq!(tokens,
+               vec!["Ident(\"iffy\")",
+                    "Ident(\"trueish\")",
+                    "Ident(\"x1\")",
+                    "Ident(\"is_even\")",
+                    "Keyword(PrintInt)",
+                    "Ident(\"printer\")"]);
+}
+
+#[test]
+fn test_keywords() {
+    use syntax_ll::{Keyword, Keywords};
+
+    let keywords = Keywords::default().with_spelling(Keyword::Fun, "fn").with_spelling(Keyword::Is, "=>");
+    assert_eq!(keywords.keyword("fn"), Some(Keyword::Fun));
+    assert_eq!(keywords.keyword("fun"), None);
+    let expr = syntax_ll::parse_with_keywords("fn id(x: int): int => fun x", &keywords).unwrap();
+    assert_eq!(format!("{:?}", expr), "(λ id (x: int): int (fun x))");
+
+    let error = syntax_ll::parse_with_keywords("if x else 1", &keywords).unwrap_err();
+    assert_eq!(error.expected(), ["`then`"]);
+    let keywords = keywords.with_spelling(Keyword::Then, "alors");
+    let error = syntax_ll::parse_with_keywords("if x else 1", &keywords).unwrap_err();
+    assert_eq!(error.expected(), ["`alors`"]);
+}
+
 #[test]
 fn test_bad_expressions() {
     you_shall_not_parse("((92)");
     you_shall_not_parse("1 == 1 == 1");
     you_shall_not_parse("1 < 1 > 1");
+    you_shall_not_parse("1 <= 1 != 1");
+    you_shall_not_parse("1 !");
+}
+
+#[test]
+fn test_error_messages() {
+    let error = syntax_ll::parse("if x\n  else 2").unwrap_err();
+    assert_eq!(error.to_string(), "Expected `then` after if condition, found `else`");
+    assert_eq!(error.expected(), ["`then`"]);
+    assert_eq!(error.span(), Span::new(7, 11));
+    assert_eq!(error.line_col("if x\n  else 2"), (2, 3));
+
+    let error = syntax_ll::parse("if x then 1").unwrap_err();
+    assert_eq!(error.to_string(), "Expected `else` after then branch, found end of input");
+    assert_eq!(error.span(), Span::new(11, 11));
+
+    let error = syntax_ll::parse("1 # 2").unwrap_err();
+    assert_eq!(error.to_string(), "Unknown token, found `#`");
+    assert_eq!(error.span(), Span::new(2, 3));
 }
 
 #[test]
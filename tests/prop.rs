@@ -0,0 +1,73 @@
+//! Checks the compiler and the machine against the reference interpreter of
+//! the fuzz targets on random well typed expressions, with the garbage
+//! collector running after every instruction to shake out objects it frees
+//! too early. quickcheck shrinks a failing case to a small expression.
+
+extern crate ast;
+extern crate miniml;
+extern crate quickcheck;
+
+#[path = "../fuzz/src/gen.rs"]
+mod gen;
+#[path = "../fuzz/src/reference.rs"]
+mod reference;
+
+use miniml::{GcConfig, GcTrigger, Machine, Status, compile};
+use quickcheck::{QuickCheck, TestResult};
+
+/// Runs `expr` for at most `fuel` instructions, showing its value after
+/// whatever it printed, or `None` if it doesn't finish in time.
+fn run_machine(expr: &ast::Expr, gc: GcConfig, fuel: u64) -> Option<Result<String, String>> {
+    let frame = compile(expr);
+    let mut output = Vec::new();
+    let result = {
+        let mut machine = Machine::new(&frame).with_gc(gc).with_output(&mut output);
+        step(&mut machine, fuel)
+    };
+    result.map(|result| result.map(|value| String::from_utf8_lossy(&output).into_owned() + &value))
+}
+
+fn step(machine: &mut Machine, fuel: u64) -> Option<Result<String, String>> {
+    for _ in 0..fuel {
+        match machine.step() {
+            Status::Finished(value) => return Some(Ok(machine.show(value))),
+            Status::Running => {}
+            Status::Error(e) => return Some(Err(e.to_string())),
+        }
+    }
+    None
+}
+
+/// The expression built from `data` computes the same thing on the machine
+/// as in the reference interpreter, whichever way the heap is collected.
+fn agrees_with_reference(data: Vec<u8>) -> TestResult {
+    let expr = gen::expr(&data);
+    let expected = match reference::eval(&expr, 2_000) {
+        Ok(value) => Ok(value),
+        Err(reference::Error::Runtime(message)) => Err(message),
+        Err(reference::Error::Overflow) | Err(reference::Error::OutOfFuel) => return TestResult::discard(),
+    };
+    for &generational in &[false, true] {
+        let gc = GcConfig {
+            trigger: GcTrigger::Steps(1),
+            generational: generational,
+        };
+        match run_machine(&expr, gc, 100_000) {
+            Some(actual) => {
+                if actual != expected {
+                    return TestResult::error(format!("{:?}\nmachine:   {:?}\nreference: {:?}",
+                                                     expr,
+                                                     actual,
+                                                     expected));
+                }
+            }
+            None => return TestResult::discard(),
+        }
+    }
+    TestResult::passed()
+}
+
+#[test]
+fn machine_agrees_with_reference() {
+    QuickCheck::new().tests(500).quickcheck(agrees_with_reference as fn(Vec<u8>) -> TestResult);
+}